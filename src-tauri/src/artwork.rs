@@ -0,0 +1,161 @@
+//! Cover art embedded in song files.
+//!
+//! Songs store a hash of their front cover when they're read, thumbnails are generated on first
+//! request and cached on disk by that hash so songs sharing an album cover share a thumbnail.
+//! Thumbnails are served to the frontend through the `artwork://` URI protocol.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use image::{codecs::jpeg::JpegEncoder, ImageReader};
+use lofty::{
+    file::{TaggedFile, TaggedFileExt},
+    picture::PictureType,
+};
+
+/// Thumbnails are resized to fit within a square of this many pixels.
+const THUMBNAIL_SIZE: u32 = 300;
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Numbers temporary thumbnail files so concurrent requests for the same cover don't write to the
+/// same file.
+static TEMPORARY_FILE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to parse song file: {0}")]
+    Lofty(#[from] lofty::error::LoftyError),
+    #[error("failed to read or write artwork: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to decode or encode artwork: {0}")]
+    Image(#[from] image::ImageError),
+}
+
+/// Finds the front cover in a song's tags, falling back to the first picture when none are marked
+/// as the front cover.
+pub fn front_cover(tagged_file: &TaggedFile) -> Option<&[u8]> {
+    let pictures: Vec<_> = tagged_file
+        .tags()
+        .iter()
+        .flat_map(|tag| tag.pictures())
+        .collect();
+
+    pictures
+        .iter()
+        .find(|picture| picture.pic_type() == PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|picture| picture.data())
+}
+
+pub fn read_front_cover(song_path: &str) -> Result<Option<Vec<u8>>, Error> {
+    let tagged_file = lofty::read_from_path(song_path)?;
+    Ok(front_cover(&tagged_file).map(<[u8]>::to_vec))
+}
+
+/// The content hash used to key cached thumbnails.
+pub fn hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+/// Returns the path of the cached thumbnail for a song's cover, creating it if it doesn't exist.
+/// Returns `None` if the song has no cover.
+///
+/// When the hash of the cover is already known the song file is only read if the thumbnail isn't
+/// cached yet.
+pub fn thumbnail(
+    song_path: &str,
+    known_hash: Option<&str>,
+    cache_dir: &Path,
+) -> Result<Option<PathBuf>, Error> {
+    if let Some(known_hash) = known_hash {
+        let cached = thumbnail_path(cache_dir, known_hash);
+        if cached.exists() {
+            return Ok(Some(cached));
+        }
+    }
+
+    let Some(cover) = read_front_cover(song_path)? else {
+        return Ok(None);
+    };
+    let cached = thumbnail_path(cache_dir, &hash(&cover));
+    if cached.exists() {
+        return Ok(Some(cached));
+    }
+
+    let image = ImageReader::new(std::io::Cursor::new(cover))
+        .with_guessed_format()?
+        .decode()?;
+    // JPEG doesn't support transparency, covers with an alpha channel are flattened.
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+
+    std::fs::create_dir_all(cache_dir)?;
+    // Written to a temporary file first so a half written thumbnail is never served.
+    let temporary = temporary_path(&cached);
+    let mut file = std::fs::File::create(&temporary)?;
+    JpegEncoder::new_with_quality(&mut file, THUMBNAIL_QUALITY).encode_image(&thumbnail)?;
+    std::fs::rename(&temporary, &cached)?;
+
+    Ok(Some(cached))
+}
+
+fn thumbnail_path(cache_dir: &Path, hash: &str) -> PathBuf {
+    cache_dir.join(format!("{hash}.jpg"))
+}
+
+fn temporary_path(cached: &Path) -> PathBuf {
+    let count = TEMPORARY_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+    cached.with_extension(format!("{}-{count}.tmp", std::process::id()))
+}
+
+/// Decodes a percent encoded URI path, as produced by `encodeURIComponent` in the frontend.
+pub fn decode_uri_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_uri_paths() {
+        assert_eq!(
+            decode_uri_path("%2FMusic%2FMy%20Song%20%23%201.mp3"),
+            "/Music/My Song # 1.mp3"
+        );
+        assert_eq!(decode_uri_path("%C3%9Cn%C3%AF"), "Ünï");
+        // Invalid escapes are kept as they are.
+        assert_eq!(decode_uri_path("100%"), "100%");
+        assert_eq!(decode_uri_path("%zz"), "%zz");
+    }
+
+    #[test]
+    fn uses_unique_temporary_files() {
+        let cached = thumbnail_path(Path::new("/cache"), "abc");
+        let first = temporary_path(&cached);
+        let second = temporary_path(&cached);
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(Path::new("/cache")));
+        assert!(first.to_string_lossy().ends_with(".tmp"));
+    }
+}
@@ -0,0 +1,580 @@
+//! Finds where songs sit in a recording by matching their audio.
+//!
+//! An excerpt of each song's onset envelope is cross-correlated with the recording's around the
+//! track's cue time. DJs change the tempo of songs to beatmatch, so the excerpt is stretched to
+//! every speed within the range of a pitch fader and the best match gives both the position and
+//! the speed. Onsets are compared rather than spectra since they're unaffected by key lock,
+//! EQ and effects, which change the pitch and timbre but not the rhythm.
+//!
+//! Whether the pitch changed with the tempo, or was held by key lock, is then found by comparing
+//! the song's spectrum with the recording's at the matched position.
+
+use std::{path::Path, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::Serialize;
+
+use crate::{
+    audio::{
+        self,
+        tempo::{OnsetEnvelope, OnsetStream},
+        Error,
+    },
+    recording::Track,
+};
+
+/// Alignments less confident than this aren't used to correct cue sheets by default.
+pub const DEFAULT_MIN_CONFIDENCE: f64 = 0.5;
+
+/// Length of each song matched against the recording, long enough to be unique within a mix.
+const EXCERPT_SECONDS: f64 = 30.0;
+/// How long a track is assumed to play for when it's the last in the recording.
+const DEFAULT_PLAY_SECONDS: f64 = 240.0;
+/// How early a song can start before its cue time.
+const SEARCH_BEFORE_SECONDS: f64 = 60.0;
+/// The largest tempo change searched for, most pitch faders are set to ±10% or less.
+const MAX_SPEED_CHANGE: f64 = 0.1;
+/// Speeds are searched in coarse steps with smoothed onsets, then refined around the best.
+const COARSE_SPEED_STEP: f64 = 0.002;
+const FINE_SPEED_STEP: f64 = 0.0001;
+/// Onsets are smoothed over this many frames either side in the coarse search, enough to still
+/// line up when the speed is half a step out over the whole excerpt.
+const SMOOTHING_FRAMES: usize = 6;
+/// Spectra used to measure pitch.
+const FFT_SIZE: usize = 8192;
+const MIN_FREQUENCY_HZ: f64 = 100.0;
+const MAX_FREQUENCY_HZ: f64 = 5_000.0;
+/// Spectra are compared at this many points, spaced evenly in pitch.
+const PITCH_POINTS: usize = 600;
+const MAX_PITCH_CHANGE_SEMITONES: f64 = 2.0;
+const PITCH_STEP_SEMITONES: f64 = 0.05;
+/// Samples quieter than this, about -60 dBFS, are treated as silence at the start of a song.
+const SILENCE_THRESHOLD: f32 = 0.001;
+
+/// Where a track's song sits in a recording.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Alignment {
+    /// Index of the track within the recording's tracks.
+    pub track_index: usize,
+    pub song_path: String,
+    /// When the cue sheet says the track started.
+    pub cue_seconds: f64,
+    /// Where the start of the song falls in the recording, before the cue time when the song was
+    /// started partway through.
+    pub song_start_seconds: f64,
+    /// When the song becomes audible in the recording, after any silence at its start. Songs
+    /// started partway through are assumed to be faded in no earlier than the search allows
+    /// before their cue.
+    pub audible_start_seconds: f64,
+    /// How far the song is from where the cue sheet puts it, `song_start_seconds - cue_seconds`.
+    pub offset_seconds: f64,
+    /// How well the song's onsets match the recording, from 0 to 1.
+    pub confidence: f64,
+    /// How much faster the song was played than its original tempo, e.g. 2.0 for +2%.
+    pub tempo_change_percent: f64,
+    /// How far the song's pitch was shifted, 0 when it was played with key lock.
+    pub pitch_change_semitones: f64,
+}
+
+/// Aligns the song of each track with a file and start time against the recording. Tracks whose
+/// songs can't be decoded are skipped.
+pub fn align(recording_path: &Path, tracks: &[Track]) -> Result<Vec<Alignment>, Error> {
+    let mut stream = None;
+    audio::decode_stream(recording_path, |samples, sample_rate| {
+        stream
+            .get_or_insert_with(|| OnsetStream::new(sample_rate))
+            .push(samples);
+        true
+    })?;
+    let Some(recording) = stream.map(OnsetStream::finish) else {
+        return Ok(Vec::new());
+    };
+    let duration = recording.frame_seconds(recording.values.len());
+
+    let cues: Vec<(usize, &str, f64)> = tracks
+        .iter()
+        .enumerate()
+        .filter_map(|(i, track)| {
+            Some((
+                i,
+                track.file.as_ref()?.name.as_str(),
+                track.start_seconds()? as f64,
+            ))
+        })
+        .filter(|(_, _, cue)| *cue < duration)
+        .collect();
+
+    let mut matches = Vec::new();
+    for (n, &(track_index, song_path, cue)) in cues.iter().enumerate() {
+        let next_cue = cues
+            .get(n + 1)
+            .map_or(cue + DEFAULT_PLAY_SECONDS, |(_, _, next_cue)| *next_cue)
+            .min(duration);
+        match match_song(&recording, Path::new(song_path), cue, next_cue) {
+            Ok(Some(song_match)) => matches.push((track_index, song_path, cue, song_match)),
+            Ok(None) => {}
+            Err(error) => tracing::warn!(?error, song_path, "failed to align song"),
+        }
+    }
+
+    // The recording is decoded again for the audio at each match to measure its pitch, rather
+    // than holding the whole recording in memory.
+    let mut spectra: Vec<Option<AverageSpectrum>> = matches.iter().map(|_| None).collect();
+    let mut position = 0;
+    audio::decode_stream(recording_path, |samples, sample_rate| {
+        for ((_, _, _, song_match), spectrum) in matches.iter().zip(&mut spectra) {
+            let start = (song_match.excerpt_seconds * sample_rate as f64) as usize;
+            let end = start + (EXCERPT_SECONDS / song_match.speed * sample_rate as f64) as usize;
+            if position + samples.len() > start && position < end {
+                let from = start.saturating_sub(position);
+                let to = (end - position).min(samples.len());
+                spectrum
+                    .get_or_insert_with(|| AverageSpectrum::new(sample_rate))
+                    .push(&samples[from..to]);
+            }
+        }
+        position += samples.len();
+        true
+    })?;
+
+    Ok(matches
+        .into_iter()
+        .zip(spectra)
+        .map(|((track_index, song_path, cue, song_match), spectrum)| {
+            let pitch_change_semitones = spectrum
+                .and_then(|spectrum| pitch_change(&song_match.spectrum, &spectrum.finish()))
+                .unwrap_or_else(|| 12.0 * song_match.speed.log2());
+            Alignment {
+                track_index,
+                song_path: song_path.to_string(),
+                cue_seconds: cue,
+                song_start_seconds: song_match.song_start_seconds,
+                audible_start_seconds: song_match.audible_start_seconds.max(0.0),
+                offset_seconds: song_match.song_start_seconds - cue,
+                confidence: song_match.confidence,
+                tempo_change_percent: ((song_match.speed - 1.0) * 10_000.0).round() / 100.0,
+                pitch_change_semitones: (pitch_change_semitones * 100.0).round() / 100.0,
+            }
+        })
+        .collect())
+}
+
+/// The start time in seconds to write to the cue sheet for each track with a confident
+/// alignment, when its song becomes audible. Start times have to keep increasing through the
+/// recording, so a correction that would come at or before the previous track's start, or at or
+/// after the next track's, is skipped and the track keeps its cue time.
+pub fn corrected_start_times(
+    tracks: &[Track],
+    alignments: &[Alignment],
+    min_confidence: f64,
+) -> Vec<(usize, u64)> {
+    let corrections: Vec<Option<u64>> = (0..tracks.len())
+        .map(|track_index| {
+            alignments
+                .iter()
+                .find(|alignment| alignment.track_index == track_index)
+                .filter(|alignment| alignment.confidence >= min_confidence)
+                .map(|alignment| alignment.audible_start_seconds.max(0.0).round() as u64)
+        })
+        .collect();
+
+    let mut start_times = Vec::new();
+    let mut previous = None;
+    for (track_index, track) in tracks.iter().enumerate() {
+        let cue = track.start_seconds();
+        // The next track starts no later than the earlier of its cue time and correction,
+        // whichever it ends up with.
+        let next = tracks[track_index + 1..]
+            .iter()
+            .zip(&corrections[track_index + 1..])
+            .find_map(
+                |(track, correction)| match (track.start_seconds(), correction) {
+                    (Some(cue), Some(correction)) => Some(cue.min(*correction)),
+                    (cue, correction) => cue.or(*correction),
+                },
+            );
+        let corrected = corrections[track_index].filter(|seconds| {
+            previous.is_none_or(|previous| *seconds > previous)
+                && next.is_none_or(|next| *seconds < next)
+        });
+        match corrected {
+            Some(seconds) => {
+                start_times.push((track_index, seconds));
+                previous = Some(seconds);
+            }
+            None => previous = cue.or(previous),
+        }
+    }
+    start_times
+}
+
+struct SongMatch {
+    song_start_seconds: f64,
+    audible_start_seconds: f64,
+    /// Where the excerpt starts in the recording.
+    excerpt_seconds: f64,
+    speed: f64,
+    confidence: f64,
+    /// The average spectrum of the excerpt in the song.
+    spectrum: Spectrum,
+}
+
+/// Finds the best match for an excerpt of a song in the recording, between starting a little
+/// before its cue time and the next track's.
+fn match_song(
+    recording: &OnsetEnvelope,
+    song_path: &Path,
+    cue: f64,
+    next_cue: f64,
+) -> Result<Option<SongMatch>, Error> {
+    let decoded = audio::decode(song_path, None)?;
+    let song = audio::tempo::onset_envelope(&decoded);
+    if decoded.duration_seconds() < EXCERPT_SECONDS {
+        return Ok(None);
+    }
+
+    // The middle of the time the song plays for is most likely to have it playing alone, assuming
+    // it was started from the beginning.
+    let excerpt_start = ((next_cue - cue) / 2.0 - EXCERPT_SECONDS / 2.0)
+        .clamp(0.0, decoded.duration_seconds() - EXCERPT_SECONDS);
+
+    let fastest = 1.0 + MAX_SPEED_CHANGE;
+    let slowest = 1.0 - MAX_SPEED_CHANGE;
+    let search_start = recording
+        .frame_seconds(0)
+        .max(cue - SEARCH_BEFORE_SECONDS + excerpt_start / fastest);
+    let search_end = next_cue + (excerpt_start + EXCERPT_SECONDS) / slowest;
+    let first = ((search_start - recording.offset_seconds) * recording.frame_rate) as usize;
+    let last = (((search_end - recording.offset_seconds) * recording.frame_rate) as usize)
+        .min(recording.values.len());
+    let Some(signal) = recording.values.get(first..last) else {
+        return Ok(None);
+    };
+
+    let excerpt = |speed: f64| {
+        let frames = (EXCERPT_SECONDS / speed * recording.frame_rate) as usize;
+        (0..frames)
+            .map(|frame| {
+                let seconds = excerpt_start + frame as f64 / recording.frame_rate * speed;
+                interpolate(&song, seconds)
+            })
+            .collect::<Vec<f32>>()
+    };
+
+    let max_excerpt_len = (EXCERPT_SECONDS / slowest * recording.frame_rate) as usize + 1;
+    let smoothed = Correlator::new(&smooth(signal), max_excerpt_len);
+    let coarse_steps = (MAX_SPEED_CHANGE / COARSE_SPEED_STEP).round() as i32;
+    let coarse = (-coarse_steps..=coarse_steps)
+        .map(|step| 1.0 + step as f64 * COARSE_SPEED_STEP)
+        .filter_map(|speed| {
+            let (lag, score) = smoothed.best_lag(&smooth(&excerpt(speed)))?;
+            Some((speed, lag, score))
+        })
+        .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+    let Some((coarse_speed, _, _)) = coarse else {
+        return Ok(None);
+    };
+
+    let exact = Correlator::new(signal, max_excerpt_len);
+    let fine_steps = (COARSE_SPEED_STEP / FINE_SPEED_STEP).round() as i32;
+    let fine = (-fine_steps..=fine_steps)
+        .map(|step| coarse_speed + step as f64 * FINE_SPEED_STEP)
+        .filter_map(|speed| {
+            let (lag, score) = exact.best_lag(&excerpt(speed))?;
+            Some((speed, lag, score))
+        })
+        .max_by(|(_, _, a), (_, _, b)| a.total_cmp(b));
+    let Some((speed, lag, confidence)) = fine else {
+        return Ok(None);
+    };
+
+    let excerpt_seconds = recording.frame_seconds(first + lag);
+    let mut spectrum = AverageSpectrum::new(decoded.sample_rate);
+    let start = (excerpt_start * decoded.sample_rate as f64) as usize;
+    let end = start + (EXCERPT_SECONDS * decoded.sample_rate as f64) as usize;
+    spectrum.push(&decoded.samples[start..end.min(decoded.samples.len())]);
+
+    let song_start_seconds = excerpt_seconds - excerpt_start / speed;
+    let silence_seconds = decoded
+        .samples
+        .iter()
+        .position(|sample| sample.abs() > SILENCE_THRESHOLD)
+        .unwrap_or(0) as f64
+        / decoded.sample_rate as f64;
+    Ok(Some(SongMatch {
+        song_start_seconds,
+        audible_start_seconds: (song_start_seconds + silence_seconds / speed)
+            .max(cue - SEARCH_BEFORE_SECONDS),
+        excerpt_seconds,
+        speed,
+        confidence: confidence.clamp(0.0, 1.0),
+        spectrum: spectrum.finish(),
+    }))
+}
+
+/// The onset strength at a time, interpolated between frames.
+fn interpolate(envelope: &OnsetEnvelope, seconds: f64) -> f32 {
+    let position = (seconds - envelope.offset_seconds) * envelope.frame_rate;
+    if position < 0.0 {
+        return 0.0;
+    }
+    let index = position as usize;
+    let fraction = (position - index as f64) as f32;
+    let before = envelope.values.get(index).copied().unwrap_or(0.0);
+    let after = envelope.values.get(index + 1).copied().unwrap_or(0.0);
+    before + (after - before) * fraction
+}
+
+/// Averages values with a triangular window `SMOOTHING_FRAMES` either side.
+fn smooth(values: &[f32]) -> Vec<f32> {
+    let radius = SMOOTHING_FRAMES as isize;
+    let total: f32 = (-radius..=radius)
+        .map(|offset| (radius + 1 - offset.abs()) as f32)
+        .sum();
+    (0..values.len() as isize)
+        .map(|i| {
+            (-radius..=radius)
+                .filter_map(|offset| {
+                    let value = values.get((i + offset) as usize)?;
+                    Some(value * (radius + 1 - offset.abs()) as f32)
+                })
+                .sum::<f32>()
+                / total
+        })
+        .collect()
+}
+
+/// Matches templates against a signal by normalised cross correlation, computed with FFTs.
+struct Correlator {
+    size: usize,
+    forward: Arc<dyn Fft<f32>>,
+    inverse: Arc<dyn Fft<f32>>,
+    signal_len: usize,
+    signal_spectrum: Vec<Complex<f32>>,
+    /// Running sums of the signal and its squares, giving its variance under the template at
+    /// every lag.
+    sums: Vec<(f64, f64)>,
+}
+
+impl Correlator {
+    /// Prepares to match templates up to `max_template_len` long against `signal`.
+    fn new(signal: &[f32], max_template_len: usize) -> Self {
+        let size = (signal.len() + max_template_len).next_power_of_two();
+        let mut planner = FftPlanner::new();
+        let forward = planner.plan_fft_forward(size);
+        let inverse = planner.plan_fft_inverse(size);
+
+        let mut signal_spectrum = padded(signal, size);
+        forward.process(&mut signal_spectrum);
+
+        let mut sums = vec![(0.0, 0.0); signal.len() + 1];
+        for (i, value) in signal.iter().enumerate() {
+            let value = *value as f64;
+            sums[i + 1] = (sums[i].0 + value, sums[i].1 + value * value);
+        }
+
+        Self {
+            size,
+            forward,
+            inverse,
+            signal_len: signal.len(),
+            signal_spectrum,
+            sums,
+        }
+    }
+
+    /// Finds the lag of the signal that best matches `template`, scored from -1 to 1.
+    fn best_lag(&self, template: &[f32]) -> Option<(usize, f64)> {
+        if template.is_empty() || self.signal_len < template.len() {
+            return None;
+        }
+        let mean = template.iter().sum::<f32>() / template.len() as f32;
+        let template: Vec<f32> = template.iter().map(|value| value - mean).collect();
+        let template_norm = template
+            .iter()
+            .map(|value| (*value as f64).powi(2))
+            .sum::<f64>()
+            .sqrt();
+        if template_norm <= f64::EPSILON {
+            return None;
+        }
+
+        let mut correlation = padded(&template, self.size);
+        self.forward.process(&mut correlation);
+        for (value, signal) in correlation.iter_mut().zip(&self.signal_spectrum) {
+            *value = signal * value.conj();
+        }
+        self.inverse.process(&mut correlation);
+
+        let length = template.len() as f64;
+        (0..=self.signal_len - template.len())
+            .filter_map(|lag| {
+                let end = lag + template.len();
+                let sum = self.sums[end].0 - self.sums[lag].0;
+                let squares = self.sums[end].1 - self.sums[lag].1;
+                let signal_norm = (squares - sum * sum / length).max(0.0).sqrt();
+                if signal_norm <= f64::EPSILON {
+                    return None;
+                }
+                let covariance = correlation[lag].re as f64 / self.size as f64;
+                Some((lag, covariance / (template_norm * signal_norm)))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+}
+
+fn padded(values: &[f32], size: usize) -> Vec<Complex<f32>> {
+    values
+        .iter()
+        .map(|value| Complex::new(*value, 0.0))
+        .chain(std::iter::repeat(Complex::default()))
+        .take(size)
+        .collect()
+}
+
+/// An average power spectrum and the width of its bins.
+struct Spectrum {
+    powers: Vec<f64>,
+    bin_hz: f64,
+}
+
+struct AverageSpectrum {
+    sample_rate: u32,
+    /// Samples not yet analysed.
+    pending: Vec<f32>,
+    total: Vec<f64>,
+    count: usize,
+}
+
+impl AverageSpectrum {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            pending: Vec::new(),
+            total: vec![0.0; FFT_SIZE / 2 + 1],
+            count: 0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        self.pending.extend_from_slice(samples);
+        let mut analysed = 0;
+        for spectrum in audio::spectra(&self.pending, FFT_SIZE, FFT_SIZE) {
+            for (total, power) in self.total.iter_mut().zip(spectrum) {
+                *total += power as f64;
+            }
+            self.count += 1;
+            analysed += FFT_SIZE;
+        }
+        self.pending.drain(..analysed);
+    }
+
+    fn finish(self) -> Spectrum {
+        Spectrum {
+            powers: self
+                .total
+                .iter()
+                .map(|total| total / self.count.max(1) as f64)
+                .collect(),
+            bin_hz: self.sample_rate as f64 / FFT_SIZE as f64,
+        }
+    }
+}
+
+/// Finds how many semitones the recording's spectrum is shifted from the song's, `None` when
+/// either is silent.
+fn pitch_change(song: &Spectrum, recording: &Spectrum) -> Option<f64> {
+    // Log power at points spaced evenly in pitch, shifted by a number of semitones.
+    let points = |spectrum: &Spectrum, semitones: f64| -> Vec<f64> {
+        let octaves = (MAX_FREQUENCY_HZ / MIN_FREQUENCY_HZ).log2();
+        (0..PITCH_POINTS)
+            .map(|point| {
+                let hz = MIN_FREQUENCY_HZ
+                    * 2f64.powf(octaves * point as f64 / PITCH_POINTS as f64 + semitones / 12.0);
+                let bin = hz / spectrum.bin_hz;
+                let index = bin as usize;
+                let fraction = bin - index as f64;
+                let before = spectrum.powers.get(index).copied().unwrap_or(0.0);
+                let after = spectrum.powers.get(index + 1).copied().unwrap_or(0.0);
+                (before + (after - before) * fraction + f64::EPSILON).ln()
+            })
+            .collect()
+    };
+
+    let recording_points = points(recording, 0.0);
+    let steps = (MAX_PITCH_CHANGE_SEMITONES / PITCH_STEP_SEMITONES).round() as i32;
+    (-steps..=steps)
+        .map(|step| step as f64 * PITCH_STEP_SEMITONES)
+        .filter_map(|semitones| {
+            // A song shifted up is matched by reading its spectrum lower down.
+            let correlation = audio::correlation(&points(song, -semitones), &recording_points)?;
+            Some((semitones, correlation))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(semitones, _)| semitones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(start_time: &str) -> Track {
+        Track {
+            start_time: Some(start_time.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn alignment(track_index: usize, audible_start_seconds: f64, confidence: f64) -> Alignment {
+        Alignment {
+            track_index,
+            song_path: String::new(),
+            cue_seconds: 0.0,
+            song_start_seconds: audible_start_seconds - 5.0,
+            audible_start_seconds,
+            offset_seconds: 0.0,
+            confidence,
+            tempo_change_percent: 0.0,
+            pitch_change_semitones: 0.0,
+        }
+    }
+
+    #[test]
+    fn corrects_start_times_to_when_songs_become_audible() {
+        let tracks = [track("00:00:00"), track("00:03:00"), track("00:06:00")];
+        let alignments = [alignment(1, 170.4, 0.9), alignment(2, 365.6, 0.9)];
+        assert_eq!(
+            corrected_start_times(&tracks, &alignments, 0.5),
+            vec![(1, 170), (2, 366)]
+        );
+    }
+
+    #[test]
+    fn skips_unconfident_alignments() {
+        let tracks = [track("00:00:00"), track("00:03:00")];
+        let alignments = [alignment(1, 170.0, 0.2)];
+        assert_eq!(corrected_start_times(&tracks, &alignments, 0.5), vec![]);
+    }
+
+    #[test]
+    fn keeps_start_times_increasing() {
+        let tracks = [
+            track("00:00:00"),
+            track("00:03:00"),
+            track("00:06:00"),
+            track("00:09:00"),
+        ];
+        // Track 0's correction would come after track 1's, and track 2's after track 3's.
+        let alignments = [
+            alignment(0, 10.0, 0.9),
+            alignment(1, 8.0, 0.9),
+            alignment(2, 500.0, 0.9),
+            alignment(3, 480.0, 0.9),
+        ];
+        assert_eq!(
+            corrected_start_times(&tracks, &alignments, 0.5),
+            vec![(1, 8), (3, 480)]
+        );
+    }
+}
@@ -0,0 +1,289 @@
+//! Tracks beats and downbeats through a whole song.
+//!
+//! Beats are placed with dynamic programming (Ellis, 2007): every onset strength frame is scored
+//! as the onset strength there plus the best score of a previous beat about one beat period
+//! earlier, penalised by how far the gap is from the period. Tracing back from the best final
+//! beat gives beats that follow the onsets while keeping a steady tempo.
+//!
+//! Harmony tends to change at the start of a bar, so the downbeats are taken to be whichever of
+//! the four beat positions in a bar has the largest chord changes.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::audio::{
+    self, key,
+    tempo::{self, OnsetEnvelope},
+    Error, Samples,
+};
+
+/// Electronic music is almost entirely in 4/4.
+const BEATS_PER_BAR: usize = 4;
+/// How strongly beats are held to the tempo, higher values let beats drift less towards onsets.
+const TIGHTNESS: f64 = 100.0;
+/// Leading and trailing beats weaker than this fraction of a typical beat are dropped, they're
+/// in silence or a beatless intro or outro.
+const WEAK_BEAT: f32 = 0.2;
+/// The length of audio after each beat used to measure its harmony.
+const CHROMA_FFT_SIZE: usize = 8192;
+
+/// A single beat in the beatgrid.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Beat {
+    /// Position of the beat within its bar, 1 to 4 where 1 is the downbeat.
+    pub beat_number: u8,
+    pub time_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Beatgrid {
+    pub file_path: String,
+    pub bpm: f64,
+    /// The time of the first beat, where the grid starts.
+    pub first_beat_ms: f64,
+    pub beats: Vec<Beat>,
+}
+
+/// Tracks the beats through a song, using a tempo within `bpm_range`. Returns `None` when the
+/// song has no pulse to follow.
+pub fn analyse(path: &Path, bpm_range: (f64, f64)) -> Result<Option<Beatgrid>, Error> {
+    let decoded = audio::decode(path, None)?;
+    let envelope = tempo::onset_envelope(&decoded);
+    let Some(tempo) = tempo::estimate(&envelope, bpm_range) else {
+        return Ok(None);
+    };
+
+    let frames = track_beats(&envelope, tempo.bpm);
+    let Some(&first_frame) = frames.first() else {
+        return Ok(None);
+    };
+    let times_ms: Vec<f64> = frames
+        .iter()
+        .map(|frame| envelope.frame_seconds(*frame) * 1000.0)
+        .collect();
+    let downbeat = downbeat_phase(&decoded, &times_ms);
+
+    Ok(Some(Beatgrid {
+        file_path: path.to_string_lossy().into_owned(),
+        bpm: tempo.bpm,
+        first_beat_ms: envelope.frame_seconds(first_frame) * 1000.0,
+        beats: times_ms
+            .into_iter()
+            .enumerate()
+            .map(|(i, time_ms)| Beat {
+                beat_number: ((i + BEATS_PER_BAR - downbeat) % BEATS_PER_BAR) as u8 + 1,
+                time_ms,
+            })
+            .collect(),
+    }))
+}
+
+/// Finds the frames of the beats in the onset envelope given the tempo.
+fn track_beats(envelope: &OnsetEnvelope, bpm: f64) -> Vec<usize> {
+    let values = &envelope.values;
+    let period = 60.0 * envelope.frame_rate / bpm;
+    if values.len() < (period * 2.0) as usize {
+        return Vec::new();
+    }
+
+    // Normalised so the tightness means the same for quiet and loud songs.
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let deviation = (values
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f32>()
+        / values.len() as f32)
+        .sqrt();
+    if deviation <= f32::EPSILON {
+        return Vec::new();
+    }
+    let onsets: Vec<f64> = values
+        .iter()
+        .map(|value| (value / deviation) as f64)
+        .collect();
+
+    let shortest_gap = (period / 2.0).round() as usize;
+    let longest_gap = (period * 2.0).round() as usize;
+    let mut scores = vec![0.0; onsets.len()];
+    let mut previous = vec![None; onsets.len()];
+    for frame in 0..onsets.len() {
+        let best = (frame.saturating_sub(longest_gap)..frame.saturating_sub(shortest_gap))
+            .map(|before| {
+                let gap = (frame - before) as f64 / period;
+                (before, scores[before] - TIGHTNESS * gap.ln().powi(2))
+            })
+            .max_by(|(_, a), (_, b): &(usize, f64)| a.total_cmp(b));
+        scores[frame] = onsets[frame];
+        if let Some((before, score)) = best.filter(|(_, score)| *score > 0.0) {
+            scores[frame] += score;
+            previous[frame] = Some(before);
+        }
+    }
+
+    // The last beat is the best scoring frame within the final beat period.
+    let last_period = onsets.len().saturating_sub(period.ceil() as usize);
+    let Some(mut frame) =
+        (last_period..onsets.len()).max_by(|a, b| scores[*a].total_cmp(&scores[*b]))
+    else {
+        return Vec::new();
+    };
+    let mut frames = vec![frame];
+    while let Some(before) = previous[frame] {
+        frames.push(before);
+        frame = before;
+    }
+    frames.reverse();
+
+    trim_weak_beats(&frames, values)
+}
+
+/// Drops beats at the start and end that are much weaker than the rest.
+fn trim_weak_beats(frames: &[usize], values: &[f32]) -> Vec<usize> {
+    // Onsets can land a frame either side of the beat.
+    let strength = |frame: usize| {
+        values[frame.saturating_sub(1)..(frame + 2).min(values.len())]
+            .iter()
+            .copied()
+            .fold(0.0, f32::max)
+    };
+    let strengths: Vec<f32> = frames.iter().map(|frame| strength(*frame)).collect();
+    let typical =
+        (strengths.iter().map(|value| value.powi(2)).sum::<f32>() / strengths.len() as f32).sqrt();
+    let threshold = typical * WEAK_BEAT;
+
+    let Some(first) = strengths.iter().position(|value| *value >= threshold) else {
+        return Vec::new();
+    };
+    let last = strengths
+        .iter()
+        .rposition(|value| *value >= threshold)
+        .unwrap_or(first);
+    frames[first..=last].to_vec()
+}
+
+/// Finds which beat (0 to 3) the bars start on, measured as the position in the bar where the
+/// harmony changes the most from the previous beat.
+fn downbeat_phase(decoded: &Samples, times_ms: &[f64]) -> usize {
+    let bin_hz = decoded.sample_rate as f64 / CHROMA_FFT_SIZE as f64;
+    let chromas: Vec<Option<[f64; 12]>> = times_ms
+        .iter()
+        .map(|time_ms| {
+            let start = (time_ms / 1000.0 * decoded.sample_rate as f64) as usize;
+            let samples = decoded.samples.get(start..start + CHROMA_FFT_SIZE)?;
+            let spectrum = audio::spectra(samples, CHROMA_FFT_SIZE, CHROMA_FFT_SIZE).next()?;
+            let chroma = key::spectrum_chroma(&spectrum, bin_hz);
+            chroma.iter().any(|value| *value > 0.0).then_some(chroma)
+        })
+        .collect();
+
+    let mut changes = [0.0; BEATS_PER_BAR];
+    for (i, pair) in chromas.windows(2).enumerate() {
+        if let [Some(before), Some(after)] = pair {
+            // Chromas sum to 1 so their distance ranges from 0 (the same) to 2.
+            let distance: f64 = before.iter().zip(after).map(|(a, b)| (a - b).abs()).sum();
+            changes[(i + 1) % BEATS_PER_BAR] += distance;
+        }
+    }
+
+    // Without any harmony to go on the first beat is taken as a downbeat.
+    (1..BEATS_PER_BAR).fold(0, |best, phase| {
+        if changes[phase] > changes[best] {
+            phase
+        } else {
+            best
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_RATE: f64 = 100.0;
+
+    /// An onset envelope with an impulse at each of `frames` over a faint noise floor.
+    fn impulses(frames: &[usize], len: usize) -> OnsetEnvelope {
+        let mut values: Vec<f32> = (0..len).map(|i| (i % 7) as f32 * 0.01).collect();
+        for frame in frames {
+            values[*frame] = 1.0;
+        }
+        OnsetEnvelope {
+            values,
+            frame_rate: FRAME_RATE,
+            offset_seconds: 0.0,
+        }
+    }
+
+    #[test]
+    fn tracks_beats_through_jittered_onsets() {
+        // 120 BPM is a beat every 50 frames, with the onsets a little early or late.
+        let jitter = [0, 2, -1, -2, 1, 0, 3, -3];
+        let onsets: Vec<usize> = (0..40)
+            .map(|beat| (20 + beat * 50) as isize + jitter[beat % jitter.len()])
+            .map(|frame| frame as usize)
+            .collect();
+        let envelope = impulses(&onsets, 2030);
+
+        assert_eq!(track_beats(&envelope, 120.0), onsets);
+    }
+
+    #[test]
+    fn finds_no_beats_without_onsets() {
+        let envelope = OnsetEnvelope {
+            values: vec![0.5; 2000],
+            frame_rate: FRAME_RATE,
+            offset_seconds: 0.0,
+        };
+        assert_eq!(track_beats(&envelope, 120.0), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn trims_beats_in_silent_intros_and_outros() {
+        let frames: Vec<usize> = (0..40).map(|beat| beat * 50).collect();
+        let mut values = vec![0.0; 2000];
+        for frame in &frames[8..32] {
+            values[*frame] = 1.0;
+        }
+        // An onset a frame late still counts as the beat.
+        values[frames[8]] = 0.0;
+        values[frames[8] + 1] = 0.5;
+
+        assert_eq!(trim_weak_beats(&frames, &values), frames[8..32]);
+    }
+
+    #[test]
+    fn finds_downbeats_where_the_chords_change() {
+        const SAMPLE_RATE: u32 = 22050;
+        const C_MAJOR: [f32; 3] = [261.63, 329.63, 392.0];
+        const F_SHARP_MAJOR: [f32; 3] = [369.99, 466.16, 554.37];
+        let beat_samples = SAMPLE_RATE as usize / 2;
+
+        // Bars of four beats starting on the second beat, so the chords change on beats 1, 5, 9...
+        let samples = (0..32 * beat_samples)
+            .map(|i| {
+                let beat = i / beat_samples;
+                let chord = if beat.div_ceil(4).is_multiple_of(2) {
+                    C_MAJOR
+                } else {
+                    F_SHARP_MAJOR
+                };
+                let t = i as f32 / SAMPLE_RATE as f32;
+                chord
+                    .iter()
+                    .map(|hz| (t * hz * std::f32::consts::TAU).sin())
+                    .sum::<f32>()
+            })
+            .collect();
+        let decoded = Samples {
+            samples,
+            sample_rate: SAMPLE_RATE,
+        };
+        let times_ms: Vec<f64> = (0..32).map(|beat| beat as f64 * 500.0).collect();
+
+        assert_eq!(downbeat_phase(&decoded, &times_ms), 1);
+        assert_eq!(downbeat_phase(&decoded, &times_ms[..1]), 0);
+    }
+}
@@ -0,0 +1,519 @@
+//! Landmark fingerprints for recognising songs by their audio.
+//!
+//! Prominent peaks are picked from a spectrogram with a pitch scaled frequency axis and nearby
+//! pairs of peaks are hashed, each hash with the frame of its first peak is a landmark. A song
+//! and an excerpt of it share many landmarks at a consistent offset in time, while unrelated
+//! audio only shares a few at random offsets.
+//!
+//! Songs in a mix are often played faster or slower, moving every peak up or down by the same
+//! number of bands and their frames slightly closer together or further apart. Hashes use the
+//! difference between the bands of each pair so only the first band moves, and queries also look
+//! up the hashes one band and one frame either side.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::Serialize;
+
+use crate::audio::{self, Error, Samples};
+
+/// The time between frames, landmark frames are multiples of this.
+pub const FRAME_SECONDS: f64 = 0.032;
+/// Matches with fewer landmarks in common than this are coincidences.
+pub const MIN_MATCH_SCORE: u32 = 10;
+
+const MIN_FREQUENCY_HZ: f64 = 200.0;
+const MAX_FREQUENCY_HZ: f64 = 5_000.0;
+/// Quarter tone bands, a song played 3% faster moves up by one band.
+const BANDS_PER_OCTAVE: f64 = 24.0;
+/// Peaks quieter than this, around 75 dB below a full scale sine, are noise.
+const MIN_LEVEL_DB: f32 = -10.0;
+const MAX_PEAKS_PER_FRAME: usize = 5;
+/// A peak masks quieter peaks near it until the mask decays by this much each frame, and by
+/// this much more for each band squared away from it.
+const MASK_DECAY_DB: f32 = 0.3;
+const MASK_SPREAD_DB: f32 = 1.0;
+/// Each peak is paired with up to this many of the peaks following it.
+const FAN_OUT: usize = 3;
+const MIN_FRAME_DELTA: usize = 2;
+const MAX_FRAME_DELTA: usize = 63;
+const MAX_BAND_DELTA: i32 = 31;
+/// Songs are compared for copies by an excerpt this long from their middle.
+const COPY_EXCERPT_SECONDS: f64 = 30.0;
+/// Copies of a song, e.g. the same track encoded as MP3 and FLAC, share at least this much of
+/// the landmarks the song shares with itself. Lossy encoding and noise move a lot of peaks, but
+/// chance matches are rare once they also have to be at the same position.
+const MIN_COPY_SCORE_RATIO: f64 = 0.15;
+const MAX_COPY_OFFSET_SECONDS: f64 = 1.0;
+/// Offsets are counted in bins about a second wide, wide enough that a song played a few
+/// percent faster or slower than the original still lines up over an excerpt.
+const OFFSET_BIN_FRAMES: i64 = 32;
+
+/// A hashed pair of spectral peaks and the frame of the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Landmark {
+    pub hash: u32,
+    pub frame: u32,
+}
+
+/// The landmarks of a song or recording, in order of frame.
+#[derive(Debug, Clone, Default)]
+pub struct Fingerprint {
+    pub landmarks: Vec<Landmark>,
+}
+
+impl Fingerprint {
+    /// The landmarks from `start_seconds` up to `end_seconds`.
+    pub fn range(&self, start_seconds: f64, end_seconds: f64) -> &[Landmark] {
+        let start = (start_seconds / FRAME_SECONDS).max(0.0) as u32;
+        let end = (end_seconds / FRAME_SECONDS).max(0.0) as u32;
+        let first = self
+            .landmarks
+            .partition_point(|landmark| landmark.frame < start);
+        let last = self
+            .landmarks
+            .partition_point(|landmark| landmark.frame < end);
+        &self.landmarks[first..last.max(first)]
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.landmarks
+            .last()
+            .map_or(0.0, |landmark| landmark.frame as f64 * FRAME_SECONDS)
+    }
+}
+
+/// Fingerprints decoded samples, such as an excerpt of a song or recording.
+pub fn fingerprint_samples(samples: &Samples) -> Fingerprint {
+    let mut stream = FingerprintStream::new(samples.sample_rate);
+    stream.push(&samples.samples);
+    stream.finish()
+}
+
+/// Fingerprints an audio file, decoding it a packet at a time.
+pub fn fingerprint(path: &Path) -> Result<Fingerprint, Error> {
+    let mut stream = None;
+    audio::decode_stream(path, |samples, sample_rate| {
+        stream
+            .get_or_insert_with(|| FingerprintStream::new(sample_rate))
+            .push(samples);
+        true
+    })?;
+    Ok(stream.map(FingerprintStream::finish).unwrap_or_default())
+}
+
+/// Picks spectral peaks as samples are decoded, pairing them into landmarks once all samples
+/// have been pushed.
+pub struct FingerprintStream {
+    fft_size: usize,
+    hop: usize,
+    /// The spectrum bins in each band, bands below the lowest bin are left out.
+    bands: Vec<std::ops::Range<usize>>,
+    /// Samples not yet analysed, the start of the next frame onwards.
+    pending: Vec<f32>,
+    /// The levels of the last two frames, a frame's peaks are picked once the next is known.
+    previous: Option<Vec<f32>>,
+    current: Option<Vec<f32>>,
+    frame: usize,
+    mask: Vec<f32>,
+    /// Frame and band of every peak picked so far.
+    peaks: Vec<(usize, usize)>,
+}
+
+impl FingerprintStream {
+    pub fn new(sample_rate: u32) -> Self {
+        // Around 0.17 seconds of audio, enough to resolve quarter tones at the lowest band.
+        let fft_size = (sample_rate as usize / 6).next_power_of_two();
+        let bin_hz = sample_rate as f64 / fft_size as f64;
+        let band_count =
+            ((MAX_FREQUENCY_HZ / MIN_FREQUENCY_HZ).log2() * BANDS_PER_OCTAVE).floor() as usize;
+        let bands: Vec<_> = (0..band_count)
+            .map(|band| {
+                let edge = |band: f64| {
+                    MIN_FREQUENCY_HZ * 2f64.powf((band - 0.5) / BANDS_PER_OCTAVE) / bin_hz
+                };
+                let centre = edge(band as f64 + 0.5).round() as usize;
+                let start = (edge(band as f64).ceil() as usize).min(centre);
+                let end = (edge(band as f64 + 1.0).ceil() as usize).max(centre + 1);
+                start..end.min(fft_size / 2 + 1)
+            })
+            .collect();
+
+        Self {
+            fft_size,
+            hop: ((sample_rate as f64 * FRAME_SECONDS).round() as usize).max(1),
+            mask: vec![f32::NEG_INFINITY; bands.len()],
+            bands,
+            pending: Vec::new(),
+            previous: None,
+            current: None,
+            frame: 0,
+            peaks: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        let mut pending = std::mem::take(&mut self.pending);
+        if pending.is_empty() {
+            let analysed = self.analyse(samples);
+            pending.extend_from_slice(&samples[analysed..]);
+        } else {
+            pending.extend_from_slice(samples);
+            // Decoded packets are small, so frames are analysed many at a time.
+            if pending.len() >= self.fft_size + 32 * self.hop {
+                let analysed = self.analyse(&pending);
+                pending.drain(..analysed);
+            }
+        }
+        self.pending = pending;
+    }
+
+    pub fn finish(mut self) -> Fingerprint {
+        let pending = std::mem::take(&mut self.pending);
+        self.analyse(&pending);
+        if let Some(current) = self.current.take() {
+            self.pick_peaks(&current, None);
+        }
+
+        let mut landmarks = Vec::new();
+        for (i, &(frame, band)) in self.peaks.iter().enumerate() {
+            let targets = self.peaks[i + 1..]
+                .iter()
+                .take_while(|(target_frame, _)| target_frame - frame <= MAX_FRAME_DELTA)
+                .filter(|(target_frame, target_band)| {
+                    target_frame - frame >= MIN_FRAME_DELTA
+                        && (*target_band as i32 - band as i32).abs() <= MAX_BAND_DELTA
+                })
+                .take(FAN_OUT);
+            for &(target_frame, target_band) in targets {
+                landmarks.push(Landmark {
+                    hash: hash(band, target_band as i32 - band as i32, target_frame - frame),
+                    frame: frame as u32,
+                });
+            }
+        }
+
+        Fingerprint { landmarks }
+    }
+
+    /// Measures the band levels of every whole frame in `samples`, returning how many samples
+    /// were analysed, up to the start of the next frame.
+    fn analyse(&mut self, samples: &[f32]) -> usize {
+        let mut frames = 0;
+        for spectrum in audio::spectra(samples, self.fft_size, self.hop) {
+            let levels: Vec<f32> = self
+                .bands
+                .iter()
+                .map(|bins| {
+                    let power = spectrum[bins.clone()].iter().fold(0.0f32, |a, &b| a.max(b));
+                    10.0 * (power + 1e-12).log10()
+                })
+                .collect();
+            if let Some(current) = self.current.take() {
+                self.pick_peaks(&current, Some(&levels));
+                self.previous = Some(current);
+            }
+            self.current = Some(levels);
+            frames += 1;
+        }
+        frames * self.hop
+    }
+
+    /// Picks the peaks of the current frame, the loudest bands that are louder than the bands
+    /// either side, the same band in the frames either side and the mask left by earlier peaks.
+    fn pick_peaks(&mut self, levels: &[f32], next: Option<&[f32]>) {
+        for mask in &mut self.mask {
+            *mask -= MASK_DECAY_DB;
+        }
+
+        let mut candidates: Vec<usize> = (0..levels.len())
+            .filter(|&band| {
+                let level = levels[band];
+                level > MIN_LEVEL_DB
+                    && level > self.mask[band]
+                    && band
+                        .checked_sub(1)
+                        .is_none_or(|below| level > levels[below])
+                    && levels.get(band + 1).is_none_or(|&above| level >= above)
+                    && self
+                        .previous
+                        .as_ref()
+                        .is_none_or(|previous| level > previous[band])
+                    && next.is_none_or(|next| level >= next[band])
+            })
+            .collect();
+        candidates.sort_by(|a, b| levels[*b].total_cmp(&levels[*a]));
+
+        let mut picked = 0;
+        for band in candidates {
+            if picked == MAX_PEAKS_PER_FRAME {
+                break;
+            }
+            let level = levels[band];
+            if level <= self.mask[band] {
+                continue;
+            }
+            for (other, mask) in self.mask.iter_mut().enumerate() {
+                let distance = other as f32 - band as f32;
+                *mask = mask.max(level - MASK_SPREAD_DB * distance * distance);
+            }
+            self.peaks.push((self.frame, band));
+            picked += 1;
+        }
+        self.frame += 1;
+    }
+}
+
+fn hash(band: usize, band_delta: i32, frame_delta: usize) -> u32 {
+    (band as u32) << 12 | ((band_delta + MAX_BAND_DELTA + 1) as u32) << 6 | frame_delta as u32
+}
+
+/// The hashes a landmark would have if its song was played a little faster or slower.
+pub fn hash_variants(landmark_hash: u32) -> impl Iterator<Item = u32> {
+    let band = (landmark_hash >> 12) as i64;
+    let band_delta = ((landmark_hash >> 6) & 0x3f) as i32 - MAX_BAND_DELTA - 1;
+    let frame_delta = (landmark_hash & 0x3f) as i64;
+    (-1..=1)
+        .flat_map(move |band_shift| {
+            (-1..=1).map(move |frame_shift| (band + band_shift, frame_delta + frame_shift))
+        })
+        .filter(|&(band, frame_delta)| {
+            band >= 0 && (MIN_FRAME_DELTA as i64..=MAX_FRAME_DELTA as i64).contains(&frame_delta)
+        })
+        .map(move |(band, frame_delta)| hash(band as usize, band_delta, frame_delta as usize))
+}
+
+/// A song sharing landmarks with an excerpt.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FingerprintMatch {
+    pub song_path: String,
+    /// Where the start of the song falls relative to the start of the excerpt, negative when
+    /// the excerpt starts partway through the song.
+    pub offset_seconds: f64,
+    /// How many landmarks line up at that offset.
+    pub score: u32,
+}
+
+/// Counts the landmarks an excerpt shares with each song at each offset.
+#[derive(Debug, Default)]
+pub struct Votes {
+    /// Landmarks with the same offset, by song and offset bin, and the sum of their offsets.
+    votes: HashMap<(u32, i64), (u32, i64)>,
+    hits: Vec<(u32, i64, i64)>,
+}
+
+impl Votes {
+    /// Counts the landmarks of songs, as song and frame, with the same hash as one of the
+    /// excerpt's or one of its `hash_variants`.
+    pub fn add(&mut self, landmark: &Landmark, postings: impl IntoIterator<Item = (u32, u32)>) {
+        self.hits.clear();
+        for (song, frame) in postings {
+            let offset = landmark.frame as i64 - frame as i64;
+            self.hits
+                .push((song, offset.div_euclid(OFFSET_BIN_FRAMES), offset));
+        }
+        // Music repeats, so a landmark can match a song many times at nearly the same offset.
+        // Each landmark only votes once for each offset bin so repetition doesn't count.
+        self.hits.sort_unstable();
+        self.hits.dedup_by_key(|(song, bin, _)| (*song, *bin));
+        for &(song, bin, offset) in &self.hits {
+            let vote = self.votes.entry((song, bin)).or_default();
+            vote.0 += 1;
+            vote.1 += offset;
+        }
+    }
+
+    /// The songs sharing at least `MIN_MATCH_SCORE` landmarks at a consistent offset, with the
+    /// offset in seconds and the score, best first.
+    pub fn finish(self) -> Vec<(u32, f64, u32)> {
+        // An offset near the edge of a bin is split with its neighbour, so pairs of bins are
+        // counted together.
+        let mut best: HashMap<u32, (u32, i64, i64)> = HashMap::new();
+        for (&(song, bin), &(count, sum)) in &self.votes {
+            let (next_count, next_sum) = self
+                .votes
+                .get(&(song, bin + 1))
+                .copied()
+                .unwrap_or_default();
+            let pair = (count + next_count, bin, sum + next_sum);
+            // Ties go to the earliest offset so the order votes were counted in doesn't matter.
+            best.entry(song)
+                .and_modify(|best| {
+                    if (pair.0, -pair.1) > (best.0, -best.1) {
+                        *best = pair;
+                    }
+                })
+                .or_insert(pair);
+        }
+
+        let mut matches: Vec<(u32, f64, u32)> = best
+            .into_iter()
+            .filter(|(_, (score, _, _))| *score >= MIN_MATCH_SCORE)
+            .map(|(song, (score, _, sum))| (song, sum as f64 / score as f64 * FRAME_SECONDS, score))
+            .collect();
+        matches.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        matches
+    }
+}
+
+/// Landmarks of many songs held in memory, for matching many excerpts such as every part of a
+/// recording.
+#[derive(Debug, Default)]
+pub struct FingerprintIndex {
+    paths: Vec<String>,
+    /// The song and frame of every landmark with each hash.
+    postings: HashMap<u32, Vec<(u32, u32)>>,
+}
+
+impl FingerprintIndex {
+    pub fn insert(&mut self, path: &str, landmarks: impl IntoIterator<Item = Landmark>) {
+        let song = self.paths.len() as u32;
+        self.paths.push(path.to_string());
+        for landmark in landmarks {
+            self.postings
+                .entry(landmark.hash)
+                .or_default()
+                .push((song, landmark.frame));
+        }
+    }
+
+    /// The other songs in the index with the same audio as a song, such as other encodings of
+    /// it. The song itself should be in the index.
+    pub fn find_copies(&self, path: &str, fingerprint: &Fingerprint) -> Vec<String> {
+        let middle = fingerprint.duration_seconds() / 2.0;
+        let excerpt = fingerprint.range(
+            middle - COPY_EXCERPT_SECONDS / 2.0,
+            middle + COPY_EXCERPT_SECONDS / 2.0,
+        );
+        let matches = self.query(excerpt);
+        let own_score = matches
+            .iter()
+            .find(|song| song.song_path == path)
+            .map_or(excerpt.len() as u32, |song| song.score);
+
+        matches
+            .into_iter()
+            .filter(|song| {
+                song.song_path != path
+                    && song.offset_seconds.abs() <= MAX_COPY_OFFSET_SECONDS
+                    && song.score as f64 >= own_score as f64 * MIN_COPY_SCORE_RATIO
+            })
+            .map(|song| song.song_path)
+            .collect()
+    }
+
+    /// Finds the songs sharing at least `MIN_MATCH_SCORE` landmarks with an excerpt at a
+    /// consistent offset, best first. Landmark frames are relative to the start of the excerpt.
+    pub fn query(&self, landmarks: &[Landmark]) -> Vec<FingerprintMatch> {
+        let mut votes = Votes::default();
+        for landmark in landmarks {
+            let postings = hash_variants(landmark.hash)
+                .flat_map(|hash| self.postings.get(&hash).into_iter().flatten().copied());
+            votes.add(landmark, postings);
+        }
+
+        votes
+            .finish()
+            .into_iter()
+            .map(|(song, offset_seconds, score)| FingerprintMatch {
+                song_path: self.paths[song as usize].clone(),
+                offset_seconds,
+                score,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    /// A tune of pseudo random two note chords, a quarter of a second each.
+    fn tune(seed: u32, seconds: f64) -> Samples {
+        let mut seed = seed;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            seed as f64 / u32::MAX as f64
+        };
+        let note_samples = SAMPLE_RATE as usize / 4;
+        let notes = (seconds * 4.0) as usize;
+        let octaves = (MAX_FREQUENCY_HZ / MIN_FREQUENCY_HZ).log2();
+        let mut samples = Vec::with_capacity(notes * note_samples);
+        for _ in 0..notes {
+            let chord = [random(), random()]
+                .map(|position| MIN_FREQUENCY_HZ * 2f64.powf((0.1 + position * 0.8) * octaves));
+            samples.extend((0..note_samples).map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let sum: f64 = chord
+                    .iter()
+                    .map(|hz| (std::f64::consts::TAU * hz * t).sin())
+                    .sum();
+                (sum * 0.25) as f32
+            }));
+        }
+        Samples {
+            samples,
+            sample_rate: SAMPLE_RATE,
+        }
+    }
+
+    #[test]
+    fn matches_excerpts_at_their_offset() {
+        let song = tune(1, 60.0);
+        let mut index = FingerprintIndex::default();
+        index.insert("/music/a.flac", fingerprint_samples(&song).landmarks);
+        index.insert(
+            "/music/b.flac",
+            fingerprint_samples(&tune(2, 60.0)).landmarks,
+        );
+
+        // 15 seconds starting 20.1 seconds into the song, not aligned to a frame.
+        let start = (20.1 * SAMPLE_RATE as f64) as usize;
+        let excerpt = Samples {
+            samples: song.samples[start..start + 15 * SAMPLE_RATE as usize].to_vec(),
+            sample_rate: SAMPLE_RATE,
+        };
+        let matches = index.query(&fingerprint_samples(&excerpt).landmarks);
+
+        let best = matches.first().expect("no matches");
+        assert_eq!(best.song_path, "/music/a.flac");
+        assert!(
+            (best.offset_seconds + 20.1).abs() <= FRAME_SECONDS,
+            "{best:?}"
+        );
+        assert!(best.score >= MIN_MATCH_SCORE * 10, "{best:?}");
+        assert!(matches
+            .iter()
+            .all(|song| song.song_path != "/music/b.flac" || song.score < best.score / 10));
+    }
+
+    #[test]
+    fn finds_copies_of_songs() {
+        let song = tune(1, 60.0);
+        // A quieter copy with a little noise.
+        let noisy = Samples {
+            samples: song
+                .samples
+                .iter()
+                .enumerate()
+                .map(|(i, sample)| sample * 0.8 + ((i * 7919) % 101) as f32 * 1e-4)
+                .collect(),
+            sample_rate: SAMPLE_RATE,
+        };
+        let fingerprint = fingerprint_samples(&song);
+        let mut index = FingerprintIndex::default();
+        index.insert("/music/a.flac", fingerprint.landmarks.clone());
+        index.insert("/music/a.mp3", fingerprint_samples(&noisy).landmarks);
+        index.insert(
+            "/music/b.flac",
+            fingerprint_samples(&tune(2, 60.0)).landmarks,
+        );
+
+        assert_eq!(
+            index.find_copies("/music/a.flac", &fingerprint),
+            vec!["/music/a.mp3"]
+        );
+    }
+}
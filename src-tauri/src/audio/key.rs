@@ -0,0 +1,190 @@
+//! Estimates a song's musical key from its audio.
+//!
+//! The spectrum is folded into a chromagram, the energy of each of the 12 pitch classes summed
+//! over every octave and the whole song. Music in a key uses some pitch classes far more than
+//! others, so the chromagram is correlated with a profile of how often each pitch class is used
+//! in major and minor keys, rotated to each of the 12 tonics. The best correlating key wins.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    audio::{self, Error},
+    songs::key::{Key, Mode},
+};
+
+/// Key rarely changes so a few minutes is enough to estimate it.
+const ANALYSIS_SECONDS: f64 = 180.0;
+/// Large enough to separate semitones in the bass, about 2.7 Hz per bin at 44.1 kHz.
+const FFT_SIZE: usize = 16384;
+const HOP: usize = FFT_SIZE / 2;
+/// Only this range is used, below it semitones can't be separated and above it there's mostly
+/// percussion and harmonics.
+const MIN_FREQUENCY_HZ: f64 = 55.0;
+const MAX_FREQUENCY_HZ: f64 = 4_000.0;
+/// Frames quieter than this (dB relative to the loudest frame) are skipped, e.g. silent intros.
+const SILENCE_DB: f32 = -50.0;
+
+/// Krumhansl-Kessler key profiles, how well each pitch class fits a key starting from the tonic.
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetectedKey {
+    pub key: Key,
+    /// How clearly the best key beat the others, from 0 (a tie) to 1.
+    pub confidence: f64,
+}
+
+/// Estimates the key of a song, returns `None` when there's no tonal content, e.g. silence or
+/// only drums.
+pub fn detect(path: &Path) -> Result<Option<DetectedKey>, Error> {
+    let decoded = audio::decode(path, Some(ANALYSIS_SECONDS))?;
+    let Some(chroma) = chromagram(&decoded.samples, decoded.sample_rate) else {
+        return Ok(None);
+    };
+    Ok(estimate(&chroma))
+}
+
+/// The energy of each pitch class (0 is C) summed over the whole song.
+fn chromagram(samples: &[f32], sample_rate: u32) -> Option<[f64; 12]> {
+    let spectra = audio::spectrogram(samples, FFT_SIZE, HOP);
+    let bin_hz = sample_rate as f64 / FFT_SIZE as f64;
+    let band = |spectrum: &Vec<f32>| -> f32 {
+        let (first_bin, last_bin) = band_bins(spectrum.len(), bin_hz);
+        spectrum[first_bin..=last_bin].iter().sum()
+    };
+
+    let loudest = spectra.iter().map(band).fold(0.0, f32::max);
+    if loudest <= 0.0 {
+        return None;
+    }
+    let silence = loudest * 10f32.powf(SILENCE_DB / 10.0);
+
+    let mut chroma = [0.0; 12];
+    for spectrum in spectra.iter().filter(|spectrum| band(spectrum) >= silence) {
+        for (chroma, value) in chroma.iter_mut().zip(spectrum_chroma(spectrum, bin_hz)) {
+            *chroma += value;
+        }
+    }
+
+    chroma.iter().any(|value| *value > 0.0).then_some(chroma)
+}
+
+/// The share of a power spectrum's tonal energy in each pitch class (0 is C), summing to 1 unless
+/// the spectrum is silent.
+///
+/// Only spectral peaks are counted, which keeps broadband noise and drums from smearing energy
+/// across every pitch class.
+pub fn spectrum_chroma(spectrum: &[f32], bin_hz: f64) -> [f64; 12] {
+    let mut chroma = [0.0; 12];
+    let (first_bin, last_bin) = band_bins(spectrum.len(), bin_hz);
+    for bin in first_bin..=last_bin {
+        let power = spectrum[bin];
+        if power > spectrum[bin - 1] && power >= spectrum[bin + 1] {
+            // MIDI note numbers, 69 is A4 at 440 Hz and note 0 is a C.
+            let note = 69.0 + 12.0 * (bin as f64 * bin_hz / 440.0).log2();
+            chroma[(note.round() as i64).rem_euclid(12) as usize] += power.sqrt() as f64;
+        }
+    }
+
+    let total = chroma.iter().sum::<f64>();
+    if total > 0.0 {
+        for value in &mut chroma {
+            *value /= total;
+        }
+    }
+    chroma
+}
+
+/// The first and last bins within the analysed frequency range, leaving a bin either side for
+/// peak picking.
+fn band_bins(bins: usize, bin_hz: f64) -> (usize, usize) {
+    let first_bin = ((MIN_FREQUENCY_HZ / bin_hz).ceil() as usize).max(1);
+    let last_bin = ((MAX_FREQUENCY_HZ / bin_hz) as usize).min(bins.saturating_sub(2));
+    (first_bin, last_bin.max(first_bin))
+}
+
+/// Correlates the chromagram with the profile of every key.
+fn estimate(chroma: &[f64; 12]) -> Option<DetectedKey> {
+    let mut correlations: Vec<(Key, f64)> = (0..12u8)
+        .flat_map(|tonic| {
+            [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)].map(|(mode, profile)| {
+                let rotated: Vec<f64> = (0..12)
+                    .map(|pitch_class| profile[(pitch_class + 12 - tonic as usize) % 12])
+                    .collect();
+                (
+                    Key::new(tonic, mode),
+                    audio::correlation(chroma, &rotated).unwrap_or(0.0),
+                )
+            })
+        })
+        .collect();
+    correlations.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    let (key, best) = correlations[0];
+    let (_, second) = correlations[1];
+    if best <= 0.0 {
+        return None;
+    }
+
+    Some(DetectedKey {
+        key,
+        // The gap to the runner up relative to how far the best was from a perfect fit.
+        confidence: ((best - second) / (1.0 - second).max(f64::EPSILON)).clamp(0.0, 1.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 44100.0;
+
+    /// A power spectrum with a peak at each frequency.
+    fn spectrum(frequencies: &[f64]) -> Vec<f32> {
+        let bin_hz = SAMPLE_RATE / FFT_SIZE as f64;
+        let mut spectrum = vec![0.0; FFT_SIZE / 2 + 1];
+        for frequency in frequencies {
+            spectrum[(frequency / bin_hz).round() as usize] = 1.0;
+        }
+        spectrum
+    }
+
+    #[test]
+    fn folds_peaks_into_pitch_classes() {
+        // A2, A3 and E4 (A and E), C#5 (C#).
+        let chroma = spectrum_chroma(
+            &spectrum(&[110.0, 220.0, 329.63, 554.37]),
+            SAMPLE_RATE / FFT_SIZE as f64,
+        );
+        assert_eq!(chroma[9], 0.5);
+        assert_eq!(chroma[4], 0.25);
+        assert_eq!(chroma[1], 0.25);
+        assert_eq!(chroma.iter().sum::<f64>(), 1.0);
+    }
+
+    #[test]
+    fn estimates_keys_from_their_profiles() {
+        for (tonic, mode, profile) in [
+            (0, Mode::Major, MAJOR_PROFILE),
+            (9, Mode::Minor, MINOR_PROFILE),
+            (6, Mode::Minor, MINOR_PROFILE),
+        ] {
+            let mut chroma = [0.0; 12];
+            for (pitch_class, weight) in profile.iter().enumerate() {
+                chroma[(pitch_class + tonic as usize) % 12] = *weight;
+            }
+            let detected = estimate(&chroma).unwrap();
+            assert_eq!(detected.key, Key::new(tonic, mode));
+            assert!(detected.confidence > 0.0);
+        }
+        assert!(estimate(&[1.0; 12]).is_none());
+    }
+}
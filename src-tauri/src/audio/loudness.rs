@@ -0,0 +1,253 @@
+//! Measures how loud songs sound, following ITU-R BS.1770 as used by EBU R128 and streaming
+//! services' normalisation.
+//!
+//! Each channel is K-weighted, a filter approximating how loud people hear each frequency, then
+//! the mean square power is measured over 400 ms blocks. Silent blocks are gated out, then blocks
+//! more than 10 LU below the average of the rest, so quiet intros and breakdowns don't pull down
+//! the loudness of the song.
+
+use std::{f64::consts::PI, path::Path};
+
+use serde::Serialize;
+
+use crate::audio::{self, Error};
+
+/// Blocks are measured every 100 ms, each block covering four of them.
+const STEP_SECONDS: f64 = 0.1;
+const STEPS_PER_BLOCK: usize = 4;
+/// Blocks quieter than this are silence.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the average loudness of the blocks above the absolute gate are left out.
+const RELATIVE_GATE_LU: f64 = 10.0;
+
+/// How loud a song is.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Loudness {
+    pub file_path: String,
+    /// Integrated loudness in LUFS, `None` when the song is silent or shorter than a block.
+    pub integrated_lufs: Option<f64>,
+    /// The highest sample level in dB relative to full scale, `None` when the song is silent.
+    pub peak_dbfs: Option<f64>,
+}
+
+/// Measures the integrated loudness and peak level of a song.
+pub fn analyse(path: &Path) -> Result<Loudness, Error> {
+    let mut meter: Option<Meter> = None;
+    audio::decode_stream_channels(path, |samples, channels, sample_rate| {
+        meter
+            .get_or_insert_with(|| Meter::new(channels, sample_rate))
+            .push(samples);
+        true
+    })?;
+
+    let (integrated_lufs, peak) = match meter {
+        Some(meter) => (meter.integrated_lufs(), meter.peak),
+        None => (None, 0.0),
+    };
+    Ok(Loudness {
+        file_path: path.to_string_lossy().into_owned(),
+        integrated_lufs,
+        peak_dbfs: (peak > 0.0).then(|| 20.0 * (peak as f64).log10()),
+    })
+}
+
+/// Measures loudness as samples are decoded.
+struct Meter {
+    channels: usize,
+    /// The K-weighting filters of each channel.
+    filters: Vec<[Biquad; 2]>,
+    step_len: usize,
+    /// Squared weighted samples summed over every channel within the current step.
+    step_sum: f64,
+    step_position: usize,
+    /// The mean square power of each finished step.
+    steps: Vec<f64>,
+    peak: f32,
+}
+
+impl Meter {
+    fn new(channels: usize, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        let sample_rate = sample_rate as f64;
+        Self {
+            channels,
+            filters: (0..channels)
+                .map(|_| [Biquad::shelf(sample_rate), Biquad::high_pass(sample_rate)])
+                .collect(),
+            step_len: ((STEP_SECONDS * sample_rate) as usize).max(1),
+            step_sum: 0.0,
+            step_position: 0,
+            steps: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Adds interleaved samples.
+    fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (sample, [shelf, high_pass]) in frame.iter().zip(&mut self.filters) {
+                self.peak = self.peak.max(sample.abs());
+                let weighted = high_pass.process(shelf.process(*sample as f64));
+                self.step_sum += weighted * weighted;
+            }
+            self.step_position += 1;
+            if self.step_position == self.step_len {
+                self.steps.push(self.step_sum / self.step_len as f64);
+                self.step_sum = 0.0;
+                self.step_position = 0;
+            }
+        }
+    }
+
+    fn integrated_lufs(&self) -> Option<f64> {
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(STEPS_PER_BLOCK)
+            .map(|steps| steps.iter().sum::<f64>() / STEPS_PER_BLOCK as f64)
+            .filter(|power| loudness(*power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        let relative_gate = loudness(mean(&blocks)?) - RELATIVE_GATE_LU;
+        let gated: Vec<f64> = blocks
+            .into_iter()
+            .filter(|power| loudness(*power) > relative_gate)
+            .collect();
+        Some(loudness(mean(&gated)?))
+    }
+}
+
+/// The loudness in LUFS of a mean square power, the offset cancels the K-weighting's gain at
+/// 1 kHz so a full scale sine in one channel measures -3 LUFS.
+fn loudness(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// A second order IIR filter.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    /// The last two inputs and outputs.
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// The high shelf of the K-weighting, modelling the acoustic effect of the head. BS.1770 only
+    /// gives coefficients at 48 kHz, these are designed from its parameters for any sample rate.
+    fn shelf(sample_rate: f64) -> Self {
+        let frequency = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * frequency / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    /// The high pass of the K-weighting, the revised low frequency B curve.
+    fn high_pass(sample_rate: f64) -> Self {
+        let frequency = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (PI * frequency / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        Self::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    /// Measures interleaved sines at 1 kHz with a peak level of `dbfs` in each channel.
+    fn measure_sine(channels: usize, dbfs: f64, seconds: f64) -> Meter {
+        let amplitude = 10f64.powf(dbfs / 20.0);
+        let samples: Vec<f32> = (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .flat_map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let sample = (amplitude * (2.0 * PI * 1000.0 * t).sin()) as f32;
+                std::iter::repeat_n(sample, channels)
+            })
+            .collect();
+        let mut meter = Meter::new(channels, SAMPLE_RATE);
+        meter.push(&samples);
+        meter
+    }
+
+    #[test]
+    fn designs_the_bs1770_filters_at_48_khz() {
+        let shelf = Biquad::shelf(48_000.0);
+        let expected_b = [1.53512485958697, -2.69169618940638, 1.19839281085285];
+        let expected_a = [-1.69065929318241, 0.73248077421585];
+        for (actual, expected) in shelf.b.iter().zip(expected_b) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+        for (actual, expected) in shelf.a.iter().zip(expected_a) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+
+        let high_pass = Biquad::high_pass(48_000.0);
+        let expected_a = [-1.99004745483398, 0.99007225036621];
+        for (actual, expected) in high_pass.a.iter().zip(expected_a) {
+            assert!((actual - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn measures_sines_at_their_level() {
+        // A 1 kHz sine in both channels of a stereo file measures the same as its peak level.
+        let stereo = measure_sine(2, -23.0, 5.0).integrated_lufs().unwrap();
+        assert!((stereo + 23.0).abs() < 0.1, "{stereo}");
+
+        let mono = measure_sine(1, -20.0, 5.0).integrated_lufs().unwrap();
+        assert!((mono + 23.01).abs() < 0.1, "{mono}");
+    }
+
+    #[test]
+    fn gates_out_silence() {
+        let mut meter = measure_sine(2, -23.0, 5.0);
+        meter.push(&vec![0.0; SAMPLE_RATE as usize * 2 * 20]);
+        // Only the few blocks across the change to silence are partly silent.
+        let lufs = meter.integrated_lufs().unwrap();
+        assert!((lufs + 23.0).abs() < 0.2, "{lufs}");
+
+        let silent = Meter::new(2, SAMPLE_RATE);
+        assert_eq!(silent.integrated_lufs(), None);
+    }
+}
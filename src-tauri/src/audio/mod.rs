@@ -0,0 +1,400 @@
+//! Decoding song and recording files to raw samples for analysis.
+//!
+//! Most analysis works on mono samples, channels are averaged as they're decoded so long files
+//! don't need to be held in memory at full size.
+
+pub mod alignment;
+pub mod beats;
+pub mod fingerprint;
+pub mod key;
+pub mod loudness;
+pub mod quality;
+pub mod tempo;
+pub mod tracklist;
+pub mod transitions;
+pub mod waveform;
+
+use std::{
+    cell::RefCell,
+    f32::consts::PI,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CodecType, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read audio file: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to decode audio file: {0}")]
+    Symphonia(#[from] SymphoniaError),
+    #[error("audio file has no decodable track")]
+    NoTrack,
+    #[error("analysis cancelled")]
+    Cancelled,
+}
+
+/// Lets whoever runs an analysis follow and stop the decoding it does, see `monitored`.
+pub struct Monitor {
+    cancelled: Arc<AtomicBool>,
+    on_progress: Box<dyn Fn(f64)>,
+    /// The first file the analysis decodes, the song or recording it's analysing. Progress
+    /// isn't reported for other files it decodes along the way.
+    path: Option<PathBuf>,
+}
+
+impl Monitor {
+    /// Decoding stops with `Error::Cancelled` once `cancelled` is set. `on_progress` is called
+    /// with how far through the analysed file decoding is, from 0 to 1, each time it moves on by
+    /// a percent.
+    pub fn new(cancelled: Arc<AtomicBool>, on_progress: impl Fn(f64) + 'static) -> Self {
+        Self {
+            cancelled,
+            on_progress: Box::new(on_progress),
+            path: None,
+        }
+    }
+}
+
+thread_local! {
+    static MONITOR: RefCell<Option<Monitor>> = const { RefCell::new(None) };
+}
+
+/// Runs an analysis with every file it decodes on this thread checked against `monitor`.
+pub fn monitored<T>(monitor: Monitor, analyse: impl FnOnce() -> T) -> T {
+    /// Clears the monitor even if the analysis panics.
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            MONITOR.set(None);
+        }
+    }
+
+    MONITOR.set(Some(monitor));
+    let _reset = Reset;
+    analyse()
+}
+
+/// Mono samples decoded from an audio file.
+#[derive(Debug, Clone)]
+pub struct Samples {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+}
+
+impl Samples {
+    pub fn duration_seconds(&self) -> f64 {
+        self.samples.len() as f64 / self.sample_rate as f64
+    }
+}
+
+/// Decodes an audio file to mono samples, stopping after `max_seconds` when given.
+pub fn decode(path: &Path, max_seconds: Option<f64>) -> Result<Samples, Error> {
+    decode_range(path, 0.0, max_seconds)
+}
+
+/// Decodes part of an audio file to mono samples, starting `start_seconds` in and stopping after
+/// `max_seconds` when given. Files are decoded from the beginning rather than seeking since
+/// seeking isn't sample accurate for every format.
+pub fn decode_range(
+    path: &Path,
+    start_seconds: f64,
+    max_seconds: Option<f64>,
+) -> Result<Samples, Error> {
+    let mut samples = Vec::new();
+    let mut skipped = 0;
+    let sample_rate = decode_stream(path, |decoded, sample_rate| {
+        let start = (start_seconds * sample_rate as f64) as usize;
+        let max = max_seconds.map(|max_seconds| (max_seconds * sample_rate as f64) as usize);
+
+        let skip = start.saturating_sub(skipped).min(decoded.len());
+        skipped += skip;
+        samples.extend_from_slice(&decoded[skip..]);
+
+        match max {
+            Some(max) if samples.len() >= max => {
+                samples.truncate(max);
+                false
+            }
+            _ => true,
+        }
+    })?;
+
+    Ok(Samples {
+        samples,
+        sample_rate,
+    })
+}
+
+/// The codec of an audio file's default track, read without decoding any audio.
+pub fn codec(path: &Path) -> Result<CodecType, Error> {
+    let format = probe(path)?;
+    let track = format.default_track().ok_or(Error::NoTrack)?;
+    Ok(track.codec_params.codec)
+}
+
+fn probe(path: &Path) -> Result<Box<dyn FormatReader>, Error> {
+    let file = File::open(path)?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    Ok(probed.format)
+}
+
+/// Decodes an audio file a packet at a time, passing the mono samples and sample rate of each to
+/// `on_samples` until it returns `false`. Returns the sample rate.
+///
+/// Used for recordings, which can be hours long, so they can be analysed without decoding the
+/// whole file into memory.
+pub fn decode_stream(
+    path: &Path,
+    mut on_samples: impl FnMut(&[f32], u32) -> bool,
+) -> Result<u32, Error> {
+    let mut mono = Vec::new();
+    decode_stream_channels(path, |samples, channels, sample_rate| {
+        mono.clear();
+        mono.extend(
+            samples
+                .chunks_exact(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32),
+        );
+        on_samples(&mono, sample_rate)
+    })
+}
+
+/// Like `decode_stream` but passes the samples of every channel interleaved, along with the
+/// number of channels, for analyses that measure channels separately.
+pub fn decode_stream_channels(
+    path: &Path,
+    mut on_samples: impl FnMut(&[f32], usize, u32) -> bool,
+) -> Result<u32, Error> {
+    let mut format = probe(path)?;
+
+    let track = format.default_track().ok_or(Error::NoTrack)?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let frames = track.codec_params.n_frames.filter(|frames| *frames > 0);
+    let mut reported_percent = None;
+    MONITOR.with_borrow_mut(|monitor| {
+        if let Some(monitor) = monitor {
+            monitor.path.get_or_insert_with(|| path.to_path_buf());
+        }
+    });
+
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let cancelled = MONITOR.with_borrow(|monitor| {
+            monitor
+                .as_ref()
+                .is_some_and(|monitor| monitor.cancelled.load(Ordering::Relaxed))
+        });
+        if cancelled {
+            return Err(Error::Cancelled);
+        }
+
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(error))
+                if error.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(error) => return Err(error.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        if let Some(frames) = frames {
+            let percent = (packet.ts() * 100 / frames).min(100);
+            if reported_percent != Some(percent) {
+                reported_percent = Some(percent);
+                MONITOR.with_borrow(|monitor| match monitor {
+                    Some(monitor) if monitor.path.as_deref() == Some(path) => {
+                        (monitor.on_progress)(percent as f64 / 100.0)
+                    }
+                    _ => {}
+                });
+            }
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupt packets are skipped, the rest of the file is usually fine.
+            Err(SymphoniaError::DecodeError(error)) => {
+                tracing::debug!(error, ?path, "skipping undecodable packet");
+                continue;
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let spec = *decoded.spec();
+        sample_rate = spec.rate;
+        let channels = spec.channels.count().max(1);
+
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * channels => buffer,
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+
+        if !on_samples(buffer.samples(), channels, sample_rate) {
+            break;
+        }
+    }
+
+    if sample_rate == 0 {
+        return Err(Error::NoTrack);
+    }
+
+    Ok(sample_rate)
+}
+
+/// Splits samples into overlapping Hann windowed frames of `fft_size` samples, `hop` samples
+/// apart, and returns the power spectrum of each frame. Each spectrum has `fft_size / 2 + 1` bins,
+/// bin `i` is centred on `i * sample_rate / fft_size` Hz.
+pub fn spectrogram(samples: &[f32], fft_size: usize, hop: usize) -> Vec<Vec<f32>> {
+    spectra(samples, fft_size, hop).collect()
+}
+
+/// Like `spectrogram` but computes each spectrum as it's needed, for analysing whole songs
+/// without holding every spectrum in memory.
+pub fn spectra(
+    samples: &[f32],
+    fft_size: usize,
+    hop: usize,
+) -> impl Iterator<Item = Vec<f32>> + '_ {
+    let frames = if samples.len() < fft_size || hop == 0 {
+        0
+    } else {
+        (samples.len() - fft_size) / hop + 1
+    };
+
+    let fft = FftPlanner::new().plan_fft_forward(fft_size);
+    let window: Vec<f32> = (0..fft_size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / fft_size as f32).cos())
+        .collect();
+    let mut buffer = vec![Complex::default(); fft_size];
+
+    (0..frames).map(move |frame| {
+        let start = frame * hop;
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = Complex::new(samples[start + i] * window[i], 0.0);
+        }
+        fft.process(&mut buffer);
+        buffer[..=fft_size / 2]
+            .iter()
+            .map(|value| value.norm_sqr())
+            .collect()
+    })
+}
+
+/// Pearson correlation coefficient, `None` when either is constant.
+pub fn correlation(a: &[f64], b: &[f64]) -> Option<f64> {
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let (a_mean, b_mean) = (mean(a), mean(b));
+    let (mut covariance, mut a_variance, mut b_variance) = (0.0, 0.0, 0.0);
+    for (a, b) in a.iter().zip(b) {
+        covariance += (a - a_mean) * (b - b_mean);
+        a_variance += (a - a_mean).powi(2);
+        b_variance += (b - b_mean).powi(2);
+    }
+    let denominator = (a_variance * b_variance).sqrt();
+    (denominator > 0.0).then(|| covariance / denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn correlates() {
+        assert_eq!(correlation(&[1.0, 2.0, 3.0], &[2.0, 4.0, 6.0]), Some(1.0));
+        assert_eq!(correlation(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]), Some(-1.0));
+        assert_eq!(correlation(&[1.0, 1.0, 1.0], &[1.0, 2.0, 3.0]), None);
+    }
+
+    #[test]
+    fn finds_sine_peaks_in_spectra() {
+        let sample_rate = 8000.0;
+        let samples: Vec<f32> = (0..4096)
+            .map(|i| (2.0 * PI * 1000.0 * i as f32 / sample_rate).sin())
+            .collect();
+        let spectra = spectrogram(&samples, 1024, 512);
+        assert_eq!(spectra.len(), 7);
+        for spectrum in spectra {
+            let peak = (0..spectrum.len())
+                .max_by(|a, b| spectrum[*a].total_cmp(&spectrum[*b]))
+                .unwrap();
+            assert_eq!(peak, 128);
+        }
+        assert!(spectrogram(&samples[..100], 1024, 512).is_empty());
+    }
+
+    fn write_silence(path: &Path, seconds: u32) {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..seconds * 8000 * 2 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn reports_progress_and_stops_when_cancelled() {
+        let dir = TempDir::new("monitor");
+        let path = dir.join("silence.wav");
+        write_silence(&path, 2);
+
+        let progress = std::rc::Rc::new(RefCell::new(Vec::new()));
+        let reported = progress.clone();
+        let monitor = Monitor::new(Arc::new(AtomicBool::new(false)), move |value| {
+            reported.borrow_mut().push(value)
+        });
+        let decoded = monitored(monitor, || decode(&path, None)).unwrap();
+        assert_eq!(decoded.samples.len(), 16_000);
+        let progress = progress.borrow();
+        assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(progress.first(), Some(&0.0));
+        assert!(progress.last().is_some_and(|last| *last > 0.9));
+
+        let monitor = Monitor::new(Arc::new(AtomicBool::new(true)), |_| {});
+        let cancelled = monitored(monitor, || decode(&path, None));
+        assert!(matches!(cancelled, Err(Error::Cancelled)));
+        // The monitor only applies within `monitored`.
+        assert!(decode(&path, None).is_ok());
+    }
+}
@@ -0,0 +1,358 @@
+//! Detects lossy encoding from the frequency spectrum.
+//!
+//! Lossy encoders discard everything above a cutoff frequency chosen from the bitrate (around
+//! 16 kHz for 128 kbps MP3 and 20 kHz for 320 kbps), leaving a sharp cliff in the spectrum. The
+//! cliff survives transcoding so a lossless file made from an MP3 still shows it, while genuine
+//! lossless files roll off gradually or extend up to the Nyquist frequency.
+
+use std::path::Path;
+
+use serde::Serialize;
+use symphonia::core::codecs::CODEC_TYPE_ALAC;
+
+use crate::audio::{self, Error};
+
+/// Lossy files estimated below this bitrate are reported as low quality by default.
+pub const DEFAULT_MIN_BITRATE_KBPS: u32 = 192;
+
+const FFT_SIZE: usize = 4096;
+/// One FFT window is kept this often across the whole song, so quiet intros and outros don't
+/// decide the verdict and the song doesn't have to be held in memory.
+const WINDOW_INTERVAL_SECONDS: f64 = 0.5;
+/// The band used as the reference loudness of the music.
+const REFERENCE_BAND_HZ: (f64, f64) = (2_000.0, 8_000.0);
+/// Frequencies more than this far below the reference level count as empty.
+const EMPTY_BELOW_REFERENCE_DB: f32 = 55.0;
+/// How far the level must drop within `CLIFF_WIDTH_HZ` above the cutoff to count as a cliff.
+const CLIFF_DROP_DB: f32 = 25.0;
+const CLIFF_WIDTH_HZ: f64 = 600.0;
+/// Songs quieter than this (dB relative to full scale) don't have enough signal to judge.
+const MINIMUM_REFERENCE_DB: f32 = -90.0;
+
+/// Lossless masters are band limited too, usually just below the Nyquist frequency, so lossless
+/// files are only flagged as fake when the cutoff is below where mastering filters sit.
+const MAX_FAKE_LOSSLESS_CUTOFF_HZ: f64 = 20_000.0;
+const LOSSLESS_EXTENSIONS: [&str; 6] = ["flac", "wav", "aif", "aiff", "ape", "wv"];
+/// MP4 files hold either AAC or ALAC, the codec has to be read to know which.
+const MP4_EXTENSIONS: [&str; 2] = ["m4a", "mp4"];
+
+/// Typical encoder low pass frequencies and the bitrate they indicate, highest first.
+const CUTOFFS: [(f64, u32); 7] = [
+    (20_500.0, 320),
+    (19_800.0, 256),
+    (18_800.0, 192),
+    (17_200.0, 160),
+    (15_800.0, 128),
+    (14_000.0, 96),
+    (0.0, 64),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    /// The spectrum is consistent with the file's format.
+    Ok,
+    /// A lossy file encoded at a low bitrate, or transcoded from one.
+    LowQuality,
+    /// A lossless file with the spectrum of a lossy encoding.
+    FakeLossless,
+    /// Not enough signal to tell, e.g. silence or a very short file.
+    Unknown,
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::LowQuality => "lowQuality",
+            Self::FakeLossless => "fakeLossless",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(verdict: &str) -> Self {
+        match verdict {
+            "ok" => Self::Ok,
+            "lowQuality" => Self::LowQuality,
+            "fakeLossless" => Self::FakeLossless,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The estimated original quality of a song.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QualityReport {
+    pub file_path: String,
+    /// The highest frequency with significant content, `None` when the spectrum extends to the
+    /// Nyquist frequency without a cutoff.
+    pub cutoff_hz: Option<f64>,
+    /// The bitrate the cutoff suggests the song was originally encoded at, `None` when there's no
+    /// sign of lossy encoding.
+    pub estimated_bitrate_kbps: Option<u32>,
+    pub verdict: Verdict,
+}
+
+/// Analyses the spectrum of a song, lossless formats with a lossy cutoff are flagged as fake and
+/// lossy formats whose cutoff indicates less than `min_bitrate_kbps` are flagged as low quality.
+pub fn analyse(path: &Path, min_bitrate_kbps: u32) -> Result<QualityReport, Error> {
+    let decoded = decode_windows(path)?;
+    let file_path = path.to_string_lossy().into_owned();
+    let is_lossless = is_lossless(path);
+
+    let cutoff_hz = match find_cutoff(&decoded.samples, decoded.sample_rate) {
+        Cutoff::Unknown => {
+            return Ok(QualityReport {
+                file_path,
+                cutoff_hz: None,
+                estimated_bitrate_kbps: None,
+                verdict: Verdict::Unknown,
+            })
+        }
+        Cutoff::None => None,
+        Cutoff::Lossy(cutoff_hz) => Some(cutoff_hz),
+    };
+
+    let estimated_bitrate_kbps = cutoff_hz.map(|cutoff_hz| {
+        CUTOFFS
+            .iter()
+            .find(|(frequency, _)| cutoff_hz >= *frequency)
+            .map(|(_, bitrate)| *bitrate)
+            .unwrap_or(64)
+    });
+
+    let verdict = match estimated_bitrate_kbps {
+        None => Verdict::Ok,
+        Some(_) if is_lossless && cutoff_hz < Some(MAX_FAKE_LOSSLESS_CUTOFF_HZ) => {
+            Verdict::FakeLossless
+        }
+        Some(_) if is_lossless => Verdict::Ok,
+        Some(bitrate) if bitrate < min_bitrate_kbps => Verdict::LowQuality,
+        Some(_) => Verdict::Ok,
+    };
+
+    Ok(QualityReport {
+        file_path,
+        cutoff_hz,
+        estimated_bitrate_kbps,
+        verdict,
+    })
+}
+
+/// Whether a file is encoded with a lossless codec.
+pub fn is_lossless(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+    if LOSSLESS_EXTENSIONS
+        .iter()
+        .any(|lossless| extension.eq_ignore_ascii_case(lossless))
+    {
+        return true;
+    }
+    if !MP4_EXTENSIONS
+        .iter()
+        .any(|mp4| extension.eq_ignore_ascii_case(mp4))
+    {
+        return false;
+    }
+
+    match audio::codec(path) {
+        Ok(codec) => codec == CODEC_TYPE_ALAC,
+        Err(error) => {
+            tracing::debug!(?error, ?path, "failed to read codec");
+            false
+        }
+    }
+}
+
+/// Decodes a song keeping one FFT window every `WINDOW_INTERVAL_SECONDS`.
+fn decode_windows(path: &Path) -> Result<audio::Samples, Error> {
+    let mut windows = Vec::new();
+    let mut position = 0;
+    let sample_rate = audio::decode_stream(path, |samples, sample_rate| {
+        let interval = ((WINDOW_INTERVAL_SECONDS * sample_rate as f64) as usize).max(FFT_SIZE);
+        for &sample in samples {
+            if position % interval < FFT_SIZE {
+                windows.push(sample);
+            }
+            position += 1;
+        }
+        true
+    })?;
+
+    // The last window may be cut short by the end of the song.
+    windows.truncate(windows.len() / FFT_SIZE * FFT_SIZE);
+    Ok(audio::Samples {
+        samples: windows,
+        sample_rate,
+    })
+}
+
+enum Cutoff {
+    /// Too quiet or short to judge.
+    Unknown,
+    None,
+    Lossy(f64),
+}
+
+/// Finds the frequency of a lossy cutoff in the average spectrum.
+fn find_cutoff(samples: &[f32], sample_rate: u32) -> Cutoff {
+    let spectra = audio::spectrogram(samples, FFT_SIZE, FFT_SIZE);
+    if spectra.is_empty() {
+        return Cutoff::Unknown;
+    }
+
+    let bin_hz = sample_rate as f64 / FFT_SIZE as f64;
+    let mut levels = vec![0.0; FFT_SIZE / 2 + 1];
+    for spectrum in &spectra {
+        for (level, power) in levels.iter_mut().zip(spectrum) {
+            *level += power / spectra.len() as f32;
+        }
+    }
+    // Power is relative to a full scale sine wave, which peaks at (FFT_SIZE / 4)^2 with a Hann
+    // window.
+    let full_scale = (FFT_SIZE as f32 / 4.0).powi(2);
+    let levels: Vec<f32> = levels
+        .iter()
+        .map(|power| 10.0 * (power / full_scale + 1e-20).log10())
+        .collect();
+
+    let bin = |hz: f64| ((hz / bin_hz) as usize).min(levels.len() - 1);
+    let reference_band = &levels[bin(REFERENCE_BAND_HZ.0)..bin(REFERENCE_BAND_HZ.1)];
+    let reference = reference_band.iter().sum::<f32>() / reference_band.len().max(1) as f32;
+    if reference < MINIMUM_REFERENCE_DB {
+        return Cutoff::Unknown;
+    }
+
+    // Smooth over ~200 Hz so single noisy bins don't count as content.
+    let smoothing = (200.0 / bin_hz).ceil() as usize;
+    let smoothed: Vec<f32> = (0..levels.len())
+        .map(|i| {
+            let window =
+                &levels[i.saturating_sub(smoothing)..(i + smoothing + 1).min(levels.len())];
+            window.iter().sum::<f32>() / window.len() as f32
+        })
+        .collect();
+
+    let threshold = reference - EMPTY_BELOW_REFERENCE_DB;
+    let Some(highest) = (bin(REFERENCE_BAND_HZ.1)..smoothed.len())
+        .rev()
+        .find(|&i| smoothed[i] > threshold)
+    else {
+        // Nothing above the reference band at all, likely a low sample rate or a very dull song.
+        return Cutoff::Unknown;
+    };
+
+    // Content right up to Nyquist means nothing was cut off.
+    let cliff_bins = (CLIFF_WIDTH_HZ / bin_hz).ceil() as usize;
+    if highest + cliff_bins >= smoothed.len() {
+        return Cutoff::None;
+    }
+
+    // A lossy cutoff drops sharply, a natural roll off (or an old recording) doesn't.
+    let before = smoothed[highest.saturating_sub(cliff_bins)];
+    let after = smoothed[(highest + cliff_bins).min(smoothed.len() - 1)];
+    if before - after < CLIFF_DROP_DB {
+        return Cutoff::None;
+    }
+
+    Cutoff::Lossy(highest as f64 * bin_hz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    /// Noise made of sines every 50 Hz up to `max_hz` with pseudo random phases.
+    fn noise(seconds: f64, max_hz: f64) -> Vec<f32> {
+        let mut seed = 1u32;
+        let sines: Vec<(f64, f64)> = (1..)
+            .map(|i| i as f64 * 50.0)
+            .take_while(|hz| *hz <= max_hz)
+            .map(|hz| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (hz, seed as f64 / u32::MAX as f64 * std::f64::consts::TAU)
+            })
+            .collect();
+        let amplitude = 0.5 / (sines.len() as f64).sqrt();
+        (0..(seconds * SAMPLE_RATE as f64) as usize)
+            .map(|i| {
+                let t = i as f64 / SAMPLE_RATE as f64;
+                let sum: f64 = sines
+                    .iter()
+                    .map(|(hz, phase)| (std::f64::consts::TAU * hz * t + phase).sin())
+                    .sum();
+                (sum * amplitude) as f32
+            })
+            .collect()
+    }
+
+    fn write_wav(path: &Path, samples: &[f32]) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            // Float samples keep quantisation noise from filling the empty band.
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[test]
+    fn finds_lossy_cutoffs() {
+        match find_cutoff(&noise(1.0, 16_000.0), SAMPLE_RATE) {
+            Cutoff::Lossy(cutoff_hz) => assert!((15_800.0..16_300.0).contains(&cutoff_hz)),
+            _ => panic!("expected a lossy cutoff"),
+        }
+        assert!(matches!(
+            find_cutoff(&noise(1.0, 22_000.0), SAMPLE_RATE),
+            Cutoff::None
+        ));
+        assert!(matches!(
+            find_cutoff(&vec![0.0; SAMPLE_RATE as usize], SAMPLE_RATE),
+            Cutoff::Unknown
+        ));
+    }
+
+    #[test]
+    fn checks_lossless_extensions() {
+        assert!(is_lossless(Path::new("/music/a.FLAC")));
+        assert!(is_lossless(Path::new("/music/a.aiff")));
+        assert!(!is_lossless(Path::new("/music/a.mp3")));
+        // MP4 files that can't be read aren't assumed to be ALAC.
+        assert!(!is_lossless(Path::new("/missing/a.m4a")));
+        assert!(!is_lossless(Path::new("/music/a")));
+    }
+
+    #[test]
+    fn analyses_windows_across_the_whole_song() {
+        let dir = TempDir::new("quality");
+
+        // A silent intro longer than a window interval doesn't hide the cutoff.
+        let mut transcoded = vec![0.0; SAMPLE_RATE as usize];
+        transcoded.extend(noise(1.0, 16_000.0));
+        let transcoded_path = dir.join("transcoded.wav");
+        write_wav(&transcoded_path, &transcoded);
+        // Mastering filters just below Nyquist aren't a sign of lossy encoding.
+        let mastered_path = dir.join("mastered.wav");
+        write_wav(&mastered_path, &noise(1.0, 20_500.0));
+
+        let windows = decode_windows(&transcoded_path).unwrap();
+        let transcoded = analyse(&transcoded_path, DEFAULT_MIN_BITRATE_KBPS).unwrap();
+        let mastered = analyse(&mastered_path, DEFAULT_MIN_BITRATE_KBPS).unwrap();
+
+        assert_eq!(windows.samples.len(), 4 * FFT_SIZE);
+        assert_eq!(transcoded.verdict, Verdict::FakeLossless, "{transcoded:?}");
+        assert_eq!(transcoded.estimated_bitrate_kbps, Some(128));
+        assert_eq!(mastered.verdict, Verdict::Ok);
+    }
+}
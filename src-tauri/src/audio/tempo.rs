@@ -0,0 +1,337 @@
+//! Estimates a song's tempo from its audio.
+//!
+//! Onsets (drum hits, note attacks) show up as sudden increases in spectral energy. The onset
+//! strength over time repeats every beat, so the tempo is the period at which it best correlates
+//! with itself. Periods of one beat, two beats, etc. all correlate, so candidates are scored on
+//! several multiples of their period and only tempos within the expected range are considered,
+//! which stops half or double time being reported.
+
+use std::path::Path;
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::Serialize;
+
+use crate::audio::{self, Error, Samples};
+
+/// The range most electronic music falls in, tempos outside it are halved or doubled.
+pub const DEFAULT_BPM_RANGE: (f64, f64) = (70.0, 180.0);
+
+/// Tempo is usually steady so a couple of minutes is enough to estimate it.
+const ANALYSIS_SECONDS: f64 = 120.0;
+const FFT_SIZE: usize = 1024;
+/// Onset strength frames per second, high enough to resolve tempo to a fraction of a BPM.
+const FRAME_RATE: f64 = 172.0;
+/// Onsets are measured relative to the average onset strength over this many seconds.
+const LOCAL_MEAN_SECONDS: f64 = 0.5;
+/// Candidates are scored on this many multiples of their beat period.
+const PERIOD_MULTIPLES: usize = 4;
+const BPM_STEP: f64 = 0.05;
+/// The best candidate is refined within this fraction of its tempo, scored on up to
+/// `REFINE_MULTIPLES` beats since errors in the period add up over many beats.
+const REFINE_RANGE: f64 = 0.01;
+const REFINE_MULTIPLES: usize = 64;
+const REFINE_STEP: f64 = 0.005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tempo {
+    pub bpm: f64,
+    /// How strongly the onsets repeat every beat, from 0 (no pulse) to 1 (a metronome).
+    pub confidence: f64,
+}
+
+/// Onset strength over time, one value per frame.
+#[derive(Debug, Clone)]
+pub struct OnsetEnvelope {
+    pub values: Vec<f32>,
+    /// Frames per second.
+    pub frame_rate: f64,
+    /// The time of the onsets measured in the first frame.
+    pub offset_seconds: f64,
+}
+
+impl OnsetEnvelope {
+    pub fn frame_seconds(&self, frame: usize) -> f64 {
+        self.offset_seconds + frame as f64 / self.frame_rate
+    }
+}
+
+/// Estimates the tempo of a song within `bpm_range`, returns `None` when the song is too short
+/// or has no pulse, e.g. silence or a beatless intro.
+pub fn detect(path: &Path, bpm_range: (f64, f64)) -> Result<Option<Tempo>, Error> {
+    let decoded = audio::decode(path, Some(ANALYSIS_SECONDS))?;
+    Ok(estimate(&onset_envelope(&decoded), bpm_range))
+}
+
+/// Measures onset strength as the increase in log magnitude summed over frequency bins, known as
+/// spectral flux.
+pub fn onset_envelope(decoded: &Samples) -> OnsetEnvelope {
+    let mut stream = OnsetStream::new(decoded.sample_rate);
+    stream.push(&decoded.samples);
+    stream.finish()
+}
+
+/// Measures onset strength like `onset_envelope` as samples are decoded, for recordings too long
+/// to hold in memory.
+pub struct OnsetStream {
+    sample_rate: u32,
+    hop: usize,
+    /// Samples not yet analysed, the start of the next frame onwards.
+    pending: Vec<f32>,
+    previous: Option<Vec<f32>>,
+    flux: Vec<f32>,
+}
+
+impl OnsetStream {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            hop: ((sample_rate as f64 / FRAME_RATE).round() as usize).max(1),
+            pending: Vec::new(),
+            previous: None,
+            flux: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        let mut pending = std::mem::take(&mut self.pending);
+        if pending.is_empty() {
+            let analysed = self.analyse(samples);
+            pending.extend_from_slice(&samples[analysed..]);
+        } else {
+            pending.extend_from_slice(samples);
+            // Decoded packets are small, so frames are analysed a second or so at a time.
+            if pending.len() >= FFT_SIZE + self.sample_rate as usize {
+                let analysed = self.analyse(&pending);
+                pending.drain(..analysed);
+            }
+        }
+        self.pending = pending;
+    }
+
+    pub fn finish(mut self) -> OnsetEnvelope {
+        let pending = std::mem::take(&mut self.pending);
+        self.analyse(&pending);
+
+        let frame_rate = self.sample_rate as f64 / self.hop as f64;
+        let flux = self.flux;
+
+        // Subtracting the local mean leaves only the peaks, so loud and quiet sections count
+        // equally.
+        let radius = (LOCAL_MEAN_SECONDS * frame_rate / 2.0) as usize;
+        let values = (0..flux.len())
+            .map(|i| {
+                let window = &flux[i.saturating_sub(radius)..(i + radius + 1).min(flux.len())];
+                let mean = window.iter().sum::<f32>() / window.len() as f32;
+                (flux[i] - mean).max(0.0)
+            })
+            .collect();
+
+        OnsetEnvelope {
+            values,
+            frame_rate,
+            // The flux of a frame is the change from the previous frame, an onset shows up in the
+            // first frame with it past the middle of the window, where the Hann window peaks.
+            offset_seconds: (FFT_SIZE / 2 + self.hop) as f64 / self.sample_rate as f64,
+        }
+    }
+
+    /// Measures the flux of every whole frame in `samples`, returning how many samples were
+    /// analysed, up to the start of the next frame.
+    fn analyse(&mut self, samples: &[f32]) -> usize {
+        let mut frames = 0;
+        for spectrum in audio::spectra(samples, FFT_SIZE, self.hop) {
+            let magnitudes: Vec<f32> = spectrum
+                .iter()
+                .map(|power| (1.0 + 100.0 * power.sqrt()).ln())
+                .collect();
+            let flux = self.previous.as_ref().map_or(0.0, |previous| {
+                magnitudes
+                    .iter()
+                    .zip(previous)
+                    .map(|(magnitude, previous)| (magnitude - previous).max(0.0))
+                    .sum()
+            });
+            self.flux.push(flux);
+            self.previous = Some(magnitudes);
+            frames += 1;
+        }
+        frames * self.hop
+    }
+}
+
+/// Finds the tempo within `bpm_range` whose beat period best fits the onset envelope.
+///
+/// When a tempo and its double are both in range they're compared by score, weighted slightly
+/// towards the middle of the range.
+pub fn estimate(envelope: &OnsetEnvelope, (min_bpm, max_bpm): (f64, f64)) -> Option<Tempo> {
+    if min_bpm <= 0.0 || max_bpm < min_bpm {
+        return None;
+    }
+    let longest_period = 60.0 * envelope.frame_rate / min_bpm;
+    // At least a few periods are needed to measure any repetition.
+    if (envelope.values.len() as f64) < longest_period * (PERIOD_MULTIPLES as f64 + 2.0) {
+        return None;
+    }
+
+    let autocorrelation = autocorrelation(&envelope.values)?;
+    let at = |lag: f64| {
+        let index = lag as usize;
+        let fraction = (lag - index as f64) as f32;
+        let before = autocorrelation.get(index).copied().unwrap_or(0.0);
+        let after = autocorrelation.get(index + 1).copied().unwrap_or(0.0);
+        before + (after - before) * fraction
+    };
+    let score = |bpm: f64, multiples: usize| {
+        let period = 60.0 * envelope.frame_rate / bpm;
+        (1..=multiples)
+            .map(|multiple| at(period * multiple as f64) as f64)
+            .sum::<f64>()
+            / multiples as f64
+    };
+
+    // When beats are split into halves (e.g. off beat hi-hats) a period of one and a half beats
+    // also lines up with onsets, giving two thirds of the tempo. Such a period splits into
+    // thirds, not halves, so candidates are penalised when their third correlates better than
+    // their half.
+    let ternary = |bpm: f64| {
+        let period = 60.0 * envelope.frame_rate / bpm;
+        (at(period / 3.0) - at(period / 2.0)).max(0.0) as f64
+    };
+    let centre = (min_bpm * max_bpm).sqrt();
+    let weight = |bpm: f64| 1.0 - 0.1 * (bpm / centre).log2().abs();
+    let (bpm, confidence) = best(min_bpm, max_bpm, BPM_STEP, |bpm| {
+        let score = score(bpm, PERIOD_MULTIPLES) - ternary(bpm);
+        (score * weight(bpm), score)
+    })?;
+    if confidence <= 0.0 {
+        return None;
+    }
+
+    // Only beats that fit in the second half of the envelope are used so every lag still
+    // overlaps with enough of the song.
+    let multiples = ((envelope.values.len() as f64 / 2.0 / (60.0 * envelope.frame_rate / bpm))
+        as usize)
+        .clamp(PERIOD_MULTIPLES, REFINE_MULTIPLES);
+    let (bpm, _) = best(
+        (bpm * (1.0 - REFINE_RANGE)).max(min_bpm),
+        (bpm * (1.0 + REFINE_RANGE)).min(max_bpm),
+        REFINE_STEP,
+        |bpm| (score(bpm, multiples), ()),
+    )?;
+
+    Some(Tempo {
+        bpm: (bpm * 100.0).round() / 100.0,
+        confidence: confidence.min(1.0),
+    })
+}
+
+/// Finds the BPM between `min_bpm` and `max_bpm` with the highest score, `score` returns the
+/// value to maximise and a value to return alongside the BPM.
+fn best<T>(
+    min_bpm: f64,
+    max_bpm: f64,
+    step: f64,
+    score: impl Fn(f64) -> (f64, T),
+) -> Option<(f64, T)> {
+    let steps = ((max_bpm - min_bpm) / step).round() as usize;
+    (0..=steps)
+        .map(|i| {
+            let bpm = min_bpm + i as f64 * step;
+            (bpm, score(bpm))
+        })
+        .max_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b))
+        .map(|(bpm, (_, value))| (bpm, value))
+}
+
+/// The autocorrelation of the mean removed values, normalised so lag 0 is 1 and scaled up at
+/// longer lags to make up for the values overlapping less. Returns `None` when the values are
+/// constant.
+fn autocorrelation(values: &[f32]) -> Option<Vec<f32>> {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    let size = (values.len() * 2).next_power_of_two();
+    let mut buffer: Vec<Complex<f32>> = values
+        .iter()
+        .map(|value| Complex::new(value - mean, 0.0))
+        .chain(std::iter::repeat(Complex::default()))
+        .take(size)
+        .collect();
+
+    let mut planner = FftPlanner::new();
+    planner.plan_fft_forward(size).process(&mut buffer);
+    for value in &mut buffer {
+        *value = Complex::new(value.norm_sqr(), 0.0);
+    }
+    planner.plan_fft_inverse(size).process(&mut buffer);
+
+    let energy = buffer[0].re;
+    if energy <= f32::EPSILON {
+        return None;
+    }
+    Some(
+        buffer[..values.len()]
+            .iter()
+            .enumerate()
+            .map(|(lag, value)| {
+                let overlap = (values.len() - lag) as f32 / values.len() as f32;
+                value.re / energy / overlap
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    /// A click track: a short decaying 1 kHz burst on every beat.
+    pub(crate) fn clicks(bpm: f64, seconds: f64) -> Samples {
+        let beat_seconds = 60.0 / bpm;
+        let mut samples = vec![0.0; (seconds * SAMPLE_RATE as f64) as usize];
+        let mut beat = 0;
+        while let Some(start) = Some((beat as f64 * beat_seconds * SAMPLE_RATE as f64) as usize)
+            .filter(|start| *start < samples.len())
+        {
+            for (i, sample) in samples[start..].iter_mut().take(400).enumerate() {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                *sample = (t * 1000.0 * std::f32::consts::TAU).sin() * (-t * 300.0).exp();
+            }
+            beat += 1;
+        }
+        Samples {
+            samples,
+            sample_rate: SAMPLE_RATE,
+        }
+    }
+
+    #[test]
+    fn estimates_the_tempo_of_a_click_track() {
+        let tempo = estimate(&onset_envelope(&clicks(128.0, 30.0)), DEFAULT_BPM_RANGE).unwrap();
+        assert!((tempo.bpm - 128.0).abs() <= 0.5, "{tempo:?}");
+        assert!(
+            tempo.confidence > 0.5 && tempo.confidence <= 1.0,
+            "{tempo:?}"
+        );
+    }
+
+    #[test]
+    fn doubles_tempos_below_the_range() {
+        let tempo = estimate(&onset_envelope(&clicks(64.0, 30.0)), (70.0, 180.0)).unwrap();
+        assert!((tempo.bpm - 128.0).abs() <= 0.5, "{tempo:?}");
+    }
+
+    #[test]
+    fn finds_no_tempo_in_silence_or_short_clips() {
+        let silence = Samples {
+            samples: vec![0.0; SAMPLE_RATE as usize * 30],
+            sample_rate: SAMPLE_RATE,
+        };
+        assert_eq!(estimate(&onset_envelope(&silence), DEFAULT_BPM_RANGE), None);
+        assert_eq!(
+            estimate(&onset_envelope(&clicks(128.0, 2.0)), DEFAULT_BPM_RANGE),
+            None
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Reconstructs the tracklist of a recording without a cue sheet by recognising library songs in
+//! its audio.
+//!
+//! The recording is fingerprinted and split into overlapping windows, each window is matched
+//! against the library and runs of windows matching the same song become a track. Start times
+//! are where the song's start lines up with the recording, so they're only as close as the
+//! fingerprint offsets, aligning the songs afterwards (see `audio::alignment`) refines them.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::audio::{
+    fingerprint::{self, FingerprintIndex},
+    Error,
+};
+
+/// Long enough to hold a few hundred landmarks, short enough that most windows have one song
+/// playing alone.
+const WINDOW_SECONDS: f64 = 20.0;
+const WINDOW_HOP_SECONDS: f64 = 10.0;
+/// Songs have to be the best match for this many windows to count as played, which rules out
+/// samples and loops shared by other songs.
+const MIN_WINDOWS: usize = 2;
+/// The best match of a window has to share this many times more landmarks than the next best,
+/// otherwise the window is a song missing from the library or too many songs at once.
+const MIN_SCORE_RATIO: f64 = 1.2;
+
+/// A library song recognised in a recording.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentifiedTrack {
+    pub song_path: String,
+    /// When the song starts playing in the recording.
+    pub start_seconds: f64,
+    /// The end of the last window the song was recognised in.
+    pub end_seconds: f64,
+    /// The most landmarks shared with any one window.
+    pub score: u32,
+}
+
+/// The best match of a window.
+struct WindowMatch {
+    start_seconds: f64,
+    song_path: String,
+    /// Where the start of the song falls in the recording.
+    song_start_seconds: f64,
+    score: u32,
+}
+
+/// A run of windows matching the same song.
+struct Run {
+    song_path: String,
+    song_start_seconds: f64,
+    first_window_seconds: f64,
+    last_window_seconds: f64,
+    windows: usize,
+    score: u32,
+}
+
+/// Recognises the songs played in a recording, in the order they were played.
+pub fn identify(
+    recording_path: &Path,
+    index: &FingerprintIndex,
+) -> Result<Vec<IdentifiedTrack>, Error> {
+    let recording = fingerprint::fingerprint(recording_path)?;
+    let duration = recording.duration_seconds();
+
+    let mut windows: Vec<Option<WindowMatch>> = Vec::new();
+    let mut start_seconds = 0.0;
+    while start_seconds < duration {
+        // Landmark frames are from the start of the recording, so offsets are too.
+        let matches = index.query(recording.range(start_seconds, start_seconds + WINDOW_SECONDS));
+        let runner_up = matches
+            .get(1)
+            .map_or(0.0, |runner_up| runner_up.score as f64);
+        let best = matches
+            .into_iter()
+            .next()
+            .filter(|best| best.score as f64 >= runner_up * MIN_SCORE_RATIO);
+        windows.push(best.map(|best| WindowMatch {
+            start_seconds,
+            song_path: best.song_path,
+            song_start_seconds: best.offset_seconds,
+            score: best.score,
+        }));
+        start_seconds += WINDOW_HOP_SECONDS;
+    }
+
+    Ok(tracks(windows, duration))
+}
+
+/// Turns the best match of each window into the songs played, dropping songs only recognised
+/// briefly.
+fn tracks(mut windows: Vec<Option<WindowMatch>>, duration: f64) -> Vec<IdentifiedTrack> {
+    // A single window matching another song between two of the same song is a loop or sample
+    // shared between them.
+    for i in 1..windows.len().saturating_sub(1) {
+        let song = |window: &Option<WindowMatch>| window.as_ref().map(|w| w.song_path.clone());
+        let (before, this, after) = (
+            song(&windows[i - 1]),
+            song(&windows[i]),
+            song(&windows[i + 1]),
+        );
+        if before.is_some() && before == after && this != before {
+            windows[i] = None;
+        }
+    }
+
+    let runs = merge_runs(
+        merge_runs(windows.into_iter().flatten().map(Run::from).collect())
+            .into_iter()
+            .filter(|run| run.windows >= MIN_WINDOWS)
+            .collect(),
+    );
+
+    let mut tracks: Vec<IdentifiedTrack> = Vec::new();
+    let mut earliest_start = 0.0;
+    for run in runs {
+        // Songs started partway through line up before they were heard, they can't have
+        // started before the previous song stopped being recognised on its own.
+        let start_seconds = run
+            .song_start_seconds
+            .min(run.first_window_seconds)
+            .max(earliest_start);
+        earliest_start = run.last_window_seconds;
+        tracks.push(IdentifiedTrack {
+            song_path: run.song_path,
+            start_seconds,
+            end_seconds: (run.last_window_seconds + WINDOW_SECONDS).min(duration),
+            score: run.score,
+        });
+    }
+
+    tracks
+}
+
+impl From<WindowMatch> for Run {
+    fn from(window: WindowMatch) -> Self {
+        Self {
+            song_path: window.song_path,
+            song_start_seconds: window.song_start_seconds,
+            first_window_seconds: window.start_seconds,
+            last_window_seconds: window.start_seconds,
+            windows: 1,
+            score: window.score,
+        }
+    }
+}
+
+/// Joins consecutive runs of the same song. The first run's song start is kept, it's measured
+/// closest to the start of the song where a change in tempo has moved it the least.
+fn merge_runs(runs: Vec<Run>) -> Vec<Run> {
+    let mut merged: Vec<Run> = Vec::new();
+    for run in runs {
+        match merged.last_mut() {
+            Some(last) if last.song_path == run.song_path => {
+                last.last_window_seconds = run.last_window_seconds;
+                last.windows += run.windows;
+                last.score = last.score.max(run.score);
+            }
+            _ => merged.push(run),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Windows every `WINDOW_HOP_SECONDS` matching each song, as its path and where it starts.
+    fn windows(songs: &[Option<(&str, f64)>]) -> Vec<Option<WindowMatch>> {
+        songs
+            .iter()
+            .enumerate()
+            .map(|(i, song)| {
+                song.map(|(song_path, song_start_seconds)| WindowMatch {
+                    start_seconds: i as f64 * WINDOW_HOP_SECONDS,
+                    song_path: song_path.to_string(),
+                    song_start_seconds,
+                    score: 100 + i as u32,
+                })
+            })
+            .collect()
+    }
+
+    fn track(song_path: &str, start_seconds: f64, end_seconds: f64, score: u32) -> IdentifiedTrack {
+        IdentifiedTrack {
+            song_path: song_path.to_string(),
+            start_seconds,
+            end_seconds,
+            score,
+        }
+    }
+
+    #[test]
+    fn splits_overlapping_songs() {
+        // b lines up before a stops being recognised, it was mixed in under a.
+        let windows = windows(&[
+            Some(("a", 0.0)),
+            Some(("a", 0.0)),
+            Some(("a", 0.0)),
+            Some(("b", 15.0)),
+            Some(("b", 15.0)),
+            Some(("b", 15.0)),
+        ]);
+        assert_eq!(
+            tracks(windows, 70.0),
+            vec![track("a", 0.0, 40.0, 102), track("b", 20.0, 70.0, 105)]
+        );
+    }
+
+    #[test]
+    fn ignores_short_spurious_matches() {
+        let windows = windows(&[
+            Some(("c", -5.0)),
+            None,
+            Some(("a", 10.0)),
+            Some(("a", 10.0)),
+            // A loop shared with c within a.
+            Some(("c", 0.0)),
+            Some(("a", 10.0)),
+            Some(("a", 10.0)),
+        ]);
+        assert_eq!(tracks(windows, 90.0), vec![track("a", 10.0, 80.0, 106)]);
+    }
+
+    #[test]
+    fn leaves_gaps_for_unrecognised_songs() {
+        let windows = windows(&[
+            Some(("a", 0.0)),
+            Some(("a", 0.0)),
+            None,
+            None,
+            None,
+            Some(("b", 45.0)),
+            Some(("b", 45.0)),
+            // a played again later.
+            Some(("a", 68.0)),
+            Some(("a", 68.0)),
+        ]);
+        assert_eq!(
+            tracks(windows, 100.0),
+            vec![
+                track("a", 0.0, 30.0, 101),
+                track("b", 45.0, 80.0, 106),
+                track("a", 68.0, 100.0, 108),
+            ]
+        );
+    }
+}
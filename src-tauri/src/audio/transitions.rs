@@ -0,0 +1,424 @@
+//! Finds where a recording transitions from one track to the next.
+//!
+//! Cue sheet times are when a track was loaded or started on a deck, which can be well before it's
+//! heard in the mix. The recording is split into short frames, each described by its spectral
+//! shape and chroma, and compared with the frames around it. Where the frames before are alike
+//! and the frames after are alike but the two differ, the mix has changed, measured as a novelty
+//! curve (Foote, 2000). The strongest change near each cue time is taken as the transition.
+//!
+//! The blend is where the mix is a combination of both tracks. The previous track plays alone
+//! just before each track is started and the new track plays alone just before the track after
+//! it is started, so each frame is measured as how much closer it sounds to one than the other.
+
+use std::{ops::Range, path::Path};
+
+use serde::Serialize;
+
+use crate::{
+    audio::{self, key, Error},
+    recording::Track,
+};
+
+/// Length of audio described by each frame.
+const FRAME_SECONDS: f64 = 0.5;
+const FFT_SIZE: usize = 8192;
+/// Spectral shape is measured as the energy in this many bands, spaced evenly in pitch between
+/// `MIN_FREQUENCY_HZ` and `MAX_FREQUENCY_HZ`.
+const BANDS: usize = 16;
+const MIN_FREQUENCY_HZ: f64 = 40.0;
+const MAX_FREQUENCY_HZ: f64 = 12_000.0;
+/// How far either side of each frame is compared to measure novelty, long enough to ignore
+/// changes within a track like drum fills but short enough to separate quick mixes.
+const KERNEL_SECONDS: f64 = 12.0;
+/// How early a track can be heard before its cue time, allowing for cue sheets timed to the
+/// second.
+const CUE_TOLERANCE_SECONDS: f64 = 10.0;
+/// How late after its cue time a track can be mixed in.
+const MAX_DELAY_SECONDS: f64 = 180.0;
+/// Length of audio used to describe each track playing alone.
+const PROFILE_SECONDS: f64 = 30.0;
+/// The blend is where the mix sounds between this far and `1 - BLEND_THRESHOLD` of the way from
+/// the previous track to the next.
+const BLEND_THRESHOLD: f64 = 0.1;
+/// How much the closeness to either track is smoothed over to ignore brief changes.
+const SMOOTHING_SECONDS: f64 = 4.0;
+
+/// A transition from one track to the next in a recording.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transition {
+    /// Index of the track being mixed in within the recording's tracks.
+    pub track_index: usize,
+    /// When the cue sheet says the track started.
+    pub cue_seconds: f64,
+    /// Where the mix changes most from the previous track to this one.
+    pub transition_seconds: f64,
+    /// When this track starts being heard alongside the previous one.
+    pub blend_start_seconds: f64,
+    /// When the previous track stops being heard.
+    pub blend_end_seconds: f64,
+    /// How distinct the change is compared to the largest in the recording, from 0 to 1.
+    pub confidence: f64,
+}
+
+/// Proposes the transition into each track of a recording that has a start time, except the
+/// first which plays from the start.
+pub fn detect(path: &Path, tracks: &[Track]) -> Result<Vec<Transition>, Error> {
+    let features = features(path)?;
+    if features.is_empty() {
+        return Ok(Vec::new());
+    }
+    let novelty = novelty(&features);
+    let largest = novelty.iter().copied().fold(0.0, f64::max);
+    let duration = features.len() as f64 * FRAME_SECONDS;
+
+    let cues: Vec<(usize, f64)> = tracks
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter_map(|(i, track)| Some((i, track.start_seconds()? as f64)))
+        .filter(|(_, cue)| *cue < duration)
+        .collect();
+
+    let mut transitions = Vec::new();
+    let mut previous_cue = 0.0;
+    let mut previous_transition = 0.0;
+    for (n, &(track_index, cue)) in cues.iter().enumerate() {
+        let next_cue = cues.get(n + 1).map_or(duration, |(_, next_cue)| *next_cue);
+
+        // The transition can't come before the previous one or after the next track is started.
+        let search_start = (cue - CUE_TOLERANCE_SECONDS).max(previous_transition);
+        let search_end = (cue + MAX_DELAY_SECONDS)
+            .min(next_cue - CUE_TOLERANCE_SECONDS)
+            .max(search_start);
+        let (first, last) = (
+            frame(search_start),
+            frame(search_end).min(novelty.len() - 1),
+        );
+        let Some(peak) = (first..=last).max_by(|a, b| novelty[*a].total_cmp(&novelty[*b])) else {
+            continue;
+        };
+
+        // Each track plays alone just before the next is started.
+        let previous = Profile::new(
+            &features,
+            (cue - CUE_TOLERANCE_SECONDS - PROFILE_SECONDS).max(previous_cue),
+            cue - CUE_TOLERANCE_SECONDS,
+        );
+        let next = Profile::new(
+            &features,
+            (next_cue - CUE_TOLERANCE_SECONDS - PROFILE_SECONDS).max(search_start),
+            next_cue - CUE_TOLERANCE_SECONDS,
+        );
+        let blend_last = frame(next_cue - CUE_TOLERANCE_SECONDS).clamp(peak, features.len() - 1);
+        let (blend_start, blend_end) = match (previous, next) {
+            (Some(previous), Some(next)) => {
+                blend(&features, (first, blend_last), peak, &previous, &next)
+            }
+            _ => (peak, peak),
+        };
+
+        let transition_seconds = seconds(peak);
+        transitions.push(Transition {
+            track_index,
+            cue_seconds: cue,
+            transition_seconds,
+            blend_start_seconds: seconds(blend_start).min(transition_seconds),
+            blend_end_seconds: seconds(blend_end).max(transition_seconds),
+            confidence: if largest > 0.0 {
+                novelty[peak] / largest
+            } else {
+                0.0
+            },
+        });
+        previous_cue = cue;
+        previous_transition = transition_seconds;
+    }
+
+    Ok(transitions)
+}
+
+fn frame(seconds: f64) -> usize {
+    (seconds.max(0.0) / FRAME_SECONDS) as usize
+}
+
+/// The middle of a frame.
+fn seconds(frame: usize) -> f64 {
+    (frame as f64 + 0.5) * FRAME_SECONDS
+}
+
+/// Describes each frame of the recording by its spectral shape and chroma, as a vector of unit
+/// length so frames are compared by their dot product. Silent frames are all zeros.
+fn features(path: &Path) -> Result<Vec<Vec<f64>>, Error> {
+    let mut features = Vec::new();
+    let mut frame = Vec::new();
+    audio::decode_stream(path, |samples, sample_rate| {
+        let frame_size = (FRAME_SECONDS * sample_rate as f64) as usize;
+        for chunk in samples.chunks(frame_size) {
+            let needed = (frame_size - frame.len()).min(chunk.len());
+            frame.extend_from_slice(&chunk[..needed]);
+            if frame.len() == frame_size {
+                features.push(frame_features(&frame, sample_rate));
+                frame.clear();
+                frame.extend_from_slice(&chunk[needed..]);
+            }
+        }
+        true
+    })?;
+    Ok(features)
+}
+
+fn frame_features(samples: &[f32], sample_rate: u32) -> Vec<f64> {
+    let mut spectrum = vec![0.0; FFT_SIZE / 2 + 1];
+    for window in audio::spectra(samples, FFT_SIZE, FFT_SIZE / 2) {
+        for (total, power) in spectrum.iter_mut().zip(window) {
+            *total += power;
+        }
+    }
+    let bin_hz = sample_rate as f64 / FFT_SIZE as f64;
+
+    let mut bands = [0.0; BANDS];
+    let octaves = (MAX_FREQUENCY_HZ / MIN_FREQUENCY_HZ).log2();
+    for (bin, power) in spectrum.iter().enumerate() {
+        let position = (bin as f64 * bin_hz / MIN_FREQUENCY_HZ).log2() / octaves;
+        if (0.0..1.0).contains(&position) {
+            bands[(position * BANDS as f64) as usize] += *power as f64;
+        }
+    }
+    if bands.iter().all(|energy| *energy <= f64::EPSILON) {
+        return vec![0.0; BANDS + 12];
+    }
+    let bands = bands.map(|energy| (energy + f64::EPSILON).log10());
+
+    // Both halves are unit length so the whole vector is too, weighting them equally.
+    let mut features = centred_unit(&bands);
+    features.extend(centred_unit(&key::spectrum_chroma(&spectrum, bin_hz)));
+    for value in &mut features {
+        *value /= 2f64.sqrt();
+    }
+    features
+}
+
+/// Subtracts the mean and scales to unit length, leaving zeros when all the values are equal.
+fn centred_unit(values: &[f64]) -> Vec<f64> {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let centred: Vec<f64> = values.iter().map(|value| value - mean).collect();
+    let length = centred
+        .iter()
+        .map(|value| value * value)
+        .sum::<f64>()
+        .sqrt();
+    if length <= f64::EPSILON {
+        return vec![0.0; values.len()];
+    }
+    centred.iter().map(|value| value / length).collect()
+}
+
+fn similarity(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// How much the frames before each frame differ from the frames after it, using a Gaussian
+/// tapered checkerboard kernel over the frames' self similarity.
+fn novelty(features: &[Vec<f64>]) -> Vec<f64> {
+    let radius = (KERNEL_SECONDS / FRAME_SECONDS) as isize;
+    let taper: Vec<f64> = (-radius..radius)
+        .map(|offset| {
+            // The centre of the kernel is between the last frame before and the first after.
+            let distance = (offset as f64 + 0.5) / (radius as f64 / 2.0);
+            (-distance * distance / 2.0).exp()
+        })
+        .collect();
+
+    (0..features.len() as isize)
+        .map(|centre| {
+            // Pairs on the same side of the centre and pairs across it are averaged separately so
+            // frames missing at the start and end of the recording don't skew the balance.
+            let (mut same, mut same_weights, mut across, mut across_weights) = (0.0, 0.0, 0.0, 0.0);
+            for (a, a_taper) in (-radius..radius).zip(&taper) {
+                let Some(a_features) = features.get((centre + a) as usize) else {
+                    continue;
+                };
+                for (b, b_taper) in (-radius..radius).zip(&taper) {
+                    let Some(b_features) = features.get((centre + b) as usize) else {
+                        continue;
+                    };
+                    let weight = a_taper * b_taper;
+                    let similarity = weight * similarity(a_features, b_features);
+                    if (a < 0) == (b < 0) {
+                        same += similarity;
+                        same_weights += weight;
+                    } else {
+                        across += similarity;
+                        across_weights += weight;
+                    }
+                }
+            }
+            if across_weights > 0.0 {
+                (same / same_weights - across / across_weights).max(0.0)
+            } else {
+                0.0
+            }
+        })
+        .collect()
+}
+
+/// How a track sounds playing alone.
+struct Profile {
+    /// The average features of its audible frames.
+    features: Vec<f64>,
+    frames: Range<usize>,
+}
+
+impl Profile {
+    /// Describes the audio between two times, `None` when there are no audible frames.
+    fn new(features: &[Vec<f64>], start_seconds: f64, end_seconds: f64) -> Option<Self> {
+        let frames = frame(start_seconds)..frame(end_seconds).min(features.len());
+        let audible = features
+            .get(frames.clone())?
+            .iter()
+            .filter(|features| features.iter().any(|value| *value != 0.0));
+
+        let mut total = vec![0.0; BANDS + 12];
+        let mut count = 0;
+        for features in audible {
+            for (total, value) in total.iter_mut().zip(features) {
+                *total += value;
+            }
+            count += 1;
+        }
+        (count > 0).then(|| Self {
+            features: total.iter().map(|total| total / count as f64).collect(),
+            frames,
+        })
+    }
+}
+
+/// Finds the first and last frames of the blend around `peak`, where each frame sounds partway
+/// between the previous and next track playing alone. The blend is kept between `first` and
+/// `last`.
+fn blend(
+    features: &[Vec<f64>],
+    (first, last): (usize, usize),
+    peak: usize,
+    previous: &Profile,
+    next: &Profile,
+) -> (usize, usize) {
+    // How much closer a frame sounds to the next track than the previous, from 0 to 1.
+    let closeness = |features: &Vec<f64>| {
+        let to_previous = (1.0 - similarity(features, &previous.features)).max(0.0);
+        let to_next = (1.0 - similarity(features, &next.features)).max(0.0);
+        if to_previous + to_next > 0.0 {
+            to_previous / (to_previous + to_next)
+        } else {
+            0.5
+        }
+    };
+    let average = |frames: &Range<usize>| {
+        features[frames.clone()].iter().map(closeness).sum::<f64>() / frames.len() as f64
+    };
+
+    // Even alone a track isn't identical to its average, so progress is measured from how close
+    // each track alone is, 0 sounding like the previous track alone and 1 like the next.
+    let (previous_alone, next_alone) = (average(&previous.frames), average(&next.frames));
+    if next_alone - previous_alone <= f64::EPSILON {
+        return (peak, peak);
+    }
+    let progress: Vec<f64> = features[first..=last]
+        .iter()
+        .map(|features| (closeness(features) - previous_alone) / (next_alone - previous_alone))
+        .collect();
+
+    let radius = (SMOOTHING_SECONDS / FRAME_SECONDS / 2.0) as usize;
+    let smoothed = |frame: usize| {
+        let window =
+            &progress[frame.saturating_sub(radius)..(frame + radius + 1).min(progress.len())];
+        window.iter().sum::<f64>() / window.len() as f64
+    };
+
+    let mut start = peak - first;
+    while start > 0 && smoothed(start - 1) > BLEND_THRESHOLD {
+        start -= 1;
+    }
+    let mut end = peak - first;
+    while end + 1 < progress.len() && smoothed(end + 1) < 1.0 - BLEND_THRESHOLD {
+        end += 1;
+    }
+    (first + start, first + end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const SAMPLE_RATE: u32 = 22050;
+
+    fn chord(hzs: &[f64], t: f64) -> f64 {
+        hzs.iter()
+            .map(|hz| (std::f64::consts::TAU * hz * t).sin())
+            .sum::<f64>()
+            / hzs.len() as f64
+    }
+
+    fn track(start_time: Option<&str>) -> Track {
+        Track {
+            title: None,
+            performer: None,
+            file: None,
+            start_time: start_time.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn finds_crossfades_between_tracks() {
+        let dir = TempDir::new("transitions");
+        let path = dir.join("mix.wav");
+
+        // A C major chord crossfaded into an F# major chord from 2:00 to 2:16, the second track
+        // having been started at 1:55.
+        let (fade_start, fade_end) = (120.0, 136.0);
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..240 * SAMPLE_RATE {
+            let t = i as f64 / SAMPLE_RATE as f64;
+            let mix = ((t - fade_start) / (fade_end - fade_start)).clamp(0.0, 1.0);
+            let sample = (1.0 - mix) * chord(&[261.63, 329.63, 392.0], t)
+                + mix * chord(&[369.99, 466.16, 554.37], t);
+            writer.write_sample(sample as f32 * 0.5).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let transitions =
+            detect(&path, &[track(Some("00:00:00")), track(Some("00:01:55"))]).unwrap();
+        let [transition] = transitions.as_slice() else {
+            panic!("expected one transition, found {transitions:?}");
+        };
+        assert_eq!(transition.track_index, 1);
+        assert_eq!(transition.cue_seconds, 115.0);
+        assert!(
+            (125.0..=131.0).contains(&transition.transition_seconds),
+            "{transition:?}"
+        );
+        assert!(
+            (118.0..=125.0).contains(&transition.blend_start_seconds),
+            "{transition:?}"
+        );
+        assert!(
+            (130.0..=138.0).contains(&transition.blend_end_seconds),
+            "{transition:?}"
+        );
+        assert_eq!(transition.confidence, 1.0);
+
+        // Tracks without a start time are skipped.
+        assert_eq!(
+            detect(&path, &[track(None), track(None)]).unwrap(),
+            Vec::new()
+        );
+    }
+}
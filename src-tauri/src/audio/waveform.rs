@@ -0,0 +1,138 @@
+//! Summarises songs as waveforms for display.
+//!
+//! The song is split into columns of equal length and the peak and RMS level of each is kept,
+//! drawn as the outline of the waveform and the body within it. Columns are short enough to zoom
+//! in on a few beats at a time while a whole song still fits in a few kilobytes.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::audio::{self, Error};
+
+/// How many columns there are per second of audio.
+pub const COLUMNS_PER_SECOND: u32 = 50;
+
+/// The level of a column of a waveform, from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaveformColumn {
+    pub peak: f32,
+    pub rms: f32,
+}
+
+/// A song's waveform, a column every `1 / columns_per_second` seconds.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Waveform {
+    pub file_path: String,
+    pub columns_per_second: u32,
+    pub columns: Vec<WaveformColumn>,
+}
+
+/// Decodes a song into its waveform.
+pub fn analyse(path: &Path) -> Result<Waveform, Error> {
+    let mut columns = Vec::new();
+    let mut column = Column::default();
+    audio::decode_stream(path, |samples, sample_rate| {
+        let column_len = (sample_rate / COLUMNS_PER_SECOND).max(1) as usize;
+        for sample in samples {
+            column.push(*sample);
+            if column.len == column_len {
+                columns.push(column.finish());
+                column = Column::default();
+            }
+        }
+        true
+    })?;
+    if column.len > 0 {
+        columns.push(column.finish());
+    }
+
+    Ok(Waveform {
+        file_path: path.to_string_lossy().into_owned(),
+        columns_per_second: COLUMNS_PER_SECOND,
+        columns,
+    })
+}
+
+#[derive(Default)]
+struct Column {
+    len: usize,
+    peak: f32,
+    squares: f64,
+}
+
+impl Column {
+    fn push(&mut self, sample: f32) {
+        self.len += 1;
+        self.peak = self.peak.max(sample.abs());
+        self.squares += (sample as f64).powi(2);
+    }
+
+    fn finish(&self) -> WaveformColumn {
+        WaveformColumn {
+            peak: self.peak.min(1.0),
+            rms: ((self.squares / self.len as f64).sqrt() as f32).min(1.0),
+        }
+    }
+}
+
+/// Packs columns into two bytes each, peak then RMS, for storing.
+pub fn to_bytes(columns: &[WaveformColumn]) -> Vec<u8> {
+    columns
+        .iter()
+        .flat_map(|column| [to_byte(column.peak), to_byte(column.rms)])
+        .collect()
+}
+
+/// Unpacks columns packed by `to_bytes`.
+pub fn from_bytes(bytes: &[u8]) -> Vec<WaveformColumn> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| WaveformColumn {
+            peak: pair[0] as f32 / u8::MAX as f32,
+            rms: pair[1] as f32 / u8::MAX as f32,
+        })
+        .collect()
+}
+
+fn to_byte(level: f32) -> u8 {
+    (level.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_peak_and_rms() {
+        let mut column = Column::default();
+        for sample in [0.5, -1.0, 0.5, -1.0] {
+            column.push(sample);
+        }
+        let column = column.finish();
+        assert_eq!(column.peak, 1.0);
+        assert!((column.rms - 0.625f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn packs_columns_into_bytes() {
+        let columns = [
+            WaveformColumn {
+                peak: 1.0,
+                rms: 0.5,
+            },
+            WaveformColumn {
+                peak: 0.0,
+                rms: 0.0,
+            },
+        ];
+        let bytes = to_bytes(&columns);
+        assert_eq!(bytes, vec![255, 128, 0, 0]);
+        let unpacked = from_bytes(&bytes);
+        assert_eq!(unpacked[0].peak, 1.0);
+        assert!((unpacked[0].rms - 0.5).abs() < 0.01);
+        assert_eq!(unpacked[1], columns[1]);
+    }
+}
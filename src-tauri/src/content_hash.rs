@@ -0,0 +1,342 @@
+//! Content hashes of audio files that ignore their tags.
+//!
+//! Only the audio data is hashed so editing tags or artwork doesn't change the hash, and a file
+//! keeps its hash when it's moved or renamed. The audio data is found from the container: the
+//! `data` chunk of WAV files, the `SSND` chunk of AIFF files, the frames after the metadata blocks
+//! of FLAC files, the `mdat` atom of MP4 files, and for MP3 and anything else the bytes between
+//! leading ID3v2 tags and trailing ID3v1 and APE tags. Formats that keep tags inside the audio
+//! stream, such as Ogg, change hash when their tags change.
+//!
+//! Large files are sampled at their start, middle and end rather than read in full, together
+//! with the audio data's length that's enough to tell files apart while hashing a library of
+//! recordings in seconds.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+/// How much audio data is hashed at the start, middle and end of large files.
+const SAMPLE_BYTES: u64 = 256 * 1024;
+const ID3V1_BYTES: u64 = 128;
+const APE_FOOTER_BYTES: u64 = 32;
+
+/// Hashes the audio data of a file.
+pub fn hash(path: &Path) -> std::io::Result<String> {
+    let mut file = BufReader::new(File::open(path)?);
+    let len = file.get_ref().metadata()?.len();
+    let audio = audio_range(&mut file, len)?;
+    let audio_len = audio.end.saturating_sub(audio.start);
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&audio_len.to_le_bytes());
+    let samples = if audio_len <= SAMPLE_BYTES * 3 {
+        vec![audio]
+    } else {
+        let middle = audio.start + (audio_len - SAMPLE_BYTES) / 2;
+        vec![
+            audio.start..audio.start + SAMPLE_BYTES,
+            middle..middle + SAMPLE_BYTES,
+            audio.end - SAMPLE_BYTES..audio.end,
+        ]
+    };
+    for sample in samples {
+        file.seek(SeekFrom::Start(sample.start))?;
+        hasher.update_reader((&mut file).take(sample.end - sample.start))?;
+    }
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Finds the byte range of a file's audio data, see the module docs for each format.
+fn audio_range<R: Read + Seek>(file: &mut R, len: u64) -> std::io::Result<Range<u64>> {
+    let mut header = [0; 12];
+    if len < header.len() as u64 {
+        return Ok(0..len);
+    }
+    file.read_exact(&mut header)?;
+
+    let range = match (&header[0..4], &header[4..8], &header[8..12]) {
+        (b"RIFF", _, b"WAVE") => find_chunk(file, len, 12, b"data", Endian::Little)?,
+        (b"FORM", _, b"AIFF" | b"AIFC") => find_chunk(file, len, 12, b"SSND", Endian::Big)?,
+        (_, b"ftyp", _) => find_atom(file, len, b"mdat")?,
+        (b"fLaC", _, _) => Some(skip_flac_metadata(file, len)?..trailing_tags_start(file, len)?),
+        _ => Some(skip_id3v2(file, len)?..trailing_tags_start(file, len)?),
+    };
+
+    // Unrecognised or truncated files are hashed whole rather than not at all.
+    Ok(range
+        .filter(|range| range.start < range.end)
+        .unwrap_or(0..len))
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+/// Finds the contents of a RIFF or IFF chunk with the given ID.
+fn find_chunk<R: Read + Seek>(
+    file: &mut R,
+    len: u64,
+    mut position: u64,
+    id: &[u8; 4],
+    endian: Endian,
+) -> std::io::Result<Option<Range<u64>>> {
+    let mut header = [0; 8];
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let size_bytes = [header[4], header[5], header[6], header[7]];
+        let size = match endian {
+            Endian::Little => u32::from_le_bytes(size_bytes),
+            Endian::Big => u32::from_be_bytes(size_bytes),
+        } as u64;
+        let start = position + 8;
+        if &header[0..4] == id {
+            return Ok(Some(start..(start + size).min(len)));
+        }
+        // Chunks are padded to an even size.
+        position = start + size + size % 2;
+    }
+    Ok(None)
+}
+
+/// Finds the contents of a top level MP4 atom with the given type.
+fn find_atom<R: Read + Seek>(
+    file: &mut R,
+    len: u64,
+    atom_type: &[u8; 4],
+) -> std::io::Result<Option<Range<u64>>> {
+    let mut position = 0;
+    let mut header = [0; 8];
+    while position + 8 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let (size, header_size) =
+            match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                // The atom extends to the end of the file.
+                0 => (len - position, 8),
+                // The size follows the type as 64 bits.
+                1 => {
+                    let mut size = [0; 8];
+                    file.read_exact(&mut size)?;
+                    (u64::from_be_bytes(size), 16)
+                }
+                size => (size as u64, 8),
+            };
+        if size < header_size {
+            return Ok(None);
+        }
+        if &header[4..8] == atom_type {
+            return Ok(Some(position + header_size..(position + size).min(len)));
+        }
+        position += size;
+    }
+    Ok(None)
+}
+
+/// The position of the first FLAC frame, after the metadata blocks holding the tags and pictures.
+fn skip_flac_metadata<R: Read + Seek>(file: &mut R, len: u64) -> std::io::Result<u64> {
+    let mut position = 4;
+    let mut header = [0; 4];
+    while position + 4 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        position += 4 + size;
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    Ok(position.min(len))
+}
+
+/// The position after any ID3v2 tags at the start of a file.
+fn skip_id3v2<R: Read + Seek>(file: &mut R, len: u64) -> std::io::Result<u64> {
+    let mut position = 0;
+    let mut header = [0; 10];
+    while position + 10 <= len {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        if &header[0..3] != b"ID3" {
+            break;
+        }
+        // Sizes are stored in 7 bits per byte, the tag has a footer when flagged.
+        let size = header[6..10]
+            .iter()
+            .fold(0, |size, byte| size << 7 | (*byte & 0x7f) as u64);
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        position += 10 + size + footer;
+    }
+    Ok(position.min(len))
+}
+
+/// The position of the first ID3v1 or APE tag at the end of a file, or its length without any.
+fn trailing_tags_start<R: Read + Seek>(file: &mut R, len: u64) -> std::io::Result<u64> {
+    let mut end = len;
+    loop {
+        if end >= ID3V1_BYTES {
+            let mut id = [0; 3];
+            file.seek(SeekFrom::Start(end - ID3V1_BYTES))?;
+            file.read_exact(&mut id)?;
+            if &id == b"TAG" {
+                end -= ID3V1_BYTES;
+                continue;
+            }
+        }
+        if end >= APE_FOOTER_BYTES {
+            let mut footer = [0; 32];
+            file.seek(SeekFrom::Start(end - APE_FOOTER_BYTES))?;
+            file.read_exact(&mut footer)?;
+            if &footer[0..8] == b"APETAGEX" {
+                // The size includes the footer but not the header, which is flagged separately.
+                let size = u32::from_le_bytes([footer[12], footer[13], footer[14], footer[15]]);
+                let header = if footer[23] & 0x80 != 0 { 32 } else { 0 };
+                end = end.saturating_sub((size as u64).max(APE_FOOTER_BYTES) + header);
+                continue;
+            }
+        }
+        return Ok(end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn range_of(bytes: &[u8]) -> Range<u64> {
+        audio_range(&mut Cursor::new(bytes), bytes.len() as u64).unwrap()
+    }
+
+    fn chunk(id: &[u8; 4], contents: &[u8], endian: Endian) -> Vec<u8> {
+        let size = contents.len() as u32;
+        let mut chunk = id.to_vec();
+        chunk.extend(match endian {
+            Endian::Little => size.to_le_bytes(),
+            Endian::Big => size.to_be_bytes(),
+        });
+        chunk.extend(contents);
+        if contents.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn id3v2(size: u8, footer: bool) -> Vec<u8> {
+        let mut tag = b"ID3\x04\x00".to_vec();
+        tag.push(if footer { 0x10 } else { 0 });
+        tag.extend([0, 0, 0, size]);
+        tag.extend(vec![0; size as usize + if footer { 10 } else { 0 }]);
+        tag
+    }
+
+    fn id3v1() -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.resize(ID3V1_BYTES as usize, b' ');
+        tag
+    }
+
+    fn ape(items: usize) -> Vec<u8> {
+        let mut tag = vec![0; items];
+        tag.extend(b"APETAGEX");
+        tag.extend(2000u32.to_le_bytes());
+        tag.extend((items as u32 + 32).to_le_bytes());
+        tag.extend([0; 16]);
+        tag
+    }
+
+    #[test]
+    fn finds_wave_data_after_padded_chunks() {
+        let mut wave = b"RIFF\0\0\0\0WAVE".to_vec();
+        wave.extend(chunk(b"LIST", b"odd", Endian::Little));
+        wave.extend(chunk(b"data", b"audio", Endian::Little));
+        assert_eq!(range_of(&wave), 32..37);
+    }
+
+    #[test]
+    fn finds_aiff_sound_data() {
+        let mut aiff = b"FORM\0\0\0\0AIFF".to_vec();
+        aiff.extend(chunk(b"COMM", &[0; 18], Endian::Big));
+        aiff.extend(chunk(b"SSND", b"audio", Endian::Big));
+        assert_eq!(range_of(&aiff), 46..51);
+    }
+
+    #[test]
+    fn finds_mp4_media_data() {
+        let mut mp4 = 16u32.to_be_bytes().to_vec();
+        mp4.extend(b"ftypM4A \0\0\0\0");
+        // A 64 bit size after the type.
+        mp4.extend(1u32.to_be_bytes());
+        mp4.extend(b"moov");
+        mp4.extend(20u64.to_be_bytes());
+        mp4.extend([0; 4]);
+        mp4.extend(13u32.to_be_bytes());
+        mp4.extend(b"mdat");
+        mp4.extend(b"audio");
+        assert_eq!(range_of(&mp4), 44..49);
+    }
+
+    #[test]
+    fn skips_flac_metadata_blocks() {
+        let mut flac = b"fLaC".to_vec();
+        flac.extend([0, 0, 0, 34]);
+        flac.extend([0; 34]);
+        // The last block, flagged in its header's top bit.
+        flac.extend([0x84, 0, 0, 4]);
+        flac.extend(b"tags");
+        flac.extend(b"audio");
+        assert_eq!(range_of(&flac), 50..55);
+    }
+
+    #[test]
+    fn skips_mp3_tags_at_both_ends() {
+        let mut mp3 = id3v2(20, false);
+        mp3.extend(id3v2(5, true));
+        let start = mp3.len() as u64;
+        mp3.extend(b"audio frames");
+        let end = mp3.len() as u64;
+        mp3.extend(ape(40));
+        mp3.extend(id3v1());
+        assert_eq!(range_of(&mp3), start..end);
+    }
+
+    #[test]
+    fn uses_whole_files_when_no_audio_data_is_found() {
+        assert_eq!(range_of(b"short"), 0..5);
+        let wave = b"RIFF\0\0\0\0WAVEfmt \x04\0\0\0abcd".to_vec();
+        assert_eq!(range_of(&wave), 0..wave.len() as u64);
+    }
+
+    #[test]
+    fn ignores_tags_and_samples_large_files() {
+        let dir = TempDir::new("content-hash");
+        let hash_of = |name: &str, bytes: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, bytes).unwrap();
+            hash(&path).unwrap()
+        };
+
+        let audio: Vec<u8> = (0..SAMPLE_BYTES * 4).map(|i| (i % 251) as u8).collect();
+        let mut tagged = id3v2(100, false);
+        tagged.extend(&audio);
+        tagged.extend(id3v1());
+        let original = hash_of("original.mp3", &audio);
+        assert_eq!(hash_of("tagged.mp3", &tagged), original);
+
+        // Only the samples are read, so a change between them goes unnoticed.
+        let mut between = audio.clone();
+        between[SAMPLE_BYTES as usize + 10] ^= 1;
+        assert_eq!(hash_of("between.mp3", &between), original);
+        let mut middle = audio.clone();
+        middle[audio.len() / 2] ^= 1;
+        assert_ne!(hash_of("middle.mp3", &middle), original);
+        assert_ne!(hash_of("shorter.mp3", &audio[1..]), original);
+    }
+}
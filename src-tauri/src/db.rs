@@ -1,9 +1,26 @@
+use std::path::Path;
+
 use rusqlite::{Connection, Row};
 
-use crate::songs::Song;
+use crate::{
+    audio::{
+        alignment::Alignment,
+        beats::{Beat, Beatgrid},
+        fingerprint::{self, Fingerprint, FingerprintIndex, FingerprintMatch, Landmark, Votes},
+        key::DetectedKey,
+        loudness::Loudness,
+        quality::{QualityReport, Verdict},
+        tempo::Tempo,
+        transitions::Transition,
+        waveform::{self, Waveform},
+    },
+    songs::{key::Key, Song},
+};
 
-/// All migrations are run when the database is initialised.
-const MIGRATIONS: [&str; 1] = ["CREATE TABLE songs (
+/// Migrations are run in order when the database is opened, the database's `user_version` is the
+/// number of migrations already run.
+const MIGRATIONS: [&str; 17] = [
+    "CREATE TABLE songs (
         file_path TEXT PRIMARY KEY,
         title TEXT,
         artist TEXT,
@@ -11,7 +28,128 @@ const MIGRATIONS: [&str; 1] = ["CREATE TABLE songs (
         genre TEXT,
         bpm FLOAT,
         duration_seconds INTEGER NOT NULL
-    )"];
+    )",
+    "CREATE TABLE song_analysis_files (
+        file_path TEXT PRIMARY KEY REFERENCES songs (file_path),
+        analysis_file_path TEXT NOT NULL
+    )",
+    "ALTER TABLE songs ADD COLUMN key TEXT;
+    ALTER TABLE songs ADD COLUMN year INTEGER;
+    ALTER TABLE songs ADD COLUMN release_date TEXT;
+    ALTER TABLE songs ADD COLUMN label TEXT;
+    ALTER TABLE songs ADD COLUMN remixer TEXT;
+    ALTER TABLE songs ADD COLUMN composer TEXT;
+    ALTER TABLE songs ADD COLUMN comment TEXT;
+    ALTER TABLE songs ADD COLUMN track_number INTEGER;
+    ALTER TABLE songs ADD COLUMN rating INTEGER;
+    ALTER TABLE songs ADD COLUMN isrc TEXT;
+    ALTER TABLE songs ADD COLUMN date_added_unix_seconds INTEGER;",
+    "ALTER TABLE songs ADD COLUMN bitrate_kbps INTEGER;
+    ALTER TABLE songs ADD COLUMN sample_rate INTEGER;
+    ALTER TABLE songs ADD COLUMN bit_depth INTEGER;
+    ALTER TABLE songs ADD COLUMN channels INTEGER;
+    ALTER TABLE songs ADD COLUMN file_type TEXT;
+    ALTER TABLE songs ADD COLUMN file_size_bytes INTEGER;
+    ALTER TABLE songs ADD COLUMN duration_ms INTEGER;",
+    "CREATE TABLE song_quality (
+        file_path TEXT PRIMARY KEY REFERENCES songs (file_path),
+        cutoff_hz FLOAT,
+        estimated_bitrate_kbps INTEGER,
+        verdict TEXT NOT NULL
+    )",
+    "ALTER TABLE songs ADD COLUMN artwork_hash TEXT",
+    "ALTER TABLE songs ADD COLUMN last_modified_unix_ms INTEGER",
+    "ALTER TABLE songs ADD COLUMN detected_bpm FLOAT;
+    ALTER TABLE songs ADD COLUMN detected_bpm_confidence FLOAT;",
+    "ALTER TABLE songs ADD COLUMN detected_key TEXT;
+    ALTER TABLE songs ADD COLUMN detected_key_confidence FLOAT;",
+    "CREATE TABLE song_beatgrids (
+        file_path TEXT PRIMARY KEY REFERENCES songs (file_path),
+        bpm FLOAT NOT NULL,
+        first_beat_ms FLOAT NOT NULL
+    );
+    CREATE TABLE song_beats (
+        file_path TEXT NOT NULL REFERENCES song_beatgrids (file_path),
+        time_ms FLOAT NOT NULL,
+        beat_number INTEGER NOT NULL
+    );
+    CREATE INDEX song_beats_file_path ON song_beats (file_path);",
+    "CREATE TABLE recording_transitions (
+        recording_path TEXT NOT NULL,
+        track_index INTEGER NOT NULL,
+        cue_seconds FLOAT NOT NULL,
+        transition_seconds FLOAT NOT NULL,
+        blend_start_seconds FLOAT NOT NULL,
+        blend_end_seconds FLOAT NOT NULL,
+        confidence FLOAT NOT NULL,
+        PRIMARY KEY (recording_path, track_index)
+    );",
+    "CREATE TABLE recording_alignments (
+        recording_path TEXT NOT NULL,
+        track_index INTEGER NOT NULL,
+        song_path TEXT NOT NULL,
+        cue_seconds FLOAT NOT NULL,
+        song_start_seconds FLOAT NOT NULL,
+        offset_seconds FLOAT NOT NULL,
+        confidence FLOAT NOT NULL,
+        tempo_change_percent FLOAT NOT NULL,
+        pitch_change_semitones FLOAT NOT NULL,
+        PRIMARY KEY (recording_path, track_index)
+    );",
+    // Landmarks are keyed by hash first so the table itself is the inverted index.
+    "CREATE TABLE song_fingerprints (
+        id INTEGER PRIMARY KEY,
+        file_path TEXT NOT NULL UNIQUE REFERENCES songs (file_path),
+        landmark_count INTEGER NOT NULL
+    );
+    CREATE TABLE fingerprint_landmarks (
+        hash INTEGER NOT NULL,
+        song_id INTEGER NOT NULL REFERENCES song_fingerprints (id),
+        frame INTEGER NOT NULL,
+        PRIMARY KEY (hash, song_id, frame)
+    ) WITHOUT ROWID;
+    CREATE INDEX fingerprint_landmarks_song_id ON fingerprint_landmarks (song_id);",
+    "ALTER TABLE songs ADD COLUMN content_hash TEXT;
+    CREATE INDEX songs_content_hash ON songs (content_hash);",
+    "ALTER TABLE recording_alignments ADD COLUMN audible_start_seconds FLOAT;",
+    "CREATE TABLE song_loudness (
+        file_path TEXT PRIMARY KEY REFERENCES songs (file_path),
+        integrated_lufs FLOAT,
+        peak_dbfs FLOAT
+    );
+    CREATE TABLE song_waveforms (
+        file_path TEXT PRIMARY KEY REFERENCES songs (file_path),
+        columns_per_second INTEGER NOT NULL,
+        columns BLOB NOT NULL
+    );",
+    "CREATE TABLE song_moves (
+        old_file_path TEXT PRIMARY KEY,
+        file_path TEXT NOT NULL
+    );",
+];
+
+/// Tables keyed by a song's file path.
+const SONG_TABLES: [&str; 8] = [
+    "songs",
+    "song_analysis_files",
+    "song_quality",
+    "song_beatgrids",
+    "song_beats",
+    "song_fingerprints",
+    "song_loudness",
+    "song_waveforms",
+];
+
+/// The columns read from song files, in the order `row_to_song` reads them.
+const SONG_COLUMNS: &str =
+    "file_path, title, artist, album, genre, bpm, duration_seconds, key, year,
+    release_date, label, remixer, composer, comment, track_number, rating, isrc,
+    date_added_unix_seconds, bitrate_kbps, sample_rate, bit_depth, channels, file_type,
+    file_size_bytes, duration_ms, artwork_hash, last_modified_unix_ms";
+/// The columns written by analysers, selected after `SONG_COLUMNS`. They're kept when a song is
+/// updated from its file.
+const SONG_ANALYSIS_COLUMNS: &str =
+    "detected_bpm, detected_bpm_confidence, detected_key, detected_key_confidence, content_hash";
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -21,29 +159,131 @@ pub enum Error {
     RowNotFound,
 }
 
-/// In memory database using sqlite, state is always wiped between session (no data is persisted).
-/// All data is created from the user's files such as songs and recordings.
+/// The library database using sqlite. Songs are read from the user's files every session, the
+/// database keeps what's expensive to work out again, like analysis results and fingerprints,
+/// and songs whose files have gone missing.
 pub struct Database {
     conn: Connection,
 }
 
 impl Database {
-    /// Creates a new in memory database and runs all migrations.
+    /// Opens (or creates) the database at `path` and runs any new migrations.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::migrate(Connection::open(path)?)
+    }
+
+    /// Creates a new in memory database and runs all migrations, nothing is kept between sessions.
     pub fn init() -> rusqlite::Result<Self> {
-        let conn = Connection::open_in_memory()?;
+        Self::migrate(Connection::open_in_memory()?)
+    }
 
-        for migration in MIGRATIONS {
-            conn.execute(migration, ())?;
+    fn migrate(conn: Connection) -> rusqlite::Result<Self> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!(
+                "BEGIN; {migration}; PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
         }
 
         Ok(Self { conn })
     }
 
+    /// Inserts a song, used when importing other libraries. If a song with the same file path
+    /// already exists only the fields it's missing are filled in, so metadata read from the file
+    /// itself takes priority over other libraries.
     pub fn insert_song(&self, song: &Song) -> rusqlite::Result<()> {
+        let updates = SONG_COLUMNS
+            .split(',')
+            .map(str::trim)
+            .filter(|column| *column != "file_path")
+            .map(|column| format!("{column} = COALESCE({column}, excluded.{column})"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.write_song(
+            &format!("ON CONFLICT (file_path) DO UPDATE SET {updates}"),
+            song,
+        )
+    }
+
+    /// Inserts a song replacing any existing song with the same file path, used after the song's
+    /// file has been changed. Analysis results are kept, the content hash only if the file hasn't
+    /// been modified since it was hashed.
+    pub fn update_song(&self, song: &Song) -> rusqlite::Result<()> {
+        let updates = SONG_COLUMNS
+            .split(',')
+            .map(str::trim)
+            .filter(|column| *column != "file_path")
+            .map(|column| format!("{column} = excluded.{column}"))
+            // Unqualified columns are the existing row's, so this compares against the modification
+            // time the song had when it was hashed.
+            .chain(std::iter::once(
+                "content_hash = CASE WHEN last_modified_unix_ms IS excluded.last_modified_unix_ms
+                    THEN content_hash END"
+                    .to_string(),
+            ))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.write_song(
+            &format!("ON CONFLICT (file_path) DO UPDATE SET {updates}"),
+            song,
+        )
+    }
+
+    /// Moves a song and everything stored about it to a new file path, then updates its metadata
+    /// from `song` read from the new file. What's stored for the old path replaces what's stored
+    /// for the new path, which is usually already a song read from the moved file.
+    pub fn relink_song(&self, old_file_path: &str, song: &Song) -> rusqlite::Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM fingerprint_landmarks
+            WHERE song_id IN (SELECT id FROM song_fingerprints WHERE file_path = ?2)
+                AND EXISTS (SELECT 1 FROM song_fingerprints WHERE file_path = ?1)",
+            (old_file_path, &song.file_path),
+        )?;
+        for table in SONG_TABLES {
+            transaction.execute(
+                &format!(
+                    "DELETE FROM {table}
+                    WHERE file_path = ?2
+                        AND EXISTS (SELECT 1 FROM {table} WHERE file_path = ?1)"
+                ),
+                (old_file_path, &song.file_path),
+            )?;
+            transaction.execute(
+                &format!("UPDATE {table} SET file_path = ?2 WHERE file_path = ?1"),
+                (old_file_path, &song.file_path),
+            )?;
+        }
+        transaction.execute(
+            "UPDATE recording_alignments SET song_path = ?2 WHERE song_path = ?1",
+            (old_file_path, &song.file_path),
+        )?;
+        // Cue sheets that still list the old path, or any path the song had before, are the song's
+        // play history, so those paths are redirected to the new one.
+        transaction.execute(
+            "UPDATE song_moves SET file_path = ?2 WHERE file_path = ?1",
+            (old_file_path, &song.file_path),
+        )?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO song_moves (old_file_path, file_path) VALUES (?1, ?2)",
+            (old_file_path, &song.file_path),
+        )?;
+        transaction.execute("DELETE FROM song_moves WHERE old_file_path = file_path", [])?;
+        self.update_song(song)?;
+
+        transaction.commit()
+    }
+
+    fn write_song(&self, on_conflict: &str, song: &Song) -> rusqlite::Result<()> {
         self.conn.execute(
-            "INSERT INTO songs (file_path, title, artist, album, genre, bpm, duration_seconds)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-            (
+            &format!(
+                "INSERT INTO songs ({SONG_COLUMNS})
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                    ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27)
+                {on_conflict}"
+            ),
+            rusqlite::params![
                 &song.file_path,
                 &song.title,
                 &song.artist,
@@ -51,18 +291,43 @@ impl Database {
                 &song.genre,
                 &song.bpm,
                 &song.duration_seconds,
-            ),
+                &song.key,
+                &song.year,
+                &song.release_date,
+                &song.label,
+                &song.remixer,
+                &song.composer,
+                &song.comment,
+                &song.track_number,
+                &song.rating,
+                &song.isrc,
+                &song.date_added_unix_seconds,
+                &song.bitrate_kbps,
+                &song.sample_rate,
+                &song.bit_depth,
+                &song.channels,
+                &song.file_type,
+                &song.file_size_bytes,
+                &song.duration_ms,
+                &song.artwork_hash,
+                &song.last_modified_unix_ms,
+            ],
         )?;
 
         Ok(())
     }
 
+    /// Gets a song by its file path, or by a path it had before it was relinked so cue sheets
+    /// listing the old path still find it.
     pub fn get_song(&self, file_path: &str) -> Result<Song, Error> {
-        let mut statement = self.conn.prepare(
-            "SELECT file_path, title, artist, album, genre, bpm, duration_seconds
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {SONG_COLUMNS}, {SONG_ANALYSIS_COLUMNS}
             FROM songs
-            WHERE file_path = ?1",
-        )?;
+            WHERE file_path = ?1
+                OR file_path = (SELECT file_path FROM song_moves WHERE old_file_path = ?1)
+            ORDER BY file_path = ?1 DESC
+            LIMIT 1"
+        ))?;
         let mut rows = statement.query([file_path])?;
 
         if let Some(row) = rows.next()? {
@@ -73,9 +338,9 @@ impl Database {
     }
 
     pub fn list_songs(&self) -> rusqlite::Result<Vec<Song>> {
-        let mut statement = self.conn.prepare(
-            "SELECT file_path, title, artist, album, genre, bpm, duration_seconds FROM songs",
-        )?;
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {SONG_COLUMNS}, {SONG_ANALYSIS_COLUMNS} FROM songs"
+        ))?;
         let mut rows = statement.query([])?;
 
         let mut songs = Vec::new();
@@ -85,9 +350,594 @@ impl Database {
 
         Ok(songs)
     }
+
+    /// Lists songs with a bitrate below `max_bitrate_kbps`, lowest first. Songs with an unknown
+    /// bitrate aren't included.
+    pub fn list_low_bitrate_songs(&self, max_bitrate_kbps: u32) -> rusqlite::Result<Vec<Song>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {SONG_COLUMNS}, {SONG_ANALYSIS_COLUMNS}
+            FROM songs
+            WHERE bitrate_kbps < ?1
+            ORDER BY bitrate_kbps"
+        ))?;
+        let mut rows = statement.query([max_bitrate_kbps])?;
+
+        let mut songs = Vec::new();
+        while let Some(row) = rows.next()? {
+            songs.push(row_to_song(row)?);
+        }
+
+        Ok(songs)
+    }
+
+    /// Links a song to the Rekordbox analysis (`.DAT`) file containing its beat grid and cues.
+    pub fn insert_song_analysis_file(
+        &self,
+        file_path: &str,
+        analysis_file_path: &str,
+    ) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO song_analysis_files (file_path, analysis_file_path)
+            VALUES (?1, ?2)",
+            (file_path, analysis_file_path),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_song_analysis_file(&self, file_path: &str) -> Result<String, Error> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT analysis_file_path FROM song_analysis_files WHERE file_path = ?1")?;
+        let mut rows = statement.query([file_path])?;
+
+        if let Some(row) = rows.next()? {
+            Ok(row.get(0)?)
+        } else {
+            Err(Error::RowNotFound)
+        }
+    }
+
+    /// Stores the tempo detected from a song's audio, `None` when no tempo could be detected.
+    pub fn set_detected_tempo(&self, file_path: &str, tempo: Option<&Tempo>) -> Result<(), Error> {
+        let updated = self.conn.execute(
+            "UPDATE songs SET detected_bpm = ?2, detected_bpm_confidence = ?3 WHERE file_path = ?1",
+            (
+                file_path,
+                tempo.map(|tempo| tempo.bpm),
+                tempo.map(|tempo| tempo.confidence),
+            ),
+        )?;
+
+        if updated == 0 {
+            return Err(Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    /// Stores the hash of a song's audio data, see `content_hash`.
+    pub fn set_content_hash(&self, file_path: &str, content_hash: &str) -> Result<(), Error> {
+        let updated = self.conn.execute(
+            "UPDATE songs SET content_hash = ?2 WHERE file_path = ?1",
+            (file_path, content_hash),
+        )?;
+
+        if updated == 0 {
+            return Err(Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    /// Lists the songs that haven't been hashed since their file was last read.
+    pub fn list_unhashed_songs(&self) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT file_path FROM songs WHERE content_hash IS NULL")?;
+        let mut rows = statement.query([])?;
+
+        let mut file_paths = Vec::new();
+        while let Some(row) = rows.next()? {
+            file_paths.push(row.get(0)?);
+        }
+
+        Ok(file_paths)
+    }
+
+    /// Stores the key detected from a song's audio, `None` when no key could be detected.
+    pub fn set_detected_key(
+        &self,
+        file_path: &str,
+        detected: Option<&DetectedKey>,
+    ) -> Result<(), Error> {
+        let updated = self.conn.execute(
+            "UPDATE songs SET detected_key = ?2, detected_key_confidence = ?3 WHERE file_path = ?1",
+            (
+                file_path,
+                detected.map(|detected| detected.key.standard()),
+                detected.map(|detected| detected.confidence),
+            ),
+        )?;
+
+        if updated == 0 {
+            return Err(Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    /// Stores a song's beatgrid, replacing any previous beatgrid for the song.
+    pub fn insert_beatgrid(&self, beatgrid: &Beatgrid) -> rusqlite::Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM song_beats WHERE file_path = ?1",
+            [&beatgrid.file_path],
+        )?;
+        transaction.execute(
+            "INSERT OR REPLACE INTO song_beatgrids (file_path, bpm, first_beat_ms)
+            VALUES (?1, ?2, ?3)",
+            (&beatgrid.file_path, beatgrid.bpm, beatgrid.first_beat_ms),
+        )?;
+
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO song_beats (file_path, time_ms, beat_number) VALUES (?1, ?2, ?3)",
+            )?;
+            for beat in &beatgrid.beats {
+                statement.execute((&beatgrid.file_path, beat.time_ms, beat.beat_number))?;
+            }
+        }
+
+        transaction.commit()
+    }
+
+    /// Removes a song's beatgrid, used when a new analysis finds no beats to follow.
+    pub fn delete_beatgrid(&self, file_path: &str) -> rusqlite::Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute("DELETE FROM song_beats WHERE file_path = ?1", [file_path])?;
+        transaction.execute(
+            "DELETE FROM song_beatgrids WHERE file_path = ?1",
+            [file_path],
+        )?;
+        transaction.commit()
+    }
+
+    pub fn get_beatgrid(&self, file_path: &str) -> Result<Beatgrid, Error> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT bpm, first_beat_ms FROM song_beatgrids WHERE file_path = ?1")?;
+        let mut rows = statement.query([file_path])?;
+        let Some(row) = rows.next()? else {
+            return Err(Error::RowNotFound);
+        };
+        let (bpm, first_beat_ms) = (row.get(0)?, row.get(1)?);
+
+        let mut statement = self.conn.prepare(
+            "SELECT time_ms, beat_number FROM song_beats WHERE file_path = ?1 ORDER BY time_ms",
+        )?;
+        let mut rows = statement.query([file_path])?;
+
+        let mut beats = Vec::new();
+        while let Some(row) = rows.next()? {
+            beats.push(Beat {
+                time_ms: row.get(0)?,
+                beat_number: row.get(1)?,
+            });
+        }
+
+        Ok(Beatgrid {
+            file_path: file_path.to_string(),
+            bpm,
+            first_beat_ms,
+            beats,
+        })
+    }
+
+    /// Stores the loudness of a song, replacing any previous measurement.
+    pub fn insert_loudness(&self, loudness: &Loudness) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO song_loudness (file_path, integrated_lufs, peak_dbfs)
+            VALUES (?1, ?2, ?3)",
+            (
+                &loudness.file_path,
+                loudness.integrated_lufs,
+                loudness.peak_dbfs,
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_loudness(&self, file_path: &str) -> Result<Loudness, Error> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT integrated_lufs, peak_dbfs FROM song_loudness WHERE file_path = ?1")?;
+        let mut rows = statement.query([file_path])?;
+        let Some(row) = rows.next()? else {
+            return Err(Error::RowNotFound);
+        };
+
+        Ok(Loudness {
+            file_path: file_path.to_string(),
+            integrated_lufs: row.get(0)?,
+            peak_dbfs: row.get(1)?,
+        })
+    }
+
+    /// Stores the waveform of a song, replacing any previous waveform.
+    pub fn insert_waveform(&self, waveform: &Waveform) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO song_waveforms (file_path, columns_per_second, columns)
+            VALUES (?1, ?2, ?3)",
+            (
+                &waveform.file_path,
+                waveform.columns_per_second,
+                waveform::to_bytes(&waveform.columns),
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_waveform(&self, file_path: &str) -> Result<Waveform, Error> {
+        let mut statement = self.conn.prepare(
+            "SELECT columns_per_second, columns FROM song_waveforms WHERE file_path = ?1",
+        )?;
+        let mut rows = statement.query([file_path])?;
+        let Some(row) = rows.next()? else {
+            return Err(Error::RowNotFound);
+        };
+
+        Ok(Waveform {
+            file_path: file_path.to_string(),
+            columns_per_second: row.get(0)?,
+            columns: waveform::from_bytes(&row.get::<_, Vec<u8>>(1)?),
+        })
+    }
+
+    /// Stores the transitions detected in a recording, replacing any previously detected.
+    pub fn insert_transitions(
+        &self,
+        recording_path: &str,
+        transitions: &[Transition],
+    ) -> rusqlite::Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM recording_transitions WHERE recording_path = ?1",
+            [recording_path],
+        )?;
+
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO recording_transitions (recording_path, track_index, cue_seconds,
+                transition_seconds, blend_start_seconds, blend_end_seconds, confidence)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for transition in transitions {
+                statement.execute((
+                    recording_path,
+                    transition.track_index,
+                    transition.cue_seconds,
+                    transition.transition_seconds,
+                    transition.blend_start_seconds,
+                    transition.blend_end_seconds,
+                    transition.confidence,
+                ))?;
+            }
+        }
+
+        transaction.commit()
+    }
+
+    /// The stored transitions of a recording in track order, empty when they haven't been
+    /// detected.
+    pub fn list_transitions(&self, recording_path: &str) -> rusqlite::Result<Vec<Transition>> {
+        let mut statement = self.conn.prepare(
+            "SELECT track_index, cue_seconds, transition_seconds, blend_start_seconds,
+            blend_end_seconds, confidence
+            FROM recording_transitions WHERE recording_path = ?1 ORDER BY track_index",
+        )?;
+        let mut rows = statement.query([recording_path])?;
+
+        let mut transitions = Vec::new();
+        while let Some(row) = rows.next()? {
+            transitions.push(Transition {
+                track_index: row.get(0)?,
+                cue_seconds: row.get(1)?,
+                transition_seconds: row.get(2)?,
+                blend_start_seconds: row.get(3)?,
+                blend_end_seconds: row.get(4)?,
+                confidence: row.get(5)?,
+            });
+        }
+
+        Ok(transitions)
+    }
+
+    /// Stores where the songs of a recording were found, replacing any previous alignments.
+    pub fn insert_alignments(
+        &self,
+        recording_path: &str,
+        alignments: &[Alignment],
+    ) -> rusqlite::Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM recording_alignments WHERE recording_path = ?1",
+            [recording_path],
+        )?;
+
+        {
+            let mut statement = transaction.prepare(
+                "INSERT INTO recording_alignments (recording_path, track_index, song_path,
+                cue_seconds, song_start_seconds, offset_seconds, confidence, tempo_change_percent,
+                pitch_change_semitones, audible_start_seconds)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for alignment in alignments {
+                statement.execute((
+                    recording_path,
+                    alignment.track_index,
+                    &alignment.song_path,
+                    alignment.cue_seconds,
+                    alignment.song_start_seconds,
+                    alignment.offset_seconds,
+                    alignment.confidence,
+                    alignment.tempo_change_percent,
+                    alignment.pitch_change_semitones,
+                    alignment.audible_start_seconds,
+                ))?;
+            }
+        }
+
+        transaction.commit()
+    }
+
+    /// The stored alignments of a recording in track order, empty when it hasn't been aligned.
+    pub fn list_alignments(&self, recording_path: &str) -> rusqlite::Result<Vec<Alignment>> {
+        let mut statement = self.conn.prepare(
+            "SELECT track_index, song_path, cue_seconds, song_start_seconds, offset_seconds,
+            confidence, tempo_change_percent, pitch_change_semitones,
+            COALESCE(audible_start_seconds, song_start_seconds)
+            FROM recording_alignments WHERE recording_path = ?1 ORDER BY track_index",
+        )?;
+        let mut rows = statement.query([recording_path])?;
+
+        let mut alignments = Vec::new();
+        while let Some(row) = rows.next()? {
+            alignments.push(Alignment {
+                track_index: row.get(0)?,
+                song_path: row.get(1)?,
+                cue_seconds: row.get(2)?,
+                song_start_seconds: row.get(3)?,
+                audible_start_seconds: row.get(8)?,
+                offset_seconds: row.get(4)?,
+                confidence: row.get(5)?,
+                tempo_change_percent: row.get(6)?,
+                pitch_change_semitones: row.get(7)?,
+            });
+        }
+
+        Ok(alignments)
+    }
+
+    /// Stores the fingerprint of a song, replacing any previous fingerprint.
+    pub fn insert_fingerprint(
+        &self,
+        file_path: &str,
+        fingerprint: &Fingerprint,
+    ) -> rusqlite::Result<()> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM fingerprint_landmarks WHERE song_id IN
+            (SELECT id FROM song_fingerprints WHERE file_path = ?1)",
+            [file_path],
+        )?;
+        transaction.execute(
+            "DELETE FROM song_fingerprints WHERE file_path = ?1",
+            [file_path],
+        )?;
+        transaction.execute(
+            "INSERT INTO song_fingerprints (file_path, landmark_count) VALUES (?1, 0)",
+            [file_path],
+        )?;
+        let song_id = transaction.last_insert_rowid();
+
+        // Landmarks repeated at the same frame are only stored once, so only those stored count.
+        let mut landmark_count = 0;
+        {
+            let mut statement = transaction.prepare(
+                "INSERT OR IGNORE INTO fingerprint_landmarks (hash, song_id, frame)
+                VALUES (?1, ?2, ?3)",
+            )?;
+            for landmark in &fingerprint.landmarks {
+                landmark_count += statement.execute((landmark.hash, song_id, landmark.frame))?;
+            }
+        }
+        transaction.execute(
+            "UPDATE song_fingerprints SET landmark_count = ?2 WHERE id = ?1",
+            (song_id, landmark_count),
+        )?;
+
+        transaction.commit()
+    }
+
+    /// The file paths of every song with a stored fingerprint.
+    pub fn list_fingerprinted_songs(&self) -> rusqlite::Result<Vec<String>> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT file_path FROM song_fingerprints")?;
+        let mut rows = statement.query([])?;
+
+        let mut file_paths = Vec::new();
+        while let Some(row) = rows.next()? {
+            file_paths.push(row.get(0)?);
+        }
+
+        Ok(file_paths)
+    }
+
+    pub fn get_fingerprint(&self, file_path: &str) -> Result<Fingerprint, Error> {
+        let mut statement = self
+            .conn
+            .prepare("SELECT id FROM song_fingerprints WHERE file_path = ?1")?;
+        let mut rows = statement.query([file_path])?;
+        let Some(row) = rows.next()? else {
+            return Err(Error::RowNotFound);
+        };
+        let song_id: i64 = row.get(0)?;
+
+        let mut statement = self.conn.prepare(
+            "SELECT hash, frame FROM fingerprint_landmarks WHERE song_id = ?1 ORDER BY frame",
+        )?;
+        let mut rows = statement.query([song_id])?;
+
+        let mut landmarks = Vec::new();
+        while let Some(row) = rows.next()? {
+            landmarks.push(Landmark {
+                hash: row.get(0)?,
+                frame: row.get(1)?,
+            });
+        }
+
+        Ok(Fingerprint { landmarks })
+    }
+
+    /// Loads every stored fingerprint into memory, for matching many excerpts at once.
+    pub fn load_fingerprint_index(&self) -> rusqlite::Result<FingerprintIndex> {
+        let mut statement = self.conn.prepare(
+            "SELECT f.file_path, l.hash, l.frame FROM fingerprint_landmarks l
+            JOIN song_fingerprints f ON f.id = l.song_id ORDER BY l.song_id, l.frame",
+        )?;
+        let mut rows = statement.query([])?;
+
+        let mut index = FingerprintIndex::default();
+        let mut song: Option<(String, Vec<Landmark>)> = None;
+        while let Some(row) = rows.next()? {
+            let file_path: String = row.get(0)?;
+            let landmark = Landmark {
+                hash: row.get(1)?,
+                frame: row.get(2)?,
+            };
+            match &mut song {
+                Some((current, landmarks)) if *current == file_path => landmarks.push(landmark),
+                _ => {
+                    if let Some((file_path, landmarks)) = song.take() {
+                        index.insert(&file_path, landmarks);
+                    }
+                    song = Some((file_path, vec![landmark]));
+                }
+            }
+        }
+        if let Some((file_path, landmarks)) = song {
+            index.insert(&file_path, landmarks);
+        }
+
+        Ok(index)
+    }
+
+    /// Finds the songs sharing landmarks with an excerpt, see `FingerprintIndex::query`. The
+    /// excerpt's hashes are looked up together by joining a temporary table of them against the
+    /// index.
+    pub fn query_fingerprint(
+        &self,
+        landmarks: &[Landmark],
+    ) -> rusqlite::Result<Vec<FingerprintMatch>> {
+        let transaction = self.conn.unchecked_transaction()?;
+        transaction.execute(
+            "CREATE TEMP TABLE IF NOT EXISTS fingerprint_query (
+                landmark INTEGER NOT NULL,
+                hash INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        transaction.execute("DELETE FROM temp.fingerprint_query", [])?;
+        {
+            let mut statement = transaction
+                .prepare("INSERT INTO temp.fingerprint_query (landmark, hash) VALUES (?1, ?2)")?;
+            for (i, landmark) in landmarks.iter().enumerate() {
+                for hash in fingerprint::hash_variants(landmark.hash) {
+                    statement.execute((i, hash))?;
+                }
+            }
+        }
+
+        let mut votes = Votes::default();
+        {
+            let mut statement = transaction.prepare(
+                "SELECT q.landmark, l.song_id, l.frame FROM temp.fingerprint_query q
+                JOIN fingerprint_landmarks l ON l.hash = q.hash ORDER BY q.landmark",
+            )?;
+            let mut rows = statement.query([])?;
+            let mut postings = Vec::new();
+            let mut current = None;
+            while let Some(row) = rows.next()? {
+                let i: usize = row.get(0)?;
+                if current != Some(i) {
+                    if let Some(current) = current {
+                        votes.add(&landmarks[current], postings.drain(..));
+                    }
+                    current = Some(i);
+                }
+                postings.push((row.get(1)?, row.get(2)?));
+            }
+            if let Some(current) = current {
+                votes.add(&landmarks[current], postings.drain(..));
+            }
+        }
+        transaction.execute("DELETE FROM temp.fingerprint_query", [])?;
+        transaction.commit()?;
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT file_path FROM song_fingerprints WHERE id = ?1")?;
+        votes
+            .finish()
+            .into_iter()
+            .map(|(song_id, offset_seconds, score)| {
+                Ok(FingerprintMatch {
+                    song_path: statement.query_row([song_id], |row| row.get(0))?,
+                    offset_seconds,
+                    score,
+                })
+            })
+            .collect()
+    }
+
+    pub fn insert_quality_report(&self, report: &QualityReport) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO song_quality (file_path, cutoff_hz, estimated_bitrate_kbps, verdict)
+            VALUES (?1, ?2, ?3, ?4)",
+            (
+                &report.file_path,
+                &report.cutoff_hz,
+                &report.estimated_bitrate_kbps,
+                report.verdict.as_str(),
+            ),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn list_quality_reports(&self) -> rusqlite::Result<Vec<QualityReport>> {
+        let mut statement = self.conn.prepare(
+            "SELECT file_path, cutoff_hz, estimated_bitrate_kbps, verdict FROM song_quality",
+        )?;
+        let mut rows = statement.query([])?;
+
+        let mut reports = Vec::new();
+        while let Some(row) = rows.next()? {
+            reports.push(QualityReport {
+                file_path: row.get(0)?,
+                cutoff_hz: row.get(1)?,
+                estimated_bitrate_kbps: row.get(2)?,
+                verdict: Verdict::parse(&row.get::<_, String>(3)?),
+            });
+        }
+
+        Ok(reports)
+    }
 }
 
 fn row_to_song(row: &Row) -> rusqlite::Result<Song> {
+    let key: Option<String> = row.get(7)?;
     Ok(Song {
         file_path: row.get(0)?,
         title: row.get(1)?,
@@ -96,5 +946,293 @@ fn row_to_song(row: &Row) -> rusqlite::Result<Song> {
         genre: row.get(4)?,
         bpm: row.get(5)?,
         duration_seconds: row.get(6)?,
+        parsed_key: key.as_deref().and_then(Key::parse),
+        key,
+        year: row.get(8)?,
+        release_date: row.get(9)?,
+        label: row.get(10)?,
+        remixer: row.get(11)?,
+        composer: row.get(12)?,
+        comment: row.get(13)?,
+        track_number: row.get(14)?,
+        rating: row.get(15)?,
+        isrc: row.get(16)?,
+        date_added_unix_seconds: row.get(17)?,
+        bitrate_kbps: row.get(18)?,
+        sample_rate: row.get(19)?,
+        bit_depth: row.get(20)?,
+        channels: row.get(21)?,
+        file_type: row.get(22)?,
+        file_size_bytes: row.get(23)?,
+        duration_ms: row.get(24)?,
+        artwork_hash: row.get(25)?,
+        last_modified_unix_ms: row.get(26)?,
+        detected_bpm: row.get(27)?,
+        detected_bpm_confidence: row.get(28)?,
+        detected_key: row
+            .get::<_, Option<String>>(29)?
+            .as_deref()
+            .and_then(Key::parse),
+        detected_key_confidence: row.get(30)?,
+        content_hash: row.get(31)?,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn keeps_analysis_when_reopened_and_songs_are_updated() {
+        let dir = TempDir::new("db");
+        let path = dir.join("library.sqlite");
+        let song = Song {
+            file_path: "/music/song.mp3".to_string(),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+
+        {
+            let database = Database::open(&path).unwrap();
+            database.insert_song(&song).unwrap();
+            let tempo = Tempo {
+                bpm: 124.0,
+                confidence: 0.8,
+            };
+            database
+                .set_detected_tempo(&song.file_path, Some(&tempo))
+                .unwrap();
+        }
+
+        let database = Database::open(&path).unwrap();
+        database
+            .update_song(&Song {
+                title: Some("Song (Retagged)".to_string()),
+                ..song.clone()
+            })
+            .unwrap();
+        let stored = database.get_song(&song.file_path).unwrap();
+        assert_eq!(stored.title.as_deref(), Some("Song (Retagged)"));
+        assert_eq!(stored.detected_bpm, Some(124.0));
+    }
+
+    #[test]
+    fn fills_in_missing_fields_from_imported_songs() {
+        let database = Database::init().unwrap();
+        database
+            .update_song(&Song {
+                file_path: "/music/song.mp3".to_string(),
+                title: Some("Song".to_string()),
+                bpm: Some(124.0),
+                ..Default::default()
+            })
+            .unwrap();
+
+        database
+            .insert_song(&Song {
+                file_path: "/music/song.mp3".to_string(),
+                title: Some("Song (Imported)".to_string()),
+                bpm: Some(125.0),
+                key: Some("Am".to_string()),
+                rating: Some(4),
+                genre: Some("House".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+
+        let stored = database.get_song("/music/song.mp3").unwrap();
+        assert_eq!(stored.title.as_deref(), Some("Song"));
+        assert_eq!(stored.bpm, Some(124.0));
+        assert_eq!(stored.key.as_deref(), Some("Am"));
+        assert_eq!(stored.rating, Some(4));
+        assert_eq!(stored.genre.as_deref(), Some("House"));
+    }
+
+    #[test]
+    fn keeps_content_hashes_until_files_are_modified() {
+        let database = Database::init().unwrap();
+        let song = Song {
+            file_path: "/music/song.mp3".to_string(),
+            last_modified_unix_ms: Some(1000),
+            ..Default::default()
+        };
+        database.insert_song(&song).unwrap();
+        database.set_content_hash(&song.file_path, "abc").unwrap();
+
+        database.update_song(&song).unwrap();
+        let stored = database.get_song(&song.file_path).unwrap();
+        assert_eq!(stored.content_hash.as_deref(), Some("abc"));
+        assert!(database.list_unhashed_songs().unwrap().is_empty());
+
+        database
+            .update_song(&Song {
+                last_modified_unix_ms: Some(2000),
+                ..song.clone()
+            })
+            .unwrap();
+        let stored = database.get_song(&song.file_path).unwrap();
+        assert_eq!(stored.content_hash, None);
+        assert_eq!(
+            database.list_unhashed_songs().unwrap(),
+            vec![song.file_path.clone()]
+        );
+    }
+
+    #[test]
+    fn finds_relinked_songs_by_their_old_paths() {
+        let database = Database::init().unwrap();
+        let song = |file_path: &str| Song {
+            file_path: file_path.to_string(),
+            ..Default::default()
+        };
+        database.insert_song(&song("/music/a.mp3")).unwrap();
+        database
+            .relink_song("/music/a.mp3", &song("/music/b.mp3"))
+            .unwrap();
+        database
+            .relink_song("/music/b.mp3", &song("/music/c.mp3"))
+            .unwrap();
+
+        for old_file_path in ["/music/a.mp3", "/music/b.mp3", "/music/c.mp3"] {
+            let found = database.get_song(old_file_path).unwrap();
+            assert_eq!(found.file_path, "/music/c.mp3");
+        }
+
+        // A song moved back to where it was isn't redirected away from its own path.
+        database
+            .relink_song("/music/c.mp3", &song("/music/a.mp3"))
+            .unwrap();
+        assert_eq!(
+            database.get_song("/music/c.mp3").unwrap().file_path,
+            "/music/a.mp3"
+        );
+        assert_eq!(
+            database.get_song("/music/a.mp3").unwrap().file_path,
+            "/music/a.mp3"
+        );
+    }
+
+    #[test]
+    fn relinks_songs_onto_songs_read_from_the_moved_file() {
+        let database = Database::init().unwrap();
+        let old = Song {
+            file_path: "/old/song.mp3".to_string(),
+            ..Default::default()
+        };
+        let moved = Song {
+            file_path: "/new/song.mp3".to_string(),
+            title: Some("Song".to_string()),
+            ..Default::default()
+        };
+        database.insert_song(&old).unwrap();
+        database.insert_song(&moved).unwrap();
+        database
+            .set_detected_tempo(
+                &old.file_path,
+                Some(&Tempo {
+                    bpm: 124.0,
+                    confidence: 0.8,
+                }),
+            )
+            .unwrap();
+        database
+            .insert_fingerprint(
+                &old.file_path,
+                &Fingerprint {
+                    landmarks: landmarks(0, 5),
+                },
+            )
+            .unwrap();
+        database
+            .insert_fingerprint(
+                &moved.file_path,
+                &Fingerprint {
+                    landmarks: landmarks(0, 8),
+                },
+            )
+            .unwrap();
+
+        database.relink_song(&old.file_path, &moved).unwrap();
+        let songs = database.list_songs().unwrap();
+        assert_eq!(songs.len(), 1);
+        assert_eq!(songs[0].title.as_deref(), Some("Song"));
+        assert_eq!(songs[0].detected_bpm, Some(124.0));
+        let fingerprint = database.get_fingerprint(&moved.file_path).unwrap();
+        assert_eq!(fingerprint.landmarks.len(), 5);
+        let landmark_count: usize = database
+            .conn
+            .query_row("SELECT COUNT(*) FROM fingerprint_landmarks", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(landmark_count, 5);
+    }
+
+    /// Landmarks every 10 frames starting at `first_frame`, with hashes far enough apart that
+    /// none is a variant of another.
+    fn landmarks(first_frame: u32, count: u32) -> Vec<Landmark> {
+        (0..count)
+            .map(|i| Landmark {
+                hash: (i * 3 + 1) << 12 | 32 << 6 | 10,
+                frame: first_frame + i * 10,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counts_stored_landmarks() {
+        let database = Database::init().unwrap();
+        let mut landmarks = landmarks(0, 20);
+        landmarks.extend(landmarks.clone());
+        database
+            .insert_fingerprint("/music/song.mp3", &Fingerprint { landmarks })
+            .unwrap();
+
+        let landmark_count: usize = database
+            .conn
+            .query_row("SELECT landmark_count FROM song_fingerprints", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(landmark_count, 20);
+    }
+
+    #[test]
+    fn queries_fingerprints_by_excerpt() {
+        let database = Database::init().unwrap();
+        database
+            .insert_fingerprint(
+                "/music/song.mp3",
+                &Fingerprint {
+                    landmarks: landmarks(500, 40),
+                },
+            )
+            .unwrap();
+        database
+            .insert_fingerprint(
+                "/music/other.mp3",
+                &Fingerprint {
+                    landmarks: landmarks(0, 5),
+                },
+            )
+            .unwrap();
+
+        // The excerpt starts 600 frames into the song.
+        let excerpt: Vec<Landmark> = landmarks(500, 40)[10..30]
+            .iter()
+            .map(|landmark| Landmark {
+                hash: landmark.hash,
+                frame: landmark.frame - 600,
+            })
+            .collect();
+        let matches = database.query_fingerprint(&excerpt).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].song_path, "/music/song.mp3");
+        assert_eq!(matches[0].score, 20);
+        assert!((matches[0].offset_seconds + 600.0 * fingerprint::FRAME_SECONDS).abs() < 1e-9);
+
+        // Querying again gives the same result, the temporary table is cleared between queries.
+        assert_eq!(database.query_fingerprint(&excerpt).unwrap(), matches);
+    }
+}
@@ -0,0 +1,542 @@
+//! Read only importer for Engine DJ libraries (the `Engine Library` directory on a computer or
+//! drive).
+//!
+//! The library is the SQLite database `Database2/m.db`, track paths are relative to the
+//! `Engine Library` directory. Hot cues are stored per track in `PerformanceData` as Qt
+//! compressed blobs (a big endian length followed by a zlib stream). Play history is kept in
+//! `Historylist` tables, which depending on the Engine version are in `m.db` or `hm.db`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use flate2::read::ZlibDecoder;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::Serialize;
+
+use crate::{
+    recording::{self, Recording},
+    songs::{key::Key, Song},
+};
+
+/// Engine DJ stores keys as an index into the circle of fifths alternating major and minor.
+const KEYS: [&str; 24] = [
+    "C", "Am", "G", "Em", "D", "Bm", "A", "F#m", "E", "C#m", "B", "G#m", "F#", "D#m", "Db", "Bbm",
+    "Ab", "Fm", "Eb", "Cm", "Bb", "Gm", "F", "Dm",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("rusqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("no Engine DJ database found in {0}")]
+    NotFound(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cue {
+    /// The hot cue slot starting at 0.
+    pub index: u32,
+    pub position_ms: f64,
+    pub name: Option<String>,
+    pub color: [u8; 3],
+}
+
+/// An Engine DJ track converted to a song along with the Engine specific analysis.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Track {
+    pub song: Song,
+    pub key: Option<String>,
+    pub cues: Vec<Cue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Playlist {
+    pub name: String,
+    /// The names of the parent playlists from the top level down.
+    pub folders: Vec<String>,
+    pub song_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Library {
+    pub tracks: Vec<Track>,
+    pub playlists: Vec<Playlist>,
+    /// One recording per history session.
+    pub history: Vec<Recording>,
+}
+
+/// Reads the library from an `Engine Library` directory, the databases are opened read only.
+pub fn read_library(library_dir: &str) -> Result<Library, Error> {
+    let library_dir = Path::new(library_dir);
+    let database_path = library_dir.join("Database2").join("m.db");
+    if !database_path.exists() {
+        return Err(Error::NotFound(library_dir.to_string_lossy().into_owned()));
+    }
+    let conn = Connection::open_with_flags(&database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let mut tracks = read_tracks(&conn, library_dir)?;
+    for (track_id, cues) in read_cues(&conn)? {
+        if let Some(track) = tracks.get_mut(&track_id) {
+            track.cues = cues;
+        }
+    }
+
+    let playlists = read_playlists(&conn, &tracks)?;
+
+    // Older versions keep the history in the main database, newer ones in a separate file.
+    let history_path = library_dir.join("Database2").join("hm.db");
+    let history = if has_table(&conn, "HistorylistEntity")? {
+        read_history(&conn, &database_path, &tracks)?
+    } else if history_path.exists() {
+        let history_conn =
+            Connection::open_with_flags(&history_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        read_history(&history_conn, &history_path, &tracks)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Library {
+        tracks: tracks.into_values().collect(),
+        playlists,
+        history,
+    })
+}
+
+fn read_tracks(conn: &Connection, library_dir: &Path) -> rusqlite::Result<HashMap<i64, Track>> {
+    let mut statement = conn.prepare(
+        "SELECT id, path, title, artist, album, genre, bpmAnalyzed, bpm, length, key
+        FROM Track
+        WHERE path IS NOT NULL",
+    )?;
+
+    let rows = statement.query_map([], |row| {
+        let path: String = row.get(1)?;
+        let bpm_analyzed: Option<f64> = row.get(6)?;
+        let bpm: Option<f64> = row.get(7)?;
+        let length: Option<f64> = row.get(8)?;
+        let key = row
+            .get::<_, Option<i64>>(9)?
+            .and_then(|key| usize::try_from(key).ok())
+            .and_then(|key| KEYS.get(key))
+            .map(|key| key.to_string());
+
+        Ok((
+            row.get(0)?,
+            Track {
+                song: Song {
+                    file_path: resolve_path(library_dir, &path),
+                    title: row.get(2)?,
+                    artist: row.get(3)?,
+                    album: row.get(4)?,
+                    genre: row.get(5)?,
+                    bpm: bpm_analyzed.or(bpm).filter(|bpm| *bpm > 0.0),
+                    duration_seconds: length.unwrap_or(0.0) as u64,
+                    key: key.clone(),
+                    parsed_key: key.as_deref().and_then(Key::parse),
+                    ..Default::default()
+                },
+                key,
+                cues: Vec::new(),
+            },
+        ))
+    })?;
+
+    rows.collect()
+}
+
+/// Track paths are relative to the library directory (usually starting with `../`), they're
+/// normalised without touching the file system since the drive may not be mounted.
+fn resolve_path(library_dir: &Path, path: &str) -> String {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.to_string_lossy().into_owned();
+    }
+
+    let mut resolved = PathBuf::new();
+    for component in library_dir.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            component => resolved.push(component),
+        }
+    }
+    resolved.to_string_lossy().into_owned()
+}
+
+fn read_cues(conn: &Connection) -> rusqlite::Result<Vec<(i64, Vec<Cue>)>> {
+    let mut statement = conn.prepare(
+        "SELECT trackId, trackData, quickCues
+        FROM PerformanceData
+        WHERE quickCues IS NOT NULL",
+    )?;
+
+    let rows = statement.query_map([], |row| {
+        let track_data: Option<Vec<u8>> = row.get(1)?;
+        let quick_cues: Vec<u8> = row.get(2)?;
+        Ok((row.get(0)?, track_data, quick_cues))
+    })?;
+
+    let mut cues = Vec::new();
+    for row in rows {
+        let (track_id, track_data, quick_cues) = row?;
+
+        // Track data starts with the sample rate, cue positions are in samples.
+        let sample_rate = track_data
+            .as_deref()
+            .and_then(decompress)
+            .and_then(|data| read_f64(&data, 0))
+            .filter(|sample_rate| *sample_rate > 0.0);
+        let (Some(sample_rate), Some(quick_cues)) = (sample_rate, decompress(&quick_cues)) else {
+            tracing::debug!(track_id, "failed to decode engine dj performance data");
+            continue;
+        };
+
+        cues.push((track_id, parse_quick_cues(&quick_cues, sample_rate)));
+    }
+
+    Ok(cues)
+}
+
+/// Quick cues are a cue count followed by each cue's name (length prefixed), position in samples
+/// (-1 when the slot is empty) and ARGB colour.
+fn parse_quick_cues(data: &[u8], sample_rate: f64) -> Vec<Cue> {
+    let Some(count) = read_u64(data, 0) else {
+        return Vec::new();
+    };
+
+    let mut cues = Vec::new();
+    let mut offset = 8;
+    for index in 0..count as u32 {
+        let Some(&name_len) = data.get(offset) else {
+            break;
+        };
+        let name_end = offset + 1 + name_len as usize;
+        let Some(name) = data.get(offset + 1..name_end) else {
+            break;
+        };
+        let (Some(position), Some(argb)) = (
+            read_f64(data, name_end),
+            data.get(name_end + 8..name_end + 12),
+        ) else {
+            break;
+        };
+        offset = name_end + 12;
+
+        if position < 0.0 {
+            continue;
+        }
+        cues.push(Cue {
+            index,
+            position_ms: position / sample_rate * 1000.0,
+            name: Some(String::from_utf8_lossy(name).into_owned()).filter(|name| !name.is_empty()),
+            color: [argb[1], argb[2], argb[3]],
+        });
+    }
+
+    cues
+}
+
+/// Playlist entries form a linked list through `nextEntityId`, the first entry is the one no
+/// other entry points to.
+fn read_playlists(
+    conn: &Connection,
+    tracks: &HashMap<i64, Track>,
+) -> rusqlite::Result<Vec<Playlist>> {
+    let mut statement = conn.prepare("SELECT id, title, parentListId FROM Playlist")?;
+    let lists: HashMap<i64, (String, i64)> = statement
+        .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut statement =
+        conn.prepare("SELECT id, listId, trackId, nextEntityId FROM PlaylistEntity")?;
+    let mut entities: HashMap<i64, Vec<(i64, i64, i64)>> = HashMap::new();
+    for row in statement.query_map([], |row| {
+        Ok((row.get(1)?, (row.get(0)?, row.get(2)?, row.get(3)?)))
+    })? {
+        let (list_id, entity) = row?;
+        entities.entry(list_id).or_default().push(entity);
+    }
+
+    let mut playlists: Vec<Playlist> = lists
+        .iter()
+        .filter_map(|(list_id, (name, parent_id))| {
+            let entities = entities.get(list_id)?;
+            let track_ids: HashMap<i64, (i64, i64)> = entities
+                .iter()
+                .map(|(id, track_id, next_id)| (*id, (*track_id, *next_id)))
+                .collect();
+            let next_ids: HashSet<i64> = entities.iter().map(|(_, _, next_id)| *next_id).collect();
+
+            let mut song_paths = Vec::new();
+            let mut current = entities
+                .iter()
+                .find(|(id, _, _)| !next_ids.contains(id))
+                .map(|(id, _, _)| *id);
+            // The length guard stops a malformed list from looping forever.
+            while let Some(id) = current.filter(|_| song_paths.len() < entities.len()) {
+                let Some((track_id, next_id)) = track_ids.get(&id) else {
+                    break;
+                };
+                if let Some(track) = tracks.get(track_id) {
+                    song_paths.push(track.song.file_path.clone());
+                }
+                current = Some(*next_id).filter(|next_id| *next_id != 0);
+            }
+
+            let mut folders = Vec::new();
+            let mut parent_id = *parent_id;
+            while let Some((parent_name, grandparent_id)) = lists.get(&parent_id) {
+                if folders.len() >= lists.len() {
+                    break;
+                }
+                folders.insert(0, parent_name.clone());
+                parent_id = *grandparent_id;
+            }
+
+            Some(Playlist {
+                name: name.clone(),
+                folders,
+                song_paths,
+            })
+        })
+        .collect();
+
+    playlists.sort_by(|a, b| (&a.folders, &a.name).cmp(&(&b.folders, &b.name)));
+    Ok(playlists)
+}
+
+/// Converts each history list to a recording, track start times are relative to the first track
+/// played.
+fn read_history(
+    conn: &Connection,
+    database_path: &Path,
+    tracks: &HashMap<i64, Track>,
+) -> rusqlite::Result<Vec<Recording>> {
+    let mut statement = conn.prepare(
+        "SELECT Historylist.id, Historylist.title, HistorylistEntity.trackId,
+            HistorylistEntity.startTime
+        FROM Historylist
+        JOIN HistorylistEntity ON HistorylistEntity.listId = Historylist.id
+        ORDER BY Historylist.id, HistorylistEntity.startTime, HistorylistEntity.id",
+    )?;
+
+    let mut recordings: Vec<(i64, i64, Recording)> = Vec::new();
+    let mut rows = statement.query([])?;
+
+    while let Some(row) = rows.next()? {
+        let list_id: i64 = row.get(0)?;
+        let played_at: i64 = row.get::<_, Option<i64>>(3)?.unwrap_or(0);
+
+        if recordings.last().map(|(id, _, _)| *id) != Some(list_id) {
+            recordings.push((
+                list_id,
+                played_at,
+                Recording {
+                    // History isn't stored in its own file, the list id keeps paths unique.
+                    file_path: format!("{}#{list_id}", database_path.to_string_lossy()),
+                    title: row.get(1)?,
+                    ..Default::default()
+                },
+            ));
+        }
+        let Some((_, first_played_at, recording)) = recordings.last_mut() else {
+            continue;
+        };
+
+        let track = tracks.get(&row.get(2)?);
+        recording.last_modified_unix_seconds = recording.last_modified_unix_seconds.max(played_at);
+        recording.tracks.push(recording::Track {
+            title: track.and_then(|track| track.song.title.clone()),
+            performer: track.and_then(|track| track.song.artist.clone()),
+            file: track.map(|track| recording::File::from_path(&track.song.file_path)),
+            start_time: Some(recording::format_start_time(
+                (played_at - *first_played_at).max(0) as u64,
+            )),
+        });
+    }
+
+    Ok(recordings
+        .into_iter()
+        .map(|(_, _, recording)| recording)
+        .collect())
+}
+
+fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Decompresses a blob written by Qt's `qCompress`, a 4 byte big endian length followed by zlib
+/// data.
+fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(data.get(4..)?)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    Some(decompressed)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn read_f64(data: &[u8], offset: usize) -> Option<f64> {
+    read_u64(data, offset).map(f64::from_bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    const SCHEMA: &str = "
+        CREATE TABLE Track (
+            id INTEGER PRIMARY KEY, path TEXT, title TEXT, artist TEXT, album TEXT, genre TEXT,
+            bpmAnalyzed REAL, bpm INTEGER, length INTEGER, key INTEGER
+        );
+        CREATE TABLE PerformanceData (trackId INTEGER, trackData BLOB, quickCues BLOB);
+        CREATE TABLE Playlist (id INTEGER PRIMARY KEY, title TEXT, parentListId INTEGER);
+        CREATE TABLE PlaylistEntity (
+            id INTEGER PRIMARY KEY, listId INTEGER, trackId INTEGER, nextEntityId INTEGER
+        );
+
+        INSERT INTO Track VALUES
+            (1, '../Music/a.mp3', 'A', 'Artist', 'Album', 'House', NULL, 124, 300, 1),
+            (2, '../Music/b.mp3', 'B', 'Artist', NULL, NULL, 127.9, 128, 360, 23),
+            (3, NULL, 'Stream', NULL, NULL, NULL, NULL, NULL, NULL, NULL);
+        INSERT INTO Playlist VALUES (1, 'Sets', 0), (2, 'Friday', 1);
+        -- Entries are linked through `nextEntityId`, 0 ends the list.
+        INSERT INTO PlaylistEntity VALUES (10, 2, 2, 0), (11, 2, 1, 10);
+    ";
+
+    const HISTORY_SCHEMA: &str = "
+        CREATE TABLE Historylist (id INTEGER PRIMARY KEY, title TEXT);
+        CREATE TABLE HistorylistEntity (
+            id INTEGER PRIMARY KEY, listId INTEGER, trackId INTEGER, startTime INTEGER
+        );
+
+        INSERT INTO Historylist VALUES (1, '2024-03-01');
+        INSERT INTO HistorylistEntity VALUES (1, 1, 2, 1709330700), (2, 1, 1, 1709330400);
+    ";
+
+    /// Compresses data the way Qt's `qCompress` does.
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut compressed = (data.len() as u32).to_be_bytes().to_vec();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        compressed.extend(encoder.finish().unwrap());
+        compressed
+    }
+
+    fn quick_cue(name: &str, position: f64, argb: [u8; 4]) -> Vec<u8> {
+        let mut cue = vec![name.len() as u8];
+        cue.extend(name.as_bytes());
+        cue.extend(position.to_be_bytes());
+        cue.extend(argb);
+        cue
+    }
+
+    #[test]
+    fn reads_libraries() {
+        let dir = TempDir::new("engine-dj");
+        let library_dir = dir.join("Engine Library");
+        std::fs::create_dir_all(library_dir.join("Database2")).unwrap();
+
+        let conn = Connection::open(library_dir.join("Database2").join("m.db")).unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let mut quick_cues = 3u64.to_be_bytes().to_vec();
+        quick_cues.extend(quick_cue("Drop", 88200.0, [255, 1, 2, 3]));
+        quick_cues.extend(quick_cue("", -1.0, [0; 4]));
+        quick_cues.extend(quick_cue("", 22050.0, [255, 4, 5, 6]));
+        conn.execute(
+            "INSERT INTO PerformanceData VALUES (1, ?1, ?2)",
+            (compress(&44100f64.to_be_bytes()), compress(&quick_cues)),
+        )
+        .unwrap();
+        // Newer versions keep the history in its own database.
+        Connection::open(library_dir.join("Database2").join("hm.db"))
+            .unwrap()
+            .execute_batch(HISTORY_SCHEMA)
+            .unwrap();
+
+        let mut library = read_library(library_dir.to_str().unwrap()).unwrap();
+        library
+            .tracks
+            .sort_by(|a, b| a.song.file_path.cmp(&b.song.file_path));
+        let a_path = dir.join("Music/a.mp3").to_string_lossy().into_owned();
+        let b_path = dir.join("Music/b.mp3").to_string_lossy().into_owned();
+        assert_eq!(library.tracks.len(), 2);
+
+        let a = &library.tracks[0];
+        assert_eq!(a.song.file_path, a_path);
+        assert_eq!(a.song.bpm, Some(124.0));
+        assert_eq!(a.song.duration_seconds, 300);
+        assert_eq!(a.key.as_deref(), Some("Am"));
+        assert_eq!(a.song.parsed_key, Key::parse("Am"));
+        let cues: Vec<(u32, f64, Option<&str>, [u8; 3])> = a
+            .cues
+            .iter()
+            .map(|cue| (cue.index, cue.position_ms, cue.name.as_deref(), cue.color))
+            .collect();
+        assert_eq!(
+            cues,
+            vec![
+                (0, 2000.0, Some("Drop"), [1, 2, 3]),
+                (2, 500.0, None, [4, 5, 6])
+            ]
+        );
+
+        let b = &library.tracks[1];
+        assert_eq!(b.song.bpm, Some(127.9));
+        assert_eq!(b.key.as_deref(), Some("Dm"));
+
+        assert_eq!(library.playlists.len(), 1);
+        assert_eq!(library.playlists[0].name, "Friday");
+        assert_eq!(library.playlists[0].folders, vec!["Sets"]);
+        assert_eq!(
+            library.playlists[0].song_paths,
+            vec![a_path.clone(), b_path.clone()]
+        );
+
+        assert_eq!(library.history.len(), 1);
+        let history = &library.history[0];
+        assert_eq!(history.title.as_deref(), Some("2024-03-01"));
+        assert_eq!(history.last_modified_unix_seconds, 1_709_330_700);
+        let tracks: Vec<(Option<&str>, Option<u64>)> = history
+            .tracks
+            .iter()
+            .map(|track| (track.title.as_deref(), track.start_seconds()))
+            .collect();
+        assert_eq!(tracks, vec![(Some("A"), Some(0)), (Some("B"), Some(300))]);
+    }
+
+    #[test]
+    fn needs_a_database() {
+        let dir = TempDir::new("engine-dj-empty");
+        assert!(matches!(
+            read_library(dir.join("Engine Library").to_str().unwrap()),
+            Err(Error::NotFound(_))
+        ));
+    }
+}
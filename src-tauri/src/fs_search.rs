@@ -3,7 +3,7 @@ use std::path::Path;
 use lofty::file::FileType;
 
 use crate::{
-    recording::{Recording, WaveFile},
+    recording::{File, Recording, WaveFile},
     songs::Song,
 };
 
@@ -69,6 +69,102 @@ pub fn find_recordings(path: &str) -> Vec<Recording> {
     recordings
 }
 
+/// Searches the given directory for wave (.wav) files without a cue sheet next to them, such as
+/// recordings made on a mixer or field recorder rather than by Rekordbox.
+///
+/// Any IO errors are logged and otherwise ignored.
+pub fn find_bare_recordings(path: &str) -> Vec<Recording> {
+    let dir = match std::fs::read_dir(path) {
+        Ok(dir) => dir,
+        Err(error) => {
+            tracing::warn!(?error, path, "failed to read directory");
+            return Vec::new();
+        }
+    };
+
+    let mut dirs: Vec<String> = Vec::new();
+    let mut wave_files: Vec<String> = Vec::new();
+
+    for entry in dir {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                tracing::warn!(?error, "failed to read entry in dir");
+                continue;
+            }
+        };
+
+        let entry_path = entry.path();
+        let Some(entry_path_str) = entry_path.to_str().map(|str| str.to_string()) else {
+            continue;
+        };
+
+        if entry_path.is_dir() {
+            dirs.push(entry_path_str);
+        } else if entry_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+            && !entry_path.with_extension("cue").exists()
+        {
+            wave_files.push(entry_path_str);
+        }
+    }
+
+    let mut recordings: Vec<Recording> = Vec::new();
+
+    for file in wave_files {
+        match read_bare_recording(&file) {
+            Ok(recording) => {
+                recordings.push(recording);
+            }
+            Err(error) => {
+                tracing::warn!(?error, file, "failed to read wave file");
+            }
+        }
+    }
+
+    for dir in dirs {
+        recordings.append(&mut find_bare_recordings(&dir));
+    }
+
+    recordings
+}
+
+/// Reads a wave file without a cue sheet as a `Recording` with no tracks. Its `file_path` is
+/// where the cue sheet would be saved, next to the wave file with the same name.
+pub fn read_bare_recording(path: &str) -> Result<Recording, hound::Error> {
+    let wave_path = Path::new(path);
+    let wave_file = read_wave_file(wave_path)?;
+
+    let mut recording = Recording {
+        file_path: wave_path
+            .with_extension("cue")
+            .to_string_lossy()
+            .to_string(),
+        title: wave_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned()),
+        file: wave_path.file_name().map(|name| File {
+            name: name.to_string_lossy().into_owned(),
+            format: "WAVE".to_string(),
+        }),
+        wave_file: Some(wave_file),
+        ..Default::default()
+    };
+
+    // Recorders write the file as they record, so it's last modified when the recording ended
+    // like a Rekordbox cue sheet.
+    if let Ok(metadata) = std::fs::metadata(wave_path) {
+        let modified_time = filetime::FileTime::from_last_modification_time(&metadata);
+        recording.last_modified_unix_seconds = modified_time.unix_seconds();
+
+        let accessed_time = filetime::FileTime::from_last_access_time(&metadata);
+        recording.last_accessed_unix_seconds = accessed_time.unix_seconds();
+    }
+
+    Ok(recording)
+}
+
 pub fn find_songs(path: &str) -> Vec<Song> {
     let dir = match std::fs::read_dir(path) {
         Ok(dir) => dir,
@@ -100,7 +196,7 @@ pub fn find_songs(path: &str) -> Vec<Song> {
         } else if entry_path
             .extension()
             // Check if the file type is an audio file supported by `lofty`.
-            .and_then(|ext| FileType::from_ext(ext))
+            .and_then(FileType::from_ext)
             .is_some()
         {
             song_files.push(entry_path_str);
@@ -144,24 +240,31 @@ pub fn read_recording(path: &str) -> Result<Recording, std::io::Error> {
 fn try_find_wav_for_cue_file(cue_file_path: &str) -> Option<WaveFile> {
     let wave_path = Path::new(cue_file_path);
     let wave_path = wave_path.with_extension("wav");
-    let wave_path_string = wave_path.to_string_lossy().to_string();
-
-    match hound::WavReader::open(wave_path) {
-        Ok(reader) => {
-            let spec = reader.spec();
-            Some(WaveFile {
-                file_path: wave_path_string,
-                channels: spec.channels,
-                sample_rate: spec.sample_rate,
-                bits_per_sample: spec.bits_per_sample,
-                sample_format: format!("{:?}", spec.sample_format),
-                duration_seconds: reader.duration() as f64 / spec.sample_rate as f64,
-                total_samples: reader.duration(),
-            })
-        }
+
+    match read_wave_file(&wave_path) {
+        Ok(wave_file) => Some(wave_file),
         Err(error) => {
-            tracing::warn!(?error, file = wave_path_string, "failed to read wave file");
+            let file = wave_path.to_string_lossy().to_string();
+            tracing::warn!(?error, file, "failed to read wave file");
             None
         }
     }
 }
+
+/// Reads the format and duration of a wave (.wav) file.
+fn read_wave_file(wave_path: &Path) -> Result<WaveFile, hound::Error> {
+    let reader = hound::WavReader::open(wave_path)?;
+    let spec = reader.spec();
+    Ok(WaveFile {
+        file_path: wave_path.to_string_lossy().to_string(),
+        channels: spec.channels,
+        sample_rate: spec.sample_rate,
+        bits_per_sample: spec.bits_per_sample,
+        sample_format: format!("{:?}", spec.sample_format),
+        duration_seconds: reader.duration() as f64 / spec.sample_rate as f64,
+        total_samples: reader.duration(),
+        last_modified_unix_seconds: std::fs::metadata(wave_path).ok().map(|metadata| {
+            filetime::FileTime::from_last_modification_time(&metadata).unix_seconds()
+        }),
+    })
+}
@@ -0,0 +1,601 @@
+//! Background analysis jobs.
+//!
+//! Analyses take seconds per song, so rather than running in commands they're queued as jobs and
+//! processed by a bounded pool of worker threads. Higher priority jobs run first, then jobs run in
+//! the order they were queued.
+//!
+//! Jobs are kept in their own SQLite database on disk next to the library's, which their results
+//! are stored in, so both queued work and finished analysis survive a restart. Jobs that were
+//! running when the app closed are queued again when it starts.
+
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+
+/// Migrations are run in order on startup, the database's `user_version` is the number of
+/// migrations already run.
+const MIGRATIONS: [&str; 1] = ["CREATE TABLE jobs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        kind TEXT NOT NULL,
+        path TEXT NOT NULL,
+        priority INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        created_unix_ms INTEGER NOT NULL,
+        updated_unix_ms INTEGER NOT NULL
+    );
+    CREATE INDEX jobs_status ON jobs (status, priority, id);"];
+
+const JOB_COLUMNS: &str =
+    "id, kind, path, priority, status, error, created_unix_ms, updated_unix_ms";
+
+/// How long idle workers wait before checking for jobs again after a database error.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("rusqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("invalid job kind: {0}")]
+    Kind(#[from] serde_json::Error),
+    #[error("job not found")]
+    NotFound,
+}
+
+/// What a job does, stored as JSON so new kinds don't need a migration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JobKind {
+    /// Song audio quality, see `audio::quality`.
+    #[serde(rename_all = "camelCase")]
+    Quality { min_bitrate_kbps: Option<u32> },
+    /// Song tempo, see `audio::tempo`.
+    #[serde(rename_all = "camelCase")]
+    Bpm {
+        min_bpm: Option<f64>,
+        max_bpm: Option<f64>,
+    },
+    /// Song key, see `audio::key`.
+    Key,
+    /// Song beatgrid, see `audio::beats`.
+    #[serde(rename_all = "camelCase")]
+    Beats {
+        min_bpm: Option<f64>,
+        max_bpm: Option<f64>,
+    },
+    /// Song fingerprint, see `audio::fingerprint`.
+    Fingerprint,
+    /// Song waveform, see `audio::waveform`.
+    Waveform,
+    /// Song loudness, see `audio::loudness`.
+    Loudness,
+    /// Transitions between the tracks of a recording, see `audio::transitions`.
+    Transitions,
+    /// Where the songs of a recording's tracks are in its audio, see `audio::alignment`.
+    Alignment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Running => "running",
+            Self::Completed => "completed",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+        }
+    }
+
+    pub fn parse(status: &str) -> Self {
+        match status {
+            "pending" => Self::Pending,
+            "running" => Self::Running,
+            "completed" => Self::Completed,
+            "cancelled" => Self::Cancelled,
+            _ => Self::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Job {
+    pub id: i64,
+    pub kind: JobKind,
+    /// The song or recording (cue sheet) the job analyses.
+    pub path: String,
+    /// Higher priority jobs run first.
+    pub priority: i32,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub created_unix_ms: i64,
+    pub updated_unix_ms: i64,
+}
+
+/// Sent to the frontend whenever a job changes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobUpdate {
+    pub job: Job,
+    /// How many jobs are pending or running, including this one if it isn't finished.
+    pub remaining: usize,
+}
+
+/// Sent to the frontend as a running job works through the file it analyses.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    pub id: i64,
+    /// How much of the file has been analysed, from 0 to 1.
+    pub progress: f64,
+}
+
+/// Job state stored in SQLite.
+pub struct JobStore {
+    conn: Connection,
+}
+
+impl JobStore {
+    /// Opens (or creates) the job database at `path`. Jobs left running by a previous session
+    /// are queued again.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// A store that doesn't survive restarts, used when the job database can't be opened.
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(&format!(
+                "BEGIN; {migration}; PRAGMA user_version = {}; COMMIT;",
+                i + 1
+            ))?;
+        }
+
+        conn.execute(
+            "UPDATE jobs SET status = 'pending', updated_unix_ms = ?1 WHERE status = 'running'",
+            [now_unix_ms()],
+        )?;
+
+        Ok(Self { conn })
+    }
+
+    /// Queues a job. If the same job is already queued or running it's returned instead, with its
+    /// priority raised if the new priority is higher.
+    pub fn enqueue(&self, kind: &JobKind, path: &str, priority: i32) -> Result<Job, Error> {
+        let kind = serde_json::to_string(kind)?;
+        let existing: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM jobs
+                WHERE kind = ?1 AND path = ?2 AND status IN ('pending', 'running')",
+                (&kind, path),
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        let now = now_unix_ms();
+        let id = match existing {
+            Some(id) => {
+                self.conn.execute(
+                    "UPDATE jobs SET priority = MAX(priority, ?2), updated_unix_ms = ?3
+                    WHERE id = ?1",
+                    (id, priority, now),
+                )?;
+                id
+            }
+            None => {
+                self.conn.execute(
+                    "INSERT INTO jobs (kind, path, priority, status, created_unix_ms, updated_unix_ms)
+                    VALUES (?1, ?2, ?3, 'pending', ?4, ?4)",
+                    (&kind, path, priority, now),
+                )?;
+                self.conn.last_insert_rowid()
+            }
+        };
+
+        self.get(id)
+    }
+
+    pub fn get(&self, id: i64) -> Result<Job, Error> {
+        self.conn
+            .query_row(
+                &format!("SELECT {JOB_COLUMNS} FROM jobs WHERE id = ?1"),
+                [id],
+                row_to_job,
+            )
+            .optional()?
+            .ok_or(Error::NotFound)
+    }
+
+    /// Lists every job, unfinished jobs first in the order they'll run.
+    pub fn list(&self) -> Result<Vec<Job>, Error> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {JOB_COLUMNS} FROM jobs
+            ORDER BY status NOT IN ('running', 'pending'), status != 'running', priority DESC, id"
+        ))?;
+        let mut rows = statement.query([])?;
+
+        let mut jobs = Vec::new();
+        while let Some(row) = rows.next()? {
+            jobs.push(row_to_job(row)?);
+        }
+
+        Ok(jobs)
+    }
+
+    /// How many jobs are pending or running.
+    pub fn count_remaining(&self) -> Result<usize, Error> {
+        Ok(self.conn.query_row(
+            "SELECT COUNT(*) FROM jobs WHERE status IN ('pending', 'running')",
+            [],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Marks the next pending job as running and returns it.
+    fn claim_next(&self) -> Result<Option<Job>, Error> {
+        let id: Option<i64> = self
+            .conn
+            .query_row(
+                "SELECT id FROM jobs WHERE status = 'pending' ORDER BY priority DESC, id LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match id {
+            Some(id) => self.set_status(id, JobStatus::Running, None).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn set_status(&self, id: i64, status: JobStatus, error: Option<&str>) -> Result<Job, Error> {
+        let updated = self.conn.execute(
+            "UPDATE jobs SET status = ?2, error = ?3, updated_unix_ms = ?4 WHERE id = ?1",
+            (id, status.as_str(), error, now_unix_ms()),
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound);
+        }
+
+        self.get(id)
+    }
+
+    pub fn set_priority(&self, id: i64, priority: i32) -> Result<Job, Error> {
+        let updated = self.conn.execute(
+            "UPDATE jobs SET priority = ?2, updated_unix_ms = ?3 WHERE id = ?1",
+            (id, priority, now_unix_ms()),
+        )?;
+        if updated == 0 {
+            return Err(Error::NotFound);
+        }
+
+        self.get(id)
+    }
+
+    /// Removes completed, failed and cancelled jobs, returning how many were removed.
+    pub fn clear_finished(&self) -> Result<usize, Error> {
+        Ok(self.conn.execute(
+            "DELETE FROM jobs WHERE status IN ('completed', 'failed', 'cancelled')",
+            [],
+        )?)
+    }
+}
+
+/// Runs queued jobs on a pool of worker threads.
+#[derive(Clone)]
+pub struct JobQueue {
+    shared: Arc<Shared>,
+}
+
+type RunJob = dyn Fn(&Job, &Cancellation) -> Result<(), String> + Send + Sync;
+type OnUpdate = dyn Fn(&JobUpdate) + Send + Sync;
+
+struct Shared {
+    store: Mutex<JobStore>,
+    /// Signalled when a job is queued.
+    queued: Condvar,
+    /// Whether each running job has been cancelled, their results are discarded when they finish.
+    running: Mutex<HashMap<i64, Arc<AtomicBool>>>,
+    run: Box<RunJob>,
+    on_update: Box<OnUpdate>,
+}
+
+impl JobQueue {
+    /// Starts `workers` threads processing jobs from the store. `run` does the work of a job and
+    /// should check for cancellation while analysing and before storing its results, `on_update`
+    /// is called whenever a job changes. Jobs that panic are failed.
+    pub fn start(
+        store: JobStore,
+        workers: usize,
+        run: impl Fn(&Job, &Cancellation) -> Result<(), String> + Send + Sync + 'static,
+        on_update: impl Fn(&JobUpdate) + Send + Sync + 'static,
+    ) -> Self {
+        let queue = Self {
+            shared: Arc::new(Shared {
+                store: Mutex::new(store),
+                queued: Condvar::new(),
+                running: Mutex::new(HashMap::new()),
+                run: Box::new(run),
+                on_update: Box::new(on_update),
+            }),
+        };
+
+        for worker in 0..workers.max(1) {
+            let queue = queue.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("job-worker-{worker}"))
+                .spawn(move || queue.work());
+            if let Err(error) = spawned {
+                tracing::error!(?error, worker, "failed to start job worker");
+            }
+        }
+
+        queue
+    }
+
+    pub fn enqueue(&self, kind: &JobKind, path: &str, priority: i32) -> Result<Job, Error> {
+        let job = self
+            .shared
+            .store
+            .lock()
+            .unwrap()
+            .enqueue(kind, path, priority)?;
+        self.shared.queued.notify_one();
+        self.notify(&job);
+        Ok(job)
+    }
+
+    pub fn list(&self) -> Result<Vec<Job>, Error> {
+        self.shared.store.lock().unwrap().list()
+    }
+
+    /// Cancels a job. Pending jobs won't run, running jobs stop when they next check and their
+    /// results are discarded. Finished jobs are left unchanged.
+    pub fn cancel(&self, id: i64) -> Result<Job, Error> {
+        let job = {
+            let store = self.shared.store.lock().unwrap();
+            let job = store.get(id)?;
+            match job.status {
+                JobStatus::Pending | JobStatus::Running => {
+                    if let Some(cancelled) = self.shared.running.lock().unwrap().get(&id) {
+                        cancelled.store(true, Ordering::Relaxed);
+                    }
+                    store.set_status(id, JobStatus::Cancelled, None)?
+                }
+                _ => return Ok(job),
+            }
+        };
+        self.notify(&job);
+        Ok(job)
+    }
+
+    pub fn set_priority(&self, id: i64, priority: i32) -> Result<Job, Error> {
+        let job = self
+            .shared
+            .store
+            .lock()
+            .unwrap()
+            .set_priority(id, priority)?;
+        self.notify(&job);
+        Ok(job)
+    }
+
+    pub fn clear_finished(&self) -> Result<usize, Error> {
+        self.shared.store.lock().unwrap().clear_finished()
+    }
+
+    fn work(&self) {
+        loop {
+            let cancellation = Cancellation {
+                cancelled: Arc::new(AtomicBool::new(false)),
+            };
+            let job = {
+                let mut store = self.shared.store.lock().unwrap();
+                let job = loop {
+                    match store.claim_next() {
+                        Ok(Some(job)) => break job,
+                        Ok(None) => store = self.shared.queued.wait(store).unwrap(),
+                        Err(error) => {
+                            tracing::error!(?error, "failed to claim next job");
+                            store = self
+                                .shared
+                                .queued
+                                .wait_timeout(store, RETRY_INTERVAL)
+                                .unwrap()
+                                .0;
+                        }
+                    }
+                };
+                // Registered before the store is unlocked so the job can be cancelled as soon as
+                // it's running.
+                self.shared
+                    .running
+                    .lock()
+                    .unwrap()
+                    .insert(job.id, cancellation.flag());
+                job
+            };
+            self.notify(&job);
+
+            tracing::debug!(id = job.id, kind = ?job.kind, path = job.path, "running job");
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| (self.shared.run)(&job, &cancellation)))
+                    .unwrap_or_else(|panic| {
+                        let message = panic
+                            .downcast_ref::<&str>()
+                            .map(|message| message.to_string())
+                            .or_else(|| panic.downcast_ref::<String>().cloned())
+                            .unwrap_or_default();
+                        Err(format!("analysis panicked: {message}"))
+                    });
+
+            let finished = {
+                // Checked while holding the store so a job can't be cancelled as it finishes.
+                let store = self.shared.store.lock().unwrap();
+                self.shared.running.lock().unwrap().remove(&job.id);
+                if cancellation.is_cancelled() {
+                    continue;
+                }
+                match &result {
+                    Ok(()) => store.set_status(job.id, JobStatus::Completed, None),
+                    Err(error) => store.set_status(job.id, JobStatus::Failed, Some(error)),
+                }
+            };
+            match finished {
+                Ok(job) => self.notify(&job),
+                Err(error) => tracing::error!(?error, id = job.id, "failed to finish job"),
+            }
+        }
+    }
+
+    fn notify(&self, job: &Job) {
+        let remaining = match self.shared.store.lock().unwrap().count_remaining() {
+            Ok(remaining) => remaining,
+            Err(error) => {
+                tracing::error!(?error, "failed to count remaining jobs");
+                0
+            }
+        };
+        (self.shared.on_update)(&JobUpdate {
+            job: job.clone(),
+            remaining,
+        });
+    }
+}
+
+/// Lets a running job check whether it's been cancelled.
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// A flag that's set when the job is cancelled, for checking from deeper in its analysis.
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+}
+
+fn row_to_job(row: &Row) -> rusqlite::Result<Job> {
+    let kind: String = row.get(1)?;
+    let kind = serde_json::from_str(&kind).map_err(|error| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(error))
+    })?;
+
+    Ok(Job {
+        id: row.get(0)?,
+        kind,
+        path: row.get(2)?,
+        priority: row.get(3)?,
+        status: JobStatus::parse(&row.get::<_, String>(4)?),
+        error: row.get(5)?,
+        created_unix_ms: row.get(6)?,
+        updated_unix_ms: row.get(7)?,
+    })
+}
+
+fn now_unix_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    /// Starts a queue with one worker, returning it and the updates it sends.
+    fn start(
+        run: impl Fn(&Job, &Cancellation) -> Result<(), String> + Send + Sync + 'static,
+    ) -> (JobQueue, mpsc::Receiver<Job>) {
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let queue = JobQueue::start(JobStore::open_in_memory().unwrap(), 1, run, move |update| {
+            sender.lock().unwrap().send(update.job.clone()).unwrap();
+        });
+        (queue, receiver)
+    }
+
+    fn wait_for(updates: &mpsc::Receiver<Job>, status: JobStatus) -> Job {
+        loop {
+            let job = updates.recv_timeout(Duration::from_secs(10)).unwrap();
+            if job.status == status {
+                return job;
+            }
+        }
+    }
+
+    #[test]
+    fn fails_jobs_that_panic() {
+        let (queue, updates) = start(|_, _| panic!("corrupt file"));
+        queue.enqueue(&JobKind::Key, "/music/song.mp3", 0).unwrap();
+
+        let job = wait_for(&updates, JobStatus::Failed);
+        assert_eq!(
+            job.error.as_deref(),
+            Some("analysis panicked: corrupt file")
+        );
+    }
+
+    #[test]
+    fn stops_running_jobs_when_cancelled() {
+        let (stopped_sender, stopped) = mpsc::channel();
+        let stopped_sender = Mutex::new(stopped_sender);
+        let (queue, updates) = start(move |_, cancellation| {
+            while !cancellation.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            stopped_sender.lock().unwrap().send(()).unwrap();
+            Err("analysis cancelled".to_string())
+        });
+        queue.enqueue(&JobKind::Key, "/music/song.mp3", 0).unwrap();
+
+        let job = wait_for(&updates, JobStatus::Running);
+        queue.cancel(job.id).unwrap();
+        stopped.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        // The worker has finished with the cancelled job once it runs the next one, and the
+        // failure after cancelling is discarded.
+        let next = queue
+            .enqueue(&JobKind::Fingerprint, "/music/song.mp3", 0)
+            .unwrap();
+        wait_for(&updates, JobStatus::Running);
+        queue.cancel(next.id).unwrap();
+        let cancelled = queue.shared.store.lock().unwrap().get(job.id).unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert_eq!(cancelled.error, None);
+    }
+}
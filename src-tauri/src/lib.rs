@@ -1,19 +1,42 @@
-use std::{borrow::Cow, process::Command, sync::Mutex};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+};
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+use std::process::Command;
 
 use db::Database;
+use jobs::{Cancellation, Job, JobKind, JobProgress, JobQueue, JobStore};
 use recording::Recording;
-use tauri::{Manager, State};
+use tauri::{Emitter, Manager, State};
 
+mod artwork;
+mod audio;
+mod content_hash;
 mod db;
+mod engine_dj;
 mod fs_search;
+mod jobs;
+mod mixxx;
 mod recording;
+mod rekordbox;
+mod serato;
 mod songs;
+#[cfg(test)]
+mod testing;
+mod traktor;
 
 /// The core app state handled by Tauri and passed into commands, etc.
 #[derive(Debug)]
 struct AppState<'a> {
     music_dir: Option<Cow<'a, str>>,
     recordings_dir: Option<Cow<'a, str>>,
+    /// Rekordbox's local data directory containing the `share/PIONEER` analysis files.
+    rekordbox_dir: Option<Cow<'a, str>>,
 }
 
 impl<'a> Default for AppState<'a> {
@@ -23,9 +46,23 @@ impl<'a> Default for AppState<'a> {
             dir.push("PioneerDJ/Recording");
             Cow::Owned(dir.to_string_lossy().into_owned())
         });
+        // Rekordbox keeps its data in `~/Library/Pioneer` on macOS rather than the usual
+        // application support directory.
+        #[cfg(target_os = "macos")]
+        let rekordbox_dir = dirs::home_dir().map(|mut dir| {
+            dir.push("Library/Pioneer/rekordbox");
+            Cow::Owned(dir.to_string_lossy().into_owned())
+        });
+        #[cfg(not(target_os = "macos"))]
+        let rekordbox_dir = dirs::data_dir().map(|mut dir| {
+            dir.push("Pioneer/rekordbox");
+            Cow::Owned(dir.to_string_lossy().into_owned())
+        });
+
         Self {
             music_dir,
             recordings_dir,
+            rekordbox_dir,
         }
     }
 }
@@ -52,6 +89,115 @@ async fn get_recording(path: &str) -> Result<Recording, String> {
     })
 }
 
+/// Detects where each track of a recording is mixed in from its audio and stores the
+/// transitions.
+#[tauri::command]
+async fn detect_recording_transitions(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Vec<audio::transitions::Transition>, String> {
+    let transitions = detect_transitions(path).map_err(|error| {
+        tracing::error!(error, path, "failed to detect recording transitions");
+        error
+    })?;
+
+    let database = database.lock().unwrap();
+    database
+        .insert_transitions(path, &transitions)
+        .map_err(|e| e.to_string())?;
+
+    Ok(transitions)
+}
+
+/// The stored transitions of a recording, empty when they haven't been detected.
+#[tauri::command]
+async fn get_recording_transitions(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Vec<audio::transitions::Transition>, String> {
+    let database = database.lock().unwrap();
+    database.list_transitions(path).map_err(|e| e.to_string())
+}
+
+/// Finds where the songs of a recording's tracks sit in its audio, their tempo and pitch changes,
+/// and stores the alignments.
+#[tauri::command]
+async fn align_recording_songs(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Vec<audio::alignment::Alignment>, String> {
+    let alignments = align_songs(path).map_err(|error| {
+        tracing::error!(error, path, "failed to align recording songs");
+        error
+    })?;
+
+    let database = database.lock().unwrap();
+    database
+        .insert_alignments(path, &alignments)
+        .map_err(|e| e.to_string())?;
+
+    Ok(alignments)
+}
+
+/// The stored alignments of a recording, empty when it hasn't been aligned.
+#[tauri::command]
+async fn get_recording_alignments(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Vec<audio::alignment::Alignment>, String> {
+    let database = database.lock().unwrap();
+    database.list_alignments(path).map_err(|e| e.to_string())
+}
+
+/// Moves each track's start time in the cue sheet to when its song becomes audible in the audio,
+/// skipping alignments less confident than `min_confidence` and any that would put tracks out of
+/// order. The original is kept as a '.cue.bak' file. Returns the corrected recording.
+#[tauri::command]
+async fn correct_recording_cue_sheet(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+    min_confidence: Option<f64>,
+) -> Result<Recording, String> {
+    let min_confidence = min_confidence.unwrap_or(audio::alignment::DEFAULT_MIN_CONFIDENCE);
+    let alignments = {
+        let database = database.lock().unwrap();
+        database.list_alignments(path).map_err(|e| e.to_string())?
+    };
+    let tracks = fs_search::read_recording(path)
+        .map_err(|e| e.to_string())?
+        .tracks;
+    let start_times = audio::alignment::corrected_start_times(&tracks, &alignments, min_confidence);
+
+    let input = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let output = recording::set_start_times(&input, &start_times);
+    recording::rewrite_cue_sheet(path, &output).map_err(|error| {
+        tracing::error!(?error, path, "failed to write cue sheet");
+        error.to_string()
+    })?;
+
+    fs_search::read_recording(path).map_err(|e| e.to_string())
+}
+
+/// Reads a recording's cue sheet and aligns its songs against its wave file.
+fn align_songs(path: &str) -> Result<Vec<audio::alignment::Alignment>, String> {
+    let recording = fs_search::read_recording(path).map_err(|e| e.to_string())?;
+    let wave_file = recording
+        .wave_file
+        .ok_or_else(|| "recording has no wave file".to_string())?;
+    audio::alignment::align(Path::new(&wave_file.file_path), &recording.tracks)
+        .map_err(|e| e.to_string())
+}
+
+/// Reads a recording's cue sheet and detects the transitions in its wave file.
+fn detect_transitions(path: &str) -> Result<Vec<audio::transitions::Transition>, String> {
+    let recording = fs_search::read_recording(path).map_err(|e| e.to_string())?;
+    let wave_file = recording
+        .wave_file
+        .ok_or_else(|| "recording has no wave file".to_string())?;
+    audio::transitions::detect(Path::new(&wave_file.file_path), &recording.tracks)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn find_recordings(state: State<'_, Mutex<AppState<'_>>>) -> Result<Vec<Recording>, String> {
     let state = state.lock().unwrap();
@@ -75,6 +221,175 @@ async fn find_recordings(state: State<'_, Mutex<AppState<'_>>>) -> Result<Vec<Re
     Ok(recordings)
 }
 
+/// How much of a file is matched by `find_songs_by_audio` when no duration is given.
+const DEFAULT_EXCERPT_SECONDS: f64 = 20.0;
+
+/// Finds the fingerprinted songs playing in an excerpt of an audio file, starting `start_seconds`
+/// in. Match offsets are where each song starts relative to the start of the excerpt.
+#[tauri::command]
+async fn find_songs_by_audio(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+    start_seconds: Option<f64>,
+    duration_seconds: Option<f64>,
+) -> Result<Vec<audio::fingerprint::FingerprintMatch>, String> {
+    let samples = audio::decode_range(
+        Path::new(path),
+        start_seconds.unwrap_or(0.0),
+        Some(duration_seconds.unwrap_or(DEFAULT_EXCERPT_SECONDS)),
+    )
+    .map_err(|error| {
+        tracing::error!(?error, path, "failed to decode excerpt");
+        error.to_string()
+    })?;
+    let fingerprint = audio::fingerprint::fingerprint_samples(&samples);
+
+    let database = database.lock().unwrap();
+    database
+        .query_fingerprint(&fingerprint.landmarks)
+        .map_err(|e| e.to_string())
+}
+
+/// Finds wave files in the recordings directory that have no cue sheet.
+#[tauri::command]
+async fn find_bare_recordings(
+    state: State<'_, Mutex<AppState<'_>>>,
+) -> Result<Vec<Recording>, String> {
+    let dir = {
+        let state = state.lock().unwrap();
+        state
+            .recordings_dir
+            .clone()
+            .ok_or_else(|| "recording directory not set".to_string())?
+    };
+
+    let mut recordings = fs_search::find_bare_recordings(&dir);
+    recordings.sort_by(|a, b| {
+        b.last_modified_unix_seconds
+            .cmp(&a.last_modified_unix_seconds)
+    });
+
+    Ok(recordings)
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ReconstructedTracklist {
+    recording: Recording,
+    /// Library songs that haven't been fingerprinted yet so couldn't be recognised, they've been
+    /// queued as fingerprint jobs.
+    unfingerprinted_songs: usize,
+}
+
+/// Recognises the library songs played in a wave file without a cue sheet by their fingerprints,
+/// returning a recording with a track for each. Songs that haven't been fingerprinted yet are
+/// queued as jobs ahead of other analysis, so the tracklist can be found again once they're done.
+/// The recording isn't saved until it's passed to `save_recording_cue_sheet`.
+#[tauri::command]
+async fn reconstruct_tracklist(
+    database: State<'_, Mutex<Database>>,
+    queue: State<'_, JobQueue>,
+    path: &str,
+) -> Result<ReconstructedTracklist, String> {
+    let (songs, fingerprinted, index) = {
+        let database = database.lock().unwrap();
+        let songs = database.list_songs().map_err(|e| e.to_string())?;
+        let fingerprinted: HashSet<String> = database
+            .list_fingerprinted_songs()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+        let index = database
+            .load_fingerprint_index()
+            .map_err(|e| e.to_string())?;
+        (songs, fingerprinted, index)
+    };
+    let mut recording = fs_search::read_bare_recording(path).map_err(|e| e.to_string())?;
+
+    let unfingerprinted: Vec<&str> = songs
+        .iter()
+        .map(|song| song.file_path.as_str())
+        .filter(|file_path| !fingerprinted.contains(*file_path))
+        .collect();
+    for file_path in &unfingerprinted {
+        if let Err(error) = queue.enqueue(&JobKind::Fingerprint, file_path, 1) {
+            tracing::error!(?error, file_path, "failed to queue fingerprint job");
+        }
+    }
+
+    let identified = audio::tracklist::identify(Path::new(path), &index).map_err(|error| {
+        tracing::error!(?error, path, "failed to reconstruct tracklist");
+        error.to_string()
+    })?;
+
+    let songs_by_path: HashMap<&str, &songs::Song> = songs
+        .iter()
+        .map(|song| (song.file_path.as_str(), song))
+        .collect();
+    recording.tracks = identified
+        .iter()
+        .map(|track| {
+            let song = songs_by_path.get(track.song_path.as_str());
+            recording::Track {
+                title: song.and_then(|song| song.title.clone()),
+                performer: song.and_then(|song| song.artist.clone()),
+                file: Some(recording::File::from_path(&track.song_path)),
+                start_time: Some(recording::format_start_time(
+                    track.start_seconds.round() as u64
+                )),
+            }
+        })
+        .collect();
+
+    Ok(ReconstructedTracklist {
+        recording,
+        unfingerprinted_songs: unfingerprinted.len(),
+    })
+}
+
+/// Saves tracks as a new cue sheet for the wave file at `path`, next to it with the same name,
+/// failing rather than overwriting an existing one. Only wave files in the recordings directory
+/// can be given cue sheets.
+#[tauri::command]
+async fn save_recording_cue_sheet(
+    state: State<'_, Mutex<AppState<'_>>>,
+    path: &str,
+    tracks: Vec<recording::Track>,
+) -> Result<Recording, String> {
+    let dir = {
+        let state = state.lock().unwrap();
+        state
+            .recordings_dir
+            .clone()
+            .ok_or_else(|| "recording directory not set".to_string())?
+    };
+    let in_recordings_dir = match (
+        Path::new(path).canonicalize(),
+        Path::new(dir.as_ref()).canonicalize(),
+    ) {
+        (Ok(path), Ok(dir)) => path.starts_with(dir),
+        _ => false,
+    };
+    if !in_recordings_dir {
+        return Err(format!("not a recording: {path}"));
+    }
+
+    let mut recording = fs_search::read_bare_recording(path).map_err(|e| e.to_string())?;
+    recording.tracks = tracks;
+    let cue_path = recording.file_path.as_str();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(cue_path)
+        .and_then(|mut file| file.write_all(recording.to_cue().as_bytes()))
+        .map_err(|error| {
+            tracing::error!(?error, cue_path, "failed to write cue sheet");
+            error.to_string()
+        })?;
+
+    fs_search::read_recording(cue_path).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn open_file_location(path: &str) -> Result<(), String> {
     #[cfg(target_os = "windows")]
@@ -93,6 +408,9 @@ async fn open_file_location(path: &str) -> Result<(), String> {
             .map_err(|e| e.to_string())?;
     }
 
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    tracing::warn!(path, "opening file locations isn't supported on this platform");
+
     Ok(())
 }
 
@@ -108,19 +426,999 @@ async fn find_songs(database: State<'_, Mutex<Database>>) -> Result<Vec<songs::S
     database.list_songs().map_err(|e| e.to_string())
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    let app_state = AppState::default();
+/// Groups songs that are copies of each other, see `songs::duplicates`. Songs that have been
+/// fingerprinted are compared by their audio as well as their content hashes and tags, songs that
+/// haven't been hashed in the background yet are hashed first.
+#[tauri::command]
+async fn find_duplicate_songs(
+    database: State<'_, Mutex<Database>>,
+) -> Result<Vec<songs::duplicates::DuplicateGroup>, String> {
+    hash_songs(&database);
+    let (library, fingerprinted, index) = {
+        let database = database.lock().unwrap();
+        (
+            database.list_songs().map_err(|e| e.to_string())?,
+            database
+                .list_fingerprinted_songs()
+                .map_err(|e| e.to_string())?,
+            database
+                .load_fingerprint_index()
+                .map_err(|e| e.to_string())?,
+        )
+    };
 
-    tracing::info!(state=?app_state, "dbeat starting");
+    let mut same_audio = Vec::new();
+    for file_path in fingerprinted {
+        let fingerprint = {
+            let database = database.lock().unwrap();
+            database.get_fingerprint(&file_path)
+        };
+        match fingerprint {
+            Ok(fingerprint) => {
+                for copy in index.find_copies(&file_path, &fingerprint) {
+                    same_audio.push((file_path.clone(), copy));
+                }
+            }
+            Err(error) => tracing::warn!(?error, file_path, "failed to read fingerprint"),
+        }
+    }
+
+    Ok(songs::duplicates::find(&library, &same_audio))
+}
+
+/// Finds songs whose file is missing and the library songs they may have moved to, see
+/// `songs::relink`. Stored songs whose file is gone are missing, and so are files listed in the
+/// recordings' cue sheets that don't exist and weren't relinked before.
+#[tauri::command]
+async fn find_missing_songs(
+    state: State<'_, Mutex<AppState<'_>>>,
+    database: State<'_, Mutex<Database>>,
+) -> Result<Vec<songs::relink::MissingSong>, String> {
+    let recordings_dir = {
+        let state = state.lock().unwrap();
+        state.recordings_dir.clone().map(|dir| dir.to_string())
+    };
+    hash_songs(&database);
+    let library = {
+        let database = database.lock().unwrap();
+        database.list_songs().map_err(|e| e.to_string())?
+    };
+    let (mut missing, found): (Vec<songs::Song>, Vec<songs::Song>) = library
+        .into_iter()
+        .partition(|song| !Path::new(&song.file_path).exists());
+
+    let mut cue_sheets: HashMap<String, Vec<String>> = HashMap::new();
+    for recording in recordings_dir
+        .as_deref()
+        .map(fs_search::find_recordings)
+        .unwrap_or_default()
+    {
+        for track in &recording.tracks {
+            let Some(file) = track
+                .file
+                .as_ref()
+                .filter(|file| !Path::new(&file.name).exists())
+            else {
+                continue;
+            };
+            let listed = cue_sheets.entry(file.name.clone()).or_default();
+            if listed.is_empty() {
+                // Stored songs are already missing, and relinked paths still find their song.
+                let stored = database.lock().unwrap().get_song(&file.name);
+                if let Err(db::Error::RowNotFound) = stored {
+                    missing.push(songs::Song {
+                        file_path: file.name.clone(),
+                        title: track.title.clone(),
+                        artist: track.performer.clone(),
+                        ..Default::default()
+                    });
+                }
+            }
+            if !listed.contains(&recording.file_path) {
+                listed.push(recording.file_path.clone());
+            }
+        }
+    }
+
+    Ok(songs::relink::find(&missing, &cue_sheets, &found))
+}
+
+/// A song to move to a new file, see `relink_songs`.
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Relink {
+    old_file_path: String,
+    new_file_path: String,
+}
+
+/// The result of relinking one song, failures don't stop other songs being relinked.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SongRelinkResult {
+    old_file_path: String,
+    /// The song read from its new file.
+    song: Option<songs::Song>,
+    error: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RelinkResult {
+    songs: Vec<SongRelinkResult>,
+    /// The cue sheets whose `FILE` lines were changed to the new paths.
+    cue_sheets: Vec<String>,
+}
+
+/// Moves songs and their analysis to new file paths, e.g. after the music folder was
+/// reorganised. When `update_cue_sheets` is set, cue sheets in the recordings directory listing
+/// the old paths are changed to the new ones, see `recording::relink_cue_sheet`.
+#[tauri::command]
+async fn relink_songs(
+    state: State<'_, Mutex<AppState<'_>>>,
+    database: State<'_, Mutex<Database>>,
+    relinks: Vec<Relink>,
+    update_cue_sheets: bool,
+) -> Result<RelinkResult, String> {
+    let recordings_dir = if update_cue_sheets {
+        let state = state.lock().unwrap();
+        let dir = state
+            .recordings_dir
+            .clone()
+            .ok_or_else(|| "recording directory not set".to_string())?;
+        Some(dir.to_string())
+    } else {
+        None
+    };
+
+    let mut results = Vec::new();
+    let mut relinked: Vec<(String, String)> = Vec::new();
+    {
+        let database = database.lock().unwrap();
+        for relink in relinks {
+            let song = songs::Song::from_file(&relink.new_file_path)
+                .map_err(|e| e.to_string())
+                .and_then(|song| {
+                    database
+                        .relink_song(&relink.old_file_path, &song)
+                        .map_err(|e| e.to_string())?;
+                    Ok(song)
+                });
+            match song {
+                Ok(song) => {
+                    relinked.push((relink.old_file_path.clone(), relink.new_file_path));
+                    results.push(SongRelinkResult {
+                        old_file_path: relink.old_file_path,
+                        song: Some(song),
+                        error: None,
+                    });
+                }
+                Err(error) => {
+                    let old_file_path = relink.old_file_path;
+                    tracing::error!(error, old_file_path, "failed to relink song");
+                    results.push(SongRelinkResult {
+                        old_file_path,
+                        song: None,
+                        error: Some(error),
+                    });
+                }
+            }
+        }
+    }
 
-    let database = Database::init().unwrap();
+    let mut cue_sheets = Vec::new();
+    if let Some(dir) = recordings_dir.filter(|_| !relinked.is_empty()) {
+        for recording in fs_search::find_recordings(&dir) {
+            let path = recording.file_path;
+            match recording::relink_cue_sheet(&path, &relinked) {
+                Ok(true) => cue_sheets.push(path),
+                Ok(false) => {}
+                Err(error) => tracing::error!(?error, path, "failed to relink cue sheet"),
+            }
+        }
+    }
 
-    // Seed the database with songs from the music dir.
-    match app_state.music_dir.clone().map(|dir| dir.to_string()) {
+    Ok(RelinkResult {
+        songs: results,
+        cue_sheets,
+    })
+}
+
+/// Lists songs in a key given in any notation, sorted by key. When `compatible` is set songs in
+/// keys that mix harmonically with it are included too.
+#[tauri::command]
+async fn find_songs_in_key(
+    database: State<'_, Mutex<Database>>,
+    key: &str,
+    compatible: bool,
+) -> Result<Vec<songs::Song>, String> {
+    let key = songs::key::Key::parse(key).ok_or_else(|| format!("unrecognised key: {key}"))?;
+    let keys = if compatible {
+        key.compatible().to_vec()
+    } else {
+        vec![key]
+    };
+
+    let database = database.lock().unwrap();
+    let mut songs: Vec<_> = database
+        .list_songs()
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .filter(|song| song.parsed_key.is_some_and(|key| keys.contains(&key)))
+        .collect();
+    songs.sort_by_key(|song| song.parsed_key);
+    Ok(songs)
+}
+
+/// The result of writing tags to one song, failures don't stop other songs being written.
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TagWriteResult {
+    file_path: String,
+    song: Option<songs::Song>,
+    error: Option<String>,
+}
+
+/// Writes the same tag changes to each song file and updates the songs in the database.
+#[tauri::command]
+async fn write_song_tags(
+    database: State<'_, Mutex<Database>>,
+    paths: Vec<String>,
+    changes: songs::TagChanges,
+) -> Result<Vec<TagWriteResult>, String> {
+    let database = database.lock().unwrap();
+
+    Ok(paths
+        .into_iter()
+        .map(|path| write_tags(&database, path, &changes))
+        .collect())
+}
+
+/// Previews the changes tag cleanup rules would make to each song.
+#[tauri::command]
+async fn preview_tag_cleanup(
+    database: State<'_, Mutex<Database>>,
+    paths: Vec<String>,
+    rules: Vec<songs::cleanup::Rule>,
+) -> Result<Vec<songs::cleanup::SongCleanup>, String> {
+    let database = database.lock().unwrap();
+    let songs = paths
+        .iter()
+        .map(|path| database.get_song(path))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    songs::cleanup::preview(rules, &songs).map_err(|e| e.to_string())
+}
+
+/// Writes the changes previewed by `preview_tag_cleanup` to the song files. Songs whose tags have
+/// changed since the preview are refused rather than overwritten.
+#[tauri::command]
+async fn apply_tag_cleanup(
+    database: State<'_, Mutex<Database>>,
+    cleanups: Vec<songs::cleanup::SongCleanup>,
+) -> Result<Vec<TagWriteResult>, String> {
+    let database = database.lock().unwrap();
+
+    Ok(cleanups
+        .into_iter()
+        .map(|cleanup| {
+            let error = match database.get_song(&cleanup.file_path) {
+                Ok(song) if cleanup.is_current(&song) => {
+                    let changes = cleanup.tag_changes();
+                    return write_tags(&database, cleanup.file_path, &changes);
+                }
+                Ok(_) => "tags changed since the cleanup was previewed".to_string(),
+                Err(error) => error.to_string(),
+            };
+            tracing::error!(
+                error,
+                path = cleanup.file_path,
+                "failed to apply tag cleanup"
+            );
+            TagWriteResult {
+                file_path: cleanup.file_path,
+                song: None,
+                error: Some(error),
+            }
+        })
+        .collect())
+}
+
+fn write_tags(database: &Database, path: String, changes: &songs::TagChanges) -> TagWriteResult {
+    let written = database
+        .get_song(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|song| song.write_tags(changes).map_err(|e| e.to_string()))
+        .and_then(|song| {
+            database.update_song(&song).map_err(|e| e.to_string())?;
+            Ok(song)
+        });
+
+    match written {
+        Ok(song) => TagWriteResult {
+            file_path: path,
+            song: Some(song),
+            error: None,
+        },
+        Err(error) => {
+            tracing::error!(error, path, "failed to write song tags");
+            TagWriteResult {
+                file_path: path,
+                song: None,
+                error: Some(error),
+            }
+        }
+    }
+}
+
+#[tauri::command]
+async fn find_low_bitrate_songs(
+    database: State<'_, Mutex<Database>>,
+    max_bitrate_kbps: u32,
+) -> Result<Vec<songs::Song>, String> {
+    let database = database.lock().unwrap();
+    database
+        .list_low_bitrate_songs(max_bitrate_kbps)
+        .map_err(|e| e.to_string())
+}
+
+/// Analyses a song's spectrum for signs of lossy encoding and stores the report.
+#[tauri::command]
+async fn analyse_song_quality(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+    min_bitrate_kbps: Option<u32>,
+) -> Result<audio::quality::QualityReport, String> {
+    let min_bitrate_kbps = min_bitrate_kbps.unwrap_or(audio::quality::DEFAULT_MIN_BITRATE_KBPS);
+    let report = audio::quality::analyse(Path::new(path), min_bitrate_kbps).map_err(|error| {
+        tracing::error!(?error, path, "failed to analyse song quality");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    database
+        .insert_quality_report(&report)
+        .map_err(|e| e.to_string())?;
+
+    Ok(report)
+}
+
+#[tauri::command]
+async fn get_quality_reports(
+    database: State<'_, Mutex<Database>>,
+) -> Result<Vec<audio::quality::QualityReport>, String> {
+    let database = database.lock().unwrap();
+    database.list_quality_reports().map_err(|e| e.to_string())
+}
+
+/// Detects a song's tempo from its audio and stores it alongside the tagged BPM. Tempos outside
+/// `min_bpm` to `max_bpm` are halved or doubled to fit.
+#[tauri::command]
+async fn detect_song_bpm(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+    min_bpm: Option<f64>,
+    max_bpm: Option<f64>,
+) -> Result<Option<audio::tempo::Tempo>, String> {
+    let bpm_range = bpm_range(min_bpm, max_bpm)?;
+    let tempo = audio::tempo::detect(Path::new(path), bpm_range).map_err(|error| {
+        tracing::error!(?error, path, "failed to detect song bpm");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    database
+        .set_detected_tempo(path, tempo.as_ref())
+        .map_err(|e| e.to_string())?;
+
+    Ok(tempo)
+}
+
+/// Tracks the beats and downbeats through a song and stores its beatgrid. The tempo is kept
+/// within `min_bpm` to `max_bpm` as when detecting BPM.
+#[tauri::command]
+async fn analyse_song_beats(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+    min_bpm: Option<f64>,
+    max_bpm: Option<f64>,
+) -> Result<Option<audio::beats::Beatgrid>, String> {
+    let bpm_range = bpm_range(min_bpm, max_bpm)?;
+    let beatgrid = audio::beats::analyse(Path::new(path), bpm_range).map_err(|error| {
+        tracing::error!(?error, path, "failed to analyse song beats");
+        error.to_string()
+    })?;
+
+    // A song without beats to follow mustn't keep the beatgrid of an earlier analysis.
+    let database = database.lock().unwrap();
+    match &beatgrid {
+        Some(beatgrid) => database.insert_beatgrid(beatgrid),
+        None => database.delete_beatgrid(path),
+    }
+    .map_err(|e| e.to_string())?;
+
+    Ok(beatgrid)
+}
+
+/// The stored beatgrid of a song, `None` when its beats haven't been analysed.
+#[tauri::command]
+async fn get_song_beatgrid(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Option<audio::beats::Beatgrid>, String> {
+    let database = database.lock().unwrap();
+    match database.get_beatgrid(path) {
+        Ok(beatgrid) => Ok(Some(beatgrid)),
+        Err(db::Error::RowNotFound) => Ok(None),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// The stored waveform of a song, `None` when it hasn't been analysed.
+#[tauri::command]
+async fn get_song_waveform(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Option<audio::waveform::Waveform>, String> {
+    let database = database.lock().unwrap();
+    match database.get_waveform(path) {
+        Ok(waveform) => Ok(Some(waveform)),
+        Err(db::Error::RowNotFound) => Ok(None),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// The stored loudness of a song, `None` when it hasn't been measured.
+#[tauri::command]
+async fn get_song_loudness(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Option<audio::loudness::Loudness>, String> {
+    let database = database.lock().unwrap();
+    match database.get_loudness(path) {
+        Ok(loudness) => Ok(Some(loudness)),
+        Err(db::Error::RowNotFound) => Ok(None),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+/// Fills in the default BPM range for detection and checks it's valid.
+fn bpm_range(min_bpm: Option<f64>, max_bpm: Option<f64>) -> Result<(f64, f64), String> {
+    let (default_min_bpm, default_max_bpm) = audio::tempo::DEFAULT_BPM_RANGE;
+    let bpm_range = (
+        min_bpm.unwrap_or(default_min_bpm),
+        max_bpm.unwrap_or(default_max_bpm),
+    );
+    if bpm_range.0 <= 0.0 || bpm_range.1 < bpm_range.0 {
+        return Err(format!("invalid BPM range: {bpm_range:?}"));
+    }
+    Ok(bpm_range)
+}
+
+/// Detects a song's key from its audio and stores it alongside the tagged key.
+#[tauri::command]
+async fn detect_song_key(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<Option<audio::key::DetectedKey>, String> {
+    let detected = audio::key::detect(Path::new(path)).map_err(|error| {
+        tracing::error!(?error, path, "failed to detect song key");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    database
+        .set_detected_key(path, detected.as_ref())
+        .map_err(|e| e.to_string())?;
+
+    Ok(detected)
+}
+
+/// Lists songs whose tagged key differs from the key detected from their audio, ignoring
+/// detections less confident than `min_confidence`.
+#[tauri::command]
+async fn find_key_mismatches(
+    database: State<'_, Mutex<Database>>,
+    min_confidence: Option<f64>,
+) -> Result<Vec<songs::Song>, String> {
+    let min_confidence = min_confidence.unwrap_or(0.0);
+    let database = database.lock().unwrap();
+    let songs = database.list_songs().map_err(|e| e.to_string())?;
+
+    Ok(songs
+        .into_iter()
+        .filter(|song| {
+            song.detected_key_confidence.unwrap_or(0.0) >= min_confidence
+                && matches!(
+                    (song.parsed_key, song.detected_key),
+                    (Some(tagged), Some(detected)) if tagged != detected
+                )
+        })
+        .collect())
+}
+
+/// Queues a job for each path, returning the queued jobs. Jobs already queued for a path are
+/// returned as they are, with their priority raised to `priority` if it's higher.
+#[tauri::command]
+async fn enqueue_jobs(
+    queue: State<'_, JobQueue>,
+    kind: JobKind,
+    paths: Vec<String>,
+    priority: Option<i32>,
+) -> Result<Vec<Job>, String> {
+    paths
+        .iter()
+        .map(|path| {
+            queue
+                .enqueue(&kind, path, priority.unwrap_or(0))
+                .map_err(|error| {
+                    tracing::error!(?error, path, "failed to queue job");
+                    error.to_string()
+                })
+        })
+        .collect()
+}
+
+#[tauri::command]
+async fn list_jobs(queue: State<'_, JobQueue>) -> Result<Vec<Job>, String> {
+    queue.list().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn cancel_job(queue: State<'_, JobQueue>, id: i64) -> Result<Job, String> {
+    queue.cancel(id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_job_priority(
+    queue: State<'_, JobQueue>,
+    id: i64,
+    priority: i32,
+) -> Result<Job, String> {
+    queue.set_priority(id, priority).map_err(|e| e.to_string())
+}
+
+/// Removes finished jobs from the queue, returning how many were removed.
+#[tauri::command]
+async fn clear_finished_jobs(queue: State<'_, JobQueue>) -> Result<usize, String> {
+    queue.clear_finished().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_rekordbox_xml(
+    state: State<'_, Mutex<AppState<'_>>>,
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<(), String> {
+    let recordings = match state.lock().unwrap().recordings_dir.clone() {
+        Some(dir) => fs_search::find_recordings(&dir),
+        None => Vec::new(),
+    };
+
+    let database = database.lock().unwrap();
+    let songs = database.list_songs().map_err(|e| e.to_string())?;
+
+    rekordbox::xml::export(path, &songs, &recordings).map_err(|error| {
+        tracing::error!(?error, path, "failed to export rekordbox xml");
+        error.to_string()
+    })
+}
+
+#[tauri::command]
+async fn get_song_analysis(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<rekordbox::anlz::Analysis, String> {
+    let analysis_file_path = {
+        let database = database.lock().unwrap();
+        database
+            .get_song_analysis_file(path)
+            .map_err(|e| e.to_string())?
+    };
+
+    rekordbox::anlz::Analysis::read(Path::new(&analysis_file_path)).map_err(|error| {
+        tracing::error!(?error, analysis_file_path, "failed to read analysis file");
+        error.to_string()
+    })
+}
+
+#[tauri::command]
+async fn import_device_library(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<rekordbox::pdb::DeviceLibrary, String> {
+    let device_path = Path::new(path);
+    let device_database =
+        rekordbox::pdb::DeviceDatabase::read_device(device_path).map_err(|error| {
+            tracing::error!(?error, path, "failed to read device database");
+            error.to_string()
+        })?;
+    let library = device_database.library(device_path);
+
+    let database = database.lock().unwrap();
+    for song in &library.songs {
+        if let Err(error) = database.insert_song(song) {
+            tracing::error!(?error, "failed to insert song");
+        }
+    }
+
+    Ok(library)
+}
+
+#[tauri::command]
+async fn find_history_matches(
+    state: State<'_, Mutex<AppState<'_>>>,
+    xml_path: Option<&str>,
+    device_path: Option<&str>,
+) -> Result<Vec<rekordbox::history::HistoryMatch>, String> {
+    let mut history = Vec::new();
+
+    if let Some(xml_path) = xml_path {
+        let mut xml_history = rekordbox::history::read_xml_history(xml_path).map_err(|error| {
+            tracing::error!(?error, xml_path, "failed to read rekordbox xml history");
+            error.to_string()
+        })?;
+        history.append(&mut xml_history);
+    }
+
+    if let Some(device_path) = device_path {
+        let mut device_history = rekordbox::history::read_device_history(Path::new(device_path))
+            .map_err(|error| {
+                tracing::error!(?error, device_path, "failed to read device history");
+                error.to_string()
+            })?;
+        history.append(&mut device_history);
+    }
+
+    let recordings = match state.lock().unwrap().recordings_dir.clone() {
+        Some(dir) => fs_search::find_recordings(&dir),
+        None => Vec::new(),
+    };
+
+    Ok(rekordbox::history::match_recordings(history, &recordings))
+}
+
+#[tauri::command]
+async fn import_serato_library(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<serato::Library, String> {
+    let library = serato::read_library(Path::new(path)).map_err(|error| {
+        tracing::error!(?error, path, "failed to read serato library");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    for song in &library.songs {
+        if let Err(error) = database.insert_song(song) {
+            tracing::error!(?error, "failed to insert song");
+        }
+    }
+
+    Ok(library)
+}
+
+#[tauri::command]
+async fn get_serato_markers(path: &str) -> Result<serato::markers::Markers, String> {
+    serato::markers::Markers::read(path).map_err(|error| {
+        tracing::error!(?error, path, "failed to read serato markers");
+        error.to_string()
+    })
+}
+
+#[tauri::command]
+async fn import_traktor_collection(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<traktor::Collection, String> {
+    let collection = traktor::read_collection(path).map_err(|error| {
+        tracing::error!(?error, path, "failed to read traktor collection");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    for entry in &collection.entries {
+        if let Err(error) = database.insert_song(&entry.song) {
+            tracing::error!(?error, "failed to insert song");
+        }
+    }
+
+    Ok(collection)
+}
+
+#[tauri::command]
+async fn find_traktor_history(path: &str) -> Result<Vec<Recording>, String> {
+    let mut recordings = traktor::find_history(path);
+    recordings.sort_by(|a, b| {
+        b.last_modified_unix_seconds
+            .cmp(&a.last_modified_unix_seconds)
+    });
+    Ok(recordings)
+}
+
+#[tauri::command]
+async fn import_mixxx_library(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<mixxx::Library, String> {
+    let library = mixxx::read_library(path).map_err(|error| {
+        tracing::error!(?error, path, "failed to read mixxx library");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    for track in &library.tracks {
+        if let Err(error) = database.insert_song(&track.song) {
+            tracing::error!(?error, "failed to insert song");
+        }
+    }
+
+    Ok(library)
+}
+
+#[tauri::command]
+async fn import_engine_dj_library(
+    database: State<'_, Mutex<Database>>,
+    path: &str,
+) -> Result<engine_dj::Library, String> {
+    let library = engine_dj::read_library(path).map_err(|error| {
+        tracing::error!(?error, path, "failed to read engine dj library");
+        error.to_string()
+    })?;
+
+    let database = database.lock().unwrap();
+    for track in &library.tracks {
+        if let Err(error) = database.insert_song(&track.song) {
+            tracing::error!(?error, "failed to insert song");
+        }
+    }
+
+    Ok(library)
+}
+
+/// Serves the cover art thumbnail of the song whose (percent encoded) file path is the URI path,
+/// e.g. `artwork://localhost/%2FMusic%2Fsong.mp3`.
+fn artwork_response(app: &tauri::AppHandle, uri_path: &str) -> tauri::http::Response<Vec<u8>> {
+    let song_path = artwork::decode_uri_path(uri_path.trim_start_matches('/'));
+    let known_hash = {
+        let database = app.state::<Mutex<Database>>();
+        let database = database.lock().unwrap();
+        database
+            .get_song(&song_path)
+            .ok()
+            .and_then(|song| song.artwork_hash)
+    };
+
+    let thumbnail = app
+        .path()
+        .app_cache_dir()
+        .map_err(|error| error.to_string())
+        .and_then(|cache_dir| {
+            artwork::thumbnail(
+                &song_path,
+                known_hash.as_deref(),
+                &cache_dir.join("artwork"),
+            )
+            .map_err(|error| error.to_string())
+        })
+        .and_then(|thumbnail| match thumbnail {
+            Some(thumbnail) => std::fs::read(thumbnail).map_err(|error| error.to_string()),
+            None => Err("song has no artwork".to_string()),
+        });
+
+    let response = tauri::http::Response::builder();
+    match thumbnail {
+        Ok(data) => response
+            .status(200)
+            .header("Content-Type", "image/jpeg")
+            .body(data),
+        Err(error) => {
+            tracing::debug!(error, song_path, "no artwork to serve");
+            response.status(404).body(Vec::new())
+        }
+    }
+    .unwrap_or_default()
+}
+
+/// Runs an analysis job, storing its results in the library database unless it's cancelled.
+/// Progress through the file being analysed is sent to the frontend as it's decoded.
+fn run_job(app: &tauri::AppHandle, job: &Job, cancellation: &Cancellation) -> Result<(), String> {
+    let progress_handle = app.clone();
+    let id = job.id;
+    let monitor = audio::Monitor::new(cancellation.flag(), move |progress| {
+        if let Err(error) = progress_handle.emit("job-progress", JobProgress { id, progress }) {
+            tracing::error!(?error, "failed to emit job progress");
+        }
+    });
+    let store = audio::monitored(monitor, || analyse_job(job))?;
+
+    if cancellation.is_cancelled() {
+        return Ok(());
+    }
+    let database = app.state::<Mutex<Database>>();
+    let database = database.lock().unwrap();
+    store(&database)
+}
+
+/// Stores the results of a job in the library database.
+type Store = Box<dyn FnOnce(&Database) -> Result<(), String>>;
+
+/// Does the work of an analysis job, returning how to store its results.
+fn analyse_job(job: &Job) -> Result<Store, String> {
+    let path = Path::new(&job.path);
+    let store: Store = match &job.kind {
+        JobKind::Quality { min_bitrate_kbps } => {
+            let min_bitrate_kbps =
+                min_bitrate_kbps.unwrap_or(audio::quality::DEFAULT_MIN_BITRATE_KBPS);
+            let report =
+                audio::quality::analyse(path, min_bitrate_kbps).map_err(|e| e.to_string())?;
+            Box::new(move |database| {
+                database
+                    .insert_quality_report(&report)
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Bpm { min_bpm, max_bpm } => {
+            let tempo = audio::tempo::detect(path, bpm_range(*min_bpm, *max_bpm)?)
+                .map_err(|e| e.to_string())?;
+            let file_path = job.path.clone();
+            Box::new(move |database| {
+                database
+                    .set_detected_tempo(&file_path, tempo.as_ref())
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Fingerprint => {
+            let fingerprint = audio::fingerprint::fingerprint(path).map_err(|e| e.to_string())?;
+            let file_path = job.path.clone();
+            Box::new(move |database| {
+                database
+                    .insert_fingerprint(&file_path, &fingerprint)
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Waveform => {
+            let waveform = audio::waveform::analyse(path).map_err(|e| e.to_string())?;
+            Box::new(move |database| {
+                database
+                    .insert_waveform(&waveform)
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Loudness => {
+            let loudness = audio::loudness::analyse(path).map_err(|e| e.to_string())?;
+            Box::new(move |database| {
+                database
+                    .insert_loudness(&loudness)
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Key => {
+            let detected = audio::key::detect(path).map_err(|e| e.to_string())?;
+            let file_path = job.path.clone();
+            Box::new(move |database| {
+                database
+                    .set_detected_key(&file_path, detected.as_ref())
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Beats { min_bpm, max_bpm } => {
+            let beatgrid = audio::beats::analyse(path, bpm_range(*min_bpm, *max_bpm)?)
+                .map_err(|e| e.to_string())?;
+            let file_path = job.path.clone();
+            Box::new(move |database| {
+                match &beatgrid {
+                    Some(beatgrid) => database.insert_beatgrid(beatgrid),
+                    None => database.delete_beatgrid(&file_path),
+                }
+                .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Alignment => {
+            let alignments = align_songs(&job.path)?;
+            let recording_path = job.path.clone();
+            Box::new(move |database| {
+                database
+                    .insert_alignments(&recording_path, &alignments)
+                    .map_err(|e| e.to_string())
+            })
+        }
+        JobKind::Transitions => {
+            let transitions = detect_transitions(&job.path)?;
+            let recording_path = job.path.clone();
+            Box::new(move |database| {
+                database
+                    .insert_transitions(&recording_path, &transitions)
+                    .map_err(|e| e.to_string())
+            })
+        }
+    };
+    Ok(store)
+}
+
+/// Opens the job database in the app data directory, falling back to keeping jobs in memory.
+fn open_job_store(app: &tauri::AppHandle) -> JobStore {
+    let opened = app
+        .path()
+        .app_data_dir()
+        .map_err(|error| error.to_string())
+        .and_then(|dir| {
+            std::fs::create_dir_all(&dir).map_err(|error| error.to_string())?;
+            JobStore::open(&dir.join("jobs.sqlite")).map_err(|error| error.to_string())
+        });
+
+    match opened {
+        Ok(store) => store,
+        Err(error) => {
+            tracing::error!(
+                error,
+                "failed to open job database, jobs won't survive a restart"
+            );
+            JobStore::open_in_memory().unwrap()
+        }
+    }
+}
+
+/// Opens the library database in the app data directory, falling back to keeping it in memory.
+fn open_database(app: &tauri::AppHandle) -> Database {
+    let opened = app
+        .path()
+        .app_data_dir()
+        .map_err(|error| error.to_string())
+        .and_then(|dir| {
+            std::fs::create_dir_all(&dir).map_err(|error| error.to_string())?;
+            Database::open(&dir.join("library.sqlite")).map_err(|error| error.to_string())
+        });
+
+    match opened {
+        Ok(database) => database,
+        Err(error) => {
+            tracing::error!(
+                error,
+                "failed to open library database, analysis won't survive a restart"
+            );
+            Database::init().unwrap()
+        }
+    }
+}
+
+/// Hashes the audio data of songs that haven't been hashed since their file was last read, see
+/// `content_hash`. Hashes are kept in the database, so only new and modified files are read.
+fn hash_songs(database: &Mutex<Database>) {
+    let file_paths = match database.lock().unwrap().list_unhashed_songs() {
+        Ok(file_paths) => file_paths,
+        Err(error) => {
+            tracing::error!(?error, "failed to list unhashed songs");
+            return;
+        }
+    };
+
+    // Songs whose file has gone missing can't be hashed, their last hash is what relinks them.
+    for file_path in file_paths
+        .iter()
+        .filter(|file_path| Path::new(file_path).exists())
+    {
+        let hash = match content_hash::hash(Path::new(file_path)) {
+            Ok(hash) => hash,
+            Err(error) => {
+                tracing::warn!(?error, file_path, "failed to hash song");
+                continue;
+            }
+        };
+        if let Err(error) = database.lock().unwrap().set_content_hash(file_path, &hash) {
+            tracing::error!(?error, file_path, "failed to store content hash");
+        }
+    }
+}
+
+/// Loads songs from the music dir into the database and links them to their Rekordbox analysis.
+fn load_library(database: &Database, state: &AppState) {
+    // Seed the database with songs from the music dir, updating songs kept from previous sessions
+    // in case their files changed.
+    match state.music_dir.clone().map(|dir| dir.to_string()) {
         Some(music_dir) => {
             for song in fs_search::find_songs(&music_dir) {
-                if let Err(error) = database.insert_song(&song) {
+                if let Err(error) = database.update_song(&song) {
                     tracing::error!(?error, "failed to insert song");
                 }
             }
@@ -130,20 +1428,124 @@ pub fn run() {
         }
     };
 
+    // Link songs to the analysis files Rekordbox created for them.
+    match state.rekordbox_dir.clone().map(|dir| dir.to_string()) {
+        Some(rekordbox_dir) => {
+            let analysis_dir = Path::new(&rekordbox_dir).join("share/PIONEER/USBANLZ");
+            let songs = database.list_songs().unwrap_or_default();
+            let song_index = rekordbox::anlz::SongIndex::new(&songs);
+            for file in rekordbox::anlz::find_analysis_files(&analysis_dir) {
+                let song_path = match rekordbox::anlz::read_song_path(&file) {
+                    Ok(song_path) => song_path,
+                    Err(error) => {
+                        tracing::warn!(?error, ?file, "failed to read analysis file");
+                        continue;
+                    }
+                };
+                let Some(song) = song_path.as_deref().and_then(|path| song_index.find(path)) else {
+                    continue;
+                };
+                if let Err(error) =
+                    database.insert_song_analysis_file(&song.file_path, &file.to_string_lossy())
+                {
+                    tracing::error!(?error, "failed to insert song analysis file");
+                }
+            }
+        }
+        None => {
+            tracing::warn!("rekordbox dir not set, can't link songs to their analysis");
+        }
+    };
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let app_state = AppState::default();
+
+    tracing::info!(state=?app_state, "dbeat starting");
+
     tauri::Builder::default()
-        .setup(|app| {
-            app.manage(Mutex::new(AppState::default()));
+        .setup(move |app| {
+            let database = open_database(app.handle());
+            load_library(&database, &app_state);
+            app.manage(Mutex::new(app_state));
             app.manage(Mutex::new(database));
+
+            // Hashing reads every new file, so it's done after startup rather than holding it up.
+            let hash_handle = app.handle().clone();
+            std::thread::spawn(move || hash_songs(&hash_handle.state::<Mutex<Database>>()));
+
+            // Analyses are CPU heavy, half the cores leaves the UI and playback responsive.
+            let workers = std::thread::available_parallelism()
+                .map_or(1, |cores| cores.get() / 2)
+                .max(1);
+            let run_handle = app.handle().clone();
+            let update_handle = app.handle().clone();
+            let queue = JobQueue::start(
+                open_job_store(app.handle()),
+                workers,
+                move |job, cancellation| run_job(&run_handle, job, cancellation),
+                move |update| {
+                    if let Err(error) = update_handle.emit("job-updated", update) {
+                        tracing::error!(?error, "failed to emit job update");
+                    }
+                },
+            );
+            app.manage(queue);
             Ok(())
         })
+        .register_uri_scheme_protocol("artwork", |ctx, request| {
+            artwork_response(ctx.app_handle(), request.uri().path())
+        })
         .invoke_handler(tauri::generate_handler![
             get_recordings_dir,
             get_music_dir,
             get_recording,
+            detect_recording_transitions,
+            get_recording_transitions,
+            align_recording_songs,
+            get_recording_alignments,
+            correct_recording_cue_sheet,
+            find_songs_by_audio,
+            find_bare_recordings,
+            reconstruct_tracklist,
+            save_recording_cue_sheet,
             find_recordings,
             open_file_location,
             find_songs,
+            find_songs_in_key,
+            find_duplicate_songs,
+            find_missing_songs,
+            relink_songs,
+            find_low_bitrate_songs,
+            write_song_tags,
+            preview_tag_cleanup,
+            apply_tag_cleanup,
+            analyse_song_quality,
+            get_quality_reports,
+            detect_song_bpm,
+            detect_song_key,
+            analyse_song_beats,
+            get_song_beatgrid,
+            get_song_waveform,
+            get_song_loudness,
+            find_key_mismatches,
+            enqueue_jobs,
+            list_jobs,
+            cancel_job,
+            set_job_priority,
+            clear_finished_jobs,
             get_song,
+            export_rekordbox_xml,
+            get_song_analysis,
+            import_device_library,
+            find_history_matches,
+            import_serato_library,
+            get_serato_markers,
+            import_traktor_collection,
+            find_traktor_history,
+            import_mixxx_library,
+            import_engine_dj_library,
         ])
         .run(tauri::generate_context!())
         .unwrap_or_else(|error| {
@@ -0,0 +1,416 @@
+//! Read only importer for Mixxx's library database (`mixxxdb.sqlite`).
+//!
+//! Tracks are in the `library` table with their paths in `track_locations`, crates and playlists
+//! reference tracks by id. Mixxx keeps its play history as hidden "set log" playlists, one per
+//! session, which are converted to recordings.
+
+use std::collections::HashMap;
+
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+
+use crate::{
+    recording::{self, Recording},
+    songs::{key::Key, Song},
+};
+
+/// The `Playlists.hidden` value Mixxx uses for history (set log) playlists.
+const HIDDEN_SET_LOG: i64 = 2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("rusqlite: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CueKind {
+    HotCue,
+    MainCue,
+    Loop,
+    Jump,
+    Intro,
+    Outro,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cue {
+    pub kind: CueKind,
+    pub position_ms: f64,
+    /// The length of loops and intro/outro ranges, 0 for point cues.
+    pub length_ms: f64,
+    /// The hot cue slot starting at 0.
+    pub hot_cue: Option<u32>,
+    pub label: Option<String>,
+}
+
+/// A Mixxx track converted to a song along with the Mixxx specific analysis.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Track {
+    pub song: Song,
+    pub key: Option<String>,
+    pub cues: Vec<Cue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Crate {
+    pub name: String,
+    pub song_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Library {
+    pub tracks: Vec<Track>,
+    /// Both crates and (non history) playlists, Mixxx treats them as different views of the
+    /// same thing.
+    pub crates: Vec<Crate>,
+    /// One recording per history session.
+    pub history: Vec<Recording>,
+}
+
+/// Reads the library from a Mixxx database, the database is opened read only.
+pub fn read_library(path: &str) -> Result<Library, Error> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+    let mut tracks = read_tracks(&conn)?;
+    let paths: HashMap<i64, String> = tracks
+        .iter()
+        .map(|(id, track)| (*id, track.song.file_path.clone()))
+        .collect();
+
+    for (track_id, cue) in read_cues(&conn)? {
+        if let Some(track) = tracks.get_mut(&track_id) {
+            track.cues.push(cue);
+        }
+    }
+
+    let mut crates = read_crates(&conn, &paths)?;
+    crates.append(&mut read_playlists(&conn, &paths)?);
+
+    Ok(Library {
+        tracks: tracks.into_values().collect(),
+        crates,
+        history: read_history(&conn, path, &paths)?,
+    })
+}
+
+fn read_tracks(conn: &Connection) -> rusqlite::Result<HashMap<i64, Track>> {
+    let mut statement = conn.prepare(
+        "SELECT library.id, track_locations.location, library.title, library.artist,
+            library.album, library.genre, library.bpm, library.duration, library.key
+        FROM library
+        JOIN track_locations ON track_locations.id = library.location
+        WHERE library.mixxx_deleted = 0",
+    )?;
+
+    let rows = statement.query_map([], |row| {
+        let bpm: Option<f64> = row.get(6)?;
+        let duration: Option<f64> = row.get(7)?;
+        let key: Option<String> = row
+            .get::<_, Option<String>>(8)?
+            .filter(|key| !key.is_empty());
+
+        Ok((
+            row.get(0)?,
+            Track {
+                song: Song {
+                    file_path: row.get(1)?,
+                    title: row.get(2)?,
+                    artist: row.get(3)?,
+                    album: row.get(4)?,
+                    genre: row.get(5)?,
+                    bpm: bpm.filter(|bpm| *bpm > 0.0),
+                    duration_seconds: duration.unwrap_or(0.0) as u64,
+                    key: key.clone(),
+                    parsed_key: key.as_deref().and_then(Key::parse),
+                    ..Default::default()
+                },
+                key,
+                cues: Vec::new(),
+            },
+        ))
+    })?;
+
+    rows.collect()
+}
+
+/// Cue positions are stored in samples counting both channels, they're converted to
+/// milliseconds using the track's sample rate.
+fn read_cues(conn: &Connection) -> rusqlite::Result<Vec<(i64, Cue)>> {
+    let mut statement = conn.prepare(
+        "SELECT cues.track_id, cues.type, cues.position, cues.length, cues.hotcue, cues.label,
+            library.samplerate
+        FROM cues
+        JOIN library ON library.id = cues.track_id
+        WHERE cues.position >= 0",
+    )?;
+
+    let rows = statement.query_map([], |row| {
+        let sample_rate: Option<f64> = row.get(6)?;
+        let samples_per_ms = sample_rate.unwrap_or(44100.0) * 2.0 / 1000.0;
+        let position: f64 = row.get(2)?;
+        let length: f64 = row.get(3)?;
+        let hot_cue: i64 = row.get(4)?;
+        let label: Option<String> = row.get(5)?;
+
+        Ok((
+            row.get(0)?,
+            Cue {
+                kind: match row.get::<_, i64>(1)? {
+                    1 => CueKind::HotCue,
+                    2 => CueKind::MainCue,
+                    4 => CueKind::Loop,
+                    5 => CueKind::Jump,
+                    6 => CueKind::Intro,
+                    7 => CueKind::Outro,
+                    _ => CueKind::Other,
+                },
+                position_ms: position / samples_per_ms,
+                length_ms: length / samples_per_ms,
+                hot_cue: u32::try_from(hot_cue).ok(),
+                label: label.filter(|label| !label.is_empty()),
+            },
+        ))
+    })?;
+
+    rows.collect()
+}
+
+fn read_crates(conn: &Connection, paths: &HashMap<i64, String>) -> rusqlite::Result<Vec<Crate>> {
+    let mut statement = conn.prepare(
+        "SELECT crates.id, crates.name, crate_tracks.track_id
+        FROM crates
+        JOIN crate_tracks ON crate_tracks.crate_id = crates.id
+        ORDER BY crates.name, crates.id",
+    )?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+    Ok(group_rows(rows.collect::<rusqlite::Result<_>>()?, paths))
+}
+
+fn read_playlists(conn: &Connection, paths: &HashMap<i64, String>) -> rusqlite::Result<Vec<Crate>> {
+    let mut statement = conn.prepare(
+        "SELECT Playlists.id, Playlists.name, PlaylistTracks.track_id
+        FROM Playlists
+        JOIN PlaylistTracks ON PlaylistTracks.playlist_id = Playlists.id
+        WHERE Playlists.hidden = 0
+        ORDER BY Playlists.position, Playlists.id, PlaylistTracks.position",
+    )?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+
+    Ok(group_rows(rows.collect::<rusqlite::Result<_>>()?, paths))
+}
+
+/// Groups (id, name, track id) rows ordered by id into crates, tracks which were deleted from
+/// the library are skipped.
+fn group_rows(rows: Vec<(i64, String, i64)>, paths: &HashMap<i64, String>) -> Vec<Crate> {
+    let mut crates: Vec<(i64, Crate)> = Vec::new();
+
+    for (id, name, track_id) in rows {
+        if crates.last().map(|(last_id, _)| *last_id) != Some(id) {
+            crates.push((
+                id,
+                Crate {
+                    name,
+                    song_paths: Vec::new(),
+                },
+            ));
+        }
+        if let (Some(path), Some((_, last))) = (paths.get(&track_id), crates.last_mut()) {
+            last.song_paths.push(path.clone());
+        }
+    }
+
+    crates.into_iter().map(|(_, crate_)| crate_).collect()
+}
+
+/// Converts each set log playlist to a recording, track start times are the times each track
+/// was added to the log relative to the first.
+fn read_history(
+    conn: &Connection,
+    database_path: &str,
+    paths: &HashMap<i64, String>,
+) -> rusqlite::Result<Vec<Recording>> {
+    let mut statement = conn.prepare(
+        "SELECT Playlists.id, Playlists.name, PlaylistTracks.track_id, library.title,
+            library.artist, CAST(strftime('%s', PlaylistTracks.pl_datetime_added) AS INTEGER)
+        FROM Playlists
+        JOIN PlaylistTracks ON PlaylistTracks.playlist_id = Playlists.id
+        JOIN library ON library.id = PlaylistTracks.track_id
+        WHERE Playlists.hidden = ?1
+        ORDER BY Playlists.id, PlaylistTracks.position",
+    )?;
+
+    let mut recordings: Vec<(i64, i64, Recording)> = Vec::new();
+    let mut rows = statement.query([HIDDEN_SET_LOG])?;
+
+    while let Some(row) = rows.next()? {
+        let playlist_id: i64 = row.get(0)?;
+        let played_at: i64 = row.get::<_, Option<i64>>(5)?.unwrap_or(0);
+
+        if recordings.last().map(|(id, _, _)| *id) != Some(playlist_id) {
+            recordings.push((
+                playlist_id,
+                played_at,
+                Recording {
+                    // History isn't stored in its own file, the playlist id keeps paths unique.
+                    file_path: format!("{database_path}#{playlist_id}"),
+                    title: row.get(1)?,
+                    ..Default::default()
+                },
+            ));
+        }
+        let Some((_, first_played_at, recording)) = recordings.last_mut() else {
+            continue;
+        };
+
+        let path = paths.get(&row.get(2)?);
+        recording.last_modified_unix_seconds = recording.last_modified_unix_seconds.max(played_at);
+        recording.tracks.push(recording::Track {
+            title: row.get(3)?,
+            performer: row.get(4)?,
+            file: path.map(|path| recording::File::from_path(path)),
+            start_time: Some(recording::format_start_time(
+                (played_at - *first_played_at).max(0) as u64,
+            )),
+        });
+    }
+
+    Ok(recordings
+        .into_iter()
+        .map(|(_, _, recording)| recording)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const SCHEMA: &str = "
+        CREATE TABLE track_locations (id INTEGER PRIMARY KEY, location TEXT);
+        CREATE TABLE library (
+            id INTEGER PRIMARY KEY, location INTEGER, title TEXT, artist TEXT, album TEXT,
+            genre TEXT, bpm REAL, duration REAL, key TEXT, samplerate INTEGER,
+            mixxx_deleted INTEGER
+        );
+        CREATE TABLE cues (
+            track_id INTEGER, type INTEGER, position INTEGER, length INTEGER, hotcue INTEGER,
+            label TEXT
+        );
+        CREATE TABLE crates (id INTEGER PRIMARY KEY, name TEXT);
+        CREATE TABLE crate_tracks (crate_id INTEGER, track_id INTEGER);
+        CREATE TABLE Playlists (id INTEGER PRIMARY KEY, name TEXT, position INTEGER, hidden INTEGER);
+        CREATE TABLE PlaylistTracks (
+            playlist_id INTEGER, track_id INTEGER, position INTEGER, pl_datetime_added TEXT
+        );
+
+        INSERT INTO track_locations VALUES
+            (1, '/music/a.mp3'), (2, '/music/deleted.mp3'), (3, '/music/c.flac');
+        INSERT INTO library VALUES
+            (1, 1, 'A', 'Artist', 'Album', 'House', 124.0, 300.5, 'Am', 44100, 0),
+            (2, 2, 'Deleted', 'Artist', NULL, NULL, 120.0, 200.0, NULL, 44100, 1),
+            (3, 3, 'C', 'Artist', NULL, NULL, 0.0, 400.0, '', 48000, 0);
+        -- Positions count the samples of both channels.
+        INSERT INTO cues VALUES
+            (1, 1, 176400, 0, 0, 'Drop'),
+            (1, 4, 88200, 352800, -1, ''),
+            (1, 2, -1, 0, -1, ''),
+            (3, 6, 96000, 0, -1, NULL);
+        INSERT INTO crates VALUES (1, 'Warmup');
+        INSERT INTO crate_tracks VALUES (1, 1), (1, 2);
+        -- Playlists hidden as 1 are Auto DJ, as 2 set logs.
+        INSERT INTO Playlists VALUES
+            (1, 'Favourites', 1, 0), (2, '2024-03-01', 2, 2), (3, 'Auto DJ', 3, 1);
+        INSERT INTO PlaylistTracks VALUES
+            (1, 3, 1, NULL), (1, 1, 2, NULL),
+            (2, 1, 1, '2024-03-01 22:00:00'), (2, 3, 2, '2024-03-01 22:05:30'),
+            (3, 1, 1, NULL);
+    ";
+
+    #[test]
+    fn reads_libraries() {
+        let dir = TempDir::new("mixxx");
+        let path = dir.join("mixxxdb.sqlite");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(SCHEMA)
+            .unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut library = read_library(path).unwrap();
+        library
+            .tracks
+            .sort_by(|a, b| a.song.file_path.cmp(&b.song.file_path));
+        assert_eq!(library.tracks.len(), 2);
+
+        let a = &library.tracks[0];
+        assert_eq!(a.song.file_path, "/music/a.mp3");
+        assert_eq!(a.song.title.as_deref(), Some("A"));
+        assert_eq!(a.song.bpm, Some(124.0));
+        assert_eq!(a.song.duration_seconds, 300);
+        assert_eq!(a.song.parsed_key, Key::parse("Am"));
+        let cues: Vec<_> = a
+            .cues
+            .iter()
+            .map(|cue| {
+                (
+                    cue.kind,
+                    cue.position_ms,
+                    cue.length_ms,
+                    cue.hot_cue,
+                    cue.label.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            cues,
+            vec![
+                (CueKind::HotCue, 2000.0, 0.0, Some(0), Some("Drop")),
+                (CueKind::Loop, 1000.0, 4000.0, None, None),
+            ]
+        );
+
+        let c = &library.tracks[1];
+        assert_eq!(c.song.bpm, None);
+        assert_eq!(c.key, None);
+        assert_eq!(c.cues.len(), 1);
+        assert_eq!(c.cues[0].kind, CueKind::Intro);
+        assert_eq!(c.cues[0].position_ms, 1000.0);
+
+        let crates: Vec<(&str, &[String])> = library
+            .crates
+            .iter()
+            .map(|crate_| (crate_.name.as_str(), crate_.song_paths.as_slice()))
+            .collect();
+        assert_eq!(
+            crates,
+            vec![
+                ("Warmup", &["/music/a.mp3".to_string()][..]),
+                (
+                    "Favourites",
+                    &["/music/c.flac".to_string(), "/music/a.mp3".to_string()][..]
+                ),
+            ]
+        );
+
+        assert_eq!(library.history.len(), 1);
+        let history = &library.history[0];
+        assert_eq!(history.file_path, format!("{path}#2"));
+        assert_eq!(history.title.as_deref(), Some("2024-03-01"));
+        // 2024-03-01 22:05:30 UTC.
+        assert_eq!(history.last_modified_unix_seconds, 1_709_330_730);
+        let tracks: Vec<(Option<&str>, Option<u64>)> = history
+            .tracks
+            .iter()
+            .map(|track| (track.title.as_deref(), track.start_seconds()))
+            .collect();
+        assert_eq!(tracks, vec![(Some("A"), Some(0)), (Some("C"), Some(330))]);
+    }
+}
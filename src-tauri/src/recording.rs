@@ -18,6 +18,13 @@ pub struct Track {
     pub start_time: Option<String>,
 }
 
+impl Track {
+    /// The start time in seconds from the start of the recording.
+    pub fn start_seconds(&self) -> Option<u64> {
+        self.start_time.as_deref().and_then(parse_start_time)
+    }
+}
+
 /// File metadata extracted from a cue sheet pointing to a local file.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -26,6 +33,20 @@ pub struct File {
     pub format: String,
 }
 
+impl File {
+    /// Creates a file entry for a song path, using the upper case extension as the format the
+    /// same way cue sheets do, e.g. 'MP3'.
+    pub fn from_path(path: &str) -> Self {
+        Self {
+            name: path.to_string(),
+            format: std::path::Path::new(path)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_uppercase())
+                .unwrap_or_default(),
+        }
+    }
+}
+
 /// Data extracted from a wave (.wav) file associated with a mix, necessary for getting the
 /// duration and other potentially useful info to display.
 ///
@@ -42,6 +63,9 @@ pub struct WaveFile {
     pub sample_format: String,
     pub duration_seconds: f64,
     pub total_samples: u32,
+    /// Last modified (unix seconds) taken from the wave file's metadata, the recorder stops
+    /// writing to it when the recording ends.
+    pub last_modified_unix_seconds: Option<i64>,
 }
 
 /// Represents a recording/mix from Rekordbox, instead of supporting all cue sheet syntax we only
@@ -93,18 +117,16 @@ impl Recording {
             let line = line.trim();
             let parts: Vec<&str> = line.split_whitespace().collect();
 
-            if parts.len() == 0 {
+            if parts.is_empty() {
                 continue;
             }
 
             match parts[0] {
                 // REM is like a comment containing metadata, we store it as a key value pair.
-                "REM" => {
-                    if parts.len() >= 3 {
-                        let key = parts[1].to_string();
-                        let value = parts[2..].join(" ").trim_matches('"').to_string();
-                        recording.rem.push((key, value));
-                    }
+                "REM" if parts.len() >= 3 => {
+                    let key = parts[1].to_string();
+                    let value = parts[2..].join(" ").trim_matches('"').to_string();
+                    recording.rem.push((key, value));
                 }
                 // TITLE can be part of the entire recording or a TRACK.
                 "TITLE" => {
@@ -166,16 +188,336 @@ impl Recording {
 
         recording
     }
+
+    /// Writes the recording as a cue sheet laid out the same way Rekordbox exports them, so it
+    /// can be read back with `parse`.
+    pub fn to_cue(&self) -> String {
+        let mut output = String::new();
+        for (key, value) in &self.rem {
+            output.push_str(&format!("REM {key} {value}\n"));
+        }
+        if let Some(title) = &self.title {
+            output.push_str(&format!("TITLE {}\n", quote(title)));
+        }
+        if let Some(performer) = &self.performer {
+            output.push_str(&format!("PERFORMER {}\n", quote(performer)));
+        }
+        if let Some(file) = &self.file {
+            output.push_str(&format!("FILE {} {}\n", quote(&file.name), file.format));
+        }
+
+        for (index, track) in self.tracks.iter().enumerate() {
+            output.push_str(&format!("\tTRACK {:02} AUDIO\n", index + 1));
+            if let Some(title) = &track.title {
+                output.push_str(&format!("\t\tTITLE {}\n", quote(title)));
+            }
+            if let Some(performer) = &track.performer {
+                output.push_str(&format!("\t\tPERFORMER {}\n", quote(performer)));
+            }
+            if let Some(file) = &track.file {
+                output.push_str(&format!("\t\tFILE {} {}\n", quote(&file.name), file.format));
+            }
+            if let Some(start_time) = &track.start_time {
+                output.push_str(&format!("\t\tINDEX 01 {start_time}\n"));
+            }
+        }
+
+        output
+    }
+}
+
+/// Formats a number of seconds from the start of a recording the same way as
+/// `Track::start_time`, e.g. '00:24:36'.
+pub fn format_start_time(seconds: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Replaces the start times of tracks in the text of a cue sheet, keeping everything else as it
+/// was. `start_times` are the indexes of tracks and their new start time in seconds.
+pub fn set_start_times(input: &str, start_times: &[(usize, u64)]) -> String {
+    let mut track_index = None;
+    input
+        .split_inclusive('\n')
+        .map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                ["TRACK", ..] => {
+                    track_index = Some(track_index.map_or(0, |index| index + 1));
+                }
+                // Index 01 is where the track starts, other indexes mark gaps before it.
+                ["INDEX", "01", _] => {
+                    let start_time = start_times
+                        .iter()
+                        .find(|(index, _)| Some(*index) == track_index)
+                        .map(|(_, seconds)| format_start_time(*seconds));
+                    if let Some(start_time) = start_time {
+                        let indent = &line[..line.len() - line.trim_start().len()];
+                        let ending = &line[line.trim_end().len()..];
+                        return format!("{indent}INDEX 01 {start_time}{ending}");
+                    }
+                }
+                _ => {}
+            }
+            line.to_string()
+        })
+        .collect()
+}
+
+/// Replaces a cue sheet with new contents, first copying the original to a sibling '.cue.bak'
+/// file. The modification time is kept since it's used to date recordings without one.
+pub fn rewrite_cue_sheet(path: &str, contents: &str) -> std::io::Result<()> {
+    let path = std::path::Path::new(path);
+    let modified_time = filetime::FileTime::from_last_modification_time(&std::fs::metadata(path)?);
+    std::fs::copy(path, path.with_extension("cue.bak"))?;
+    std::fs::write(path, contents)?;
+    filetime::set_file_mtime(path, modified_time)
+}
+
+/// Replaces the file paths of tracks in the text of a cue sheet, keeping everything else as it
+/// was. `file_paths` are pairs of old and new paths, the format is updated to the new file's.
+pub fn set_file_paths(input: &str, file_paths: &[(String, String)]) -> String {
+    input
+        .split_inclusive('\n')
+        .map(|line| {
+            let trimmed = line.trim();
+            let Some(rest) = trimmed.strip_prefix("FILE ") else {
+                return line.to_string();
+            };
+            let name = extract_quoted_string(rest);
+            let new_path = file_paths
+                .iter()
+                .find(|(old_path, _)| name.as_deref() == Some(old_path.as_str()))
+                .map(|(_, new_path)| new_path);
+            match new_path {
+                Some(new_path) => {
+                    let file = File::from_path(new_path);
+                    let indent = &line[..line.len() - line.trim_start().len()];
+                    let ending = &line[line.trim_end().len()..];
+                    format!("{indent}FILE {} {}{ending}", quote(&file.name), file.format)
+                }
+                None => line.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Replaces the file paths of tracks in a cue sheet file, see `set_file_paths`. The file is only
+/// rewritten, with a backup, when a path changed. Returns whether it was rewritten.
+pub fn relink_cue_sheet(path: &str, file_paths: &[(String, String)]) -> std::io::Result<bool> {
+    let input = std::fs::read_to_string(path)?;
+    let output = set_file_paths(&input, file_paths);
+    if output == input {
+        return Ok(false);
+    }
+    rewrite_cue_sheet(path, &output)?;
+    Ok(true)
 }
 
-/// Extracts the text between two quotes, falling back to the whole string
+/// Parses a start time formatted like `Track::start_time` back to seconds, also accepting
+/// 'MM:SS'.
+pub fn parse_start_time(start_time: &str) -> Option<u64> {
+    start_time.trim().split(':').try_fold(0, |seconds, part| {
+        Some(seconds * 60 + part.parse::<u64>().ok()?)
+    })
+}
+
+/// Extracts the text between two quotes, where `\"` is a quote within the text, falling back to
+/// the whole string
 fn extract_quoted_string(line: &str) -> Option<String> {
     if let Some(start) = line.find('"') {
-        if let Some(end) = line[start + 1..].find('"') {
-            return Some(line[start + 1..start + 1 + end].to_string());
+        let mut text = String::new();
+        let mut chars = line[start + 1..].chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.peek() == Some(&'"') => text.extend(chars.next()),
+                '"' => return Some(text),
+                c => text.push(c),
+            }
         }
     }
 
     // Fallback for unquoted strings: take whole string
     Some(line.trim().to_string())
 }
+
+/// Quotes text for a cue sheet, escaping any quotes within it so they're read back by `parse`.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const CUE_SHEET: &str = "REM DATE 2024-03-01\r
+TITLE \"Friday\"\r
+FILE \"Friday.wav\" WAVE\r
+\tTRACK 01 AUDIO\r
+\t\tTITLE \"First\"\r
+\t\tFILE \"/music/first.mp3\" MP3\r
+\t\tINDEX 01 00:00:00\r
+\tTRACK 02 AUDIO\r
+\t\tTITLE \"Second\"\r
+\t\tFILE \"/music/second.mp3\" MP3\r
+\t\tINDEX 01 00:03:10\r
+";
+
+    #[test]
+    fn formats_and_parses_start_times() {
+        assert_eq!(format_start_time(0), "00:00:00");
+        assert_eq!(format_start_time(3_723), "01:02:03");
+        assert_eq!(parse_start_time("01:02:03"), Some(3_723));
+        assert_eq!(parse_start_time("62:03"), Some(3_723));
+        assert_eq!(parse_start_time("1:xx"), None);
+    }
+
+    #[test]
+    fn sets_start_times_keeping_the_rest() {
+        let output = set_start_times(CUE_SHEET, &[(1, 185)]);
+        assert_eq!(
+            output,
+            CUE_SHEET.replace("INDEX 01 00:03:10", "INDEX 01 00:03:05")
+        );
+        assert_eq!(set_start_times(CUE_SHEET, &[]), CUE_SHEET);
+    }
+
+    #[test]
+    fn sets_file_paths_and_formats() {
+        let output = set_file_paths(
+            CUE_SHEET,
+            &[(
+                "/music/second.mp3".to_string(),
+                "/flac/second.flac".to_string(),
+            )],
+        );
+        assert_eq!(
+            output,
+            CUE_SHEET.replace(
+                "FILE \"/music/second.mp3\" MP3",
+                "FILE \"/flac/second.flac\" FLAC"
+            )
+        );
+    }
+
+    #[test]
+    fn sets_file_paths_containing_quotes() {
+        let new_path = "/music/12\" Mixes/second.mp3";
+        let output = set_file_paths(
+            CUE_SHEET,
+            &[("/music/second.mp3".to_string(), new_path.to_string())],
+        );
+        assert!(output.contains("FILE \"/music/12\\\" Mixes/second.mp3\" MP3"));
+
+        let parsed = Recording::parse("", &output);
+        assert_eq!(
+            parsed.tracks[1]
+                .file
+                .as_ref()
+                .map(|file| file.name.as_str()),
+            Some(new_path)
+        );
+        let relinked = set_file_paths(
+            &output,
+            &[(new_path.to_string(), "/music/second.mp3".to_string())],
+        );
+        assert_eq!(relinked, CUE_SHEET);
+    }
+
+    #[test]
+    fn writes_cue_sheets_that_parse_back() {
+        let recording = Recording::parse("", CUE_SHEET);
+        let parsed = Recording::parse("", &recording.to_cue());
+        assert_eq!(parsed.title.as_deref(), Some("Friday"));
+        assert_eq!(parsed.tracks.len(), 2);
+        assert_eq!(parsed.tracks[1].title.as_deref(), Some("Second"));
+        assert_eq!(parsed.tracks[1].start_seconds(), Some(190));
+        assert_eq!(
+            parsed.tracks[1]
+                .file
+                .as_ref()
+                .map(|file| file.name.as_str()),
+            Some("/music/second.mp3")
+        );
+    }
+
+    #[test]
+    fn escapes_quotes_in_cue_sheets() {
+        let recording = Recording {
+            title: Some("The \"Friday\" Mix".to_string()),
+            tracks: vec![Track {
+                title: Some("12\" Version".to_string()),
+                performer: Some("DJ \"Q\"".to_string()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let output = recording.to_cue();
+        assert!(output.contains("TITLE \"The \\\"Friday\\\" Mix\""));
+
+        let parsed = Recording::parse("", &output);
+        assert_eq!(parsed.title.as_deref(), Some("The \"Friday\" Mix"));
+        assert_eq!(parsed.tracks[0].title.as_deref(), Some("12\" Version"));
+        assert_eq!(parsed.tracks[0].performer.as_deref(), Some("DJ \"Q\""));
+    }
+
+    #[test]
+    fn relinks_cue_sheets_with_a_backup() {
+        let dir = TempDir::new("relink-cue");
+        let path = dir.join("Friday.cue");
+        std::fs::write(&path, CUE_SHEET).unwrap();
+        let modified_time = filetime::FileTime::from_unix_time(1_700_000_000, 0);
+        filetime::set_file_mtime(&path, modified_time).unwrap();
+        let path = path.to_str().unwrap();
+
+        let unrelated = [("/music/other.mp3".to_string(), "/new/other.mp3".to_string())];
+        assert!(!relink_cue_sheet(path, &unrelated).unwrap());
+        assert!(!dir.join("Friday.cue.bak").exists());
+
+        let relinked = [("/music/first.mp3".to_string(), "/new/first.mp3".to_string())];
+        assert!(relink_cue_sheet(path, &relinked).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            CUE_SHEET.replace("/music/first.mp3", "/new/first.mp3")
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("Friday.cue.bak")).unwrap(),
+            CUE_SHEET
+        );
+        let metadata = std::fs::metadata(path).unwrap();
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&metadata),
+            modified_time
+        );
+    }
+
+    #[test]
+    fn rewrites_cue_sheets_with_a_backup() {
+        let dir = TempDir::new("cue");
+        let path = dir.join("Friday.cue");
+        std::fs::write(&path, CUE_SHEET).unwrap();
+        let modified_time = filetime::FileTime::from_unix_time(1_700_000_000, 0);
+        filetime::set_file_mtime(&path, modified_time).unwrap();
+
+        rewrite_cue_sheet(path.to_str().unwrap(), "TITLE \"Saturday\"\n").unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "TITLE \"Saturday\"\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("Friday.cue.bak")).unwrap(),
+            CUE_SHEET
+        );
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&metadata),
+            modified_time
+        );
+    }
+}
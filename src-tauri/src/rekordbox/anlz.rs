@@ -0,0 +1,730 @@
+//! Parser for Rekordbox analysis files (`ANLZ0000.DAT`, `.EXT` and `.2EX`).
+//!
+//! Rekordbox writes one directory of analysis files per track under `PIONEER/USBANLZ`, both in
+//! its local share folder and on exported USB devices. Each file starts with a `PMAI` header
+//! followed by tagged sections, all integers are big endian. Only the sections we display are
+//! parsed, everything else is skipped:
+//!
+//! - `PPTH` the path of the analysed audio file
+//! - `PQTZ` the beat grid
+//! - `PCOB`/`PCO2` memory cues and hot cues (`PCO2` in `.EXT` adds colours and comments)
+//! - `PWAV` the monochrome waveform preview, `PWV3`/`PWV5` the scrolling detail waveforms
+//! - `PSSI` the song structure (phrases)
+//!
+//! The layout is documented by the Deep Symmetry crate-digger project.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::songs::Song;
+
+/// XOR mask Rekordbox 6+ applies to the body of `PSSI` sections.
+const SONG_STRUCTURE_MASK: [u8; 19] = [
+    0xCB, 0xE1, 0xEE, 0xFA, 0xE5, 0xEE, 0xAD, 0xEE, 0xE9, 0xD2, 0xE9, 0xEB, 0xE1, 0xE9, 0xF3, 0xE8,
+    0xE9, 0xF4, 0xE1,
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read analysis file: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("not an analysis file, expected PMAI header")]
+    InvalidHeader,
+    #[error("unexpected end of data in {0} section")]
+    UnexpectedEof(&'static str),
+}
+
+/// A single beat in the beat grid.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Beat {
+    /// Position of the beat within its bar, 1 to 4 where 1 is the downbeat.
+    pub beat_number: u16,
+    pub bpm: f64,
+    pub time_ms: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CueKind {
+    Point,
+    Loop,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cue {
+    /// The hot cue slot starting at 1 for A, `None` for memory cues.
+    pub hot_cue: Option<u32>,
+    pub kind: CueKind,
+    pub time_ms: u32,
+    /// The end of the loop if this is a loop cue.
+    pub loop_end_ms: Option<u32>,
+    pub comment: Option<String>,
+    /// RGB colour assigned in Rekordbox, only available from `PCO2` sections.
+    pub color: Option<[u8; 3]>,
+}
+
+/// A column of a monochrome waveform (`PWAV` or `PWV3`).
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaveformColumn {
+    /// Height from 0 to 31.
+    pub height: u8,
+    /// Whiteness from 0 to 7, higher values are rendered lighter.
+    pub whiteness: u8,
+}
+
+/// A column of the colour detail waveform (`PWV5`), each channel is 0 to 7.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColorWaveformColumn {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    /// Height from 0 to 31.
+    pub height: u8,
+}
+
+/// The overall mood Rekordbox detected, it determines how phrase kinds are named.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Mood {
+    High,
+    Mid,
+    Low,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Phrase {
+    /// The beat (counting from 1) the phrase starts on.
+    pub beat: u16,
+    /// The raw phrase kind, interpreted by `name` using the song's mood.
+    pub kind: u16,
+    pub name: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongStructure {
+    pub mood: Option<Mood>,
+    /// The beat where the last phrase ends.
+    pub end_beat: u16,
+    pub phrases: Vec<Phrase>,
+}
+
+/// All analysis data Rekordbox stored for a track, merged from the `.DAT`, `.EXT` and `.2EX`
+/// files.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Analysis {
+    /// The audio file path as Rekordbox recorded it, on USB devices this is relative to the
+    /// root of the device.
+    pub song_path: Option<String>,
+    pub beat_grid: Vec<Beat>,
+    pub cues: Vec<Cue>,
+    pub waveform_preview: Vec<WaveformColumn>,
+    pub waveform_detail: Vec<WaveformColumn>,
+    pub color_waveform_detail: Vec<ColorWaveformColumn>,
+    pub song_structure: Option<SongStructure>,
+}
+
+impl Analysis {
+    /// Reads a `.DAT` analysis file along with the `.EXT` and `.2EX` files next to it if they
+    /// exist. Missing extended files are ignored, the `.DAT` file is required.
+    pub fn read(dat_path: &Path) -> Result<Self, Error> {
+        let mut analysis = Self::default();
+        analysis.parse(&std::fs::read(dat_path)?)?;
+
+        for extension in ["EXT", "2EX"] {
+            let path = dat_path.with_extension(extension);
+            if !path.exists() {
+                continue;
+            }
+            if let Err(error) = std::fs::read(&path)
+                .map_err(Error::from)
+                .and_then(|data| analysis.parse(&data))
+            {
+                tracing::warn!(?error, ?path, "failed to read extended analysis file");
+            }
+        }
+
+        Ok(analysis)
+    }
+
+    /// Parses a single analysis file's sections into this analysis, sections found in later
+    /// files replace earlier ones (e.g. `PCO2` cues replace `PCOB` cues).
+    pub fn parse(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.get(0..4) != Some(b"PMAI") {
+            return Err(Error::InvalidHeader);
+        }
+
+        let mut offset = read_u32(data, 4, "PMAI")? as usize;
+        let mut memory_cues: Option<Vec<Cue>> = None;
+        let mut hot_cues: Option<Vec<Cue>> = None;
+
+        while offset + 12 <= data.len() {
+            let tag = &data[offset..offset + 4];
+            let len_tag = read_u32(data, offset + 8, "section")? as usize;
+            if len_tag < 12 || offset + len_tag > data.len() {
+                tracing::debug!(offset, len_tag, "invalid analysis section length");
+                break;
+            }
+            let section = &data[offset..offset + len_tag];
+
+            match tag {
+                b"PPTH" => self.song_path = Some(parse_path(section)?),
+                b"PQTZ" => self.beat_grid = parse_beat_grid(section)?,
+                b"PCOB" | b"PCO2" => {
+                    let extended = tag == b"PCO2";
+                    let (is_hot_cues, cues) = parse_cue_list(section, extended)?;
+                    let target = if is_hot_cues {
+                        &mut hot_cues
+                    } else {
+                        &mut memory_cues
+                    };
+                    // Prefer the extended list, they contain the same cues with more detail.
+                    if extended || target.is_none() {
+                        *target = Some(cues);
+                    }
+                }
+                b"PWAV" => self.waveform_preview = parse_waveform(section, 0x0c, 0x14)?,
+                b"PWV3" => self.waveform_detail = parse_waveform(section, 0x10, 0x18)?,
+                b"PWV5" => self.color_waveform_detail = parse_color_waveform(section)?,
+                b"PSSI" => self.song_structure = Some(parse_song_structure(section)?),
+                _ => {}
+            }
+
+            offset += len_tag;
+        }
+
+        if memory_cues.is_some() || hot_cues.is_some() {
+            let mut cues: Vec<Cue> = memory_cues.into_iter().chain(hot_cues).flatten().collect();
+            cues.sort_by_key(|cue| cue.time_ms);
+            self.cues = cues;
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds every `.DAT` analysis file under a `USBANLZ` directory.
+pub fn find_analysis_files(path: &Path) -> Vec<PathBuf> {
+    let dir = match std::fs::read_dir(path) {
+        Ok(dir) => dir,
+        Err(error) => {
+            tracing::warn!(?error, ?path, "failed to read directory");
+            return Vec::new();
+        }
+    };
+
+    let mut files = Vec::new();
+    for entry in dir {
+        let entry_path = match entry {
+            Ok(entry) => entry.path(),
+            Err(error) => {
+                tracing::warn!(?error, "failed to read entry in dir");
+                continue;
+            }
+        };
+
+        if entry_path.is_dir() {
+            files.append(&mut find_analysis_files(&entry_path));
+        } else if entry_path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("dat"))
+        {
+            files.push(entry_path);
+        }
+    }
+
+    files
+}
+
+/// Reads only the path of the analysed audio file from a `.DAT` analysis file. The `PPTH` section
+/// comes first, so unlike [`Analysis::read`] this skips the waveforms and cues without reading
+/// them, which matters when linking thousands of analysis files at startup.
+pub fn read_song_path(dat_path: &Path) -> Result<Option<String>, Error> {
+    let mut file = BufReader::new(File::open(dat_path)?);
+    let len = file.get_ref().metadata()?.len();
+
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+    if &header[0..4] != b"PMAI" {
+        return Err(Error::InvalidHeader);
+    }
+
+    let mut offset = read_u32(&header, 4, "PMAI")? as u64;
+    while offset + 12 <= len {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let len_tag = read_u32(&header, 8, "section")? as u64;
+        if len_tag < 12 || offset + len_tag > len {
+            tracing::debug!(offset, len_tag, "invalid analysis section length");
+            break;
+        }
+        if &header[0..4] == b"PPTH" {
+            let mut section = vec![0; len_tag as usize];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut section)?;
+            return parse_path(&section).map(Some);
+        }
+        offset += len_tag;
+    }
+
+    Ok(None)
+}
+
+/// Library songs indexed by path and file name, for finding the song an analysis file was created
+/// for without comparing every pair.
+pub struct SongIndex<'a> {
+    by_path: HashMap<String, &'a Song>,
+    by_file_name: HashMap<String, Vec<(String, &'a Song)>>,
+}
+
+impl<'a> SongIndex<'a> {
+    pub fn new(songs: &'a [Song]) -> Self {
+        let mut by_path = HashMap::new();
+        let mut by_file_name: HashMap<String, Vec<(String, &'a Song)>> = HashMap::new();
+        for song in songs {
+            let path = song.file_path.replace('\\', "/");
+            by_file_name
+                .entry(file_name(&path).to_string())
+                .or_default()
+                .push((path.clone(), song));
+            by_path.entry(path).or_insert(song);
+        }
+        Self {
+            by_path,
+            by_file_name,
+        }
+    }
+
+    /// Paths in analysis files on USB devices are relative to the device root so a suffix match
+    /// is used when there is no exact match.
+    pub fn find(&self, analysis_song_path: &str) -> Option<&'a Song> {
+        let analysis_song_path = analysis_song_path.replace('\\', "/");
+        if let Some(song) = self.by_path.get(&analysis_song_path) {
+            return Some(song);
+        }
+        if !analysis_song_path.starts_with('/') {
+            return None;
+        }
+        self.by_file_name
+            .get(file_name(&analysis_song_path))?
+            .iter()
+            .find(|(path, _)| path.ends_with(&analysis_song_path))
+            .map(|(_, song)| *song)
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn parse_path(section: &[u8]) -> Result<String, Error> {
+    let len_path = read_u32(section, 0x0c, "PPTH")? as usize;
+    let bytes = section
+        .get(0x10..0x10 + len_path)
+        .ok_or(Error::UnexpectedEof("PPTH"))?;
+    Ok(read_utf16_be(bytes))
+}
+
+fn parse_beat_grid(section: &[u8]) -> Result<Vec<Beat>, Error> {
+    let len_header = read_u32(section, 4, "PQTZ")? as usize;
+    let len_beats = read_u32(section, 0x14, "PQTZ")? as usize;
+
+    (0..len_beats)
+        .map(|index| {
+            let offset = len_header + index * 8;
+            Ok(Beat {
+                beat_number: read_u16(section, offset, "PQTZ")?,
+                bpm: read_u16(section, offset + 2, "PQTZ")? as f64 / 100.0,
+                time_ms: read_u32(section, offset + 4, "PQTZ")?,
+            })
+        })
+        .collect()
+}
+
+/// Returns whether the list contains hot cues (as opposed to memory cues) and the cues.
+fn parse_cue_list(section: &[u8], extended: bool) -> Result<(bool, Vec<Cue>), Error> {
+    let name = if extended { "PCO2" } else { "PCOB" };
+    let len_header = read_u32(section, 4, name)? as usize;
+    let is_hot_cues = read_u32(section, 0x0c, name)? == 1;
+    let len_cues = if extended {
+        read_u16(section, 0x10, name)?
+    } else {
+        read_u16(section, 0x12, name)?
+    };
+
+    let mut cues = Vec::with_capacity(len_cues as usize);
+    let mut offset = len_header;
+    for _ in 0..len_cues {
+        let len_entry = read_u32(section, offset + 8, name)? as usize;
+        let entry = section
+            .get(offset..offset + len_entry)
+            .ok_or(Error::UnexpectedEof(name))?;
+        cues.push(if extended {
+            parse_extended_cue(entry)?
+        } else {
+            parse_cue(entry)?
+        });
+        offset += len_entry;
+    }
+
+    Ok((is_hot_cues, cues))
+}
+
+/// Parses a `PCPT` cue entry.
+fn parse_cue(entry: &[u8]) -> Result<Cue, Error> {
+    let kind = cue_kind(read_u8(entry, 0x1c, "PCPT")?);
+    let loop_end_ms = read_u32(entry, 0x24, "PCPT")?;
+
+    Ok(Cue {
+        hot_cue: hot_cue_slot(read_u32(entry, 0x0c, "PCPT")?),
+        kind,
+        time_ms: read_u32(entry, 0x20, "PCPT")?,
+        loop_end_ms: (kind == CueKind::Loop).then_some(loop_end_ms),
+        comment: None,
+        color: None,
+    })
+}
+
+/// Parses a `PCP2` extended cue entry.
+fn parse_extended_cue(entry: &[u8]) -> Result<Cue, Error> {
+    let kind = cue_kind(read_u8(entry, 0x10, "PCP2")?);
+    let loop_end_ms = read_u32(entry, 0x18, "PCP2")?;
+
+    // Older versions of Rekordbox wrote shorter entries without comments or colours.
+    let len_comment = read_u32(entry, 0x48, "PCP2").unwrap_or(0) as usize;
+    let comment = entry
+        .get(0x4c..0x4c + len_comment)
+        .map(read_utf16_be)
+        .filter(|comment| !comment.is_empty());
+    let color_offset = 0x4c + len_comment;
+    let color = entry
+        .get(color_offset..color_offset + 4)
+        .filter(|color| color[0] != 0)
+        .map(|color| [color[1], color[2], color[3]]);
+
+    Ok(Cue {
+        hot_cue: hot_cue_slot(read_u32(entry, 0x0c, "PCP2")?),
+        kind,
+        time_ms: read_u32(entry, 0x14, "PCP2")?,
+        loop_end_ms: (kind == CueKind::Loop).then_some(loop_end_ms),
+        comment,
+        color,
+    })
+}
+
+fn cue_kind(value: u8) -> CueKind {
+    if value == 2 {
+        CueKind::Loop
+    } else {
+        CueKind::Point
+    }
+}
+
+fn hot_cue_slot(value: u32) -> Option<u32> {
+    (value != 0).then_some(value)
+}
+
+/// Parses `PWAV` and `PWV3` waveforms which share the same encoding, one byte per column with
+/// the height in the low 5 bits and whiteness in the high 3 bits.
+fn parse_waveform(
+    section: &[u8],
+    len_offset: usize,
+    data_offset: usize,
+) -> Result<Vec<WaveformColumn>, Error> {
+    let len = read_u32(section, len_offset, "waveform")? as usize;
+    let data = section
+        .get(data_offset..data_offset + len)
+        .ok_or(Error::UnexpectedEof("waveform"))?;
+
+    Ok(data
+        .iter()
+        .map(|byte| WaveformColumn {
+            height: byte & 0x1f,
+            whiteness: byte >> 5,
+        })
+        .collect())
+}
+
+fn parse_color_waveform(section: &[u8]) -> Result<Vec<ColorWaveformColumn>, Error> {
+    let len_header = read_u32(section, 4, "PWV5")? as usize;
+    let len_entries = read_u32(section, 0x10, "PWV5")? as usize;
+
+    (0..len_entries)
+        .map(|index| {
+            let value = read_u16(section, len_header + index * 2, "PWV5")?;
+            Ok(ColorWaveformColumn {
+                red: (value >> 13 & 0x07) as u8,
+                green: (value >> 10 & 0x07) as u8,
+                blue: (value >> 7 & 0x07) as u8,
+                height: (value >> 2 & 0x1f) as u8,
+            })
+        })
+        .collect()
+}
+
+fn parse_song_structure(section: &[u8]) -> Result<SongStructure, Error> {
+    let len_header = read_u32(section, 4, "PSSI")? as usize;
+    let len_entry = read_u32(section, 0x0c, "PSSI")? as usize;
+    let len_entries = read_u16(section, 0x10, "PSSI")?;
+
+    // Everything after the entry count is masked in files written by Rekordbox 6 and later,
+    // unmasked files always have a mood between 1 and 3.
+    let mut section = section.to_vec();
+    if !(1..=3).contains(&read_u16(&section, 0x12, "PSSI")?) {
+        for (index, byte) in section.iter_mut().skip(0x12).enumerate() {
+            let mask = SONG_STRUCTURE_MASK[index % SONG_STRUCTURE_MASK.len()];
+            *byte ^= mask.wrapping_add(len_entries as u8);
+        }
+    }
+
+    let mood = match read_u16(&section, 0x12, "PSSI")? {
+        1 => Some(Mood::High),
+        2 => Some(Mood::Mid),
+        3 => Some(Mood::Low),
+        _ => None,
+    };
+    let end_beat = read_u16(&section, 0x1a, "PSSI")?;
+
+    let phrases = (0..len_entries as usize)
+        .map(|index| {
+            let offset = len_header + index * len_entry;
+            let kind = read_u16(&section, offset + 4, "PSSI")?;
+            Ok(Phrase {
+                beat: read_u16(&section, offset + 2, "PSSI")?,
+                kind,
+                name: phrase_name(mood, kind),
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(SongStructure {
+        mood,
+        end_beat,
+        phrases,
+    })
+}
+
+fn phrase_name(mood: Option<Mood>, kind: u16) -> &'static str {
+    match (mood, kind) {
+        (_, 1) => "Intro",
+        (Some(Mood::High), 2) => "Up",
+        (Some(Mood::High), 3) => "Down",
+        (Some(Mood::High), 5) => "Chorus",
+        (Some(Mood::High), 6) => "Outro",
+        (Some(Mood::Mid), 2..=7) => [
+            "Verse 1", "Verse 2", "Verse 3", "Verse 4", "Verse 5", "Verse 6",
+        ][kind as usize - 2],
+        (Some(Mood::Low), 2..=4) => "Verse 1",
+        (Some(Mood::Low), 5..=7) => "Verse 2",
+        (Some(Mood::Mid | Mood::Low), 8) => "Bridge",
+        (Some(Mood::Mid | Mood::Low), 9) => "Chorus",
+        (Some(Mood::Mid | Mood::Low), 10) => "Outro",
+        _ => "Unknown",
+    }
+}
+
+fn read_u8(data: &[u8], offset: usize, section: &'static str) -> Result<u8, Error> {
+    data.get(offset)
+        .copied()
+        .ok_or(Error::UnexpectedEof(section))
+}
+
+fn read_u16(data: &[u8], offset: usize, section: &'static str) -> Result<u16, Error> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or(Error::UnexpectedEof(section))
+}
+
+fn read_u32(data: &[u8], offset: usize, section: &'static str) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(Error::UnexpectedEof(section))
+}
+
+/// Decodes a UTF-16BE string, dropping the trailing null terminator Rekordbox includes.
+fn read_utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn section(tag: &[u8; 4], len_header: u32, body: &[u8]) -> Vec<u8> {
+        let mut section = tag.to_vec();
+        section.extend(len_header.to_be_bytes());
+        section.extend((12 + body.len() as u32).to_be_bytes());
+        section.extend(body);
+        section
+    }
+
+    fn analysis_file(sections: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"PMAI".to_vec();
+        data.extend(0x1cu32.to_be_bytes());
+        data.resize(0x1c, 0);
+        for section in sections {
+            data.extend(section);
+        }
+        data
+    }
+
+    fn path_section(path: &str) -> Vec<u8> {
+        let path: Vec<u8> = path
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let mut body = (path.len() as u32).to_be_bytes().to_vec();
+        body.extend(path);
+        section(b"PPTH", 0x10, &body)
+    }
+
+    fn beat_grid_section(beats: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut body = vec![0; 8];
+        body.extend((beats.len() as u32).to_be_bytes());
+        for (beat_number, bpm, time_ms) in beats {
+            body.extend(beat_number.to_be_bytes());
+            body.extend(bpm.to_be_bytes());
+            body.extend(time_ms.to_be_bytes());
+        }
+        section(b"PQTZ", 0x18, &body)
+    }
+
+    fn cue_list_section(is_hot_cues: bool, cues: &[(u32, u8, u32, u32)]) -> Vec<u8> {
+        let mut body = (is_hot_cues as u32).to_be_bytes().to_vec();
+        body.extend([0, 0]);
+        body.extend((cues.len() as u16).to_be_bytes());
+        body.extend([0; 4]);
+        for (hot_cue, kind, time_ms, loop_end_ms) in cues {
+            let mut entry = b"PCPT".to_vec();
+            entry.extend(0x1cu32.to_be_bytes());
+            entry.extend(0x38u32.to_be_bytes());
+            entry.extend(hot_cue.to_be_bytes());
+            entry.resize(0x1c, 0);
+            entry.push(*kind);
+            entry.resize(0x20, 0);
+            entry.extend(time_ms.to_be_bytes());
+            entry.extend(loop_end_ms.to_be_bytes());
+            entry.resize(0x38, 0);
+            body.extend(entry);
+        }
+        section(b"PCOB", 0x18, &body)
+    }
+
+    #[test]
+    fn parses_sections() {
+        let data = analysis_file(&[
+            path_section("/Contents/Artist/Song.mp3"),
+            beat_grid_section(&[(1, 12800, 100), (2, 12800, 569)]),
+            cue_list_section(true, &[(2, 1, 569, 0)]),
+            cue_list_section(false, &[(0, 2, 100, 2000)]),
+            section(b"PUNK", 0x0c, &[1, 2, 3]),
+        ]);
+        let mut analysis = Analysis::default();
+        analysis.parse(&data).unwrap();
+
+        assert_eq!(
+            analysis.song_path.as_deref(),
+            Some("/Contents/Artist/Song.mp3")
+        );
+        let beats: Vec<_> = analysis
+            .beat_grid
+            .iter()
+            .map(|beat| (beat.beat_number, beat.bpm, beat.time_ms))
+            .collect();
+        assert_eq!(beats, vec![(1, 128.0, 100), (2, 128.0, 569)]);
+        let cues: Vec<_> = analysis
+            .cues
+            .iter()
+            .map(|cue| (cue.hot_cue, cue.kind, cue.time_ms, cue.loop_end_ms))
+            .collect();
+        assert_eq!(
+            cues,
+            vec![
+                (None, CueKind::Loop, 100, Some(2000)),
+                (Some(2), CueKind::Point, 569, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            Analysis::default().parse(b"RIFF\0\0\0\0WAVE"),
+            Err(Error::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn reads_only_the_song_path() {
+        let dir = TempDir::new("anlz");
+        let with_path = dir.join("ANLZ0000.DAT");
+        let without_path = dir.join("ANLZ0001.DAT");
+        std::fs::write(
+            &with_path,
+            analysis_file(&[
+                beat_grid_section(&[(1, 12000, 0)]),
+                path_section("/Contents/Song.mp3"),
+            ]),
+        )
+        .unwrap();
+        std::fs::write(
+            &without_path,
+            analysis_file(&[beat_grid_section(&[(1, 12000, 0)])]),
+        )
+        .unwrap();
+
+        let song_path = read_song_path(&with_path).unwrap();
+        let missing_path = read_song_path(&without_path).unwrap();
+
+        assert_eq!(song_path.as_deref(), Some("/Contents/Song.mp3"));
+        assert_eq!(missing_path, None);
+    }
+
+    #[test]
+    fn finds_songs_by_path_or_device_relative_suffix() {
+        let songs = vec![
+            Song {
+                file_path: "/music/a.mp3".to_string(),
+                ..Default::default()
+            },
+            Song {
+                file_path: "/Volumes/USB/Contents/Artist/b.mp3".to_string(),
+                ..Default::default()
+            },
+            Song {
+                file_path: "C:\\Music\\c.mp3".to_string(),
+                ..Default::default()
+            },
+        ];
+        let index = SongIndex::new(&songs);
+        let path = |path| index.find(path).map(|song| song.file_path.as_str());
+
+        assert_eq!(path("/music/a.mp3"), Some("/music/a.mp3"));
+        assert_eq!(
+            path("/Contents/Artist/b.mp3"),
+            Some("/Volumes/USB/Contents/Artist/b.mp3")
+        );
+        assert_eq!(path("C:/Music/c.mp3"), Some("C:\\Music\\c.mp3"));
+        assert_eq!(path("/Contents/Other/b.mp3"), None);
+        assert_eq!(path("b.mp3"), None);
+    }
+}
@@ -0,0 +1,367 @@
+//! Rekordbox "HISTORY" playlists record every track played in a session, they're a second
+//! source of truth for what happened during a recorded set.
+//!
+//! History playlists are read from an XML library or a device database and matched with the
+//! recording made at the same time, the tracklists are then compared so tracks missing from
+//! either side can be flagged.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::{
+    recording::{Recording, Track},
+    rekordbox::{pdb, xml},
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+/// History dates are local time while recording timestamps are UTC, the day is widened by this
+/// much either side to allow for any timezone.
+const TIMEZONE_SLACK_SECONDS: i64 = 14 * 60 * 60;
+/// The share of a history playlist's tracks a recording must contain to be matched with it. A
+/// date alone isn't enough as several sets can be recorded on the same day.
+const MIN_SHARED_TRACKS_WITH_DATE: f64 = 0.25;
+const MIN_SHARED_TRACKS_WITHOUT_DATE: f64 = 0.5;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryTrack {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub file_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPlaylist {
+    pub name: String,
+    /// The session date taken from the playlist name, format 'YYYY-MM-DD'.
+    pub date: Option<String>,
+    pub tracks: Vec<HistoryTrack>,
+}
+
+/// A history playlist associated with the recording of the same session.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryMatch {
+    pub history: HistoryPlaylist,
+    /// The cue sheet path of the matched recording.
+    pub recording_file_path: String,
+    /// Tracks played according to the history but missing from the recording's cue sheet.
+    pub missing_from_recording: Vec<HistoryTrack>,
+    /// Tracks in the recording's cue sheet which aren't in the history.
+    pub missing_from_history: Vec<Track>,
+}
+
+/// Reads history playlists from a Rekordbox XML library, these are any playlists named
+/// "HISTORY ..." or inside a "HISTORY" folder.
+pub fn read_xml_history(path: &str) -> Result<Vec<HistoryPlaylist>, xml::Error> {
+    Ok(xml::read_playlists(path)?
+        .into_iter()
+        .filter(|playlist| {
+            is_history_name(&playlist.name)
+                || playlist
+                    .folders
+                    .iter()
+                    .any(|folder| is_history_name(folder))
+        })
+        .map(|playlist| HistoryPlaylist {
+            date: parse_date(&playlist.name),
+            name: playlist.name,
+            tracks: playlist
+                .tracks
+                .into_iter()
+                .map(|track| HistoryTrack {
+                    title: track.name,
+                    artist: track.artist,
+                    file_path: track.file_path,
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Reads the history playlists from a device mounted at `device_path`.
+pub fn read_device_history(device_path: &Path) -> Result<Vec<HistoryPlaylist>, pdb::Error> {
+    let database = pdb::DeviceDatabase::read_device(device_path)?;
+
+    Ok(database
+        .history_playlists
+        .iter()
+        .map(|playlist| HistoryPlaylist {
+            name: playlist.name.clone(),
+            date: parse_date(&playlist.name),
+            tracks: playlist
+                .track_ids
+                .iter()
+                .filter_map(|id| database.song(*id, device_path))
+                .map(|song| HistoryTrack {
+                    title: song.title,
+                    artist: song.artist,
+                    file_path: Some(song.file_path),
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+/// Associates each history playlist with the recording whose timestamps overlap its date,
+/// preferring the recording sharing the most tracks when there are several. A quarter of the
+/// playlist's tracks must be in the recording, playlists without a date are matched by tracklist
+/// alone and need at least half.
+pub fn match_recordings(
+    history: Vec<HistoryPlaylist>,
+    recordings: &[Recording],
+) -> Vec<HistoryMatch> {
+    history
+        .into_iter()
+        .filter_map(|playlist| {
+            let day = playlist.date.as_deref().and_then(parse_day);
+
+            let (recording, shared) = recordings
+                .iter()
+                .filter(|recording| match day {
+                    Some(day) => recording_overlaps_day(recording, day),
+                    None => true,
+                })
+                .map(|recording| (recording, shared_track_count(&playlist, recording)))
+                .max_by_key(|(_, shared)| *shared)?;
+
+            let min_shared = if day.is_some() {
+                MIN_SHARED_TRACKS_WITH_DATE
+            } else {
+                MIN_SHARED_TRACKS_WITHOUT_DATE
+            };
+            if shared == 0 || (shared as f64) < playlist.tracks.len() as f64 * min_shared {
+                return None;
+            }
+
+            Some(compare(playlist, recording))
+        })
+        .collect()
+}
+
+fn compare(history: HistoryPlaylist, recording: &Recording) -> HistoryMatch {
+    let missing_from_recording = history
+        .tracks
+        .iter()
+        .filter(|history_track| {
+            !recording
+                .tracks
+                .iter()
+                .any(|track| is_same_track(history_track, track))
+        })
+        .cloned()
+        .collect();
+    let missing_from_history = recording
+        .tracks
+        .iter()
+        .filter(|track| {
+            !history
+                .tracks
+                .iter()
+                .any(|history_track| is_same_track(history_track, track))
+        })
+        .cloned()
+        .collect();
+
+    HistoryMatch {
+        history,
+        recording_file_path: recording.file_path.clone(),
+        missing_from_recording,
+        missing_from_history,
+    }
+}
+
+fn shared_track_count(history: &HistoryPlaylist, recording: &Recording) -> usize {
+    history
+        .tracks
+        .iter()
+        .filter(|history_track| {
+            recording
+                .tracks
+                .iter()
+                .any(|track| is_same_track(history_track, track))
+        })
+        .count()
+}
+
+/// Tracks are the same if they have the same file name, or failing that the same title and
+/// artist ignoring case.
+fn is_same_track(history_track: &HistoryTrack, track: &Track) -> bool {
+    if let (Some(history_path), Some(file)) = (&history_track.file_path, &track.file) {
+        if xml::file_name_lowercase(history_path) == xml::file_name_lowercase(&file.name) {
+            return true;
+        }
+    }
+
+    let eq = |a: &Option<String>, b: &Option<String>| match (a, b) {
+        (Some(a), Some(b)) => a.trim().eq_ignore_ascii_case(b.trim()),
+        _ => false,
+    };
+    eq(&history_track.title, &track.title) && eq(&history_track.artist, &track.performer)
+}
+
+/// The recording spans from the end of the wave file (its last modification) back by its
+/// duration. A `DATE` comment in the cue sheet takes priority when present. The cue sheet's own
+/// timestamps aren't used, it's rewritten whenever the tracklist is edited.
+fn recording_overlaps_day(recording: &Recording, day: i64) -> bool {
+    let rem_day = recording
+        .rem
+        .iter()
+        .find(|(key, _)| key == "DATE")
+        .and_then(|(_, value)| parse_day(value));
+    if let Some(rem_day) = rem_day {
+        return rem_day == day;
+    }
+
+    let Some((end, duration)) = recording.wave_file.as_ref().and_then(|wave_file| {
+        Some((
+            wave_file.last_modified_unix_seconds?,
+            wave_file.duration_seconds as i64,
+        ))
+    }) else {
+        return false;
+    };
+    let start = end - duration;
+
+    let day_start = day * SECONDS_PER_DAY - TIMEZONE_SLACK_SECONDS;
+    let day_end = (day + 1) * SECONDS_PER_DAY + TIMEZONE_SLACK_SECONDS;
+    start < day_end && end >= day_start
+}
+
+fn is_history_name(name: &str) -> bool {
+    name.trim().to_uppercase().starts_with("HISTORY")
+}
+
+/// Finds the first 'YYYY-MM-DD' (or 'YYYY/MM/DD') date in a name.
+fn parse_date(name: &str) -> Option<String> {
+    let bytes = name.as_bytes();
+    (0..bytes.len().saturating_sub(9)).find_map(|start| {
+        let candidate = name.get(start..start + 10)?.replace('/', "-");
+        parse_day(&candidate).map(|_| candidate)
+    })
+}
+
+/// Converts a 'YYYY-MM-DD' date to the number of days since the unix epoch.
+fn parse_day(date: &str) -> Option<i64> {
+    let date = date.trim().replace('/', "-");
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.get(0..2)?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+
+    // Days from civil algorithm by Howard Hinnant, years start in March so leap days are last.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recording::{File, WaveFile};
+
+    use super::*;
+
+    fn history(date: Option<&str>, titles: &[&str]) -> HistoryPlaylist {
+        HistoryPlaylist {
+            name: "HISTORY".to_string(),
+            date: date.map(str::to_string),
+            tracks: titles
+                .iter()
+                .map(|title| HistoryTrack {
+                    title: Some(title.to_string()),
+                    artist: Some("Artist".to_string()),
+                    file_path: Some(format!("/music/{title}.mp3")),
+                })
+                .collect(),
+        }
+    }
+
+    fn recording(file_path: &str, end: Option<i64>, titles: &[&str]) -> Recording {
+        Recording {
+            file_path: file_path.to_string(),
+            // Rewritten cue sheets must not move the recording to another day.
+            last_modified_unix_seconds: 0,
+            tracks: titles
+                .iter()
+                .map(|title| Track {
+                    title: Some(title.to_string()),
+                    performer: Some("artist".to_string()),
+                    file: Some(File::from_path(&format!("/other/{title}.MP3"))),
+                    start_time: None,
+                })
+                .collect(),
+            wave_file: end.map(|end| WaveFile {
+                file_path: file_path.replace(".cue", ".wav"),
+                channels: 2,
+                sample_rate: 44100,
+                bits_per_sample: 16,
+                sample_format: "Int".to_string(),
+                duration_seconds: 3600.0,
+                total_samples: 0,
+                last_modified_unix_seconds: Some(end),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_dates() {
+        assert_eq!(parse_day("1970-01-01"), Some(0));
+        assert_eq!(parse_day("2024-03-01"), Some(19783));
+        assert_eq!(parse_day("2024/02/29"), Some(19782));
+        assert_eq!(parse_day("2024-13-01"), None);
+        assert_eq!(
+            parse_date("HISTORY 2024/03/01 (2)").as_deref(),
+            Some("2024-03-01")
+        );
+        assert_eq!(parse_date("HISTORY 001"), None);
+    }
+
+    #[test]
+    fn matches_recordings_by_date_and_shared_tracks() {
+        let day_end = 19783 * SECONDS_PER_DAY + 22 * 60 * 60;
+        let recordings = vec![
+            recording("/recordings/warmup.cue", Some(day_end - 7200), &["a", "b"]),
+            recording("/recordings/main.cue", Some(day_end), &["c", "d", "e"]),
+            recording("/recordings/undated.cue", None, &["f", "g"]),
+        ];
+
+        let matches = match_recordings(
+            vec![
+                history(Some("2024-03-01"), &["c", "d", "x"]),
+                // Recorded that day but sharing no tracks.
+                history(Some("2024-03-01"), &["y", "z"]),
+                // The wave file's timestamp is the only date, undated recordings need tracks.
+                history(None, &["f", "g", "h"]),
+                history(None, &["f", "x", "y"]),
+            ],
+            &recordings,
+        );
+
+        let matched: Vec<_> = matches
+            .iter()
+            .map(|history_match| {
+                (
+                    history_match.recording_file_path.as_str(),
+                    history_match.missing_from_recording.len(),
+                    history_match.missing_from_history.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            matched,
+            vec![
+                ("/recordings/main.cue", 1, 1),
+                ("/recordings/undated.cue", 1, 0)
+            ]
+        );
+    }
+}
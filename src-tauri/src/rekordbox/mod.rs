@@ -0,0 +1,7 @@
+//! Support for reading and writing Rekordbox's own library formats so data can move between
+//! Rekordbox and dbeat in both directions.
+
+pub mod anlz;
+pub mod history;
+pub mod pdb;
+pub mod xml;
@@ -0,0 +1,807 @@
+//! Read only parser for the Rekordbox device database (`PIONEER/rekordbox/export.pdb`).
+//!
+//! USB devices exported for CDJs use a DeviceSQL database rather than SQLite. The file is split
+//! into fixed size pages, each table is a linked list of pages and each page stores its rows in a
+//! heap with an index of row offsets at the end of the page. All integers are little endian.
+//!
+//! The layout is documented by the Deep Symmetry crate-digger project.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::Serialize;
+
+use crate::songs::{key::Key, Song};
+
+/// Where the database lives relative to the root of a USB device.
+pub const DATABASE_PATH: &str = "PIONEER/rekordbox/export.pdb";
+
+/// Offset of the row heap from the start of each page.
+const HEAP_OFFSET: usize = 0x28;
+/// Size of each group of 16 row offsets stored at the end of a page.
+const ROW_GROUP_SIZE: usize = 0x24;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read device database: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("unexpected end of data at offset {0}")]
+    UnexpectedEof(usize),
+    #[error("invalid page size {0}")]
+    InvalidPageSize(u32),
+}
+
+/// The tables we read out of the many a device database contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableType {
+    Tracks,
+    Genres,
+    Artists,
+    Albums,
+    Labels,
+    Keys,
+    Colors,
+    PlaylistTree,
+    PlaylistEntries,
+    HistoryPlaylists,
+    HistoryEntries,
+}
+
+impl TableType {
+    fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0 => Self::Tracks,
+            1 => Self::Genres,
+            2 => Self::Artists,
+            3 => Self::Albums,
+            4 => Self::Labels,
+            5 => Self::Keys,
+            6 => Self::Colors,
+            7 => Self::PlaylistTree,
+            8 => Self::PlaylistEntries,
+            11 => Self::HistoryPlaylists,
+            12 => Self::HistoryEntries,
+            _ => return None,
+        })
+    }
+}
+
+/// A track row, ids reference rows in the other tables.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Track {
+    pub id: u32,
+    pub title: String,
+    pub artist_id: u32,
+    pub album_id: u32,
+    pub genre_id: u32,
+    pub label_id: u32,
+    pub key_id: u32,
+    pub color_id: u8,
+    pub bpm: Option<f64>,
+    pub duration_seconds: u16,
+    pub sample_rate: u32,
+    pub bitrate: u32,
+    pub file_size: u32,
+    pub track_number: u32,
+    pub year: u16,
+    pub rating: u8,
+    pub comment: String,
+    /// The audio file path relative to the root of the device, e.g. `/Contents/Artist/song.mp3`.
+    pub file_path: String,
+    /// The path of the track's analysis file relative to the root of the device.
+    pub analyze_path: String,
+    pub date_added: String,
+}
+
+/// A playlist or folder from the playlist tree.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Playlist {
+    pub id: u32,
+    /// The id of the containing folder, 0 for the root.
+    pub parent_id: u32,
+    pub name: String,
+    pub is_folder: bool,
+    pub sort_order: u32,
+    /// Track ids in playlist order.
+    pub track_ids: Vec<u32>,
+}
+
+/// Everything we read from a device database.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceDatabase {
+    pub tracks: Vec<Track>,
+    pub artists: HashMap<u32, String>,
+    pub albums: HashMap<u32, String>,
+    pub genres: HashMap<u32, String>,
+    pub labels: HashMap<u32, String>,
+    pub keys: HashMap<u32, String>,
+    pub colors: HashMap<u32, String>,
+    pub playlists: Vec<Playlist>,
+    /// History playlists are stored separately to normal playlists, they are never folders.
+    pub history_playlists: Vec<Playlist>,
+}
+
+/// A device's contents converted to dbeat's model, paths are absolute using the device's mount
+/// point.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceLibrary {
+    pub songs: Vec<Song>,
+    pub playlists: Vec<DevicePlaylist>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevicePlaylist {
+    pub name: String,
+    /// Names of the folders containing this playlist from the root down.
+    pub folders: Vec<String>,
+    /// File paths of the songs in the playlist in order.
+    pub song_paths: Vec<String>,
+}
+
+impl DeviceDatabase {
+    /// Reads the database from a device mounted at `device_path`.
+    pub fn read_device(device_path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read(device_path.join(DATABASE_PATH))?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let page_size = read_u32(data, 0x04)?;
+        if page_size < HEAP_OFFSET as u32 {
+            return Err(Error::InvalidPageSize(page_size));
+        }
+        let table_count = read_u32(data, 0x08)? as usize;
+
+        let mut database = Self::default();
+        let mut playlists: HashMap<u32, Playlist> = HashMap::new();
+        let mut history_playlists: HashMap<u32, Playlist> = HashMap::new();
+        // Entries are (playlist id, entry index, track id).
+        let mut playlist_entries: Vec<(u32, u32, u32)> = Vec::new();
+        let mut history_entries: Vec<(u32, u32, u32)> = Vec::new();
+
+        for table_index in 0..table_count {
+            let offset = 0x1c + table_index * 16;
+            let table_type = read_u32(data, offset)?;
+            let first_page = read_u32(data, offset + 8)?;
+            let last_page = read_u32(data, offset + 12)?;
+
+            let Some(table) = TableType::from_id(table_type) else {
+                continue;
+            };
+
+            for row in table_rows(data, page_size as usize, table_type, first_page, last_page)? {
+                let parsed = match table {
+                    TableType::Tracks => parse_track(row).map(|track| database.tracks.push(track)),
+                    TableType::Genres => parse_id_name(row, 4).map(|(id, name)| {
+                        database.genres.insert(id, name);
+                    }),
+                    TableType::Artists => parse_artist(row).map(|(id, name)| {
+                        database.artists.insert(id, name);
+                    }),
+                    TableType::Albums => parse_album(row).map(|(id, name)| {
+                        database.albums.insert(id, name);
+                    }),
+                    TableType::Labels => parse_id_name(row, 4).map(|(id, name)| {
+                        database.labels.insert(id, name);
+                    }),
+                    TableType::Keys => parse_id_name(row, 8).map(|(id, name)| {
+                        database.keys.insert(id, name);
+                    }),
+                    TableType::Colors => parse_color(row).map(|(id, name)| {
+                        database.colors.insert(id, name);
+                    }),
+                    TableType::PlaylistTree => parse_playlist(row).map(|playlist| {
+                        playlists.insert(playlist.id, playlist);
+                    }),
+                    TableType::PlaylistEntries => {
+                        parse_playlist_entry(row).map(|entry| playlist_entries.push(entry))
+                    }
+                    TableType::HistoryPlaylists => parse_id_name(row, 4).map(|(id, name)| {
+                        let playlist = Playlist {
+                            id,
+                            parent_id: 0,
+                            name,
+                            is_folder: false,
+                            sort_order: id,
+                            track_ids: Vec::new(),
+                        };
+                        history_playlists.insert(id, playlist);
+                    }),
+                    TableType::HistoryEntries => {
+                        parse_history_entry(row).map(|entry| history_entries.push(entry))
+                    }
+                };
+
+                if let Err(error) = parsed {
+                    tracing::warn!(?error, ?table, "failed to parse device database row");
+                }
+            }
+        }
+
+        database.playlists = collect_playlists(playlists, playlist_entries);
+        database.history_playlists = collect_playlists(history_playlists, history_entries);
+
+        Ok(database)
+    }
+
+    /// Converts the tracks to songs, `device_path` is prefixed to each track's path.
+    pub fn songs(&self, device_path: &Path) -> Vec<Song> {
+        self.tracks
+            .iter()
+            .map(|track| self.track_to_song(track, device_path))
+            .collect()
+    }
+
+    /// The song a track id refers to if it exists.
+    pub fn song(&self, track_id: u32, device_path: &Path) -> Option<Song> {
+        self.tracks
+            .iter()
+            .find(|track| track.id == track_id)
+            .map(|track| self.track_to_song(track, device_path))
+    }
+
+    pub fn library(&self, device_path: &Path) -> DeviceLibrary {
+        let paths: HashMap<u32, String> = self
+            .tracks
+            .iter()
+            .map(|track| (track.id, device_file_path(device_path, &track.file_path)))
+            .collect();
+        let folders: HashMap<u32, &Playlist> = self
+            .playlists
+            .iter()
+            .filter(|playlist| playlist.is_folder)
+            .map(|playlist| (playlist.id, playlist))
+            .collect();
+
+        let playlists = self
+            .playlists
+            .iter()
+            .filter(|playlist| !playlist.is_folder)
+            .map(|playlist| {
+                let mut folder_names = Vec::new();
+                let mut parent_id = playlist.parent_id;
+                // The depth limit guards against cycles in a corrupt tree.
+                while let Some(folder) = folders.get(&parent_id) {
+                    if folder_names.len() > folders.len() {
+                        break;
+                    }
+                    folder_names.insert(0, folder.name.clone());
+                    parent_id = folder.parent_id;
+                }
+
+                DevicePlaylist {
+                    name: playlist.name.clone(),
+                    folders: folder_names,
+                    song_paths: playlist
+                        .track_ids
+                        .iter()
+                        .filter_map(|id| paths.get(id).cloned())
+                        .collect(),
+                }
+            })
+            .collect();
+
+        DeviceLibrary {
+            songs: self.songs(device_path),
+            playlists,
+        }
+    }
+
+    fn track_to_song(&self, track: &Track, device_path: &Path) -> Song {
+        let lookup = |table: &HashMap<u32, String>, id: u32| {
+            table.get(&id).filter(|name| !name.is_empty()).cloned()
+        };
+        let key = lookup(&self.keys, track.key_id);
+
+        Song {
+            file_path: device_file_path(device_path, &track.file_path),
+            title: Some(track.title.clone()).filter(|title| !title.is_empty()),
+            artist: lookup(&self.artists, track.artist_id),
+            album: lookup(&self.albums, track.album_id),
+            genre: lookup(&self.genres, track.genre_id),
+            bpm: track.bpm,
+            duration_seconds: track.duration_seconds as u64,
+            key: key.clone(),
+            parsed_key: key.as_deref().and_then(Key::parse),
+            year: (track.year != 0).then_some(track.year as u32),
+            label: lookup(&self.labels, track.label_id),
+            comment: Some(track.comment.clone()).filter(|comment| !comment.is_empty()),
+            track_number: (track.track_number != 0).then_some(track.track_number),
+            rating: (track.rating != 0).then_some(track.rating),
+            bitrate_kbps: (track.bitrate != 0).then_some(track.bitrate),
+            sample_rate: (track.sample_rate != 0).then_some(track.sample_rate),
+            file_size_bytes: (track.file_size != 0).then_some(track.file_size as u64),
+            ..Default::default()
+        }
+    }
+}
+
+/// Joins a device relative path (always using `/`) onto the device's mount point.
+pub fn device_file_path(device_path: &Path, relative_path: &str) -> String {
+    device_path
+        .join(relative_path.trim_start_matches('/'))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn collect_playlists(
+    mut playlists: HashMap<u32, Playlist>,
+    mut entries: Vec<(u32, u32, u32)>,
+) -> Vec<Playlist> {
+    entries.sort_by_key(|&(playlist_id, entry_index, _)| (playlist_id, entry_index));
+    for (playlist_id, _, track_id) in entries {
+        if let Some(playlist) = playlists.get_mut(&playlist_id) {
+            playlist.track_ids.push(track_id);
+        }
+    }
+
+    let mut playlists: Vec<Playlist> = playlists.into_values().collect();
+    playlists.sort_by_key(|playlist| (playlist.parent_id, playlist.sort_order));
+    playlists
+}
+
+/// Collects the rows of every data page in a table by following the page linked list.
+fn table_rows(
+    data: &[u8],
+    page_size: usize,
+    table_type: u32,
+    first_page: u32,
+    last_page: u32,
+) -> Result<Vec<&[u8]>, Error> {
+    let mut rows = Vec::new();
+    let mut page_index = first_page;
+    let page_count = data.len() / page_size;
+
+    // Bounding the number of pages visited guards against cycles in a corrupt file.
+    for _ in 0..page_count {
+        let page_offset = page_index as usize * page_size;
+        let page = data
+            .get(page_offset..page_offset + page_size)
+            .ok_or(Error::UnexpectedEof(page_offset))?;
+
+        let page_flags = page[0x1b];
+        let is_data_page = page_flags & 0x40 == 0;
+        if is_data_page && read_u32(page, 0x08)? == table_type {
+            rows.append(&mut page_rows(page)?);
+        }
+
+        if page_index == last_page {
+            break;
+        }
+        page_index = read_u32(page, 0x0c)?;
+    }
+
+    Ok(rows)
+}
+
+/// Reads the rows of a single page using the row group index at the end of the page.
+fn page_rows(page: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let small_row_count = page[0x18] as usize;
+    let large_row_count = read_u16(page, 0x22)? as usize;
+    let row_count = if large_row_count > small_row_count && large_row_count != 0x1fff {
+        large_row_count
+    } else {
+        small_row_count
+    };
+
+    let mut rows = Vec::with_capacity(row_count);
+    for group_index in 0..row_count.div_ceil(16) {
+        let base = page.len() - group_index * ROW_GROUP_SIZE;
+        let present_flags = read_u16(page, base - 4)?;
+        let group_row_count = (row_count - group_index * 16).min(16);
+
+        for row_index in 0..group_row_count {
+            if (present_flags >> row_index) & 1 == 0 {
+                continue;
+            }
+            let row_offset = read_u16(page, base - (6 + 2 * row_index))? as usize;
+            let row = page
+                .get(HEAP_OFFSET + row_offset..)
+                .ok_or(Error::UnexpectedEof(HEAP_OFFSET + row_offset))?;
+            rows.push(row);
+        }
+    }
+
+    Ok(rows)
+}
+
+fn parse_track(row: &[u8]) -> Result<Track, Error> {
+    let string = |index: usize| -> Result<String, Error> {
+        let offset = read_u16(row, 0x5e + index * 2)? as usize;
+        read_string(row, offset)
+    };
+    let tempo = read_u32(row, 0x38)?;
+
+    Ok(Track {
+        id: read_u32(row, 0x48)?,
+        title: string(17)?,
+        artist_id: read_u32(row, 0x44)?,
+        album_id: read_u32(row, 0x40)?,
+        genre_id: read_u32(row, 0x3c)?,
+        label_id: read_u32(row, 0x28)?,
+        key_id: read_u32(row, 0x20)?,
+        color_id: read_u8(row, 0x58)?,
+        bpm: (tempo != 0).then(|| tempo as f64 / 100.0),
+        duration_seconds: read_u16(row, 0x54)?,
+        sample_rate: read_u32(row, 0x08)?,
+        bitrate: read_u32(row, 0x30)?,
+        file_size: read_u32(row, 0x10)?,
+        track_number: read_u32(row, 0x34)?,
+        year: read_u16(row, 0x50)?,
+        rating: read_u8(row, 0x59)?,
+        comment: string(16)?,
+        file_path: string(20)?,
+        analyze_path: string(14)?,
+        date_added: string(10)?,
+    })
+}
+
+/// Artist rows store their name at a near (1 byte) or far (2 byte) offset depending on subtype.
+fn parse_artist(row: &[u8]) -> Result<(u32, String), Error> {
+    let subtype = read_u16(row, 0)?;
+    let id = read_u32(row, 0x04)?;
+    let name_offset = if subtype == 0x64 {
+        read_u16(row, 0x0a)? as usize
+    } else {
+        read_u8(row, 0x09)? as usize
+    };
+    Ok((id, read_string(row, name_offset)?))
+}
+
+fn parse_album(row: &[u8]) -> Result<(u32, String), Error> {
+    let subtype = read_u16(row, 0)?;
+    let id = read_u32(row, 0x0c)?;
+    let name_offset = if subtype == 0x84 {
+        read_u16(row, 0x16)? as usize
+    } else {
+        read_u8(row, 0x15)? as usize
+    };
+    Ok((id, read_string(row, name_offset)?))
+}
+
+fn parse_color(row: &[u8]) -> Result<(u32, String), Error> {
+    let id = read_u16(row, 0x05)? as u32;
+    Ok((id, read_string(row, 0x08)?))
+}
+
+fn parse_playlist(row: &[u8]) -> Result<Playlist, Error> {
+    Ok(Playlist {
+        parent_id: read_u32(row, 0x00)?,
+        sort_order: read_u32(row, 0x08)?,
+        id: read_u32(row, 0x0c)?,
+        is_folder: read_u32(row, 0x10)? != 0,
+        name: read_string(row, 0x14)?,
+        track_ids: Vec::new(),
+    })
+}
+
+/// Returns the playlist id, entry index and track id.
+fn parse_playlist_entry(row: &[u8]) -> Result<(u32, u32, u32), Error> {
+    Ok((
+        read_u32(row, 0x08)?,
+        read_u32(row, 0x00)?,
+        read_u32(row, 0x04)?,
+    ))
+}
+
+/// Returns the playlist id, entry index and track id, history entries order their fields
+/// differently to playlist entries.
+fn parse_history_entry(row: &[u8]) -> Result<(u32, u32, u32), Error> {
+    Ok((
+        read_u32(row, 0x04)?,
+        read_u32(row, 0x08)?,
+        read_u32(row, 0x00)?,
+    ))
+}
+
+/// Parses rows which start with an id and have a name string at `name_offset`.
+fn parse_id_name(row: &[u8], name_offset: usize) -> Result<(u32, String), Error> {
+    Ok((read_u32(row, 0)?, read_string(row, name_offset)?))
+}
+
+/// Reads a DeviceSQL string. The first byte describes the encoding, short ASCII strings store
+/// their length in the same byte while long strings have a 4 byte header containing the length.
+fn read_string(row: &[u8], offset: usize) -> Result<String, Error> {
+    let header = read_u8(row, offset)?;
+
+    if header & 1 == 1 {
+        let len = ((header >> 1) as usize).saturating_sub(1);
+        let bytes = row
+            .get(offset + 1..offset + 1 + len)
+            .ok_or(Error::UnexpectedEof(offset))?;
+        return Ok(String::from_utf8_lossy(bytes).into_owned());
+    }
+
+    let len = (read_u16(row, offset + 1)? as usize).saturating_sub(4);
+    let bytes = row
+        .get(offset + 4..offset + 4 + len)
+        .ok_or(Error::UnexpectedEof(offset))?;
+
+    match header {
+        0x90 => {
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            Ok(String::from_utf16_lossy(&units))
+        }
+        _ => Ok(String::from_utf8_lossy(bytes).into_owned()),
+    }
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8, Error> {
+    data.get(offset)
+        .copied()
+        .ok_or(Error::UnexpectedEof(offset))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, Error> {
+    data.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or(Error::UnexpectedEof(offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, Error> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(Error::UnexpectedEof(offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 512;
+
+    fn short_string(value: &str) -> Vec<u8> {
+        let mut string = vec![((value.len() as u8 + 1) << 1) | 1];
+        string.extend(value.as_bytes());
+        string
+    }
+
+    /// A row with fixed fields written at their offsets followed by a string.
+    fn row(fields: &[(usize, &[u8])], len: usize, name: &str) -> Vec<u8> {
+        let mut row = vec![0; len];
+        for (offset, bytes) in fields {
+            row[*offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+        row.extend(short_string(name));
+        row
+    }
+
+    fn track_row(id: u32, title: &str, file_path: &str) -> Vec<u8> {
+        let mut row = vec![0; 0x88];
+        let fields: [(usize, u32); 12] = [
+            (0x08, 44100),
+            (0x10, 9_000_000),
+            (0x20, 1),
+            (0x28, 1),
+            (0x30, 320),
+            (0x34, 3),
+            (0x38, 12800),
+            (0x3c, 1),
+            (0x40, 1),
+            (0x44, 1),
+            (0x48, id),
+            (0x50, 2021),
+        ];
+        for (offset, value) in fields {
+            row[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        row[0x54..0x56].copy_from_slice(&240u16.to_le_bytes());
+        row[0x59] = 4;
+
+        // Every string defaults to the empty string stored first.
+        let empty = row.len() as u16;
+        row.extend(short_string(""));
+        for index in 0..21 {
+            row[0x5e + index * 2..0x60 + index * 2].copy_from_slice(&empty.to_le_bytes());
+        }
+        for (index, value) in [(16, "Comment"), (17, title), (20, file_path)] {
+            let offset = row.len() as u16;
+            row.extend(short_string(value));
+            row[0x5e + index * 2..0x60 + index * 2].copy_from_slice(&offset.to_le_bytes());
+        }
+        row
+    }
+
+    /// A data page holding up to 16 rows for the table of the given type.
+    fn page(table_type: u32, rows: &[Vec<u8>]) -> Vec<u8> {
+        let mut page = vec![0; PAGE_SIZE];
+        page[0x08..0x0c].copy_from_slice(&table_type.to_le_bytes());
+        page[0x18] = rows.len() as u8;
+        page[0x1b] = 0x24;
+
+        let mut heap_offset = 0;
+        let mut present_flags = 0u16;
+        for (index, row) in rows.iter().enumerate() {
+            let start = HEAP_OFFSET + heap_offset;
+            page[start..start + row.len()].copy_from_slice(row);
+            let index_offset = PAGE_SIZE - (6 + 2 * index);
+            page[index_offset..index_offset + 2]
+                .copy_from_slice(&(heap_offset as u16).to_le_bytes());
+            present_flags |= 1 << index;
+            heap_offset += row.len();
+        }
+        page[PAGE_SIZE - 4..PAGE_SIZE - 2].copy_from_slice(&present_flags.to_le_bytes());
+        page
+    }
+
+    /// A database with one page per table following the header page.
+    fn database(tables: &[(u32, Vec<Vec<u8>>)]) -> Vec<u8> {
+        let mut data = vec![0; PAGE_SIZE];
+        data[0x04..0x08].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        data[0x08..0x0c].copy_from_slice(&(tables.len() as u32).to_le_bytes());
+        for (index, (table_type, rows)) in tables.iter().enumerate() {
+            let offset = 0x1c + index * 16;
+            let page_index = index as u32 + 1;
+            data[offset..offset + 4].copy_from_slice(&table_type.to_le_bytes());
+            data[offset + 8..offset + 12].copy_from_slice(&page_index.to_le_bytes());
+            data[offset + 12..offset + 16].copy_from_slice(&page_index.to_le_bytes());
+            data.extend(page(*table_type, rows));
+        }
+        data
+    }
+
+    fn test_database() -> DeviceDatabase {
+        let data = database(&[
+            (
+                0,
+                vec![
+                    track_row(1, "First", "/Contents/Artist/first.mp3"),
+                    track_row(2, "Second", "/Contents/Artist/second.mp3"),
+                ],
+            ),
+            (1, vec![row(&[(0, &1u32.to_le_bytes())], 4, "House")]),
+            (
+                2,
+                vec![row(
+                    &[
+                        (0, &0x60u16.to_le_bytes()),
+                        (4, &1u32.to_le_bytes()),
+                        (9, &[10]),
+                    ],
+                    10,
+                    "Artist",
+                )],
+            ),
+            (
+                3,
+                vec![row(
+                    &[
+                        (0, &0x80u16.to_le_bytes()),
+                        (0x0c, &1u32.to_le_bytes()),
+                        (0x15, &[0x16]),
+                    ],
+                    0x16,
+                    "Album",
+                )],
+            ),
+            (4, vec![row(&[(0, &1u32.to_le_bytes())], 4, "Label")]),
+            (5, vec![row(&[(0, &1u32.to_le_bytes())], 8, "8A")]),
+            (6, vec![row(&[(5, &1u16.to_le_bytes())], 8, "Pink")]),
+            (
+                7,
+                vec![
+                    row(
+                        &[(0x0c, &1u32.to_le_bytes()), (0x10, &1u32.to_le_bytes())],
+                        0x14,
+                        "Sets",
+                    ),
+                    row(
+                        &[(0x00, &1u32.to_le_bytes()), (0x0c, &2u32.to_le_bytes())],
+                        0x14,
+                        "Friday",
+                    ),
+                ],
+            ),
+            (
+                8,
+                vec![
+                    [1u32, 2, 2].iter().flat_map(|v| v.to_le_bytes()).collect(),
+                    [0u32, 1, 2].iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ],
+            ),
+            (11, vec![row(&[(0, &5u32.to_le_bytes())], 4, "HISTORY 001")]),
+            (
+                12,
+                vec![
+                    [1u32, 5, 1].iter().flat_map(|v| v.to_le_bytes()).collect(),
+                    [2u32, 5, 0].iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ],
+            ),
+        ]);
+        DeviceDatabase::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn parses_every_table() {
+        let database = test_database();
+
+        let titles: Vec<_> = database.tracks.iter().map(|track| &track.title).collect();
+        assert_eq!(titles, vec!["First", "Second"]);
+        assert_eq!(database.genres[&1], "House");
+        assert_eq!(database.artists[&1], "Artist");
+        assert_eq!(database.albums[&1], "Album");
+        assert_eq!(database.labels[&1], "Label");
+        assert_eq!(database.keys[&1], "8A");
+        assert_eq!(database.colors[&1], "Pink");
+
+        let playlists: Vec<_> = database
+            .playlists
+            .iter()
+            .map(|playlist| {
+                (
+                    playlist.name.as_str(),
+                    playlist.is_folder,
+                    &playlist.track_ids,
+                )
+            })
+            .collect();
+        assert_eq!(
+            playlists,
+            vec![("Sets", true, &vec![]), ("Friday", false, &vec![1, 2])]
+        );
+        assert_eq!(database.history_playlists.len(), 1);
+        assert_eq!(database.history_playlists[0].name, "HISTORY 001");
+        assert_eq!(database.history_playlists[0].track_ids, vec![2, 1]);
+    }
+
+    #[test]
+    fn converts_tracks_to_songs() {
+        let database = test_database();
+        let device_path = Path::new("/media/usb");
+        let song = database.song(1, device_path).unwrap();
+
+        assert_eq!(song.file_path, "/media/usb/Contents/Artist/first.mp3");
+        assert_eq!(song.title.as_deref(), Some("First"));
+        assert_eq!(song.artist.as_deref(), Some("Artist"));
+        assert_eq!(song.album.as_deref(), Some("Album"));
+        assert_eq!(song.genre.as_deref(), Some("House"));
+        assert_eq!(song.label.as_deref(), Some("Label"));
+        assert_eq!(song.key.as_deref(), Some("8A"));
+        assert!(song.parsed_key.is_some());
+        assert_eq!(song.bpm, Some(128.0));
+        assert_eq!(song.duration_seconds, 240);
+        assert_eq!(song.year, Some(2021));
+        assert_eq!(song.rating, Some(4));
+        assert_eq!(song.comment.as_deref(), Some("Comment"));
+        assert_eq!(song.track_number, Some(3));
+        assert_eq!(song.sample_rate, Some(44100));
+        assert_eq!(song.bitrate_kbps, Some(320));
+        assert_eq!(song.file_size_bytes, Some(9_000_000));
+
+        let library = database.library(device_path);
+        assert_eq!(library.playlists.len(), 1);
+        assert_eq!(library.playlists[0].folders, vec!["Sets"]);
+        assert_eq!(
+            library.playlists[0].song_paths,
+            vec![
+                "/media/usb/Contents/Artist/first.mp3",
+                "/media/usb/Contents/Artist/second.mp3"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_long_strings() {
+        let mut utf16 = vec![0x90];
+        let units: Vec<u8> = "Ünïcödé"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        utf16.extend((units.len() as u16 + 4).to_le_bytes());
+        utf16.push(0);
+        utf16.extend(units);
+        assert_eq!(read_string(&utf16, 0).unwrap(), "Ünïcödé");
+
+        let mut ascii = vec![0x40];
+        ascii.extend(9u16.to_le_bytes());
+        ascii.push(0);
+        ascii.extend(b"Hello");
+        assert_eq!(read_string(&ascii, 0).unwrap(), "Hello");
+        assert!(read_string(&[0x40, 20, 0, 0], 0).is_err());
+    }
+}
@@ -0,0 +1,627 @@
+//! Generates a Rekordbox XML library (`rekordbox.xml`) from dbeat's songs and recordings, and
+//! reads the playlists from XML libraries exported by Rekordbox.
+//!
+//! Rekordbox can load this file in its "Imported Library" view (Preferences > Advanced >
+//! rekordbox xml), from there songs and playlists can be dragged into the main collection.
+//! Each recording is exported as a playlist containing its tracklist in order.
+
+use std::{collections::HashMap, io::Write, path::Path};
+
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, Event},
+    Reader, Writer,
+};
+
+use serde::Serialize;
+
+use crate::{
+    recording::{Recording, Track},
+    songs::Song,
+};
+
+/// Name of the playlist folder all recording playlists are placed in.
+const RECORDINGS_FOLDER_NAME: &str = "dbeat Recordings";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to write rekordbox xml: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to parse rekordbox xml: {0}")]
+    Xml(#[from] quick_xml::Error),
+}
+
+/// A track read from the `COLLECTION` of a Rekordbox XML file.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XmlTrack {
+    pub name: Option<String>,
+    pub artist: Option<String>,
+    pub file_path: Option<String>,
+}
+
+/// A playlist read from a Rekordbox XML file with its tracks resolved from the collection.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct XmlPlaylist {
+    pub name: String,
+    /// Names of the folders containing this playlist from the root down, excluding `ROOT`.
+    pub folders: Vec<String>,
+    pub tracks: Vec<XmlTrack>,
+}
+
+/// Nodes currently open while reading the `PLAYLISTS` tree.
+enum OpenNode {
+    Folder(String),
+    Playlist(PendingPlaylist),
+}
+
+/// A playlist read before its entries have been resolved against the collection.
+#[derive(Default)]
+struct PendingPlaylist {
+    name: String,
+    folders: Vec<String>,
+    /// "0" if entries reference tracks by `TrackID` and "1" if by `Location`.
+    key_type: String,
+    keys: Vec<String>,
+}
+
+/// A track in the `COLLECTION` element, Rekordbox references these by `TrackID` from playlists.
+#[derive(Debug, Clone)]
+struct CollectionTrack {
+    id: usize,
+    file_path: String,
+    name: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    genre: Option<String>,
+    bpm: Option<f64>,
+    total_time_seconds: Option<u64>,
+}
+
+impl From<&Song> for CollectionTrack {
+    fn from(song: &Song) -> Self {
+        Self {
+            id: 0,
+            file_path: song.file_path.clone(),
+            name: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            genre: song.genre.clone(),
+            bpm: song.bpm,
+            total_time_seconds: Some(song.duration_seconds),
+        }
+    }
+}
+
+/// A playlist node containing references to collection tracks by id.
+#[derive(Debug, Clone)]
+struct Playlist {
+    name: String,
+    track_ids: Vec<usize>,
+}
+
+/// Builds the collection and playlists, keeping track ids unique per file path.
+#[derive(Debug, Default)]
+struct Library {
+    tracks: Vec<CollectionTrack>,
+    ids_by_path: HashMap<String, usize>,
+    playlists: Vec<Playlist>,
+}
+
+impl Library {
+    fn add_track(&mut self, mut track: CollectionTrack) -> usize {
+        if let Some(id) = self.ids_by_path.get(&track.file_path) {
+            return *id;
+        }
+
+        track.id = self.tracks.len() + 1;
+        self.ids_by_path.insert(track.file_path.clone(), track.id);
+        self.tracks.push(track);
+        self.tracks.len()
+    }
+
+    fn add_recording(&mut self, recording: &Recording, songs: &[Song]) {
+        let mut track_ids = Vec::new();
+
+        for track in &recording.tracks {
+            if let Some(song) = match_song(track, songs) {
+                track_ids.push(self.add_track(song.into()));
+                continue;
+            }
+
+            // Tracks which aren't in the library are still exported using the cue sheet's
+            // metadata, Rekordbox will mark them as missing if the file can't be found.
+            let Some(file) = &track.file else {
+                tracing::debug!(title = track.title, "skipping track without a file");
+                continue;
+            };
+            let Some(file_path) = resolve_file_path(&recording.file_path, &file.name) else {
+                tracing::debug!(file = file.name, "skipping track with an unresolvable file");
+                continue;
+            };
+            track_ids.push(self.add_track(CollectionTrack {
+                id: 0,
+                file_path,
+                name: track.title.clone(),
+                artist: track.performer.clone(),
+                album: None,
+                genre: None,
+                bpm: None,
+                total_time_seconds: None,
+            }));
+        }
+
+        let name = recording.title.clone().unwrap_or_else(|| {
+            Path::new(&recording.file_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| recording.file_path.clone())
+        });
+
+        self.playlists.push(Playlist { name, track_ids });
+    }
+}
+
+/// Writes a Rekordbox XML file to `path` containing every song in the collection and one
+/// playlist per recording.
+pub fn export(path: &str, songs: &[Song], recordings: &[Recording]) -> Result<(), Error> {
+    let mut library = Library::default();
+
+    for song in songs {
+        library.add_track(song.into());
+    }
+
+    for recording in recordings {
+        library.add_recording(recording, songs);
+    }
+
+    let file = std::fs::File::create(path)?;
+    let mut writer = Writer::new_with_indent(std::io::BufWriter::new(file), b' ', 2);
+    write_library(&mut writer, &library)?;
+    writer.into_inner().flush()?;
+
+    Ok(())
+}
+
+/// Reads every playlist from a Rekordbox XML file. Entries referencing tracks missing from the
+/// collection are skipped.
+pub fn read_playlists(path: &str) -> Result<Vec<XmlPlaylist>, Error> {
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = Vec::new();
+
+    let mut tracks_by_id: HashMap<String, XmlTrack> = HashMap::new();
+    let mut tracks_by_location: HashMap<String, XmlTrack> = HashMap::new();
+    let mut in_collection = false;
+    let mut open_nodes: Vec<OpenNode> = Vec::new();
+    // Playlists are resolved once the whole collection has been read.
+    let mut playlists: Vec<PendingPlaylist> = Vec::new();
+
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        let is_empty = matches!(event, Event::Empty(_));
+
+        match event {
+            Event::Start(element) | Event::Empty(element) => match element.name().as_ref() {
+                b"COLLECTION" => in_collection = !is_empty,
+                b"TRACK" if in_collection => {
+                    let track = XmlTrack {
+                        name: attribute(&element, "Name"),
+                        artist: attribute(&element, "Artist"),
+                        file_path: attribute(&element, "Location")
+                            .map(|location| file_path_from_location(&location)),
+                    };
+                    if let Some(location) = attribute(&element, "Location") {
+                        tracks_by_location.insert(location, track.clone());
+                    }
+                    if let Some(id) = attribute(&element, "TrackID") {
+                        tracks_by_id.insert(id, track);
+                    }
+                }
+                b"TRACK" => {
+                    if let (Some(OpenNode::Playlist(playlist)), Some(key)) =
+                        (open_nodes.last_mut(), attribute(&element, "Key"))
+                    {
+                        playlist.keys.push(key);
+                    }
+                }
+                b"NODE" => {
+                    let name = attribute(&element, "Name").unwrap_or_default();
+                    let node = if attribute(&element, "Type").as_deref() == Some("1") {
+                        OpenNode::Playlist(PendingPlaylist {
+                            name,
+                            key_type: attribute(&element, "KeyType").unwrap_or_default(),
+                            ..Default::default()
+                        })
+                    } else {
+                        OpenNode::Folder(name)
+                    };
+                    open_nodes.push(node);
+                    if is_empty {
+                        close_node(&mut open_nodes, &mut playlists);
+                    }
+                }
+                _ => {}
+            },
+            Event::End(element) => match element.name().as_ref() {
+                b"COLLECTION" => in_collection = false,
+                b"NODE" => close_node(&mut open_nodes, &mut playlists),
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    Ok(playlists
+        .into_iter()
+        .map(|playlist| {
+            let tracks_by_key = if playlist.key_type == "1" {
+                &tracks_by_location
+            } else {
+                &tracks_by_id
+            };
+            XmlPlaylist {
+                name: playlist.name,
+                folders: playlist.folders,
+                tracks: playlist
+                    .keys
+                    .iter()
+                    .filter_map(|key| tracks_by_key.get(key).cloned())
+                    .collect(),
+            }
+        })
+        .collect())
+}
+
+fn close_node(open_nodes: &mut Vec<OpenNode>, playlists: &mut Vec<PendingPlaylist>) {
+    if let Some(OpenNode::Playlist(mut playlist)) = open_nodes.pop() {
+        playlist.folders = open_nodes
+            .iter()
+            .filter_map(|node| match node {
+                OpenNode::Folder(name) if name != "ROOT" => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        playlists.push(playlist);
+    }
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+fn write_library<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    library: &Library,
+) -> std::io::Result<()> {
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.write_event(Event::Start(
+        BytesStart::new("DJ_PLAYLISTS").with_attributes([("Version", "1.0.0")]),
+    ))?;
+
+    writer.write_event(Event::Empty(BytesStart::new("PRODUCT").with_attributes([
+        ("Name", "dbeat"),
+        ("Version", env!("CARGO_PKG_VERSION")),
+        ("Company", ""),
+    ])))?;
+
+    let entries = library.tracks.len().to_string();
+    writer.write_event(Event::Start(
+        BytesStart::new("COLLECTION").with_attributes([("Entries", entries.as_str())]),
+    ))?;
+    for track in &library.tracks {
+        writer.write_event(Event::Empty(collection_track_element(track)))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("COLLECTION")))?;
+
+    writer.write_event(Event::Start(BytesStart::new("PLAYLISTS")))?;
+    writer.write_event(Event::Start(BytesStart::new("NODE").with_attributes([
+        ("Type", "0"),
+        ("Name", "ROOT"),
+        ("Count", "1"),
+    ])))?;
+
+    let playlist_count = library.playlists.len().to_string();
+    writer.write_event(Event::Start(BytesStart::new("NODE").with_attributes([
+        ("Type", "0"),
+        ("Name", RECORDINGS_FOLDER_NAME),
+        ("Count", playlist_count.as_str()),
+    ])))?;
+    for playlist in &library.playlists {
+        write_playlist(writer, playlist)?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("NODE")))?;
+
+    writer.write_event(Event::End(BytesEnd::new("NODE")))?;
+    writer.write_event(Event::End(BytesEnd::new("PLAYLISTS")))?;
+    writer.write_event(Event::End(BytesEnd::new("DJ_PLAYLISTS")))?;
+
+    Ok(())
+}
+
+fn write_playlist<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    playlist: &Playlist,
+) -> std::io::Result<()> {
+    let entries = playlist.track_ids.len().to_string();
+    // `KeyType` 0 means tracks are referenced by `TrackID` rather than location.
+    writer.write_event(Event::Start(BytesStart::new("NODE").with_attributes([
+        ("Name", playlist.name.as_str()),
+        ("Type", "1"),
+        ("KeyType", "0"),
+        ("Entries", entries.as_str()),
+    ])))?;
+    for id in &playlist.track_ids {
+        let key = id.to_string();
+        writer.write_event(Event::Empty(
+            BytesStart::new("TRACK").with_attributes([("Key", key.as_str())]),
+        ))?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("NODE")))?;
+
+    Ok(())
+}
+
+fn collection_track_element(track: &CollectionTrack) -> BytesStart<'static> {
+    let mut element = BytesStart::new("TRACK");
+    element.push_attribute(("TrackID", track.id.to_string().as_str()));
+    if let Some(name) = &track.name {
+        element.push_attribute(("Name", name.as_str()));
+    }
+    if let Some(artist) = &track.artist {
+        element.push_attribute(("Artist", artist.as_str()));
+    }
+    if let Some(album) = &track.album {
+        element.push_attribute(("Album", album.as_str()));
+    }
+    if let Some(genre) = &track.genre {
+        element.push_attribute(("Genre", genre.as_str()));
+    }
+    if let Some(kind) = file_kind(&track.file_path) {
+        element.push_attribute(("Kind", kind.as_str()));
+    }
+    if let Some(total_time) = track.total_time_seconds {
+        element.push_attribute(("TotalTime", total_time.to_string().as_str()));
+    }
+    if let Some(bpm) = track.bpm {
+        element.push_attribute(("AverageBpm", format!("{bpm:.2}").as_str()));
+    }
+    element.push_attribute(("Location", file_location(&track.file_path).as_str()));
+    element
+}
+
+/// Finds the library song a cue sheet track refers to, first by the full file path then by the
+/// file name alone (the music folder may have moved since recording) and finally by title and
+/// artist.
+pub fn match_song<'a>(track: &Track, songs: &'a [Song]) -> Option<&'a Song> {
+    if let Some(file) = &track.file {
+        if let Some(song) = songs.iter().find(|song| song.file_path == file.name) {
+            return Some(song);
+        }
+
+        let file_name = file_name_lowercase(&file.name);
+        if let Some(song) = songs
+            .iter()
+            .find(|song| file_name_lowercase(&song.file_path) == file_name)
+        {
+            return Some(song);
+        }
+    }
+
+    let (Some(title), Some(performer)) = (&track.title, &track.performer) else {
+        return None;
+    };
+    songs.iter().find(|song| {
+        song.title.as_deref() == Some(title.as_str())
+            && song.artist.as_deref() == Some(performer.as_str())
+    })
+}
+
+/// Cue sheets may contain Windows paths even when read on another OS so both separators are
+/// handled rather than relying on `Path`.
+pub fn file_name_lowercase(path: &str) -> String {
+    path.rsplit(['/', '\\'])
+        .next()
+        .unwrap_or(path)
+        .to_lowercase()
+}
+
+/// Resolves a cue sheet `FILE` name to an absolute path, relative names are relative to the
+/// directory of the cue sheet. `None` when there's no directory to resolve against.
+fn resolve_file_path(cue_sheet_path: &str, name: &str) -> Option<String> {
+    let is_windows_absolute = matches!(
+        name.as_bytes(),
+        [drive, b':', b'\\' | b'/', ..] if drive.is_ascii_alphabetic()
+    ) || name.starts_with("\\\\");
+    if is_windows_absolute || Path::new(name).is_absolute() {
+        return Some(name.to_string());
+    }
+
+    let directory = Path::new(cue_sheet_path)
+        .parent()
+        .filter(|directory| directory.is_absolute())?;
+    Some(directory.join(name).to_string_lossy().into_owned())
+}
+
+/// Rekordbox's `Kind` column, e.g. "MP3 File".
+fn file_kind(file_path: &str) -> Option<String> {
+    let extension = Path::new(file_path).extension()?.to_str()?;
+    Some(format!("{} File", extension.to_uppercase()))
+}
+
+/// Converts a local path to the URL format Rekordbox uses, e.g.
+/// `file://localhost/C:/Music/My%20Song.mp3`.
+fn file_location(file_path: &str) -> String {
+    let path = file_path.replace('\\', "/");
+    let path = path.trim_start_matches('/');
+
+    let mut location = String::from("file://localhost/");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' | b':' => {
+                location.push(byte as char)
+            }
+            _ => location.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    location
+}
+
+/// Converts a Rekordbox location URL back to a local path, the inverse of `file_location`.
+pub fn file_path_from_location(location: &str) -> String {
+    let path = location
+        .strip_prefix("file://localhost")
+        .or_else(|| location.strip_prefix("file://"))
+        .unwrap_or(location);
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex: Vec<u8> = iter.by_ref().take(2).collect();
+            if let Some(decoded) = std::str::from_utf8(&hex)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                bytes.push(decoded);
+                continue;
+            }
+            bytes.push(byte);
+            bytes.extend(hex);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    let path = String::from_utf8_lossy(&bytes).into_owned();
+
+    // Windows paths are written as `/C:/...`, the leading slash isn't part of the path.
+    match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => path[1..].to_string(),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recording::File;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn track(title: &str, file_name: &str) -> Track {
+        Track {
+            title: Some(title.to_string()),
+            performer: Some("Artist".to_string()),
+            file: Some(File::from_path(file_name)),
+            start_time: Some("00:00:00".to_string()),
+        }
+    }
+
+    #[test]
+    fn locations_round_trip() {
+        for path in [
+            "/Users/dj/Music/My Song #1.mp3",
+            "C:/Music/Ünïcödé & more.flac",
+            "/music/100%.wav",
+        ] {
+            let location = file_location(path);
+            assert!(location.starts_with("file://localhost/"), "{location}");
+            assert!(!location[17..].contains(' '), "{location}");
+            assert_eq!(file_path_from_location(&location), path);
+        }
+        assert_eq!(
+            file_location("C:\\Music\\a b.mp3"),
+            "file://localhost/C:/Music/a%20b.mp3"
+        );
+    }
+
+    #[test]
+    fn resolves_file_paths_against_the_cue_sheet() {
+        assert_eq!(
+            resolve_file_path("/recordings/mix.cue", "/music/a.mp3").as_deref(),
+            Some("/music/a.mp3")
+        );
+        assert_eq!(
+            resolve_file_path("/recordings/mix.cue", "C:\\Music\\a.mp3").as_deref(),
+            Some("C:\\Music\\a.mp3")
+        );
+        assert_eq!(
+            resolve_file_path("/recordings/mix.cue", "a.mp3").as_deref(),
+            Some("/recordings/a.mp3")
+        );
+        assert_eq!(resolve_file_path("mix.cue", "a.mp3"), None);
+    }
+
+    #[test]
+    fn matches_songs_by_path_then_file_name_then_tags() {
+        let songs = vec![
+            Song {
+                file_path: "/music/a.mp3".to_string(),
+                ..Default::default()
+            },
+            Song {
+                file_path: "/music/moved/B.mp3".to_string(),
+                ..Default::default()
+            },
+            Song {
+                file_path: "/music/c.mp3".to_string(),
+                title: Some("C".to_string()),
+                artist: Some("Artist".to_string()),
+                ..Default::default()
+            },
+        ];
+        let path = |track: &Track| match_song(track, &songs).map(|song| song.file_path.as_str());
+
+        assert_eq!(path(&track("A", "/music/a.mp3")), Some("/music/a.mp3"));
+        assert_eq!(
+            path(&track("B", "D:\\old\\b.mp3")),
+            Some("/music/moved/B.mp3")
+        );
+        assert_eq!(path(&track("C", "/gone/other.mp3")), Some("/music/c.mp3"));
+        assert_eq!(path(&track("D", "/gone/d.mp3")), None);
+    }
+
+    #[test]
+    fn exports_recordings_as_playlists() {
+        let dir = TempDir::new("xml");
+        let path = dir.join("rekordbox.xml");
+
+        let songs = vec![Song {
+            file_path: "/music/a.mp3".to_string(),
+            title: Some("A".to_string()),
+            artist: Some("Artist".to_string()),
+            bpm: Some(124.0),
+            duration_seconds: 300,
+            ..Default::default()
+        }];
+        let recording = Recording {
+            file_path: "/recordings/mix.cue".to_string(),
+            title: Some("Friday \"live\" mix".to_string()),
+            tracks: vec![
+                track("A", "/music/a.mp3"),
+                track("Unmatched", "unmatched.mp3"),
+            ],
+            ..Default::default()
+        };
+        export(path.to_str().unwrap(), &songs, &[recording]).unwrap();
+
+        let playlists = read_playlists(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].name, "Friday \"live\" mix");
+        assert_eq!(playlists[0].folders, vec![RECORDINGS_FOLDER_NAME]);
+        let paths: Vec<_> = playlists[0]
+            .tracks
+            .iter()
+            .map(|track| track.file_path.as_deref().unwrap())
+            .collect();
+        assert_eq!(paths, vec!["/music/a.mp3", "/recordings/unmatched.mp3"]);
+    }
+}
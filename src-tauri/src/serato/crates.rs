@@ -0,0 +1,92 @@
+//! Serato crates are stored as one file per crate in `_Serato_/Subcrates`, the file name is the
+//! crate name with `%%` separating the names of parent crates.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{read_records, volume_root, Error};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Crate {
+    pub name: String,
+    /// Names of the parent crates from the top level down.
+    pub parents: Vec<String>,
+    /// Absolute file paths of the songs in the crate in order.
+    pub song_paths: Vec<String>,
+}
+
+/// Reads every crate in the `Subcrates` folder of a `_Serato_` directory.
+///
+/// Any IO errors are logged and otherwise ignored.
+pub fn find_crates(serato_dir: &Path) -> Vec<Crate> {
+    let subcrates_dir = serato_dir.join("Subcrates");
+    let dir = match std::fs::read_dir(&subcrates_dir) {
+        Ok(dir) => dir,
+        Err(error) => {
+            tracing::warn!(?error, ?subcrates_dir, "failed to read directory");
+            return Vec::new();
+        }
+    };
+
+    let root = volume_root(serato_dir);
+    let mut crates = Vec::new();
+
+    for entry in dir {
+        let entry_path = match entry {
+            Ok(entry) => entry.path(),
+            Err(error) => {
+                tracing::warn!(?error, "failed to read entry in dir");
+                continue;
+            }
+        };
+
+        if entry_path.extension().and_then(|ext| ext.to_str()) != Some("crate") {
+            continue;
+        }
+
+        match read_crate(&entry_path, &root) {
+            Ok(serato_crate) => crates.push(serato_crate),
+            Err(error) => tracing::warn!(?error, ?entry_path, "failed to read crate file"),
+        }
+    }
+
+    crates.sort_by(|a, b| (&a.parents, &a.name).cmp(&(&b.parents, &b.name)));
+    crates
+}
+
+/// Reads a single `.crate` file, track paths are joined onto `volume_root`.
+pub fn read_crate(path: &Path, volume_root: &Path) -> Result<Crate, Error> {
+    let data = std::fs::read(path)?;
+
+    let mut song_paths = Vec::new();
+    for record in read_records(&data)? {
+        if record.tag != b"otrk" {
+            continue;
+        }
+        for child in record.children()? {
+            if child.tag == b"ptrk" {
+                song_paths.push(
+                    volume_root
+                        .join(child.text())
+                        .to_string_lossy()
+                        .into_owned(),
+                );
+            }
+        }
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut parents: Vec<String> = stem.split("%%").map(String::from).collect();
+    let name = parents.pop().unwrap_or_default();
+
+    Ok(Crate {
+        name,
+        parents,
+        song_paths,
+    })
+}
@@ -0,0 +1,143 @@
+//! Serato's track library, stored in `_Serato_/database V2`.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use super::{read_records, volume_root, Error};
+use crate::songs::Song;
+
+/// A track from the Serato library, text fields are kept as Serato stores them.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Track {
+    pub file_path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub comment: Option<String>,
+    pub key: Option<String>,
+    pub bpm: Option<f64>,
+    pub duration_seconds: Option<u64>,
+    /// Seconds since the unix epoch the track was added to the library.
+    pub date_added: Option<u32>,
+    /// Serato marks tracks as missing when the file can't be found.
+    pub missing: bool,
+}
+
+impl Track {
+    pub fn to_song(&self) -> Song {
+        Song {
+            file_path: self.file_path.clone(),
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            album: self.album.clone(),
+            genre: self.genre.clone(),
+            bpm: self.bpm,
+            duration_seconds: self.duration_seconds.unwrap_or(0),
+            key: self.key.clone(),
+            comment: self.comment.clone(),
+            date_added_unix_seconds: self.date_added.map(i64::from),
+            ..Default::default()
+        }
+    }
+}
+
+/// Reads the `database V2` file in a `_Serato_` directory.
+pub fn read_database(serato_dir: &Path) -> Result<Vec<Track>, Error> {
+    let data = std::fs::read(serato_dir.join("database V2"))?;
+    let root = volume_root(serato_dir);
+
+    let mut tracks = Vec::new();
+    for record in read_records(&data)? {
+        if record.tag != b"otrk" {
+            continue;
+        }
+
+        let mut track = Track::default();
+        for field in record.children()? {
+            let text = || Some(field.text()).filter(|text| !text.is_empty());
+            match field.tag {
+                b"pfil" => track.file_path = root.join(field.text()).to_string_lossy().into_owned(),
+                b"tsng" => track.title = text(),
+                b"tart" => track.artist = text(),
+                b"talb" => track.album = text(),
+                b"tgen" => track.genre = text(),
+                b"tcom" => track.comment = text(),
+                b"tkey" => track.key = text(),
+                b"tbpm" => track.bpm = text().and_then(|bpm| bpm.trim().parse().ok()),
+                b"tlen" => track.duration_seconds = text().and_then(|len| parse_length(&len)),
+                b"uadd" => track.date_added = field.u32(),
+                b"bmis" => track.missing = field.payload.first() == Some(&1),
+                _ => {}
+            }
+        }
+
+        if !track.file_path.is_empty() {
+            tracks.push(track);
+        }
+    }
+
+    Ok(tracks)
+}
+
+/// Parses Serato's track length format 'MM:SS.cc' (the hundredths are optional).
+fn parse_length(length: &str) -> Option<u64> {
+    let (minutes, seconds) = length.trim().split_once(':')?;
+    let minutes: u64 = minutes.parse().ok()?;
+    let seconds: f64 = seconds.parse().ok()?;
+    Some(minutes * 60 + seconds as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serato::tests::{record, text};
+    use crate::testing::TempDir;
+
+    #[test]
+    fn reads_tracks() {
+        let dir = TempDir::new("serato");
+        let serato_dir = dir.join("_Serato_");
+        std::fs::create_dir_all(&serato_dir).unwrap();
+        let track = [
+            record(b"pfil", &text("Users/dj/Music/a.mp3")),
+            record(b"tsng", &text("Title")),
+            record(b"tart", &text("Artist")),
+            record(b"tbpm", &text("124.00")),
+            record(b"tlen", &text("05:30.25")),
+            record(b"tkey", &text("Am")),
+            record(b"uadd", &1_700_000_000u32.to_be_bytes()),
+            record(b"bmis", &[1]),
+        ]
+        .concat();
+        let data = [
+            record(b"vrsn", &text("2.0/Serato Scratch LIVE Database")),
+            record(b"otrk", &track),
+            record(b"otrk", &record(b"tsng", &text("No file"))),
+        ]
+        .concat();
+        std::fs::write(serato_dir.join("database V2"), data).unwrap();
+
+        let tracks = read_database(&serato_dir).unwrap();
+
+        assert_eq!(tracks.len(), 1);
+        let song = tracks[0].to_song();
+        assert_eq!(song.file_path, "/Users/dj/Music/a.mp3");
+        assert_eq!(song.title.as_deref(), Some("Title"));
+        assert_eq!(song.artist.as_deref(), Some("Artist"));
+        assert_eq!(song.bpm, Some(124.0));
+        assert_eq!(song.duration_seconds, 330);
+        assert_eq!(song.key.as_deref(), Some("Am"));
+        assert_eq!(song.date_added_unix_seconds, Some(1_700_000_000));
+        assert!(tracks[0].missing);
+    }
+
+    #[test]
+    fn parses_lengths() {
+        assert_eq!(parse_length("05:30.25"), Some(330));
+        assert_eq!(parse_length("61:02"), Some(3662));
+        assert_eq!(parse_length("5.30"), None);
+    }
+}
@@ -0,0 +1,383 @@
+//! Serato stores its analysis of a song in the song file's own tags. MP3 files use ID3v2 `GEOB`
+//! frames (`Serato Markers2`, `Serato BeatGrid` and `Serato Autotags`), other formats store the
+//! same frames base64 encoded in Vorbis comments or MP4 freeform atoms.
+
+use std::{fs::File, path::Path};
+
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use lofty::{
+    config::ParseOptions,
+    file::{AudioFile, TaggedFileExt},
+    id3::v2::Frame,
+    mpeg::MpegFile,
+    tag::ItemKey,
+};
+use serde::Serialize;
+
+const MARKERS: &str = "Serato Markers2";
+const BEAT_GRID: &str = "Serato BeatGrid";
+const AUTOTAGS: &str = "Serato Autotags";
+
+/// Tag keys used by formats without `GEOB` frames (Vorbis comments and MP4 freeform atoms).
+const ENCODED_KEYS: [&str; 6] = [
+    "SERATO_MARKERS_V2",
+    "SERATO_BEATGRID",
+    "SERATO_AUTOGAIN",
+    "----:com.serato.dj:markersv2",
+    "----:com.serato.dj:beatgrid",
+    "----:com.serato.dj:autgain",
+];
+
+/// Serato writes base64 without padding and sometimes with trailing bits set.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to parse song file: {0}")]
+    Lofty(#[from] lofty::error::LoftyError),
+    #[error("failed to read song file: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cue {
+    /// The hot cue slot starting at 0.
+    pub index: u8,
+    pub time_ms: u32,
+    pub color: [u8; 3],
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Loop {
+    pub index: u8,
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub locked: bool,
+    pub name: Option<String>,
+}
+
+/// A beat grid marker, every marker except the last specifies how many beats there are until
+/// the next marker while the last marker specifies the tempo from there on.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BeatGridMarker {
+    pub position_seconds: f32,
+    pub beats_until_next: Option<u32>,
+    pub bpm: Option<f32>,
+}
+
+/// Everything Serato stored in a song's tags.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Markers {
+    /// The track colour shown in the library.
+    pub color: Option<[u8; 3]>,
+    pub bpm_locked: Option<bool>,
+    pub cues: Vec<Cue>,
+    pub loops: Vec<Loop>,
+    pub beat_grid: Vec<BeatGridMarker>,
+    /// The BPM detected by Serato's analysis.
+    pub auto_bpm: Option<f64>,
+    /// The gain adjustment (dB) Serato applies to normalise loudness.
+    pub auto_gain: Option<f64>,
+}
+
+impl Markers {
+    /// Reads Serato's markers from a song file, songs Serato hasn't analysed return empty
+    /// markers.
+    pub fn read(path: &str) -> Result<Self, Error> {
+        let mut markers = Self::default();
+
+        let is_mp3 = Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"));
+
+        if is_mp3 {
+            let mut file = File::open(path)?;
+            let mpeg_file = MpegFile::read_from(&mut file, ParseOptions::new())?;
+            for frame in mpeg_file.id3v2().into_iter().flatten() {
+                // Lofty doesn't have a dedicated `GEOB` frame type, the content is kept as is.
+                let Frame::Binary(binary) = frame else {
+                    continue;
+                };
+                if frame.id_str() != "GEOB" {
+                    continue;
+                }
+                if let Some((description, data)) = split_geob(&binary.data) {
+                    markers.parse_frame(&description, data);
+                }
+            }
+        } else {
+            let tagged_file = lofty::read_from_path(path)?;
+            for tag in tagged_file.tags() {
+                for key in ENCODED_KEYS {
+                    let Some(value) = tag.get_string(&ItemKey::Unknown(key.to_string())) else {
+                        continue;
+                    };
+                    let Some(decoded) = decode_base64(value.as_bytes()) else {
+                        tracing::debug!(key, "failed to decode serato tag");
+                        continue;
+                    };
+                    // The encoded value is a `GEOB` frame without the text encoding byte.
+                    if let Some((description, data)) = split_geob(&[&[0], &decoded[..]].concat()) {
+                        markers.parse_frame(&description, data);
+                    }
+                }
+            }
+        }
+
+        Ok(markers)
+    }
+
+    fn parse_frame(&mut self, description: &str, data: &[u8]) {
+        match description {
+            MARKERS => self.parse_markers(data),
+            BEAT_GRID => self.parse_beat_grid(data),
+            AUTOTAGS => self.parse_autotags(data),
+            _ => {}
+        }
+    }
+
+    /// `Serato Markers2` is a version followed by base64 data, which decodes to another version
+    /// followed by entries each with a null terminated type name, a length and the entry data.
+    fn parse_markers(&mut self, data: &[u8]) {
+        let Some(decoded) = data.get(2..).and_then(decode_base64) else {
+            tracing::debug!("failed to decode serato markers");
+            return;
+        };
+
+        let mut offset = 2;
+        while offset < decoded.len() {
+            let Some(name_len) = decoded[offset..].iter().position(|&byte| byte == 0) else {
+                break;
+            };
+            let name = String::from_utf8_lossy(&decoded[offset..offset + name_len]).into_owned();
+            offset += name_len + 1;
+
+            let Some(len) = read_u32(&decoded, offset) else {
+                break;
+            };
+            offset += 4;
+            let Some(entry) = decoded.get(offset..offset + len as usize) else {
+                break;
+            };
+            offset += len as usize;
+
+            match name.as_str() {
+                "COLOR" => self.color = entry.get(1..4).map(|rgb| [rgb[0], rgb[1], rgb[2]]),
+                "BPMLOCK" => self.bpm_locked = entry.first().map(|&locked| locked != 0),
+                "CUE" => {
+                    if let (Some(&index), Some(time_ms), Some(rgb)) =
+                        (entry.get(1), read_u32(entry, 2), entry.get(7..10))
+                    {
+                        self.cues.push(Cue {
+                            index,
+                            time_ms,
+                            color: [rgb[0], rgb[1], rgb[2]],
+                            name: read_c_string(entry, 12),
+                        });
+                    }
+                }
+                "LOOP" => {
+                    if let (Some(&index), Some(start_ms), Some(end_ms)) =
+                        (entry.get(1), read_u32(entry, 2), read_u32(entry, 6))
+                    {
+                        self.loops.push(Loop {
+                            index,
+                            start_ms,
+                            end_ms,
+                            locked: entry.get(18).is_some_and(|&locked| locked != 0),
+                            name: read_c_string(entry, 19),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// `Serato BeatGrid` is a version, a marker count and 8 bytes per marker.
+    fn parse_beat_grid(&mut self, data: &[u8]) {
+        let Some(count) = read_u32(data, 2) else {
+            return;
+        };
+
+        self.beat_grid = (0..count as usize)
+            .map_while(|index| {
+                let offset = 6 + index * 8;
+                let position_seconds = f32::from_bits(read_u32(data, offset)?);
+                let value = read_u32(data, offset + 4)?;
+                let is_last = index + 1 == count as usize;
+                Some(BeatGridMarker {
+                    position_seconds,
+                    beats_until_next: (!is_last).then_some(value),
+                    bpm: is_last.then(|| f32::from_bits(value)),
+                })
+            })
+            .collect();
+    }
+
+    /// `Serato Autotags` is a version followed by null terminated strings for the BPM, auto gain
+    /// and manual gain.
+    fn parse_autotags(&mut self, data: &[u8]) {
+        let mut values = data
+            .get(2..)
+            .unwrap_or_default()
+            .split(|&byte| byte == 0)
+            .map(|value| String::from_utf8_lossy(value).trim().parse::<f64>().ok());
+
+        self.auto_bpm = values.next().flatten();
+        self.auto_gain = values.next().flatten();
+    }
+}
+
+/// Splits the content of a `GEOB` frame (text encoding, MIME type, file name, description and
+/// data) into the description and data. Serato only writes Latin-1 so other encodings are
+/// ignored.
+fn split_geob(content: &[u8]) -> Option<(String, &[u8])> {
+    let (&encoding, rest) = content.split_first()?;
+    if encoding != 0 && encoding != 3 {
+        return None;
+    }
+
+    let mut parts = rest.splitn(4, |&byte| byte == 0);
+    let _mime_type = parts.next()?;
+    let _file_name = parts.next()?;
+    let description = String::from_utf8_lossy(parts.next()?).into_owned();
+    Some((description, parts.next()?))
+}
+
+/// Decodes base64 split over multiple lines and ending at the first null byte.
+fn decode_base64(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoded: Vec<u8> = data
+        .iter()
+        .copied()
+        .take_while(|&byte| byte != 0)
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+
+    // A single leftover character can't encode a byte, Serato pads these with an extra `A`.
+    if encoded.len() % 4 == 1 {
+        encoded.push(b'A');
+    }
+
+    BASE64.decode(encoded).ok()
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_c_string(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let end = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).into_owned()).filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut entry = name.as_bytes().to_vec();
+        entry.push(0);
+        entry.extend((data.len() as u32).to_be_bytes());
+        entry.extend(data);
+        entry
+    }
+
+    #[test]
+    fn parses_markers() {
+        let mut cue = vec![0, 2];
+        cue.extend(1500u32.to_be_bytes());
+        cue.extend([0, 0xcc, 0, 0, 0, 0]);
+        cue.extend(b"Drop\0");
+        let mut loop_entry = vec![0, 0];
+        loop_entry.extend(1000u32.to_be_bytes());
+        loop_entry.extend(5000u32.to_be_bytes());
+        loop_entry.resize(18, 0);
+        loop_entry.extend([1, 0]);
+        let decoded = [
+            vec![1, 1],
+            entry("COLOR", &[0, 0xff, 0x99, 0xff]),
+            entry("CUE", &cue),
+            entry("LOOP", &loop_entry),
+            entry("BPMLOCK", &[1]),
+        ]
+        .concat();
+        // Serato splits the base64 over lines of 72 characters.
+        let encoded = BASE64.encode(decoded);
+        let mut data = vec![1, 1];
+        for line in encoded.as_bytes().chunks(72) {
+            data.extend(line);
+            data.push(b'\n');
+        }
+        data.push(0);
+
+        let mut markers = Markers::default();
+        markers.parse_frame(MARKERS, &data);
+
+        assert_eq!(markers.color, Some([0xff, 0x99, 0xff]));
+        assert_eq!(markers.bpm_locked, Some(true));
+        assert_eq!(markers.cues.len(), 1);
+        assert_eq!(markers.cues[0].index, 2);
+        assert_eq!(markers.cues[0].time_ms, 1500);
+        assert_eq!(markers.cues[0].color, [0xcc, 0, 0]);
+        assert_eq!(markers.cues[0].name.as_deref(), Some("Drop"));
+        assert_eq!(markers.loops.len(), 1);
+        assert_eq!(
+            (markers.loops[0].start_ms, markers.loops[0].end_ms),
+            (1000, 5000)
+        );
+        assert!(markers.loops[0].locked);
+        assert_eq!(markers.loops[0].name, None);
+    }
+
+    #[test]
+    fn parses_beat_grids_and_autotags() {
+        let mut data = vec![1, 0];
+        data.extend(2u32.to_be_bytes());
+        data.extend(0.5f32.to_bits().to_be_bytes());
+        data.extend(64u32.to_be_bytes());
+        data.extend(30.5f32.to_bits().to_be_bytes());
+        data.extend(128.0f32.to_bits().to_be_bytes());
+
+        let mut markers = Markers::default();
+        markers.parse_frame(BEAT_GRID, &data);
+        markers.parse_frame(AUTOTAGS, b"\x01\x01124.00\0-3.521\x000.000\0");
+
+        let grid: Vec<_> = markers
+            .beat_grid
+            .iter()
+            .map(|marker| (marker.position_seconds, marker.beats_until_next, marker.bpm))
+            .collect();
+        assert_eq!(grid, vec![(0.5, Some(64), None), (30.5, None, Some(128.0))]);
+        assert_eq!(markers.auto_bpm, Some(124.0));
+        assert_eq!(markers.auto_gain, Some(-3.521));
+    }
+
+    #[test]
+    fn splits_geob_frames() {
+        let (description, data) =
+            split_geob(b"\0application/octet-stream\0\0Serato Autotags\0\x01\x01").unwrap();
+        assert_eq!(description, AUTOTAGS);
+        assert_eq!(data, [1, 1]);
+        assert!(split_geob(b"\x01utf-16").is_none());
+    }
+}
@@ -0,0 +1,172 @@
+//! Support for reading Serato DJ's library (`_Serato_` folder) and the analysis Serato stores in
+//! song file tags.
+//!
+//! The `database V2` and crate files share the same binary format, a sequence of records each
+//! with a 4 byte ASCII tag and a big endian `u32` length followed by the payload. The first
+//! character of the tag describes the payload type, e.g. `o` is a nested list of records and
+//! `t`/`p` are UTF-16BE strings.
+
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::songs::Song;
+
+pub mod crates;
+pub mod database;
+pub mod markers;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read serato file: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("unexpected end of data at offset {0}")]
+    UnexpectedEof(usize),
+}
+
+/// A Serato library converted to dbeat's model.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Library {
+    pub songs: Vec<Song>,
+    pub crates: Vec<crates::Crate>,
+}
+
+/// Reads the songs and crates from a `_Serato_` directory.
+pub fn read_library(serato_dir: &Path) -> Result<Library, Error> {
+    let songs = database::read_database(serato_dir)?
+        .iter()
+        .map(database::Track::to_song)
+        .collect();
+
+    Ok(Library {
+        songs,
+        crates: crates::find_crates(serato_dir),
+    })
+}
+
+/// A single tagged record, `payload` may contain nested records.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub tag: &'a [u8],
+    pub payload: &'a [u8],
+}
+
+impl Record<'_> {
+    pub fn text(&self) -> String {
+        read_utf16_be(self.payload)
+    }
+
+    pub fn u32(&self) -> Option<u32> {
+        let bytes = self.payload.get(0..4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn children(&self) -> Result<Vec<Record<'_>>, Error> {
+        read_records(self.payload)
+    }
+}
+
+/// Reads a flat list of records, nested records can be read with `Record::children`.
+pub fn read_records(data: &[u8]) -> Result<Vec<Record<'_>>, Error> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset + 8 <= data.len() {
+        let tag = &data[offset..offset + 4];
+        let len = u32::from_be_bytes([
+            data[offset + 4],
+            data[offset + 5],
+            data[offset + 6],
+            data[offset + 7],
+        ]) as usize;
+        let payload = data
+            .get(offset + 8..offset + 8 + len)
+            .ok_or(Error::UnexpectedEof(offset))?;
+        records.push(Record { tag, payload });
+        offset += 8 + len;
+    }
+
+    Ok(records)
+}
+
+/// Serato stores paths relative to the root of the volume the `_Serato_` folder is on, this is
+/// the volume's mount point for external drives or the filesystem root for the system drive.
+pub fn volume_root(serato_dir: &Path) -> PathBuf {
+    let components: Vec<Component> = serato_dir.components().collect();
+
+    // External drives on macOS are mounted under `/Volumes/<name>`.
+    if let [Component::RootDir, Component::Normal(volumes), Component::Normal(name), ..] =
+        components[..]
+    {
+        if volumes == "Volumes" {
+            return PathBuf::from("/Volumes").join(name);
+        }
+    }
+
+    components
+        .into_iter()
+        .take_while(|component| matches!(component, Component::Prefix(_) | Component::RootDir))
+        .collect()
+}
+
+fn read_utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Encodes a record with the given tag and payload.
+    pub(crate) fn record(tag: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut record = tag.to_vec();
+        record.extend((payload.len() as u32).to_be_bytes());
+        record.extend(payload);
+        record
+    }
+
+    pub(crate) fn text(value: &str) -> Vec<u8> {
+        value.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    #[test]
+    fn reads_nested_records() {
+        let track = [
+            record(b"ptrk", &text("Music/a.mp3")),
+            record(b"uadd", &[0, 0, 1, 0]),
+        ]
+        .concat();
+        let data = [record(b"vrsn", &text("1.0")), record(b"otrk", &track)].concat();
+
+        let records = read_records(&data).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].text(), "1.0");
+        let children = records[1].children().unwrap();
+        assert_eq!(children[0].tag, b"ptrk");
+        assert_eq!(children[0].text(), "Music/a.mp3");
+        assert_eq!(children[1].u32(), Some(256));
+
+        assert!(matches!(
+            read_records(&data[..data.len() - 1]),
+            Err(Error::UnexpectedEof(_))
+        ));
+    }
+
+    #[test]
+    fn finds_volume_roots() {
+        assert_eq!(
+            volume_root(Path::new("/Volumes/USB/_Serato_")),
+            Path::new("/Volumes/USB")
+        );
+        assert_eq!(
+            volume_root(Path::new("/Users/dj/Music/_Serato_")),
+            Path::new("/")
+        );
+    }
+}
@@ -0,0 +1,522 @@
+//! Rules for cleaning up inconsistent tags across many songs at once.
+//!
+//! Rules are applied in order to each song, the result is previewed as a list of field changes
+//! before being written to the song files as `TagChanges`.
+
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::songs::{Song, TagChanges};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid pattern: {0}")]
+    Regex(#[from] regex::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Comment,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Case {
+    /// Every word capitalised, e.g. 'Strings Of Life'.
+    Title,
+    /// Only the first word capitalised, e.g. 'Strings of life'.
+    Sentence,
+    Upper,
+    Lower,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Rule {
+    /// Replaces every match of a regular expression, the replacement can refer to capture groups
+    /// as `$1` or `$name`.
+    #[serde(rename_all = "camelCase")]
+    Replace {
+        field: Field,
+        pattern: String,
+        replacement: String,
+    },
+    /// Changes the case of a field, when `only_all_caps` is set only values written entirely in
+    /// capitals are changed.
+    #[serde(rename_all = "camelCase")]
+    Case {
+        field: Field,
+        case: Case,
+        #[serde(default)]
+        only_all_caps: bool,
+    },
+    /// Trims leading and trailing whitespace and collapses repeated spaces.
+    Whitespace { field: Field },
+    /// Maps genres to a canonical spelling, matching ignores case and surrounding whitespace.
+    GenreMap { genres: HashMap<String, String> },
+    /// Moves a featured artist from the title to the artist, e.g. 'Song (feat. B)' by 'A'
+    /// becomes 'Song' by 'A feat. B'. 'ft.' and 'featuring' are written as 'feat.'.
+    MoveFeaturing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    pub field: Field,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// The changes the rules make to one song.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SongCleanup {
+    pub file_path: String,
+    pub changes: Vec<FieldChange>,
+}
+
+impl SongCleanup {
+    /// Whether the song's tags still have the values the changes were previewed from, a preview
+    /// made before the tags were edited elsewhere mustn't overwrite those edits.
+    pub fn is_current(&self, song: &Song) -> bool {
+        self.changes.iter().all(|change| {
+            let current = match change.field {
+                Field::Title => &song.title,
+                Field::Artist => &song.artist,
+                Field::Album => &song.album,
+                Field::Genre => &song.genre,
+                Field::Comment => &song.comment,
+            };
+            *current == change.before
+        })
+    }
+
+    pub fn tag_changes(&self) -> TagChanges {
+        let mut tag_changes = TagChanges::default();
+        for change in &self.changes {
+            // An empty string removes the tag.
+            let value = Some(change.after.clone().unwrap_or_default());
+            match change.field {
+                Field::Title => tag_changes.title = value,
+                Field::Artist => tag_changes.artist = value,
+                Field::Album => tag_changes.album = value,
+                Field::Genre => tag_changes.genre = value,
+                Field::Comment => tag_changes.comment = value,
+            }
+        }
+        tag_changes
+    }
+}
+
+/// Rules with their patterns compiled, so they can be applied to many songs.
+pub struct Rules {
+    rules: Vec<CompiledRule>,
+    featuring: Regex,
+    artist_featuring: Regex,
+}
+
+enum CompiledRule {
+    Replace {
+        field: Field,
+        pattern: Regex,
+        replacement: String,
+    },
+    Case {
+        field: Field,
+        case: Case,
+        only_all_caps: bool,
+    },
+    Whitespace {
+        field: Field,
+    },
+    GenreMap {
+        genres: HashMap<String, String>,
+    },
+    MoveFeaturing,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>) -> Result<Self, Error> {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                Ok(match rule {
+                    Rule::Replace {
+                        field,
+                        pattern,
+                        replacement,
+                    } => CompiledRule::Replace {
+                        field,
+                        pattern: Regex::new(&pattern)?,
+                        replacement,
+                    },
+                    Rule::Case {
+                        field,
+                        case,
+                        only_all_caps,
+                    } => CompiledRule::Case {
+                        field,
+                        case,
+                        only_all_caps,
+                    },
+                    Rule::Whitespace { field } => CompiledRule::Whitespace { field },
+                    Rule::GenreMap { genres } => CompiledRule::GenreMap {
+                        genres: genres
+                            .into_iter()
+                            .map(|(from, to)| (from.trim().to_lowercase(), to))
+                            .collect(),
+                    },
+                    Rule::MoveFeaturing => CompiledRule::MoveFeaturing,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self {
+            rules,
+            // Either bracketed '(feat. B)' or everything after ' feat. ' up to the next bracket, so
+            // a version such as '(Extended Mix)' stays in the title.
+            featuring: Regex::new(
+                r"(?i)\s*(?:[(\[]\s*(?:feat\.?|ft\.?|featuring)\s+([^)\]]+)[)\]]|\s(?:feat\.?|ft\.?|featuring)\s+([^(\[]*[^(\[\s]))",
+            )?,
+            artist_featuring: Regex::new(r"(?i)\s(?:ft\.?|featuring|feat)\s")?,
+        })
+    }
+
+    /// Applies the rules to a song, returning `None` if nothing changes.
+    pub fn apply(&self, song: &Song) -> Option<SongCleanup> {
+        let mut fields: HashMap<Field, Option<String>> = [
+            (Field::Title, song.title.clone()),
+            (Field::Artist, song.artist.clone()),
+            (Field::Album, song.album.clone()),
+            (Field::Genre, song.genre.clone()),
+            (Field::Comment, song.comment.clone()),
+        ]
+        .into_iter()
+        .collect();
+
+        for rule in &self.rules {
+            match rule {
+                CompiledRule::Replace {
+                    field,
+                    pattern,
+                    replacement,
+                } => update(&mut fields, *field, |value| {
+                    pattern
+                        .replace_all(value, replacement.as_str())
+                        .into_owned()
+                }),
+                CompiledRule::Case {
+                    field,
+                    case,
+                    only_all_caps,
+                } => update(&mut fields, *field, |value| {
+                    if *only_all_caps && !is_all_caps(value) {
+                        value.to_string()
+                    } else {
+                        change_case(value, *case)
+                    }
+                }),
+                CompiledRule::Whitespace { field } => update(&mut fields, *field, |value| {
+                    value.split_whitespace().collect::<Vec<_>>().join(" ")
+                }),
+                CompiledRule::GenreMap { genres } => update(&mut fields, Field::Genre, |value| {
+                    genres
+                        .get(&value.trim().to_lowercase())
+                        .cloned()
+                        .unwrap_or_else(|| value.to_string())
+                }),
+                CompiledRule::MoveFeaturing => self.move_featuring(&mut fields),
+            }
+        }
+
+        let before = [
+            (Field::Title, &song.title),
+            (Field::Artist, &song.artist),
+            (Field::Album, &song.album),
+            (Field::Genre, &song.genre),
+            (Field::Comment, &song.comment),
+        ];
+        let changes: Vec<FieldChange> = before
+            .into_iter()
+            .filter_map(|(field, before)| {
+                let after = fields.remove(&field).flatten();
+                (after != *before).then(|| FieldChange {
+                    field,
+                    before: before.clone(),
+                    after,
+                })
+            })
+            .collect();
+
+        (!changes.is_empty()).then(|| SongCleanup {
+            file_path: song.file_path.clone(),
+            changes,
+        })
+    }
+
+    fn move_featuring(&self, fields: &mut HashMap<Field, Option<String>>) {
+        let Some(Some(title)) = fields.get(&Field::Title) else {
+            return;
+        };
+        let Some(captures) = self.featuring.captures(title) else {
+            return;
+        };
+        let Some(featured) = captures.get(1).or_else(|| captures.get(2)) else {
+            return;
+        };
+        let featured = featured.as_str().trim().to_string();
+        let new_title = self.featuring.replace(title, "").trim().to_string();
+
+        let artist = fields.get(&Field::Artist).cloned().flatten();
+        let artist = match artist {
+            Some(artist) if artist.to_lowercase().contains(&featured.to_lowercase()) => self
+                .artist_featuring
+                .replace_all(&artist, " feat. ")
+                .into_owned(),
+            Some(artist) if !artist.trim().is_empty() => {
+                let artist = self.artist_featuring.replace_all(&artist, " feat. ");
+                if artist.contains(" feat. ") {
+                    format!("{artist}, {featured}")
+                } else {
+                    format!("{artist} feat. {featured}")
+                }
+            }
+            _ => featured,
+        };
+
+        fields.insert(Field::Title, Some(new_title));
+        fields.insert(Field::Artist, Some(artist));
+    }
+}
+
+/// Previews the changes the rules make to each song, songs which don't change are left out.
+pub fn preview(rules: Vec<Rule>, songs: &[Song]) -> Result<Vec<SongCleanup>, Error> {
+    let rules = Rules::new(rules)?;
+    Ok(songs.iter().filter_map(|song| rules.apply(song)).collect())
+}
+
+/// Updates a field if it has a value, values which become empty are removed.
+fn update(fields: &mut HashMap<Field, Option<String>>, field: Field, f: impl Fn(&str) -> String) {
+    if let Some(value) = fields.get_mut(&field) {
+        *value = value
+            .as_deref()
+            .map(f)
+            .filter(|value| !value.trim().is_empty());
+    }
+}
+
+fn change_case(value: &str, case: Case) -> String {
+    // Abbreviations such as 'DJ' or 'UK' keep their capitals, unless the whole value is shouting.
+    let keep_all_caps_words = !is_all_caps(value);
+    let word_case = |word: &str, capitalise_word: bool| {
+        if keep_all_caps_words && is_all_caps(word) {
+            word.to_string()
+        } else if capitalise_word {
+            capitalise(word)
+        } else {
+            word.to_lowercase()
+        }
+    };
+
+    match case {
+        Case::Upper => value.to_uppercase(),
+        Case::Lower => value.to_lowercase(),
+        Case::Title => value
+            .split(' ')
+            .map(|word| word_case(word, true))
+            .collect::<Vec<_>>()
+            .join(" "),
+        Case::Sentence => {
+            let mut capitalise_next = true;
+            value
+                .split(' ')
+                .map(|word| {
+                    let word = word_case(word, capitalise_next);
+                    capitalise_next &= !word.chars().any(char::is_alphabetic);
+                    word
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+    }
+}
+
+fn is_all_caps(value: &str) -> bool {
+    value.chars().any(char::is_alphabetic) && !value.chars().any(char::is_lowercase)
+}
+
+/// Upper cases the first letter and lower cases the rest, e.g. 'mIX' becomes 'Mix' and '(oRIGINAL'
+/// becomes '(Original'.
+fn capitalise(word: &str) -> String {
+    let mut capitalised = String::with_capacity(word.len());
+    let mut seen_letter = false;
+    for char in word.chars() {
+        if !seen_letter && char.is_alphabetic() {
+            seen_letter = true;
+            capitalised.extend(char.to_uppercase());
+        } else {
+            capitalised.extend(char.to_lowercase());
+        }
+    }
+    capitalised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, artist: &str) -> Song {
+        Song {
+            file_path: "/music/song.mp3".to_string(),
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn apply(rules: Vec<Rule>, song: &Song) -> (Option<String>, Option<String>) {
+        let rules = Rules::new(rules).unwrap();
+        let cleanup = rules.apply(song);
+        let after = |field| {
+            let change = cleanup
+                .iter()
+                .flat_map(|cleanup| &cleanup.changes)
+                .find(|change| change.field == field);
+            match (change, field) {
+                (Some(change), _) => change.after.clone(),
+                (None, Field::Title) => song.title.clone(),
+                (None, _) => song.artist.clone(),
+            }
+        };
+        (after(Field::Title), after(Field::Artist))
+    }
+
+    #[test]
+    fn moves_featured_artists() {
+        let cases = [
+            ("Song (feat. B)", "A", "Song", "A feat. B"),
+            ("Song [ft. B]", "A", "Song", "A feat. B"),
+            ("Song featuring B", "A", "Song", "A feat. B"),
+            (
+                "Song feat. B (Extended Mix)",
+                "A",
+                "Song (Extended Mix)",
+                "A feat. B",
+            ),
+            (
+                "Song (feat. B & C) [Dub]",
+                "A",
+                "Song [Dub]",
+                "A feat. B & C",
+            ),
+            ("Song (feat. B)", "A ft. B", "Song", "A feat. B"),
+            ("Song (feat. C)", "A feat. B", "Song", "A feat. B, C"),
+            ("Song (feat. B)", "", "Song", "B"),
+            ("Featherweight", "A", "Featherweight", "A"),
+        ];
+        for (title, artist, expected_title, expected_artist) in cases {
+            assert_eq!(
+                apply(vec![Rule::MoveFeaturing], &song(title, artist)),
+                (
+                    Some(expected_title.to_string()),
+                    Some(expected_artist.to_string()).filter(|artist| !artist.is_empty())
+                ),
+                "{title} by {artist}"
+            );
+        }
+    }
+
+    #[test]
+    fn changes_case() {
+        let cases = [
+            ("strings of life", Case::Title, "Strings Of Life"),
+            (
+                "live at the BBC (dj mix)",
+                Case::Title,
+                "Live At The BBC (Dj Mix)",
+            ),
+            ("DJ Set In The UK", Case::Sentence, "DJ set in the UK"),
+            ("STRINGS OF LIFE", Case::Title, "Strings Of Life"),
+            ("STRINGS OF LIFE", Case::Sentence, "Strings of life"),
+            ("(original mix)", Case::Sentence, "(Original mix)"),
+            ("Strings Of Life", Case::Upper, "STRINGS OF LIFE"),
+            ("Strings Of Life", Case::Lower, "strings of life"),
+        ];
+        for (value, case, expected) in cases {
+            assert_eq!(change_case(value, case), expected, "{value} {case:?}");
+        }
+    }
+
+    #[test]
+    fn applies_rules_in_order() {
+        let mut genres = HashMap::new();
+        genres.insert("Deep house ".to_string(), "Deep House".to_string());
+        let rules = vec![
+            Rule::Whitespace {
+                field: Field::Title,
+            },
+            Rule::Replace {
+                field: Field::Title,
+                pattern: r"\s*\(Original Mix\)".to_string(),
+                replacement: String::new(),
+            },
+            Rule::Case {
+                field: Field::Artist,
+                case: Case::Title,
+                only_all_caps: true,
+            },
+            Rule::GenreMap { genres },
+        ];
+        let song = Song {
+            genre: Some("deep HOUSE".to_string()),
+            ..song("  Song   (Original Mix) ", "ARTIST")
+        };
+
+        let cleanup = Rules::new(rules).unwrap().apply(&song).unwrap();
+        let changes: Vec<_> = cleanup
+            .changes
+            .iter()
+            .map(|change| (change.field, change.after.as_deref()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (Field::Title, Some("Song")),
+                (Field::Artist, Some("Artist")),
+                (Field::Genre, Some("Deep House")),
+            ]
+        );
+        assert!(cleanup.is_current(&song));
+        assert!(!cleanup.is_current(&Song {
+            artist: Some("Edited".to_string()),
+            ..song.clone()
+        }));
+
+        let unchanged = Rules::new(vec![Rule::Whitespace {
+            field: Field::Title,
+        }])
+        .unwrap()
+        .apply(&Song {
+            title: Some("Song".to_string()),
+            ..song
+        });
+        assert!(unchanged.is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(Rules::new(vec![Rule::Replace {
+            field: Field::Title,
+            pattern: "(".to_string(),
+            replacement: String::new(),
+        }])
+        .is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Finds songs that are copies of each other, such as the same track as MP3 and FLAC, in two
+//! folders, or with slightly different tags.
+//!
+//! Songs are linked when their files have the same contents, their normalised title and artist
+//! match with nearly the same duration, or their audio fingerprints match at the same position.
+//! Linked songs are grouped, so a FLAC linked to an MP3 by its tags and the MP3 linked to a copy
+//! by its contents end up in one group, and the best copy is suggested to keep.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::{audio, songs::Song};
+
+/// Songs with matching tags are only the same recording when their durations are this close,
+/// radio edits and extended mixes share a title but not a length.
+const DURATION_TOLERANCE_SECONDS: u64 = 3;
+/// Version names that are left out when comparing titles since they're added inconsistently.
+const IGNORED_VERSIONS: [&str; 2] = ["original mix", "original"];
+
+/// Why songs were grouped as duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Reason {
+    /// The audio data has the same content hash, only the tags differ if anything. Large files are
+    /// hashed from samples of their audio data, see `content_hash`.
+    SameContent,
+    /// The title and artist match once normalised and the durations are close.
+    SimilarTags,
+    /// The audio fingerprints match at the same position.
+    SameAudio,
+}
+
+/// Songs that are copies of each other.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    /// The songs in the group, the suggested one to keep first.
+    pub songs: Vec<Song>,
+    pub reasons: Vec<Reason>,
+    pub keep_file_path: String,
+}
+
+/// Groups the songs that are copies of each other, largest groups first. `same_audio` are pairs
+/// of file paths whose fingerprints match, it's empty when songs haven't been fingerprinted and
+/// duplicates are then only found by their content hashes and tags.
+pub fn find(songs: &[Song], same_audio: &[(String, String)]) -> Vec<DuplicateGroup> {
+    let indexes: HashMap<&str, usize> = songs
+        .iter()
+        .enumerate()
+        .map(|(i, song)| (song.file_path.as_str(), i))
+        .collect();
+    let mut groups = Groups::new(songs.len());
+
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        let Some(hash) = &song.content_hash else {
+            continue;
+        };
+        match by_hash.get(hash.as_str()) {
+            Some(&first) => groups.link(first, i, Reason::SameContent),
+            None => {
+                by_hash.insert(hash, i);
+            }
+        }
+    }
+
+    let mut by_tags: HashMap<(String, String), Vec<usize>> = HashMap::new();
+    for (i, song) in songs.iter().enumerate() {
+        let (Some(title), Some(artist)) = (&song.title, &song.artist) else {
+            continue;
+        };
+        by_tags
+            .entry((normalise_title(title), normalise(artist)))
+            .or_default()
+            .push(i);
+    }
+    for (key, similar) in &by_tags {
+        if key.0.is_empty() {
+            continue;
+        }
+        for (n, &a) in similar.iter().enumerate() {
+            for &b in &similar[n + 1..] {
+                let difference = songs[a]
+                    .duration_seconds
+                    .abs_diff(songs[b].duration_seconds);
+                if difference <= DURATION_TOLERANCE_SECONDS {
+                    groups.link(a, b, Reason::SimilarTags);
+                }
+            }
+        }
+    }
+
+    for (a, b) in same_audio {
+        if let (Some(&a), Some(&b)) = (indexes.get(a.as_str()), indexes.get(b.as_str())) {
+            groups.link(a, b, Reason::SameAudio);
+        }
+    }
+
+    let mut duplicates: Vec<DuplicateGroup> = groups
+        .into_groups()
+        .into_iter()
+        .map(|(members, reasons)| {
+            let mut songs: Vec<Song> = members.into_iter().map(|i| songs[i].clone()).collect();
+            // Ranking can read the file to find its codec, so it's only done once per song.
+            songs.sort_by_cached_key(|song| (Reverse(keep_rank(song)), song.file_path.clone()));
+            DuplicateGroup {
+                keep_file_path: songs[0].file_path.clone(),
+                songs,
+                reasons: reasons.into_iter().collect(),
+            }
+        })
+        .collect();
+    duplicates.sort_by(|a, b| {
+        b.songs
+            .len()
+            .cmp(&a.songs.len())
+            .then_with(|| a.keep_file_path.cmp(&b.keep_file_path))
+    });
+    duplicates
+}
+
+/// Whether two songs have the same title and artist once normalised and nearly the same
+/// duration.
+pub fn similar_tags(a: &Song, b: &Song) -> bool {
+    same_title_and_artist(a, b)
+        && a.duration_seconds.abs_diff(b.duration_seconds) <= DURATION_TOLERANCE_SECONDS
+}
+
+/// Whether two songs have the same title and artist once normalised, for songs whose duration
+/// isn't known.
+pub fn same_title_and_artist(a: &Song, b: &Song) -> bool {
+    let (Some(a_title), Some(a_artist), Some(b_title), Some(b_artist)) =
+        (&a.title, &a.artist, &b.title, &b.artist)
+    else {
+        return false;
+    };
+    let title = normalise_title(a_title);
+    !title.is_empty()
+        && title == normalise_title(b_title)
+        && normalise(a_artist) == normalise(b_artist)
+}
+
+/// How good a copy is, higher is better. Lossless formats, including ALAC in MP4 files, are
+/// preferred over any bitrate, then higher bitrates, sample rates and bit depths.
+fn keep_rank(song: &Song) -> (bool, u32, u32, u8) {
+    (
+        audio::quality::is_lossless(Path::new(&song.file_path)),
+        song.bitrate_kbps.unwrap_or(0),
+        song.sample_rate.unwrap_or(0),
+        song.bit_depth.unwrap_or(0),
+    )
+}
+
+/// Lower case words without punctuation, so 'Strings Of Life' and 'strings of life!' match.
+fn normalise(value: &str) -> String {
+    value
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Normalises a title, leaving out versions that don't change the recording, e.g.
+/// 'Song (Original Mix)' matches 'Song'.
+fn normalise_title(title: &str) -> String {
+    let mut title = normalise(title);
+    for version in IGNORED_VERSIONS {
+        if let Some(stripped) = title.strip_suffix(version) {
+            title = stripped.trim_end().to_string();
+            break;
+        }
+    }
+    title
+}
+
+/// Songs linked into groups, a union find over song indexes.
+struct Groups {
+    parents: Vec<usize>,
+    reasons: HashMap<usize, BTreeSet<Reason>>,
+}
+
+impl Groups {
+    fn new(len: usize) -> Self {
+        Self {
+            parents: (0..len).collect(),
+            reasons: HashMap::new(),
+        }
+    }
+
+    fn root(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn link(&mut self, a: usize, b: usize, reason: Reason) {
+        let (a, b) = (self.root(a), self.root(b));
+        if a != b {
+            self.parents[b] = a;
+            let reasons = self.reasons.remove(&b).unwrap_or_default();
+            self.reasons.entry(a).or_default().extend(reasons);
+        }
+        self.reasons.entry(a).or_default().insert(reason);
+    }
+
+    /// The members and reasons of every group with more than one song.
+    fn into_groups(mut self) -> Vec<(Vec<usize>, BTreeSet<Reason>)> {
+        let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+        for i in 0..self.parents.len() {
+            let root = self.root(i);
+            members.entry(root).or_default().push(i);
+        }
+        members
+            .into_iter()
+            .filter(|(_, members)| members.len() > 1)
+            .map(|(root, members)| (members, self.reasons.remove(&root).unwrap_or_default()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(file_path: &str, title: &str, artist: &str, duration_seconds: u64) -> Song {
+        Song {
+            file_path: file_path.to_string(),
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            duration_seconds,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn normalises_titles() {
+        assert_eq!(normalise("Strings Of Life!"), "strings of life");
+        assert_eq!(normalise("  Derrick  May "), "derrick may");
+        assert_eq!(
+            normalise_title("Strings of Life (Original Mix)"),
+            "strings of life"
+        );
+        assert_eq!(
+            normalise_title("Strings of Life - Original"),
+            "strings of life"
+        );
+        assert_eq!(
+            normalise_title("Strings of Life (Extended Mix)"),
+            "strings of life extended mix"
+        );
+        assert_eq!(normalise_title("Original"), "");
+    }
+
+    #[test]
+    fn merges_groups_and_their_reasons() {
+        let mut groups = Groups::new(5);
+        groups.link(0, 1, Reason::SimilarTags);
+        groups.link(2, 3, Reason::SameContent);
+        groups.link(3, 1, Reason::SameAudio);
+
+        let groups = groups.into_groups();
+        assert_eq!(groups.len(), 1);
+        let (mut members, reasons) = groups.into_iter().next().unwrap();
+        members.sort();
+        assert_eq!(members, vec![0, 1, 2, 3]);
+        assert_eq!(
+            reasons.into_iter().collect::<Vec<_>>(),
+            vec![Reason::SameContent, Reason::SimilarTags, Reason::SameAudio]
+        );
+    }
+
+    #[test]
+    fn groups_copies_and_suggests_the_best() {
+        let songs = vec![
+            Song {
+                bitrate_kbps: Some(320),
+                content_hash: Some("abc".to_string()),
+                ..song("/music/a/song.mp3", "Song (Original Mix)", "Artist", 360)
+            },
+            Song {
+                content_hash: Some("abc".to_string()),
+                ..song("/music/b/song.mp3", "Untagged", "Unknown", 0)
+            },
+            song("/music/song.flac", "Song", "ARTIST", 362),
+            // Same tags but a different edit.
+            song("/music/song (radio edit).mp3", "Song", "Artist", 210),
+            song("/music/other.mp3", "Other", "Artist", 300),
+            song("/music/other copy.mp3", "Different", "Tags", 300),
+        ];
+        let same_audio = vec![(
+            "/music/other.mp3".to_string(),
+            "/music/other copy.mp3".to_string(),
+        )];
+
+        let groups = find(&songs, &same_audio);
+        assert_eq!(groups.len(), 2);
+
+        let paths: Vec<&str> = groups[0]
+            .songs
+            .iter()
+            .map(|song| song.file_path.as_str())
+            .collect();
+        assert_eq!(
+            paths,
+            vec!["/music/song.flac", "/music/a/song.mp3", "/music/b/song.mp3"]
+        );
+        assert_eq!(groups[0].keep_file_path, "/music/song.flac");
+        assert_eq!(
+            groups[0].reasons,
+            vec![Reason::SameContent, Reason::SimilarTags]
+        );
+
+        assert_eq!(groups[1].songs.len(), 2);
+        assert_eq!(groups[1].reasons, vec![Reason::SameAudio]);
+    }
+
+    #[test]
+    fn compares_tags_within_the_duration_tolerance() {
+        let a = song("/music/a.mp3", "Song", "Artist", 300);
+        assert!(similar_tags(
+            &a,
+            &song("/music/b.mp3", "song!", "artist", 303)
+        ));
+        assert!(!similar_tags(
+            &a,
+            &song("/music/b.mp3", "Song", "Artist", 304)
+        ));
+        assert!(!similar_tags(
+            &a,
+            &song("/music/b.mp3", "Song", "Other", 300)
+        ));
+        assert!(!similar_tags(
+            &song("/music/a.mp3", "Original Mix", "Artist", 300),
+            &song("/music/b.mp3", "Original", "Artist", 300)
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+//! Musical keys in the notations used by DJ software.
+//!
+//! Analysers write keys as standard notation ('Am', 'A minor', 'Amin'), Camelot ('8A') or Open
+//! Key ('1m'). They're all parsed to the same `Key` so keys from different sources compare and
+//! sort consistently.
+
+use std::{cmp::Ordering, fmt};
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+/// Note names for each pitch class starting from C, spelled the way DJ software usually shows
+/// them.
+const MAJOR_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const MINOR_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    /// The pitch class of the tonic, 0 is C and 11 is B.
+    tonic: u8,
+    mode: Mode,
+}
+
+impl Key {
+    pub fn new(tonic: u8, mode: Mode) -> Self {
+        Self {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    /// Parses a key in standard, Camelot or Open Key notation. Tags combining notations such as
+    /// '8A - Am' or 'Am/8A' are parsed from the first part that's a valid key.
+    pub fn parse(key: &str) -> Option<Self> {
+        let key = key.trim();
+        Self::parse_camelot(key)
+            .or_else(|| Self::parse_open_key(key))
+            .or_else(|| Self::parse_standard(key))
+            .or_else(|| {
+                key.split(['/', '-', ',', '|'])
+                    .filter(|part| part.trim() != key)
+                    .find_map(|part| {
+                        let part = part.trim();
+                        Self::parse_camelot(part)
+                            .or_else(|| Self::parse_open_key(part))
+                            .or_else(|| Self::parse_standard(part))
+                    })
+            })
+    }
+
+    /// The Camelot wheel number (1 to 12), keys next to each other on the wheel mix well.
+    pub fn camelot_number(&self) -> u8 {
+        // 8B is C major and each step around the wheel is a fifth (7 semitones) up.
+        let relative_major = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        (relative_major * 7 + 7) % 12 + 1
+    }
+
+    /// Camelot notation, e.g. '8A' for A minor.
+    pub fn camelot(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'B',
+            Mode::Minor => 'A',
+        };
+        format!("{}{letter}", self.camelot_number())
+    }
+
+    /// Open Key notation, e.g. '1m' for A minor.
+    pub fn open_key(&self) -> String {
+        let letter = match self.mode {
+            Mode::Major => 'd',
+            Mode::Minor => 'm',
+        };
+        format!("{}{letter}", (self.camelot_number() + 4) % 12 + 1)
+    }
+
+    /// Keys that mix harmonically with this one: itself, its neighbours on the Camelot wheel and
+    /// its relative major or minor.
+    pub fn compatible(&self) -> [Self; 4] {
+        let number = self.camelot_number();
+        let relative_mode = match self.mode {
+            Mode::Major => Mode::Minor,
+            Mode::Minor => Mode::Major,
+        };
+        [
+            *self,
+            Self::from_camelot_number(number % 12 + 1, self.mode),
+            Self::from_camelot_number((number + 10) % 12 + 1, self.mode),
+            Self::from_camelot_number(number, relative_mode),
+        ]
+    }
+
+    /// Standard notation, e.g. 'Am' or 'Eb'.
+    pub fn standard(&self) -> String {
+        match self.mode {
+            Mode::Major => MAJOR_NAMES[self.tonic as usize].to_string(),
+            Mode::Minor => format!("{}m", MINOR_NAMES[self.tonic as usize]),
+        }
+    }
+
+    /// The key at a position on the Camelot wheel, `number` must be from 1 to 12.
+    fn from_camelot_number(number: u8, mode: Mode) -> Self {
+        // Inverse of `camelot_number`, 7 is its own inverse modulo 12.
+        let relative_major = ((number + 12 - 8) * 7) % 12;
+        match mode {
+            Mode::Major => Self::new(relative_major, mode),
+            Mode::Minor => Self::new(relative_major + 9, mode),
+        }
+    }
+
+    fn parse_camelot(key: &str) -> Option<Self> {
+        let (number, letter) = split_number(key)?;
+        match letter.to_ascii_uppercase().as_str() {
+            "A" => Some(Self::from_camelot_number(number, Mode::Minor)),
+            "B" => Some(Self::from_camelot_number(number, Mode::Major)),
+            _ => None,
+        }
+    }
+
+    fn parse_open_key(key: &str) -> Option<Self> {
+        let (number, letter) = split_number(key)?;
+        let camelot_number = (number + 6) % 12 + 1;
+        match letter.to_ascii_lowercase().as_str() {
+            "m" => Some(Self::from_camelot_number(camelot_number, Mode::Minor)),
+            "d" => Some(Self::from_camelot_number(camelot_number, Mode::Major)),
+            _ => None,
+        }
+    }
+
+    fn parse_standard(key: &str) -> Option<Self> {
+        let mut chars = key.chars();
+        let tonic = match chars.next()?.to_ascii_uppercase() {
+            'C' => 0,
+            'D' => 2,
+            'E' => 4,
+            'F' => 5,
+            'G' => 7,
+            'A' => 9,
+            'B' => 11,
+            _ => return None,
+        };
+
+        let mut rest = chars.as_str();
+        let mut tonic = tonic;
+        if let Some(accidental @ ('#' | '♯' | 'b' | '♭')) = rest.chars().next() {
+            tonic += if matches!(accidental, '#' | '♯') {
+                1
+            } else {
+                11
+            };
+            rest = &rest[accidental.len_utf8()..];
+        }
+
+        // A lone capital 'M' is sometimes used for major.
+        let rest = rest.trim();
+        if rest == "M" {
+            return Some(Self::new(tonic, Mode::Major));
+        }
+        // Written out accidentals, e.g. 'A flat minor'.
+        let rest = rest.to_lowercase();
+        let rest = if let Some(rest) = rest.strip_prefix("sharp") {
+            tonic += 1;
+            rest.trim()
+        } else if let Some(rest) = rest.strip_prefix("flat") {
+            tonic += 11;
+            rest.trim()
+        } else {
+            rest.as_str()
+        };
+
+        let mode = match rest {
+            "" | "maj" | "major" | "dur" => Mode::Major,
+            "m" | "min" | "minor" | "moll" | "-" => Mode::Minor,
+            _ => return None,
+        };
+        Some(Self::new(tonic, mode))
+    }
+}
+
+/// Splits a key like '12A' into its number and letter, the number must be from 1 to 12.
+fn split_number(key: &str) -> Option<(u8, String)> {
+    let digits = key.chars().take_while(char::is_ascii_digit).count();
+    let number: u8 = key[..digits].parse().ok()?;
+    let letter = key[digits..].trim();
+    ((1..=12).contains(&number) && letter.chars().count() == 1)
+        .then(|| (number, letter.to_string()))
+}
+
+/// Keys sort around the Camelot wheel, minor before major.
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let mode_order = |mode: Mode| match mode {
+            Mode::Minor => 0,
+            Mode::Major => 1,
+        };
+        (self.camelot_number(), mode_order(self.mode))
+            .cmp(&(other.camelot_number(), mode_order(other.mode)))
+    }
+}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.standard())
+    }
+}
+
+/// Keys are sent to the frontend in every notation so it doesn't need to convert them.
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut key = serializer.serialize_struct("Key", 3)?;
+        key.serialize_field("standard", &self.standard())?;
+        key.serialize_field("camelot", &self.camelot())?;
+        key.serialize_field("openKey", &self.open_key())?;
+        key.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every key with its Camelot and Open Key notation.
+    const KEYS: [(&str, &str, &str); 24] = [
+        ("C", "8B", "1d"),
+        ("Db", "3B", "8d"),
+        ("D", "10B", "3d"),
+        ("Eb", "5B", "10d"),
+        ("E", "12B", "5d"),
+        ("F", "7B", "12d"),
+        ("F#", "2B", "7d"),
+        ("G", "9B", "2d"),
+        ("Ab", "4B", "9d"),
+        ("A", "11B", "4d"),
+        ("Bb", "6B", "11d"),
+        ("B", "1B", "6d"),
+        ("Cm", "5A", "10m"),
+        ("C#m", "12A", "5m"),
+        ("Dm", "7A", "12m"),
+        ("Ebm", "2A", "7m"),
+        ("Em", "9A", "2m"),
+        ("Fm", "4A", "9m"),
+        ("F#m", "11A", "4m"),
+        ("Gm", "6A", "11m"),
+        ("G#m", "1A", "6m"),
+        ("Am", "8A", "1m"),
+        ("Bbm", "3A", "8m"),
+        ("Bm", "10A", "3m"),
+    ];
+
+    #[test]
+    fn converts_every_key() {
+        for (standard, camelot, open_key) in KEYS {
+            let key = Key::parse(standard).unwrap();
+            assert_eq!(key.standard(), standard);
+            assert_eq!(key.camelot(), camelot, "{standard}");
+            assert_eq!(key.open_key(), open_key, "{standard}");
+            assert_eq!(Key::parse(camelot), Some(key), "{camelot}");
+            assert_eq!(Key::parse(open_key), Some(key), "{open_key}");
+            assert_eq!(
+                Key::from_camelot_number(key.camelot_number(), key.mode),
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn parses_other_spellings() {
+        let a_minor = Key::new(9, Mode::Minor);
+        for spelling in [
+            "Am", "A minor", "Amin", "a moll", "A-", "8a", "08A", "1M", "8A - Am", "Am/8A",
+        ] {
+            assert_eq!(Key::parse(spelling), Some(a_minor), "{spelling}");
+        }
+        let a_flat = Key::new(8, Mode::Major);
+        for spelling in ["Ab", "G#", "A♭", "A flat major", "AbM", "G sharp"] {
+            assert_eq!(Key::parse(spelling), Some(a_flat), "{spelling}");
+        }
+        for invalid in ["", "H", "13A", "0B", "8C", "Am7", "A major minor"] {
+            assert_eq!(Key::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn finds_compatible_keys() {
+        let compatible: Vec<_> = Key::parse("8A")
+            .unwrap()
+            .compatible()
+            .iter()
+            .map(Key::camelot)
+            .collect();
+        assert_eq!(compatible, vec!["8A", "9A", "7A", "8B"]);
+        let compatible: Vec<_> = Key::parse("12B")
+            .unwrap()
+            .compatible()
+            .iter()
+            .map(Key::camelot)
+            .collect();
+        assert_eq!(compatible, vec!["12B", "1B", "11B", "12A"]);
+    }
+}
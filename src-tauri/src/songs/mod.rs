@@ -0,0 +1,557 @@
+pub mod cleanup;
+pub mod duplicates;
+pub mod key;
+pub mod relink;
+
+use std::{fs::File, io::BufReader, time::UNIX_EPOCH};
+
+use lofty::{
+    aac::AacFile,
+    config::{ParseOptions, WriteOptions},
+    error::LoftyError,
+    file::{AudioFile, FileType, TaggedFileExt},
+    id3::v2::{Frame, Id3v2Tag},
+    iff::{aiff::AiffFile, wav::WavFile},
+    mpeg::MpegFile,
+    probe::Probe,
+    tag::{Accessor, ItemKey, Tag, TagExt, TagType},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::artwork;
+
+use self::key::Key;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SongFromFileError {
+    #[error("failed to parse song file: {0}")]
+    Lofty(#[from] lofty::error::LoftyError),
+    #[error("failed to read song file: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("song has no metadata")]
+    NoMetadata,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SongWriteError {
+    #[error("failed to write song file: {0}")]
+    Lofty(#[from] lofty::error::LoftyError),
+    #[error("failed to write song file: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to read song file after writing: {0}")]
+    Read(#[from] SongFromFileError),
+    #[error("song file changed on disk since it was read, reload the library before editing")]
+    Modified,
+}
+
+/// Changes to a song's tags, `None` leaves a field unchanged and an empty string removes it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagChanges {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    /// A BPM of 0 removes it.
+    pub bpm: Option<f64>,
+    pub key: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Song {
+    pub file_path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub bpm: Option<f64>,
+    pub duration_seconds: u64,
+    /// The musical key as tagged, e.g. 'Am', '8A' or '1m' depending on the software that wrote it.
+    pub key: Option<String>,
+    /// The tagged key in a canonical form, `None` when the tag isn't a recognised notation.
+    pub parsed_key: Option<Key>,
+    pub year: Option<u32>,
+    /// The full release date when the tag has more than the year, e.g. '2024-03-15'.
+    pub release_date: Option<String>,
+    pub label: Option<String>,
+    pub remixer: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    pub track_number: Option<u32>,
+    /// Star rating from 1 to 5.
+    pub rating: Option<u8>,
+    pub isrc: Option<String>,
+    /// When the file was added to the library, taken from the file's creation time.
+    pub date_added_unix_seconds: Option<i64>,
+    /// The audio bitrate, lossless formats report their average bitrate.
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_depth: Option<u8>,
+    pub channels: Option<u8>,
+    /// The container or codec name, e.g. 'MP3', 'FLAC' or 'AIFF'.
+    pub file_type: Option<String>,
+    pub file_size_bytes: Option<u64>,
+    pub duration_ms: Option<u64>,
+    /// The content hash of the embedded front cover, used to look up its cached thumbnail.
+    pub artwork_hash: Option<String>,
+    /// The file's modification time when it was read, used to detect changes made outside dbeat.
+    pub last_modified_unix_ms: Option<i64>,
+    /// The hash of the audio data without tags, see `content_hash`. It stays the same when the
+    /// file is moved, renamed or retagged. Files are hashed in the background after they're read,
+    /// so it's `None` until then.
+    pub content_hash: Option<String>,
+    /// The tempo detected from the audio, kept separate from the tagged `bpm` so they can be
+    /// compared.
+    pub detected_bpm: Option<f64>,
+    /// How confident the tempo detection was, from 0 to 1.
+    pub detected_bpm_confidence: Option<f64>,
+    /// The key detected from the audio, kept separate from the tagged `key`.
+    pub detected_key: Option<Key>,
+    /// How clearly the detected key beat the others, from 0 to 1.
+    pub detected_key_confidence: Option<f64>,
+}
+
+impl Song {
+    /// Read a song's metadata from the given file path if it exists and is a supported format.
+    pub fn from_file(path: &str) -> Result<Self, SongFromFileError> {
+        let tagged_file = Self::try_open_file_probe(path)?.read()?;
+        let tag = match tagged_file.primary_tag() {
+            Some(primary_tag) => primary_tag,
+            None => tagged_file
+                .first_tag()
+                .ok_or(SongFromFileError::NoMetadata)?,
+        };
+
+        let properties = tagged_file.properties();
+        let duration = properties.duration();
+
+        let bpm_string = tag
+            .get_string(&ItemKey::Bpm)
+            .or_else(|| tag.get_string(&ItemKey::IntegerBpm))
+            .unwrap_or("");
+        let bpm = match bpm_string.parse::<f64>() {
+            Ok(bpm) => Some(bpm),
+            Err(error) => {
+                tracing::debug!(?error, "failed to parse bpm from string");
+                None
+            }
+        };
+
+        let release_date = [
+            ItemKey::ReleaseDate,
+            ItemKey::RecordingDate,
+            ItemKey::OriginalReleaseDate,
+        ]
+        .iter()
+        .find_map(|key| tag_string(tag, key));
+        let year = tag.year().or_else(|| {
+            release_date
+                .as_deref()
+                .and_then(|date| date.get(0..4))
+                .and_then(|year| year.parse().ok())
+        });
+
+        let metadata = std::fs::metadata(path).ok();
+        let last_modified = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as i64);
+        let date_added = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.created().or_else(|_| metadata.modified()).ok())
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as i64);
+
+        let key = tag_string(tag, &ItemKey::InitialKey);
+        let id3v2 = match tagged_file.tag(TagType::Id3v2) {
+            Some(_) => read_id3v2(path, tagged_file.file_type())?,
+            None => None,
+        };
+
+        Ok(Self {
+            file_path: path.to_string(),
+            title: tag.title().map(String::from),
+            artist: tag.artist().map(String::from),
+            album: tag.album().map(String::from),
+            genre: tag.genre().map(String::from),
+            bpm,
+            duration_seconds: duration.as_secs(),
+            key: key.clone(),
+            parsed_key: key.as_deref().and_then(Key::parse),
+            year,
+            release_date: release_date.filter(|date| date.len() > 4),
+            label: tag_string(tag, &ItemKey::Label)
+                .or_else(|| tag_string(tag, &ItemKey::Publisher)),
+            remixer: tag_string(tag, &ItemKey::Remixer)
+                .or_else(|| tag_string(tag, &ItemKey::MixDj)),
+            composer: tag_string(tag, &ItemKey::Composer),
+            comment: tag
+                .comment()
+                .map(String::from)
+                .filter(|comment| !comment.is_empty()),
+            track_number: tag.track(),
+            rating: read_rating(tag, id3v2.as_ref()),
+            isrc: tag_string(tag, &ItemKey::Isrc),
+            date_added_unix_seconds: date_added,
+            bitrate_kbps: properties
+                .audio_bitrate()
+                .or_else(|| properties.overall_bitrate()),
+            sample_rate: properties.sample_rate(),
+            bit_depth: properties.bit_depth(),
+            channels: properties.channels(),
+            file_type: Some(file_type_name(tagged_file.file_type())),
+            file_size_bytes: metadata.map(|metadata| metadata.len()),
+            duration_ms: Some(duration.as_millis() as u64),
+            artwork_hash: artwork::front_cover(&tagged_file).map(artwork::hash),
+            last_modified_unix_ms: last_modified,
+            ..Default::default()
+        })
+    }
+
+    /// Writes tag changes to the song's file and returns the song read back from the file.
+    ///
+    /// Every tag already in the file is updated so other software sees the same values, the
+    /// format's primary tag (ID3v2 for MP3, Vorbis comments for FLAC, etc.) is created if it's
+    /// missing. The write is refused if the file was modified since this song was read.
+    pub fn write_tags(&self, changes: &TagChanges) -> Result<Self, SongWriteError> {
+        let metadata = std::fs::metadata(&self.file_path)?;
+        let last_modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_millis() as i64);
+        if last_modified != self.last_modified_unix_ms
+            || Some(metadata.len()) != self.file_size_bytes
+        {
+            return Err(SongWriteError::Modified);
+        }
+
+        let mut tagged_file = Self::try_open_file_probe(&self.file_path)?.read()?;
+        let primary_tag_type = tagged_file.primary_tag_type();
+        if tagged_file.tag(primary_tag_type).is_none() {
+            tagged_file.insert_tag(Tag::new(primary_tag_type));
+        }
+
+        let file_type = tagged_file.file_type();
+        let tag_types: Vec<TagType> = tagged_file
+            .tags()
+            .iter()
+            .map(Tag::tag_type)
+            .filter(|tag_type| file_type.supports_tag_type(*tag_type))
+            .collect();
+        for tag_type in tag_types {
+            let Some(tag) = tagged_file.tag_mut(tag_type) else {
+                continue;
+            };
+            apply_changes(tag, changes);
+            tag.save_to_path(&self.file_path, WriteOptions::default())?;
+        }
+
+        Ok(Self::from_file(&self.file_path)?)
+    }
+
+    /// Trys to open probe expecting the format to match the file extension (`.flac`, `.mp3`, etc).
+    /// If there is no extension or the extension isn't supported the `lofty` will try to guess the
+    /// file type based on the file's contents.
+    fn try_open_file_probe(path: &str) -> Result<Probe<BufReader<File>>, SongFromFileError> {
+        let file = std::fs::File::open(path)?;
+        let reader = std::io::BufReader::new(file);
+
+        let Some(extension) = std::path::Path::new(path).extension() else {
+            return Ok(Probe::new(reader).guess_file_type()?);
+        };
+
+        let Some(file_type) = FileType::from_ext(extension) else {
+            return Ok(Probe::new(reader).guess_file_type()?);
+        };
+
+        Ok(Probe::new(reader).set_file_type(file_type))
+    }
+}
+
+fn file_type_name(file_type: FileType) -> String {
+    match file_type {
+        FileType::Aac => "AAC",
+        FileType::Aiff => "AIFF",
+        FileType::Ape => "APE",
+        FileType::Flac => "FLAC",
+        FileType::Mpeg => "MP3",
+        FileType::Mp4 => "MP4",
+        FileType::Mpc => "MPC",
+        FileType::Opus => "OPUS",
+        FileType::Vorbis => "OGG",
+        FileType::Speex => "SPEEX",
+        FileType::Wav => "WAV",
+        FileType::WavPack => "WV",
+        FileType::Custom(name) => name,
+        _ => "UNKNOWN",
+    }
+    .to_string()
+}
+
+fn apply_changes(tag: &mut Tag, changes: &TagChanges) {
+    let text = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .map(|value| Some(value.to_string()).filter(|value| !value.is_empty()))
+    };
+
+    match text(&changes.title) {
+        Some(Some(title)) => tag.set_title(title),
+        Some(None) => tag.remove_title(),
+        None => {}
+    }
+    match text(&changes.artist) {
+        Some(Some(artist)) => tag.set_artist(artist),
+        Some(None) => tag.remove_artist(),
+        None => {}
+    }
+    match text(&changes.album) {
+        Some(Some(album)) => tag.set_album(album),
+        Some(None) => tag.remove_album(),
+        None => {}
+    }
+    match text(&changes.genre) {
+        Some(Some(genre)) => tag.set_genre(genre),
+        Some(None) => tag.remove_genre(),
+        None => {}
+    }
+    match text(&changes.comment) {
+        Some(Some(comment)) => tag.set_comment(comment),
+        Some(None) => tag.remove_comment(),
+        None => {}
+    }
+    match text(&changes.key) {
+        Some(Some(key)) => {
+            tag.insert_text(ItemKey::InitialKey, key);
+        }
+        Some(None) => tag.remove_key(&ItemKey::InitialKey),
+        None => {}
+    }
+
+    // Some formats only support whole BPMs, both are written so the decimal isn't lost where it
+    // can be kept.
+    match changes.bpm {
+        Some(bpm) if bpm > 0.0 => {
+            tag.insert_text(ItemKey::IntegerBpm, format!("{}", bpm.round() as u32));
+            tag.insert_text(ItemKey::Bpm, format!("{bpm:.2}"));
+        }
+        Some(_) => {
+            tag.remove_key(&ItemKey::IntegerBpm);
+            tag.remove_key(&ItemKey::Bpm);
+        }
+        None => {}
+    }
+}
+
+fn tag_string(tag: &Tag, key: &ItemKey) -> Option<String> {
+    tag.get_string(key)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+/// Reads a file's ID3v2 tag with all of its frames. The generic tags of a `TaggedFile` leave out
+/// frames without an `ItemKey`, such as ratings (`POPM`).
+fn read_id3v2(path: &str, file_type: FileType) -> Result<Option<Id3v2Tag>, LoftyError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let options = ParseOptions::new().read_properties(false);
+    Ok(match file_type {
+        FileType::Aac => AacFile::read_from(&mut reader, options)?.remove_id3v2(),
+        FileType::Aiff => AiffFile::read_from(&mut reader, options)?.remove_id3v2(),
+        FileType::Mpeg => MpegFile::read_from(&mut reader, options)?.remove_id3v2(),
+        FileType::Wav => WavFile::read_from(&mut reader, options)?.remove_id3v2(),
+        _ => None,
+    })
+}
+
+/// Reads a rating as 1 to 5 stars. ID3v2 stores ratings in `POPM` frames as 1-255, other formats
+/// use a `RATING` field which is either already 1-5 or a percentage.
+fn read_rating(tag: &Tag, id3v2: Option<&Id3v2Tag>) -> Option<u8> {
+    let popularimeter = id3v2.into_iter().flatten().find_map(|frame| match frame {
+        Frame::Popularimeter(popularimeter) => Some(popularimeter.rating),
+        _ => None,
+    });
+    if let Some(rating) = popularimeter {
+        return match rating {
+            0 => None,
+            1..=31 => Some(1),
+            32..=95 => Some(2),
+            96..=159 => Some(3),
+            160..=223 => Some(4),
+            _ => Some(5),
+        };
+    }
+
+    let rating: f64 = tag
+        .get_string(&ItemKey::Popularimeter)
+        .or_else(|| tag.get_string(&ItemKey::Unknown("RATING".to_string())))?
+        .trim()
+        .parse()
+        .ok()?;
+    let stars = if rating > 5.0 { rating / 20.0 } else { rating };
+    Some(stars.round().clamp(0.0, 5.0) as u8).filter(|stars| *stars > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use lofty::id3::v2::PopularimeterFrame;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// A second of silence with an ID3v2 tag holding a title and a 4 star rating.
+    fn write_song(path: &std::path::Path) -> Song {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for _ in 0..8000 {
+            writer.write_sample(0i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut tag = Id3v2Tag::new();
+        tag.set_title("Original".to_string());
+        tag.insert(popularimeter(196));
+        tag.save_to_path(path, WriteOptions::default()).unwrap();
+
+        Song::from_file(path.to_str().unwrap()).unwrap()
+    }
+
+    fn popularimeter(rating: u8) -> Frame<'static> {
+        Frame::Popularimeter(PopularimeterFrame::new(
+            "dj@example.com".to_string(),
+            rating,
+            0,
+        ))
+    }
+
+    #[test]
+    fn writes_tags_back_to_files() {
+        let dir = TempDir::new("songs");
+        let song = write_song(&dir.join("song.wav"));
+
+        let written = song
+            .write_tags(&TagChanges {
+                title: Some(" Edited ".to_string()),
+                bpm: Some(124.5),
+                key: Some("8A".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(written.title.as_deref(), Some("Edited"));
+        // ID3v2 only holds whole BPMs.
+        assert_eq!(written.bpm, Some(125.0));
+        assert_eq!(written.key.as_deref(), Some("8A"));
+        assert_eq!(written.parsed_key, Key::parse("8A"));
+        // Frames left out of the generic tag, like the rating, are kept.
+        assert_eq!(written.rating, Some(4));
+
+        // The song read back can be written again, and empty values remove tags.
+        let cleared = written
+            .write_tags(&TagChanges {
+                bpm: Some(0.0),
+                key: Some(String::new()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(cleared.title.as_deref(), Some("Edited"));
+        assert_eq!(cleared.bpm, None);
+        assert_eq!(cleared.key, None);
+        assert_eq!(cleared.rating, Some(4));
+    }
+
+    #[test]
+    fn writes_whole_and_decimal_bpms() {
+        // MP4 is one of the formats with both.
+        let mut tag = Tag::new(TagType::Mp4Ilst);
+        apply_changes(
+            &mut tag,
+            &TagChanges {
+                bpm: Some(124.5),
+                ..Default::default()
+            },
+        );
+        assert_eq!(tag.get_string(&ItemKey::IntegerBpm), Some("125"));
+        assert_eq!(tag.get_string(&ItemKey::Bpm), Some("124.50"));
+    }
+
+    #[test]
+    fn refuses_to_write_files_changed_since_they_were_read() {
+        let dir = TempDir::new("songs");
+        let path = dir.join("song.wav");
+        let song = write_song(&path);
+        let changes = TagChanges {
+            title: Some("Edited".to_string()),
+            ..Default::default()
+        };
+
+        // Modified in place, keeping its size.
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(matches!(
+            song.write_tags(&changes),
+            Err(SongWriteError::Modified)
+        ));
+
+        // Grown without the modified time changing.
+        let song = Song::from_file(path.to_str().unwrap()).unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        file.set_len(song.file_size_bytes.unwrap() + 2).unwrap();
+        file.set_modified(modified).unwrap();
+        assert!(matches!(
+            song.write_tags(&changes),
+            Err(SongWriteError::Modified)
+        ));
+
+        assert_eq!(
+            Song::from_file(path.to_str().unwrap())
+                .unwrap()
+                .title
+                .as_deref(),
+            Some("Original")
+        );
+    }
+
+    #[test]
+    fn reads_id3v2_ratings_from_files() {
+        let dir = TempDir::new("songs");
+        let song = write_song(&dir.join("song.wav"));
+        assert_eq!(song.title.as_deref(), Some("Original"));
+        assert_eq!(song.rating, Some(4));
+    }
+
+    #[test]
+    fn reads_ratings() {
+        let mut tag = Tag::new(TagType::VorbisComments);
+        for (rating, stars) in [("80", Some(4)), ("3", Some(3)), ("0", None), ("x", None)] {
+            tag.insert_text(ItemKey::Popularimeter, rating.to_string());
+            assert_eq!(read_rating(&tag, None), stars, "{rating}");
+        }
+
+        let tag = Tag::new(TagType::Id3v2);
+        for (rating, stars) in [
+            (0, None),
+            (1, Some(1)),
+            (64, Some(2)),
+            (128, Some(3)),
+            (255, Some(5)),
+        ] {
+            let mut id3v2 = Id3v2Tag::new();
+            id3v2.insert(popularimeter(rating));
+            assert_eq!(read_rating(&tag, Some(&id3v2)), stars, "{rating}");
+        }
+        assert_eq!(read_rating(&tag, Some(&Id3v2Tag::new())), None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Finds where songs went after the music folder was reorganised.
+//!
+//! Songs are keyed by their file path, so moving or renaming a file leaves its song, its analysis
+//! and the cue sheets listing it pointing at nothing. Missing songs, stored songs whose file is
+//! gone and files listed in cue sheets that don't exist, are matched against the library's songs
+//! by content hash (see `content_hash`), then by file name and finally by title, artist and
+//! duration.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{rekordbox::xml::file_name_lowercase, songs::Song};
+
+use super::duplicates;
+
+/// Why a file may be where a missing song went, strongest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Reason {
+    /// The audio data has the same content hash, the file was moved or renamed and perhaps
+    /// retagged. Large files are hashed from samples of their audio data, see `content_hash`.
+    SameContent,
+    /// The file has the same name in another folder, it may have been re-encoded.
+    SameFileName,
+    /// The title and artist match once normalised and the durations are close, or the duration of
+    /// the missing song isn't known.
+    SimilarTags,
+}
+
+/// A file that may be where a missing song went.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candidate {
+    /// The song read from the file.
+    pub song: Song,
+    pub reason: Reason,
+}
+
+/// A song whose file is missing and the files it may have moved to.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingSong {
+    /// The stored song, or for a file only listed in cue sheets its title and performer there.
+    pub song: Song,
+    /// The cue sheets listing the song's file.
+    pub cue_sheets: Vec<String>,
+    /// The candidates, most likely first.
+    pub candidates: Vec<Candidate>,
+    /// The candidate to relink to when one stands out, `None` when it has to be picked by hand.
+    pub suggested_file_path: Option<String>,
+}
+
+/// Matches songs whose file is missing against the library's songs whose file exists.
+/// `cue_sheets` are the cue sheets listing each missing file path.
+pub fn find(
+    missing: &[Song],
+    cue_sheets: &HashMap<String, Vec<String>>,
+    found: &[Song],
+) -> Vec<MissingSong> {
+    let mut missing_songs: Vec<MissingSong> = missing
+        .iter()
+        .map(|song| {
+            let mut candidates: Vec<Candidate> = found
+                .iter()
+                .filter_map(|candidate| {
+                    Some(Candidate {
+                        reason: reason(song, candidate)?,
+                        song: candidate.clone(),
+                    })
+                })
+                .collect();
+            candidates.sort_by(|a, b| {
+                a.reason
+                    .cmp(&b.reason)
+                    .then_with(|| a.song.file_path.cmp(&b.song.file_path))
+            });
+
+            // A candidate stands out when no other candidate is as likely.
+            let suggested_file_path = match candidates.as_slice() {
+                [best] => Some(best.song.file_path.clone()),
+                [best, next, ..] if best.reason < next.reason => Some(best.song.file_path.clone()),
+                _ => None,
+            };
+            MissingSong {
+                song: song.clone(),
+                cue_sheets: cue_sheets.get(&song.file_path).cloned().unwrap_or_default(),
+                candidates,
+                suggested_file_path,
+            }
+        })
+        .collect();
+
+    // Two songs can't be relinked to one file, e.g. copies of a song that were merged into one.
+    let mut suggestions: HashMap<String, usize> = HashMap::new();
+    for missing_song in &missing_songs {
+        if let Some(file_path) = &missing_song.suggested_file_path {
+            *suggestions.entry(file_path.clone()).or_default() += 1;
+        }
+    }
+    for missing_song in &mut missing_songs {
+        if let Some(file_path) = &missing_song.suggested_file_path {
+            if suggestions[file_path] > 1 {
+                missing_song.suggested_file_path = None;
+            }
+        }
+    }
+
+    missing_songs
+}
+
+/// The strongest reason a found song may be where a missing song went.
+fn reason(missing: &Song, found: &Song) -> Option<Reason> {
+    if missing.content_hash.is_some() && missing.content_hash == found.content_hash {
+        Some(Reason::SameContent)
+    } else if file_name_lowercase(&missing.file_path) == file_name_lowercase(&found.file_path) {
+        Some(Reason::SameFileName)
+    } else if duplicates::similar_tags(missing, found)
+        || (missing.duration_ms.is_none() && duplicates::same_title_and_artist(missing, found))
+    {
+        Some(Reason::SimilarTags)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(file_path: &str, title: &str, content_hash: Option<&str>) -> Song {
+        Song {
+            file_path: file_path.to_string(),
+            title: Some(title.to_string()),
+            artist: Some("Artist".to_string()),
+            duration_seconds: 300,
+            duration_ms: Some(300_000),
+            content_hash: content_hash.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn suggests_the_strongest_candidate() {
+        let missing = [song("/old/a.mp3", "A", Some("abc"))];
+        let found = [
+            song("/new/a.mp3", "A", None),
+            song("/new/renamed.mp3", "A", Some("abc")),
+            song("/new/b.mp3", "B", None),
+        ];
+        let cue_sheets = HashMap::from([(
+            "/old/a.mp3".to_string(),
+            vec!["/recordings/mix.cue".to_string()],
+        )]);
+
+        let missing_songs = find(&missing, &cue_sheets, &found);
+        assert_eq!(missing_songs.len(), 1);
+        let reasons: Vec<(&str, Reason)> = missing_songs[0]
+            .candidates
+            .iter()
+            .map(|candidate| (candidate.song.file_path.as_str(), candidate.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ("/new/renamed.mp3", Reason::SameContent),
+                ("/new/a.mp3", Reason::SameFileName),
+            ]
+        );
+        assert_eq!(
+            missing_songs[0].suggested_file_path.as_deref(),
+            Some("/new/renamed.mp3")
+        );
+        assert_eq!(missing_songs[0].cue_sheets, vec!["/recordings/mix.cue"]);
+    }
+
+    #[test]
+    fn matches_cue_sheet_entries_without_a_duration() {
+        let listed = Song {
+            file_path: "/old/track.mp3".to_string(),
+            title: Some("A".to_string()),
+            artist: Some("artist".to_string()),
+            ..Default::default()
+        };
+        let found = [song("/new/a.mp3", "A", None)];
+
+        let missing_songs = find(&[listed], &HashMap::new(), &found);
+        assert_eq!(
+            missing_songs[0].suggested_file_path.as_deref(),
+            Some("/new/a.mp3")
+        );
+        assert_eq!(missing_songs[0].candidates[0].reason, Reason::SimilarTags);
+    }
+
+    #[test]
+    fn doesnt_suggest_one_file_for_two_songs() {
+        let missing = [
+            song("/old/a.mp3", "A", Some("abc")),
+            song("/copies/a.mp3", "A", Some("abc")),
+        ];
+        let found = [song("/new/a.flac", "A", Some("abc"))];
+
+        for missing_song in find(&missing, &HashMap::new(), &found) {
+            assert_eq!(missing_song.candidates.len(), 1);
+            assert_eq!(missing_song.suggested_file_path, None);
+        }
+    }
+}
@@ -0,0 +1,541 @@
+//! Provides Traktor collection (`collection.nml`) and history (`History/*.nml`) support.
+//!
+//! Both are NML files, an XML format where each track is an `ENTRY` element in the
+//! `COLLECTION` and playlists reference entries by a key built from the track's location.
+//! History files contain the tracks played in a session with the time each was started.
+
+use std::{collections::HashMap, path::Path};
+
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use serde::Serialize;
+
+use crate::{
+    recording::{self, Recording},
+    songs::{key::Key, Song},
+};
+
+/// Traktor's `MUSICAL_KEY` values in order, majors then minors.
+const KEYS: [&str; 24] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B", "Cm", "C#m", "Dm", "D#m",
+    "Em", "Fm", "F#m", "Gm", "G#m", "Am", "A#m", "Bm",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read nml file: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to parse nml file: {0}")]
+    Xml(#[from] quick_xml::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CueKind {
+    Cue,
+    FadeIn,
+    FadeOut,
+    Load,
+    Grid,
+    Loop,
+}
+
+/// A `CUE_V2` marker.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cue {
+    pub name: Option<String>,
+    pub kind: CueKind,
+    pub start_ms: f64,
+    /// The loop length, 0 for anything other than loops.
+    pub length_ms: f64,
+    /// The hot cue slot starting at 0, `None` if the cue isn't assigned to a hot cue.
+    pub hot_cue: Option<u32>,
+}
+
+/// A collection `ENTRY` converted to a song along with the Traktor specific analysis.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub song: Song,
+    /// The key in standard notation, e.g. "Am".
+    pub key: Option<String>,
+    pub cues: Vec<Cue>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Playlist {
+    pub name: String,
+    /// Names of the folders containing this playlist from the root down.
+    pub folders: Vec<String>,
+    pub song_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Collection {
+    pub entries: Vec<Entry>,
+    pub playlists: Vec<Playlist>,
+}
+
+/// Reads a Traktor `collection.nml` file.
+pub fn read_collection(path: &str) -> Result<Collection, Error> {
+    let (entries, playlists) = parse(path)?;
+
+    // Playlists reference entries by location key, resolve them to the songs' file paths.
+    let playlists = playlists
+        .into_iter()
+        .map(|playlist| Playlist {
+            name: playlist.name,
+            folders: playlist.folders,
+            song_paths: playlist
+                .entries
+                .iter()
+                .filter_map(|(key, _)| entries.get(key))
+                .map(|entry| entry.song.file_path.clone())
+                .collect(),
+        })
+        .collect();
+
+    let mut entries: Vec<Entry> = entries.into_values().collect();
+    entries.sort_by(|a, b| a.song.file_path.cmp(&b.song.file_path));
+
+    Ok(Collection { entries, playlists })
+}
+
+/// Reads a Traktor history file as a `Recording`, each played track becomes a track starting at
+/// the time it was played relative to the first track.
+pub fn read_history(path: &str) -> Result<Recording, Error> {
+    let (entries, playlists) = parse(path)?;
+
+    let mut recording = Recording {
+        file_path: path.to_string(),
+        title: Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+
+    // The file is written when the session ends, matching how Rekordbox recordings are dated.
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let modified_time = filetime::FileTime::from_last_modification_time(&metadata);
+        recording.last_modified_unix_seconds = modified_time.unix_seconds();
+
+        let accessed_time = filetime::FileTime::from_last_access_time(&metadata);
+        recording.last_accessed_unix_seconds = accessed_time.unix_seconds();
+    }
+
+    let Some(history) = playlists.into_iter().next() else {
+        return Ok(recording);
+    };
+
+    // Entries are in the order they were played, the earliest time of day isn't the first track
+    // when the session ran past midnight.
+    let first_start_time = history
+        .entries
+        .iter()
+        .find_map(|(_, start_time)| *start_time)
+        .unwrap_or(0);
+
+    for (key, start_time) in history.entries {
+        let Some(entry) = entries.get(&key) else {
+            continue;
+        };
+
+        recording.tracks.push(recording::Track {
+            title: entry.song.title.clone(),
+            performer: entry.song.artist.clone(),
+            file: (!entry.song.file_path.is_empty())
+                .then(|| recording::File::from_path(&entry.song.file_path)),
+            start_time: start_time.map(|start_time| {
+                // Sessions running past midnight wrap around to the next day.
+                let seconds = (start_time + 24 * 60 * 60 - first_start_time) % (24 * 60 * 60);
+                recording::format_start_time(seconds as u64)
+            }),
+        });
+    }
+
+    Ok(recording)
+}
+
+/// Searches a Traktor `History` directory for history files.
+///
+/// Any IO errors are logged and otherwise ignored.
+pub fn find_history(path: &str) -> Vec<Recording> {
+    let dir = match std::fs::read_dir(path) {
+        Ok(dir) => dir,
+        Err(error) => {
+            tracing::warn!(?error, path, "failed to read directory");
+            return Vec::new();
+        }
+    };
+
+    let mut recordings = Vec::new();
+    for entry in dir {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                tracing::warn!(?error, "failed to read entry in dir");
+                continue;
+            }
+        };
+
+        let entry_path = entry.path();
+        if entry_path.extension().and_then(|ext| ext.to_str()) != Some("nml") {
+            continue;
+        }
+        let file = entry_path.to_string_lossy().into_owned();
+
+        match read_history(&file) {
+            Ok(recording) => recordings.push(recording),
+            Err(error) => tracing::warn!(?error, file, "failed to read history file"),
+        }
+    }
+
+    recordings
+}
+
+/// A playlist before its entries are resolved, entries are the location key and the time the
+/// entry was played (history only).
+#[derive(Default)]
+struct RawPlaylist {
+    name: String,
+    folders: Vec<String>,
+    entries: Vec<(String, Option<u32>)>,
+}
+
+/// Reads all collection entries (keyed by their location key) and playlists from an NML file.
+fn parse(path: &str) -> Result<(HashMap<String, Entry>, Vec<RawPlaylist>), Error> {
+    let mut reader = Reader::from_file(path)?;
+    let mut buf = Vec::new();
+
+    let mut entries: HashMap<String, Entry> = HashMap::new();
+    let mut playlists: Vec<RawPlaylist> = Vec::new();
+
+    let mut in_collection = false;
+    let mut current_entry: Option<(String, Entry)> = None;
+    // Names of the open `NODE` elements, the playlist is the last node.
+    let mut nodes: Vec<String> = Vec::new();
+    let mut current_playlist: Option<RawPlaylist> = None;
+
+    loop {
+        let event = reader.read_event_into(&mut buf)?;
+        let is_empty = matches!(event, Event::Empty(_));
+
+        match event {
+            Event::Start(element) | Event::Empty(element) => match element.name().as_ref() {
+                b"COLLECTION" => in_collection = !is_empty,
+                b"ENTRY" if in_collection => {
+                    current_entry = Some((
+                        String::new(),
+                        Entry {
+                            song: Song {
+                                title: attribute(&element, "TITLE"),
+                                artist: attribute(&element, "ARTIST"),
+                                ..Default::default()
+                            },
+                            key: None,
+                            cues: Vec::new(),
+                        },
+                    ));
+                }
+                b"LOCATION" => {
+                    if let Some((key, entry)) = &mut current_entry {
+                        let volume = attribute(&element, "VOLUME").unwrap_or_default();
+                        let dir = attribute(&element, "DIR").unwrap_or_default();
+                        let file = attribute(&element, "FILE").unwrap_or_default();
+                        *key = format!("{volume}{dir}{file}");
+                        entry.song.file_path = file_path(&volume, &dir, &file);
+                    }
+                }
+                b"ALBUM" => {
+                    if let Some((_, entry)) = &mut current_entry {
+                        entry.song.album = attribute(&element, "TITLE");
+                    }
+                }
+                b"INFO" => {
+                    if let Some((_, entry)) = &mut current_entry {
+                        entry.song.genre = attribute(&element, "GENRE");
+                        entry.song.duration_seconds = attribute(&element, "PLAYTIME")
+                            .and_then(|playtime| playtime.parse().ok())
+                            .unwrap_or(0);
+                        if entry.key.is_none() {
+                            entry.key = attribute(&element, "KEY");
+                        }
+                    }
+                }
+                b"TEMPO" => {
+                    if let Some((_, entry)) = &mut current_entry {
+                        entry.song.bpm =
+                            attribute(&element, "BPM").and_then(|bpm| bpm.parse().ok());
+                    }
+                }
+                b"MUSICAL_KEY" => {
+                    if let Some((_, entry)) = &mut current_entry {
+                        let key = attribute(&element, "VALUE")
+                            .and_then(|value| value.parse::<usize>().ok())
+                            .and_then(|value| KEYS.get(value));
+                        if let Some(key) = key {
+                            entry.key = Some(key.to_string());
+                        }
+                    }
+                }
+                b"CUE_V2" => {
+                    if let Some((_, entry)) = &mut current_entry {
+                        entry.cues.push(parse_cue(&element));
+                    }
+                }
+                b"NODE" => {
+                    nodes.push(attribute(&element, "NAME").unwrap_or_default());
+                    if attribute(&element, "TYPE").as_deref() == Some("PLAYLIST") {
+                        current_playlist = Some(RawPlaylist {
+                            name: nodes.last().cloned().unwrap_or_default(),
+                            // Skip Traktor's `$ROOT` folder.
+                            folders: nodes[..nodes.len() - 1].iter().skip(1).cloned().collect(),
+                            entries: Vec::new(),
+                        });
+                    }
+                    if is_empty {
+                        nodes.pop();
+                        playlists.extend(current_playlist.take());
+                    }
+                }
+                b"PRIMARYKEY" => {
+                    if let (Some(playlist), Some(key)) =
+                        (&mut current_playlist, attribute(&element, "KEY"))
+                    {
+                        playlist.entries.push((key, None));
+                    }
+                }
+                b"EXTENDEDDATA" => {
+                    let start_time =
+                        attribute(&element, "STARTTIME").and_then(|time| time.parse().ok());
+                    if let Some((_, last_start_time)) = current_playlist
+                        .as_mut()
+                        .and_then(|playlist| playlist.entries.last_mut())
+                    {
+                        *last_start_time = start_time;
+                    }
+                }
+                _ => {}
+            },
+            Event::End(element) => match element.name().as_ref() {
+                b"COLLECTION" => in_collection = false,
+                b"ENTRY" => {
+                    if let Some((key, mut entry)) = current_entry.take() {
+                        entry.song.key = entry.key.clone();
+                        entry.song.parsed_key = entry.key.as_deref().and_then(Key::parse);
+                        entries.insert(key, entry);
+                    }
+                }
+                b"NODE" => {
+                    nodes.pop();
+                    if let Some(playlist) = current_playlist.take() {
+                        playlists.push(playlist);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    Ok((entries, playlists))
+}
+
+fn parse_cue(element: &BytesStart) -> Cue {
+    let number = |name: &str| {
+        attribute(element, name)
+            .and_then(|value| value.parse::<f64>().ok())
+            .unwrap_or(0.0)
+    };
+
+    Cue {
+        name: attribute(element, "NAME").filter(|name| name != "n.n."),
+        kind: match attribute(element, "TYPE").as_deref() {
+            Some("1") => CueKind::FadeIn,
+            Some("2") => CueKind::FadeOut,
+            Some("3") => CueKind::Load,
+            Some("4") => CueKind::Grid,
+            Some("5") => CueKind::Loop,
+            _ => CueKind::Cue,
+        },
+        start_ms: number("START"),
+        length_ms: number("LEN"),
+        hot_cue: attribute(element, "HOTCUE").and_then(|hot_cue| hot_cue.parse().ok()),
+    }
+}
+
+/// Builds a local path from a `LOCATION`, directories are separated by `/:`. Windows volumes
+/// are drive letters while macOS volumes are volume names, only external macOS volumes need the
+/// volume in the path and those are assumed to be mounted under `/Volumes`.
+fn file_path(volume: &str, dir: &str, file: &str) -> String {
+    let dir = dir.replace("/:", "/");
+
+    if volume.ends_with(':') {
+        format!("{volume}{dir}{file}")
+    } else if cfg!(target_os = "macos") && !Path::new(&dir).exists() && !volume.is_empty() {
+        format!("/Volumes/{volume}{dir}{file}")
+    } else {
+        format!("{dir}{file}")
+    }
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const COLLECTION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<NML VERSION="19"><HEAD COMPANY="www.native-instruments.com" PROGRAM="Traktor"></HEAD>
+<COLLECTION ENTRIES="2">
+<ENTRY TITLE="First" ARTIST="Artist A">
+<LOCATION DIR="/:Music/:House/:" FILE="first.mp3" VOLUME="C:" VOLUMEID="c"></LOCATION>
+<ALBUM TITLE="Album"></ALBUM>
+<INFO GENRE="House" KEY="1m" PLAYTIME="300"></INFO>
+<TEMPO BPM="124.000000" BPM_QUALITY="100.000000"></TEMPO>
+<MUSICAL_KEY VALUE="21"></MUSICAL_KEY>
+<CUE_V2 NAME="AutoGrid" DISPL_ORDER="0" TYPE="4" START="120.5" LEN="0" REPEATS="-1" HOTCUE="0"></CUE_V2>
+<CUE_V2 NAME="n.n." DISPL_ORDER="0" TYPE="5" START="30000" LEN="8000" REPEATS="-1" HOTCUE="-1"></CUE_V2>
+</ENTRY>
+<ENTRY TITLE="Second" ARTIST="Artist B">
+<LOCATION DIR="/:Music/:Techno/:" FILE="second.mp3" VOLUME="C:" VOLUMEID="c"></LOCATION>
+<INFO GENRE="Techno" KEY="Dm" PLAYTIME="360"></INFO>
+</ENTRY>
+</COLLECTION>
+<PLAYLISTS>
+<NODE TYPE="FOLDER" NAME="$ROOT"><SUBNODES COUNT="2">
+<NODE TYPE="FOLDER" NAME="Sets"><SUBNODES COUNT="1">
+<NODE TYPE="PLAYLIST" NAME="Friday"><PLAYLIST ENTRIES="3" TYPE="LIST" UUID="a">
+<ENTRY><PRIMARYKEY TYPE="TRACK" KEY="C:/:Music/:Techno/:second.mp3"></PRIMARYKEY></ENTRY>
+<ENTRY><PRIMARYKEY TYPE="TRACK" KEY="C:/:Music/:House/:first.mp3"></PRIMARYKEY></ENTRY>
+<ENTRY><PRIMARYKEY TYPE="TRACK" KEY="C:/:Music/:gone.mp3"></PRIMARYKEY></ENTRY>
+</PLAYLIST></NODE>
+</SUBNODES></NODE>
+<NODE TYPE="PLAYLIST" NAME="Empty"><PLAYLIST ENTRIES="0" TYPE="LIST" UUID="b"></PLAYLIST></NODE>
+</SUBNODES></NODE>
+</PLAYLISTS>
+</NML>
+"#;
+
+    const HISTORY: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<NML VERSION="19"><HEAD COMPANY="www.native-instruments.com" PROGRAM="Traktor"></HEAD>
+<COLLECTION ENTRIES="2">
+<ENTRY TITLE="First" ARTIST="Artist A">
+<LOCATION DIR="/:Music/:" FILE="first.mp3" VOLUME="C:"></LOCATION>
+</ENTRY>
+<ENTRY TITLE="Second" ARTIST="Artist B">
+<LOCATION DIR="/:Music/:" FILE="second.mp3" VOLUME="C:"></LOCATION>
+</ENTRY>
+</COLLECTION>
+<PLAYLISTS>
+<NODE TYPE="FOLDER" NAME="$ROOT"><SUBNODES COUNT="1">
+<NODE TYPE="PLAYLIST" NAME="HistoryPlaylist"><PLAYLIST ENTRIES="2" TYPE="LIST" UUID="c">
+<ENTRY><PRIMARYKEY TYPE="TRACK" KEY="C:/:Music/:first.mp3"></PRIMARYKEY>
+<EXTENDEDDATA DECK="0" DURATION="400.0" EXTENDEDTYPE="HistoryData" STARTDATE="134744065" STARTTIME="86000"></EXTENDEDDATA></ENTRY>
+<ENTRY><PRIMARYKEY TYPE="TRACK" KEY="C:/:Music/:second.mp3"></PRIMARYKEY>
+<EXTENDEDDATA DECK="1" DURATION="300.0" EXTENDEDTYPE="HistoryData" STARTDATE="134744066" STARTTIME="400"></EXTENDEDDATA></ENTRY>
+</PLAYLIST></NODE>
+</SUBNODES></NODE>
+</PLAYLISTS>
+</NML>
+"#;
+
+    #[test]
+    fn reads_collections() {
+        let dir = TempDir::new("traktor-collection");
+        let path = dir.join("collection.nml");
+        std::fs::write(&path, COLLECTION).unwrap();
+
+        let collection = read_collection(path.to_str().unwrap()).unwrap();
+        assert_eq!(collection.entries.len(), 2);
+
+        let first = &collection.entries[0];
+        assert_eq!(first.song.file_path, "C:/Music/House/first.mp3");
+        assert_eq!(first.song.title.as_deref(), Some("First"));
+        assert_eq!(first.song.artist.as_deref(), Some("Artist A"));
+        assert_eq!(first.song.album.as_deref(), Some("Album"));
+        assert_eq!(first.song.genre.as_deref(), Some("House"));
+        assert_eq!(first.song.duration_seconds, 300);
+        assert_eq!(first.song.bpm, Some(124.0));
+        // `MUSICAL_KEY` takes priority over the key tag in `INFO`.
+        assert_eq!(first.key.as_deref(), Some("Am"));
+        assert_eq!(first.song.key.as_deref(), Some("Am"));
+        assert_eq!(first.song.parsed_key, Key::parse("Am"));
+
+        assert_eq!(first.cues.len(), 2);
+        assert_eq!(first.cues[0].name.as_deref(), Some("AutoGrid"));
+        assert_eq!(first.cues[0].kind, CueKind::Grid);
+        assert_eq!(first.cues[0].start_ms, 120.5);
+        assert_eq!(first.cues[0].hot_cue, Some(0));
+        assert_eq!(first.cues[1].name, None);
+        assert_eq!(first.cues[1].kind, CueKind::Loop);
+        assert_eq!(first.cues[1].length_ms, 8000.0);
+        assert_eq!(first.cues[1].hot_cue, None);
+
+        let second = &collection.entries[1];
+        assert_eq!(second.song.file_path, "C:/Music/Techno/second.mp3");
+        assert_eq!(second.key.as_deref(), Some("Dm"));
+        assert_eq!(second.song.parsed_key, Key::parse("Dm"));
+
+        assert_eq!(collection.playlists.len(), 2);
+        assert_eq!(collection.playlists[0].name, "Friday");
+        assert_eq!(collection.playlists[0].folders, vec!["Sets"]);
+        assert_eq!(
+            collection.playlists[0].song_paths,
+            vec!["C:/Music/Techno/second.mp3", "C:/Music/House/first.mp3"]
+        );
+        assert_eq!(collection.playlists[1].name, "Empty");
+        assert!(collection.playlists[1].folders.is_empty());
+        assert!(collection.playlists[1].song_paths.is_empty());
+    }
+
+    #[test]
+    fn reads_history_across_midnight() {
+        let dir = TempDir::new("traktor-history");
+        let path = dir.join("history_2024y03m01d_22h53m20s.nml");
+        std::fs::write(&path, HISTORY).unwrap();
+
+        let recordings = find_history(dir.join("").to_str().unwrap());
+        assert_eq!(recordings.len(), 1);
+        let recording = &recordings[0];
+        assert_eq!(
+            recording.title.as_deref(),
+            Some("history_2024y03m01d_22h53m20s")
+        );
+        assert_eq!(recording.tracks.len(), 2);
+        assert_eq!(recording.tracks[0].title.as_deref(), Some("First"));
+        assert_eq!(recording.tracks[0].performer.as_deref(), Some("Artist A"));
+        assert_eq!(
+            recording.tracks[0]
+                .file
+                .as_ref()
+                .map(|file| file.name.as_str()),
+            Some("C:/Music/first.mp3")
+        );
+        assert_eq!(recording.tracks[0].start_seconds(), Some(0));
+        assert_eq!(recording.tracks[1].start_seconds(), Some(800));
+    }
+
+    #[test]
+    fn builds_file_paths_from_locations() {
+        assert_eq!(file_path("C:", "/:Music/:", "a.mp3"), "C:/Music/a.mp3");
+        assert_eq!(file_path("", "/:Music/:", "a.mp3"), "/Music/a.mp3");
+    }
+}
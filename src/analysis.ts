@@ -0,0 +1,127 @@
+import type { Key } from "./song";
+
+export interface Tempo {
+    bpm: number;
+    /** How strongly the onsets repeat every beat, from 0 (no pulse) to 1 (a metronome). */
+    confidence: number;
+}
+
+export interface DetectedKey {
+    key: Key;
+    /** How clearly the best key beat the others, from 0 (a tie) to 1. */
+    confidence: number;
+}
+
+/**
+ * A beat in a beatgrid, `beatNumber` is its position in the bar from 1 to 4 where 1 is the downbeat.
+ */
+export interface Beat {
+    beatNumber: number;
+    timeMs: number;
+}
+
+export interface Beatgrid {
+    filePath: string;
+    bpm: number;
+    firstBeatMs: number;
+    beats: Beat[];
+}
+
+/**
+ * The level of a column of a waveform, from 0 to 1.
+ */
+export interface WaveformColumn {
+    peak: number;
+    rms: number;
+}
+
+/**
+ * A song's waveform, a column every `1 / columnsPerSecond` seconds.
+ */
+export interface Waveform {
+    filePath: string;
+    columnsPerSecond: number;
+    columns: WaveformColumn[];
+}
+
+/**
+ * How loud a song is, measured following ITU-R BS.1770.
+ */
+export interface Loudness {
+    filePath: string;
+    /** Integrated loudness in LUFS, missing when the song is silent. */
+    integratedLufs?: number;
+    /** The highest sample level in dB relative to full scale. */
+    peakDbfs?: number;
+}
+
+/**
+ * The start times of each bar, taken from the downbeats.
+ */
+export function barStartsMs(beatgrid: Beatgrid): number[] {
+    return beatgrid.beats.filter((beat) => beat.beatNumber === 1).map((beat) => beat.timeMs);
+}
+
+/**
+ * The start times of each phrase, electronic music is usually built from phrases of 16 bars.
+ */
+export function phraseStartsMs(beatgrid: Beatgrid, barsPerPhrase = 16): number[] {
+    return barStartsMs(beatgrid).filter((_, bar) => bar % barsPerPhrase === 0);
+}
+
+/**
+ * Where a recording changes from one track to the next, `trackIndex` is the index of the track
+ * being mixed in within the recording's tracks.
+ */
+export interface Transition {
+    trackIndex: number;
+    /** When the cue sheet says the track started. */
+    cueSeconds: number;
+    /** Where the mix changes most from the previous track to this one. */
+    transitionSeconds: number;
+    blendStartSeconds: number;
+    blendEndSeconds: number;
+    /** How distinct the change is compared to the largest in the recording, from 0 to 1. */
+    confidence: number;
+}
+
+/**
+ * How long both tracks were heard together.
+ */
+export function blendSeconds(transition: Transition): number {
+    return transition.blendEndSeconds - transition.blendStartSeconds;
+}
+
+/**
+ * Where a track's song was found in a recording by matching its audio, `trackIndex` is the index
+ * of the track within the recording's tracks.
+ */
+export interface Alignment {
+    trackIndex: number;
+    songPath: string;
+    /** When the cue sheet says the track started. */
+    cueSeconds: number;
+    /** Where the start of the song falls in the recording. */
+    songStartSeconds: number;
+    /** When the song becomes audible in the recording, where the cue sheet is corrected to. */
+    audibleStartSeconds: number;
+    /** How far the song is from where the cue sheet puts it. */
+    offsetSeconds: number;
+    /** How well the song's onsets match the recording, from 0 to 1. */
+    confidence: number;
+    /** How much faster the song was played than its original tempo, e.g. 2 for +2%. */
+    tempoChangePercent: number;
+    /** How far the song's pitch was shifted, 0 when it was played with key lock. */
+    pitchChangeSemitones: number;
+}
+
+/**
+ * A fingerprinted song heard in an excerpt of audio.
+ */
+export interface FingerprintMatch {
+    songPath: string;
+    /** Where the song starts relative to the start of the excerpt, negative when it started earlier. */
+    offsetSeconds: number;
+    /** How many landmarks the excerpt and song share at that offset. */
+    score: number;
+}
@@ -1,6 +1,26 @@
 import { invoke } from "@tauri-apps/api/core";
-import type { Recording } from "./recording";
-import type { Song } from "./song";
+import type {
+    Alignment,
+    Beatgrid,
+    DetectedKey,
+    FingerprintMatch,
+    Loudness,
+    Tempo,
+    Transition,
+    Waveform,
+} from "./analysis";
+import type { CleanupRule, SongCleanup } from "./cleanup";
+import type { DuplicateGroup } from "./duplicates";
+import type { EngineDjLibrary } from "./engine-dj";
+import type { Job, JobKind } from "./jobs";
+import type { MixxxLibrary } from "./mixxx";
+import type { QualityReport } from "./quality";
+import type { ReconstructedTracklist, Recording, Track } from "./recording";
+import type { MissingSong, Relink, RelinkResult } from "./relink";
+import type { Analysis, DeviceLibrary, HistoryMatch } from "./rekordbox";
+import type { SeratoLibrary, SeratoMarkers } from "./serato";
+import type { Song, TagChanges, TagWriteResult } from "./song";
+import type { TraktorCollection } from "./traktor";
 
 export async function getRecordingsDir(): Promise<string | null> {
     return await invoke("get_recordings_dir");
@@ -14,6 +34,82 @@ export async function getRecording(path: string): Promise<Recording> {
     return await invoke("get_recording", { path });
 }
 
+/**
+ * Detects where each track of a recording is mixed in from its audio and stores the transitions.
+ */
+export async function detectRecordingTransitions(path: string): Promise<Transition[]> {
+    return await invoke("detect_recording_transitions", { path });
+}
+
+/**
+ * The stored transitions of a recording, empty when they haven't been detected.
+ */
+export async function getRecordingTransitions(path: string): Promise<Transition[]> {
+    return await invoke("get_recording_transitions", { path });
+}
+
+/**
+ * Finds where the song of each track sits in a recording's audio and stores the alignments.
+ */
+export async function alignRecordingSongs(path: string): Promise<Alignment[]> {
+    return await invoke("align_recording_songs", { path });
+}
+
+/**
+ * The stored alignments of a recording, empty when it hasn't been aligned.
+ */
+export async function getRecordingAlignments(path: string): Promise<Alignment[]> {
+    return await invoke("get_recording_alignments", { path });
+}
+
+/**
+ * Moves each track's start time in the cue sheet to when its song becomes audible, skipping
+ * alignments less confident than `minConfidence` and any that would put tracks out of order. The
+ * original is kept as a '.cue.bak' file. Returns the corrected recording.
+ */
+export async function correctRecordingCueSheet(
+    path: string,
+    minConfidence?: number,
+): Promise<Recording> {
+    return await invoke("correct_recording_cue_sheet", { path, minConfidence });
+}
+
+/**
+ * Finds the fingerprinted songs playing in an excerpt of an audio file, by default the first 20
+ * seconds. Songs are fingerprinted by queueing `{ type: "fingerprint" }` jobs.
+ */
+export async function findSongsByAudio(
+    path: string,
+    startSeconds?: number,
+    durationSeconds?: number,
+): Promise<FingerprintMatch[]> {
+    return await invoke("find_songs_by_audio", { path, startSeconds, durationSeconds });
+}
+
+/**
+ * Finds wave files in the recordings directory that have no cue sheet.
+ */
+export async function findBareRecordings(): Promise<Recording[]> {
+    return await invoke("find_bare_recordings");
+}
+
+/**
+ * Recognises the library songs played in a wave file without a cue sheet, the recording isn't
+ * saved until its tracks are passed to `saveRecordingCueSheet`. Songs that haven't been
+ * fingerprinted are queued as jobs, so the tracklist can be found again once they're done.
+ */
+export async function reconstructTracklist(path: string): Promise<ReconstructedTracklist> {
+    return await invoke("reconstruct_tracklist", { path });
+}
+
+/**
+ * Saves tracks as a new cue sheet next to the wave file at `path` in the recordings directory,
+ * fails if one already exists.
+ */
+export async function saveRecordingCueSheet(path: string, tracks: Track[]): Promise<Recording> {
+    return await invoke("save_recording_cue_sheet", { path, tracks });
+}
+
 export async function findRecordings(): Promise<Recording[]> {
     return await invoke("find_recordings", {
         dir: "/Users/insprac/Music/PioneerDJ/Recording",
@@ -31,3 +127,212 @@ export async function getSong(path: string): Promise<Song> {
 export async function findSongs(): Promise<Song[]> {
     return await invoke("find_songs");
 }
+
+/**
+ * Songs in a key given in any notation, sorted by key. When `compatible` is set songs in keys
+ * that mix harmonically with it are included too.
+ */
+export async function findSongsInKey(key: string, compatible: boolean): Promise<Song[]> {
+    return await invoke("find_songs_in_key", { key, compatible });
+}
+
+/**
+ * Songs that are copies of each other by their content hashes, tags or fingerprints, largest
+ * groups first. Only songs that have been fingerprinted are compared by their audio.
+ */
+export async function findDuplicateSongs(): Promise<DuplicateGroup[]> {
+    return await invoke("find_duplicate_songs");
+}
+
+/**
+ * Songs whose file is missing, including files listed in cue sheets that don't exist, with the
+ * library songs they may have moved to matched by content hash, file name and tags.
+ */
+export async function findMissingSongs(): Promise<MissingSong[]> {
+    return await invoke("find_missing_songs");
+}
+
+/**
+ * Moves songs and their analysis to new file paths. When `updateCueSheets` is set, cue sheets in
+ * the recordings directory listing the old paths are changed to the new ones, keeping a backup.
+ */
+export async function relinkSongs(
+    relinks: Relink[],
+    updateCueSheets: boolean,
+): Promise<RelinkResult> {
+    return await invoke("relink_songs", { relinks, updateCueSheets });
+}
+
+export async function writeSongTags(
+    paths: string[],
+    changes: TagChanges,
+): Promise<TagWriteResult[]> {
+    return await invoke("write_song_tags", { paths, changes });
+}
+
+export async function previewTagCleanup(
+    paths: string[],
+    rules: CleanupRule[],
+): Promise<SongCleanup[]> {
+    return await invoke("preview_tag_cleanup", { paths, rules });
+}
+
+/** Writes previewed cleanups, songs whose tags changed since the preview are refused. */
+export async function applyTagCleanup(cleanups: SongCleanup[]): Promise<TagWriteResult[]> {
+    return await invoke("apply_tag_cleanup", { cleanups });
+}
+
+export async function findLowBitrateSongs(maxBitrateKbps: number): Promise<Song[]> {
+    return await invoke("find_low_bitrate_songs", { maxBitrateKbps });
+}
+
+export async function analyseSongQuality(
+    path: string,
+    minBitrateKbps?: number,
+): Promise<QualityReport> {
+    return await invoke("analyse_song_quality", { path, minBitrateKbps });
+}
+
+export async function getQualityReports(): Promise<QualityReport[]> {
+    return await invoke("get_quality_reports");
+}
+
+/**
+ * Detects a song's tempo from its audio, tempos outside the range (70-180 BPM by default) are
+ * halved or doubled to fit. Resolves to `null` when the song has no clear pulse.
+ */
+export async function detectSongBpm(
+    path: string,
+    minBpm?: number,
+    maxBpm?: number,
+): Promise<Tempo | null> {
+    return await invoke("detect_song_bpm", { path, minBpm, maxBpm });
+}
+
+/**
+ * Tracks the beats and downbeats through a song and stores its beatgrid, the tempo is kept
+ * within the range as when detecting BPM. Resolves to `null` when the song has no pulse.
+ */
+export async function analyseSongBeats(
+    path: string,
+    minBpm?: number,
+    maxBpm?: number,
+): Promise<Beatgrid | null> {
+    return await invoke("analyse_song_beats", { path, minBpm, maxBpm });
+}
+
+/**
+ * The stored beatgrid of a song, `null` when its beats haven't been analysed.
+ */
+export async function getSongBeatgrid(path: string): Promise<Beatgrid | null> {
+    return await invoke("get_song_beatgrid", { path });
+}
+
+/**
+ * The stored waveform of a song, `null` until a waveform job has analysed it.
+ */
+export async function getSongWaveform(path: string): Promise<Waveform | null> {
+    return await invoke("get_song_waveform", { path });
+}
+
+/**
+ * The stored loudness of a song, `null` until a loudness job has measured it.
+ */
+export async function getSongLoudness(path: string): Promise<Loudness | null> {
+    return await invoke("get_song_loudness", { path });
+}
+
+/**
+ * Detects a song's key from its audio. Resolves to `null` when the song has no tonal content.
+ */
+export async function detectSongKey(path: string): Promise<DetectedKey | null> {
+    return await invoke("detect_song_key", { path });
+}
+
+/**
+ * Songs whose tagged key differs from the key detected from their audio.
+ */
+export async function findKeyMismatches(minConfidence?: number): Promise<Song[]> {
+    return await invoke("find_key_mismatches", { minConfidence });
+}
+
+/**
+ * Queues a background analysis job for each path. Changes are emitted as `job-updated` events
+ * and progress through each running job as `job-progress` events.
+ * Paths already queued for the same analysis keep their job, with its priority raised if lower.
+ */
+export async function enqueueJobs(
+    kind: JobKind,
+    paths: string[],
+    priority?: number,
+): Promise<Job[]> {
+    return await invoke("enqueue_jobs", { kind, paths, priority });
+}
+
+/**
+ * Every job, unfinished jobs first in the order they'll run.
+ */
+export async function listJobs(): Promise<Job[]> {
+    return await invoke("list_jobs");
+}
+
+/**
+ * Cancels a pending or running job, a running job's results are discarded.
+ */
+export async function cancelJob(id: number): Promise<Job> {
+    return await invoke("cancel_job", { id });
+}
+
+export async function setJobPriority(id: number, priority: number): Promise<Job> {
+    return await invoke("set_job_priority", { id, priority });
+}
+
+/**
+ * Removes completed, failed and cancelled jobs, resolving to how many were removed.
+ */
+export async function clearFinishedJobs(): Promise<number> {
+    return await invoke("clear_finished_jobs");
+}
+
+export async function exportRekordboxXml(path: string): Promise<null> {
+    return await invoke("export_rekordbox_xml", { path });
+}
+
+export async function getSongAnalysis(path: string): Promise<Analysis> {
+    return await invoke("get_song_analysis", { path });
+}
+
+export async function importDeviceLibrary(path: string): Promise<DeviceLibrary> {
+    return await invoke("import_device_library", { path });
+}
+
+export async function findHistoryMatches(
+    xmlPath: string | null,
+    devicePath: string | null,
+): Promise<HistoryMatch[]> {
+    return await invoke("find_history_matches", { xmlPath, devicePath });
+}
+
+export async function importSeratoLibrary(path: string): Promise<SeratoLibrary> {
+    return await invoke("import_serato_library", { path });
+}
+
+export async function getSeratoMarkers(path: string): Promise<SeratoMarkers> {
+    return await invoke("get_serato_markers", { path });
+}
+
+export async function importTraktorCollection(path: string): Promise<TraktorCollection> {
+    return await invoke("import_traktor_collection", { path });
+}
+
+export async function findTraktorHistory(path: string): Promise<Recording[]> {
+    return await invoke("find_traktor_history", { path });
+}
+
+export async function importMixxxLibrary(path: string): Promise<MixxxLibrary> {
+    return await invoke("import_mixxx_library", { path });
+}
+
+export async function importEngineDjLibrary(path: string): Promise<EngineDjLibrary> {
+    return await invoke("import_engine_dj_library", { path });
+}
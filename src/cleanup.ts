@@ -0,0 +1,24 @@
+export type CleanupField = "title" | "artist" | "album" | "genre" | "comment";
+
+export type CleanupRule =
+    | { type: "replace"; field: CleanupField; pattern: string; replacement: string }
+    | {
+          type: "case";
+          field: CleanupField;
+          case: "title" | "sentence" | "upper" | "lower";
+          onlyAllCaps?: boolean;
+      }
+    | { type: "whitespace"; field: CleanupField }
+    | { type: "genreMap"; genres: Record<string, string> }
+    | { type: "moveFeaturing" };
+
+export interface FieldChange {
+    field: CleanupField;
+    before?: string;
+    after?: string;
+}
+
+export interface SongCleanup {
+    filePath: string;
+    changes: FieldChange[];
+}
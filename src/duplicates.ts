@@ -0,0 +1,21 @@
+import type { Song } from "./song";
+
+export type DuplicateReason = "sameContent" | "similarTags" | "sameAudio";
+
+export interface DuplicateGroup {
+    /** The songs in the group, the suggested one to keep first. */
+    songs: Song[];
+    reasons: DuplicateReason[];
+    keepFilePath: string;
+}
+
+export function displayReason(reason: DuplicateReason): string {
+    switch (reason) {
+        case "sameContent":
+            return "Same audio data";
+        case "similarTags":
+            return "Same title and artist";
+        case "sameAudio":
+            return "Matching fingerprints";
+    }
+}
@@ -0,0 +1,27 @@
+import type { Recording } from "./recording";
+import type { Song } from "./song";
+
+export interface EngineDjLibrary {
+    tracks: EngineDjTrack[];
+    playlists: EngineDjPlaylist[];
+    history: Recording[];
+}
+
+export interface EngineDjTrack {
+    song: Song;
+    key?: string;
+    cues: EngineDjCue[];
+}
+
+export interface EngineDjCue {
+    index: number;
+    positionMs: number;
+    name?: string;
+    color: [number, number, number];
+}
+
+export interface EngineDjPlaylist {
+    name: string;
+    folders: string[];
+    songPaths: string[];
+}
@@ -0,0 +1,66 @@
+export type JobKind =
+    | { type: "quality"; minBitrateKbps?: number }
+    | { type: "bpm"; minBpm?: number; maxBpm?: number }
+    | { type: "key" }
+    | { type: "beats"; minBpm?: number; maxBpm?: number }
+    | { type: "fingerprint" }
+    | { type: "waveform" }
+    | { type: "loudness" }
+    | { type: "transitions" }
+    | { type: "alignment" };
+
+export type JobStatus = "pending" | "running" | "completed" | "failed" | "cancelled";
+
+export interface Job {
+    id: number;
+    kind: JobKind;
+    /** The song or recording (cue sheet) the job analyses. */
+    path: string;
+    /** Higher priority jobs run first. */
+    priority: number;
+    status: JobStatus;
+    error?: string;
+    createdUnixMs: number;
+    updatedUnixMs: number;
+}
+
+/** Emitted as the `job-updated` event whenever a job changes. */
+export interface JobUpdate {
+    job: Job;
+    /** How many jobs are pending or running. */
+    remaining: number;
+}
+
+export const JOB_UPDATED_EVENT = "job-updated";
+
+/** Emitted as the `job-progress` event as a running job works through the file it analyses. */
+export interface JobProgress {
+    id: number;
+    /** How much of the file has been analysed, from 0 to 1. */
+    progress: number;
+}
+
+export const JOB_PROGRESS_EVENT = "job-progress";
+
+export function jobKindLabel(kind: JobKind): string {
+    switch (kind.type) {
+        case "quality":
+            return "Quality";
+        case "bpm":
+            return "BPM";
+        case "key":
+            return "Key";
+        case "beats":
+            return "Beats";
+        case "fingerprint":
+            return "Fingerprint";
+        case "waveform":
+            return "Waveform";
+        case "loudness":
+            return "Loudness";
+        case "transitions":
+            return "Transitions";
+        case "alignment":
+            return "Alignment";
+    }
+}
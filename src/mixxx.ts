@@ -0,0 +1,27 @@
+import type { Recording } from "./recording";
+import type { Song } from "./song";
+
+export interface MixxxLibrary {
+    tracks: MixxxTrack[];
+    crates: MixxxCrate[];
+    history: Recording[];
+}
+
+export interface MixxxTrack {
+    song: Song;
+    key?: string;
+    cues: MixxxCue[];
+}
+
+export interface MixxxCue {
+    kind: "hotCue" | "mainCue" | "loop" | "jump" | "intro" | "outro" | "other";
+    positionMs: number;
+    lengthMs: number;
+    hotCue?: number;
+    label?: string;
+}
+
+export interface MixxxCrate {
+    name: string;
+    songPaths: string[];
+}
@@ -0,0 +1,8 @@
+export interface QualityReport {
+    filePath: string;
+    /** The highest frequency with significant content, missing when there's no lossy cutoff. */
+    cutoffHz?: number;
+    /** The bitrate the song was likely encoded at originally. */
+    estimatedBitrateKbps?: number;
+    verdict: "ok" | "lowQuality" | "fakeLossless" | "unknown";
+}
@@ -30,4 +30,15 @@ export interface WaveFile {
     sampleFormat: string;
     durationSeconds: number;
     totalSamples: number;
+    /** When the wave file was last written, the end of the recording. */
+    lastModifiedUnixSeconds?: number;
+}
+
+/**
+ * A tracklist recognised from a recording's audio.
+ */
+export interface ReconstructedTracklist {
+    recording: Recording;
+    /** Library songs queued for fingerprinting that couldn't be recognised yet. */
+    unfingerprintedSongs: number;
 }
@@ -0,0 +1,81 @@
+import type { Track } from "./recording";
+import type { Song } from "./song";
+
+export interface Analysis {
+    songPath?: string;
+    beatGrid: Beat[];
+    cues: Cue[];
+    waveformPreview: WaveformColumn[];
+    waveformDetail: WaveformColumn[];
+    colorWaveformDetail: ColorWaveformColumn[];
+    songStructure?: SongStructure;
+}
+
+export interface Beat {
+    beatNumber: number;
+    bpm: number;
+    timeMs: number;
+}
+
+export interface Cue {
+    hotCue?: number;
+    kind: "point" | "loop";
+    timeMs: number;
+    loopEndMs?: number;
+    comment?: string;
+    color?: [number, number, number];
+}
+
+export interface WaveformColumn {
+    height: number;
+    whiteness: number;
+}
+
+export interface ColorWaveformColumn {
+    red: number;
+    green: number;
+    blue: number;
+    height: number;
+}
+
+export interface SongStructure {
+    mood?: "high" | "mid" | "low";
+    endBeat: number;
+    phrases: Phrase[];
+}
+
+export interface Phrase {
+    beat: number;
+    kind: number;
+    name: string;
+}
+
+export interface DeviceLibrary {
+    songs: Song[];
+    playlists: DevicePlaylist[];
+}
+
+export interface DevicePlaylist {
+    name: string;
+    folders: string[];
+    songPaths: string[];
+}
+
+export interface HistoryTrack {
+    title?: string;
+    artist?: string;
+    filePath?: string;
+}
+
+export interface HistoryPlaylist {
+    name: string;
+    date?: string;
+    tracks: HistoryTrack[];
+}
+
+export interface HistoryMatch {
+    history: HistoryPlaylist;
+    recordingFilePath: string;
+    missingFromRecording: HistoryTrack[];
+    missingFromHistory: Track[];
+}
@@ -0,0 +1,48 @@
+import type { Song } from "./song";
+
+export type RelinkReason = "sameContent" | "sameFileName" | "similarTags";
+
+export interface RelinkCandidate {
+    /** The song read from the candidate file. */
+    song: Song;
+    reason: RelinkReason;
+}
+
+export interface MissingSong {
+    /** The stored song, or for a file only listed in cue sheets its title and performer there. */
+    song: Song;
+    /** The cue sheets listing the song's file. */
+    cueSheets: string[];
+    /** The files the song may have moved to, most likely first. */
+    candidates: RelinkCandidate[];
+    /** The candidate to relink to when one stands out. */
+    suggestedFilePath?: string;
+}
+
+export interface Relink {
+    oldFilePath: string;
+    newFilePath: string;
+}
+
+export interface SongRelinkResult {
+    oldFilePath: string;
+    song?: Song;
+    error?: string;
+}
+
+export interface RelinkResult {
+    songs: SongRelinkResult[];
+    /** The cue sheets whose `FILE` lines were changed to the new paths. */
+    cueSheets: string[];
+}
+
+export function displayRelinkReason(reason: RelinkReason): string {
+    switch (reason) {
+        case "sameContent":
+            return "Same audio data";
+        case "sameFileName":
+            return "Same file name";
+        case "similarTags":
+            return "Same title and artist";
+    }
+}
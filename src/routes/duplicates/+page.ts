@@ -0,0 +1,7 @@
+import { findDuplicateSongs } from "../../api";
+import type { PageLoad } from "./$types";
+
+export const load: PageLoad = async () => {
+    const groups = await findDuplicateSongs();
+    return { groups };
+}
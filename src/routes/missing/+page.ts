@@ -0,0 +1,7 @@
+import { findMissingSongs } from "../../api";
+import type { PageLoad } from "./$types";
+
+export const load: PageLoad = async () => {
+    const missingSongs = await findMissingSongs();
+    return { missingSongs };
+}
@@ -1,8 +1,9 @@
-import { findRecordings, getRecordingsDir } from "../../api";
+import { findBareRecordings, findRecordings, getRecordingsDir } from "../../api";
 import type { PageLoad } from "./$types";
 
 export const load: PageLoad = async () => {
     const recordings = await findRecordings();
+    const bareRecordings = await findBareRecordings();
     const recordingsDir = await getRecordingsDir();
-    return { recordings, recordingsDir };
+    return { recordings, bareRecordings, recordingsDir };
 }
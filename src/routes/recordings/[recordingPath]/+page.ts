@@ -1,11 +1,15 @@
-import { getRecording } from "../../../api";
+import { getRecording, getRecordingAlignments, getRecordingTransitions } from "../../../api";
 import type { PageLoad } from "./$types";
 
 export const load: PageLoad = async ({ params }) => {
     const { recordingPath } = params;
     const path = decodeURIComponent(recordingPath);
 
-    const recording = await getRecording(path);
+    const [recording, transitions, alignments] = await Promise.all([
+        getRecording(path),
+        getRecordingTransitions(path),
+        getRecordingAlignments(path),
+    ]);
 
-    return { recording };
+    return { recording, transitions, alignments };
 }
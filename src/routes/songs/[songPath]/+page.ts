@@ -1,8 +1,9 @@
-import { getSong } from "../../../api";
+import { getSong, getSongBeatgrid } from "../../../api";
 import type { PageLoad } from "./$types";
 
 export const load: PageLoad = async ({ params }) => {
     const { songPath } = params;
     const path = decodeURIComponent(songPath);
-    return { song: await getSong(path) };
+    const [song, beatgrid] = await Promise.all([getSong(path), getSongBeatgrid(path)]);
+    return { song, beatgrid };
 }
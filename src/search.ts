@@ -40,6 +40,7 @@ export function searchRecordings(recordings: Recording[], searchTerm: string): R
  * - Artist
  * - Album
  * - Genre
+ * - Key, in any notation
  */
 export function searchSongs(songs: Song[], searchTerm: string): Song[] {
     searchTerm = searchTerm.trim().toLowerCase();
@@ -51,7 +52,12 @@ export function searchSongs(songs: Song[], searchTerm: string): Song[] {
             song.title?.toLowerCase().includes(searchTerm) ||
             song.artist?.toLowerCase().includes(searchTerm) ||
             song.album?.toLowerCase().includes(searchTerm) ||
-            song.genre?.toLowerCase().includes(searchTerm)
+            song.genre?.toLowerCase().includes(searchTerm) ||
+            song.key?.toLowerCase() === searchTerm ||
+            (song.parsedKey &&
+                [song.parsedKey.standard, song.parsedKey.camelot, song.parsedKey.openKey].some(
+                    (key) => key.toLowerCase() === searchTerm,
+                ))
         ) {
             filtered.push(song);
         }
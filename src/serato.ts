@@ -0,0 +1,43 @@
+import type { Song } from "./song";
+
+export interface SeratoLibrary {
+    songs: Song[];
+    crates: SeratoCrate[];
+}
+
+export interface SeratoCrate {
+    name: string;
+    parents: string[];
+    songPaths: string[];
+}
+
+export interface SeratoMarkers {
+    color?: [number, number, number];
+    bpmLocked?: boolean;
+    cues: SeratoCue[];
+    loops: SeratoLoop[];
+    beatGrid: SeratoBeatGridMarker[];
+    autoBpm?: number;
+    autoGain?: number;
+}
+
+export interface SeratoCue {
+    index: number;
+    timeMs: number;
+    color: [number, number, number];
+    name?: string;
+}
+
+export interface SeratoLoop {
+    index: number;
+    startMs: number;
+    endMs: number;
+    locked: boolean;
+    name?: string;
+}
+
+export interface SeratoBeatGridMarker {
+    positionSeconds: number;
+    beatsUntilNext?: number;
+    bpm?: number;
+}
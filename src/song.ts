@@ -1,3 +1,14 @@
+import { convertFileSrc } from "@tauri-apps/api/core";
+
+/**
+ * A musical key in each notation, e.g. "Am", "8A" and "1m".
+ */
+export interface Key {
+    standard: string;
+    camelot: string;
+    openKey: string;
+}
+
 export interface Song {
     filePath: string;
     title?: string;
@@ -6,4 +17,86 @@ export interface Song {
     genre?: string;
     bpm?: string;
     durationSeconds: number;
+    key?: string;
+    parsedKey?: Key;
+    year?: number;
+    releaseDate?: string;
+    label?: string;
+    remixer?: string;
+    composer?: string;
+    comment?: string;
+    trackNumber?: number;
+    rating?: number;
+    isrc?: string;
+    dateAddedUnixSeconds?: number;
+    bitrateKbps?: number;
+    sampleRate?: number;
+    bitDepth?: number;
+    channels?: number;
+    fileType?: string;
+    fileSizeBytes?: number;
+    durationMs?: number;
+    artworkHash?: string;
+    lastModifiedUnixMs?: number;
+    /** The hash of the audio data without tags, it stays the same when the file is moved. */
+    contentHash?: string;
+    /** The tempo detected from the audio, separate from the tagged `bpm`. */
+    detectedBpm?: number;
+    detectedBpmConfidence?: number;
+    /** The key detected from the audio, separate from the tagged `key`. */
+    detectedKey?: Key;
+    detectedKeyConfidence?: number;
+}
+
+/**
+ * Changes to write to a song's tags, missing fields are left unchanged and an empty string
+ * (or a BPM of 0) removes the tag.
+ */
+export interface TagChanges {
+    title?: string;
+    artist?: string;
+    album?: string;
+    genre?: string;
+    bpm?: number;
+    key?: string;
+    comment?: string;
+}
+
+export interface TagWriteResult {
+    filePath: string;
+    song?: Song;
+    error?: string;
+}
+
+/**
+ * The URL of a song's cover art thumbnail, served by the backend's `artwork` protocol.
+ * Songs without artwork respond with a 404.
+ */
+export function artworkUrl(filePath: string): string {
+    return convertFileSrc(filePath, "artwork");
+}
+
+/**
+ * Formats a song's file type and bitrate, e.g. "MP3 320k" or "FLAC 16/44.1".
+ * Lossless files show bit depth and sample rate since their bitrate varies.
+ */
+export function displayQuality(song: Song): string {
+    const fileType = song.fileType || "";
+    if (song.bitDepth && song.sampleRate) {
+        return `${fileType} ${song.bitDepth}/${song.sampleRate / 1000}`.trim();
+    }
+    if (song.bitrateKbps) {
+        return `${fileType} ${song.bitrateKbps}k`.trim();
+    }
+    return fileType;
+}
+
+/**
+ * Orders keys around the Camelot wheel, minor before major, songs without a key sort last.
+ */
+export function compareKeys(a?: Key, b?: Key): number {
+    if (!a || !b) {
+        return (a ? 0 : 1) - (b ? 0 : 1);
+    }
+    return parseInt(a.camelot) - parseInt(b.camelot) || a.camelot.localeCompare(b.camelot);
 }
@@ -0,0 +1,26 @@
+import type { Song } from "./song";
+
+export interface TraktorCollection {
+    entries: TraktorEntry[];
+    playlists: TraktorPlaylist[];
+}
+
+export interface TraktorEntry {
+    song: Song;
+    key?: string;
+    cues: TraktorCue[];
+}
+
+export interface TraktorCue {
+    name?: string;
+    kind: "cue" | "fadeIn" | "fadeOut" | "load" | "grid" | "loop";
+    startMs: number;
+    lengthMs: number;
+    hotCue?: number;
+}
+
+export interface TraktorPlaylist {
+    name: string;
+    folders: string[];
+    songPaths: string[];
+}